@@ -1,18 +1,32 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::measurement::WallTime;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion};
 use hex::decode as decode_hex;
 use serde::Deserialize;
-use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Once;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use unitrie_rs::core_api::TrieEngine;
+use unitrie_rs::core_trie::Unitrie;
+use unitrie_rs::latency_store::{LatencyConfig, LatencyStore};
 use unitrie_rs::next::core_trie::NextUnitrie;
-use unitrie_rs::store_adapter::RawStoreAdapter;
+use unitrie_rs::reporting::{
+    BenchReport, Checksum, ComparisonReport, WorkloadMetrics, WorkloadReport,
+};
+use unitrie_rs::store_adapter::MemoryStore;
+use unitrie_rs::workload::{self, OpOutcome, ReplayChecksum, WorkloadOp, WorkloadProfile};
 
 static SUMMARY_ONCE: Once = Once::new();
 const CORE_CORPUS_ENV: &str = "UNITRIE_JMH_CORE_CORPUS_PATH";
 const CORE_OUTPUT_ENV: &str = "UNITRIE_RUST_CORE_BENCH_OUTPUT";
+// `compare` runs every workload against both engines and writes a comparison
+// summary next to the regular one; anything else benches the next engine only.
+const BENCH_MODE_ENV: &str = "UNITRIE_BENCH_MODE";
+const COMPARISON_OUTPUT_ENV: &str = "UNITRIE_RUST_CORE_COMPARISON_OUTPUT";
+// Comma-separated `seed:profile` pairs, e.g. `42:small,7:storage-heavy`; when
+// set, generated workloads replace the corpus.
+const GENERATE_ENV: &str = "UNITRIE_BENCH_GENERATE";
 
 #[derive(Debug, Deserialize)]
 struct Corpus {
@@ -24,9 +38,37 @@ struct Workload {
     name: String,
     #[serde(default = "default_repeat")]
     repeat: usize,
+    // Delays applied to every store call; absent means HashMap speed.
+    #[serde(default)]
+    #[serde(alias = "storeLatency")]
+    store_latency: Option<StoreLatency>,
     operations: Vec<Operation>,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy)]
+struct StoreLatency {
+    #[serde(default)]
+    #[serde(alias = "readMicros")]
+    read_micros: u64,
+    #[serde(default)]
+    #[serde(alias = "writeMicros")]
+    write_micros: u64,
+    #[serde(default)]
+    #[serde(alias = "jitterMicros")]
+    jitter_micros: u64,
+    #[serde(default)]
+    seed: u64,
+}
+
+impl StoreLatency {
+    fn config(&self) -> LatencyConfig {
+        LatencyConfig::with_seed(self.seed)
+            .read_latency(Duration::from_micros(self.read_micros))
+            .write_latency(Duration::from_micros(self.write_micros))
+            .jitter(Duration::from_micros(self.jitter_micros))
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 struct Operation {
     op: String,
@@ -40,64 +82,147 @@ struct Operation {
     size: Option<usize>,
 }
 
-#[derive(Debug)]
-struct InMemoryRawStoreAdapter {
-    nodes: HashMap<Vec<u8>, Vec<u8>>,
-    values: HashMap<Vec<u8>, Vec<u8>>,
+// A workload with its operations decoded up front, so timings exclude parsing.
+#[derive(Debug, Clone)]
+struct BenchWorkload {
+    name: String,
+    repeat: usize,
+    latency: LatencyConfig,
+    operations: Vec<WorkloadOp>,
 }
 
-impl InMemoryRawStoreAdapter {
-    fn new() -> Self {
+impl From<Workload> for BenchWorkload {
+    fn from(workload: Workload) -> Self {
         Self {
-            nodes: HashMap::new(),
-            values: HashMap::new(),
+            latency: workload
+                .store_latency
+                .map(|latency| latency.config())
+                .unwrap_or_default(),
+            operations: workload.operations.iter().map(parse_operation).collect(),
+            name: workload.name,
+            repeat: workload.repeat,
         }
     }
 }
 
-impl RawStoreAdapter for InMemoryRawStoreAdapter {
-    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
-        self.nodes.get(hash).cloned()
-    }
+type BenchStore = LatencyStore<MemoryStore>;
 
-    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
-        self.values.get(hash).cloned()
-    }
+fn default_repeat() -> usize {
+    1
+}
 
-    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
-        self.nodes.insert(hash.to_vec(), serialized_node.to_vec());
-    }
+// What workloads need beyond `TrieEngine`: borrowed reads and persistence.
+trait BenchEngine: TrieEngine + Sized {
+    const NAME: &'static str;
 
-    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
-        self.values.insert(hash.to_vec(), value.to_vec());
-    }
+    fn new() -> Self;
+    fn value(&self, key: &[u8]) -> Option<&[u8]>;
+    fn save(&mut self, store: &mut BenchStore);
+    fn reload(root: &[u8], store: &mut BenchStore) -> Result<Self, String>;
 }
 
-fn default_repeat() -> usize {
-    1
+macro_rules! bench_engine {
+    ($engine:ty, $name:literal) => {
+        impl BenchEngine for $engine {
+            const NAME: &'static str = $name;
+
+            fn new() -> Self {
+                <$engine>::new()
+            }
+
+            fn value(&self, key: &[u8]) -> Option<&[u8]> {
+                self.get_ref(key)
+            }
+
+            fn save(&mut self, store: &mut BenchStore) {
+                self.save_to_store(store);
+            }
+
+            fn reload(root: &[u8], store: &mut BenchStore) -> Result<Self, String> {
+                <$engine>::from_persisted_root(root, store)
+            }
+        }
+    };
+}
+
+bench_engine!(Unitrie, "rust(legacy-v1)");
+bench_engine!(NextUnitrie, "rust(next-core)");
+
+fn compare_mode() -> bool {
+    env::var(BENCH_MODE_ENV).is_ok_and(|mode| mode.trim().eq_ignore_ascii_case("compare"))
 }
 
 fn core_trie_bench(criterion: &mut Criterion) {
-    let corpus = load_corpus();
+    let workloads = load_workloads();
+    let compare = compare_mode();
     SUMMARY_ONCE.call_once(|| {
-        if let Err(error) = write_manual_summary(&corpus) {
+        if let Err(error) = write_manual_summary(&workloads, compare) {
             panic!("failed to write rust core benchmark summary: {error}");
         }
     });
 
+    if compare {
+        let mut group = criterion.benchmark_group("TrieLegacyVsNextBenchmark");
+        for workload in &workloads {
+            bench_workload::<Unitrie>(&mut group, workload, "legacy/");
+            bench_workload::<NextUnitrie>(&mut group, workload, "next/");
+        }
+        group.finish();
+        return;
+    }
+
     let mut group = criterion.benchmark_group("TrieRustCoreBenchmark");
-    for workload in &corpus.workloads {
-        let workload = workload.clone();
-        group.bench_function(workload.name.clone(), move |bencher| {
-            bencher.iter(|| {
-                let checksum = run_workload(&workload);
-                black_box(checksum);
-            });
-        });
+    for workload in &workloads {
+        bench_workload::<NextUnitrie>(&mut group, workload, "");
     }
     group.finish();
 }
 
+fn bench_workload<T: BenchEngine>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    workload: &BenchWorkload,
+    label_prefix: &str,
+) {
+    let workload = workload.clone();
+    group.bench_function(format!("{label_prefix}{}", workload.name), move |bencher| {
+        bencher.iter(|| {
+            let checksum = run_workload::<T>(&workload);
+            black_box(checksum);
+        });
+    });
+}
+
+fn load_workloads() -> Vec<BenchWorkload> {
+    match env::var(GENERATE_ENV) {
+        Ok(specs) if !specs.trim().is_empty() => specs.split(',').map(generate_workload).collect(),
+        _ => load_corpus()
+            .workloads
+            .into_iter()
+            .map(BenchWorkload::from)
+            .collect(),
+    }
+}
+
+fn generate_workload(spec: &str) -> BenchWorkload {
+    let (seed, profile_name) = spec
+        .trim()
+        .split_once(':')
+        .unwrap_or_else(|| panic!("{GENERATE_ENV} entries must be seed:profile, got '{spec}'"));
+    let seed = seed
+        .parse()
+        .unwrap_or_else(|error| panic!("invalid workload seed '{seed}': {error}"));
+    let profile: WorkloadProfile = profile_name
+        .parse()
+        .unwrap_or_else(|error| panic!("{error}"));
+    let workload = workload::generate(seed, &profile);
+    BenchWorkload {
+        name: format!("{}-{profile_name}", workload.name),
+        repeat: 1,
+        latency: LatencyConfig::default(),
+        operations: workload.operations,
+    }
+}
+
 fn load_corpus() -> Corpus {
     let path = resolve_corpus_path();
     let payload = fs::read_to_string(&path).unwrap_or_else(|error| {
@@ -130,77 +255,99 @@ fn resolve_corpus_path() -> PathBuf {
         .join("workloads-v1.json")
 }
 
-fn run_workload(workload: &Workload) -> usize {
-    let mut trie = NextUnitrie::new();
-    let mut store = InMemoryRawStoreAdapter::new();
-    let mut checksum = 0usize;
+fn run_workload<T: BenchEngine>(workload: &BenchWorkload) -> [u8; 32] {
+    let mut trie = T::new();
+    let mut store = LatencyStore::new(MemoryStore::new(), workload.latency);
+    let mut checksum = ReplayChecksum::new();
 
     for _ in 0..workload.repeat.max(1) {
         for operation in &workload.operations {
-            checksum ^= apply_operation(&mut trie, &mut store, operation);
+            apply_operation(&mut trie, &mut store, operation, &mut checksum);
         }
     }
 
-    checksum
+    checksum.finish()
 }
 
-fn apply_operation(
-    trie: &mut NextUnitrie,
-    store: &mut InMemoryRawStoreAdapter,
-    operation: &Operation,
-) -> usize {
-    let operation_name = operation.op.trim().to_ascii_lowercase();
-    match operation_name.as_str() {
-        "put" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "put");
-            let value = decode_required(&operation.value_hex, "valueHex", "put");
-            let value_len = value.len();
-            trie.put(key, value);
-            value_len
+fn apply_operation<T: BenchEngine>(
+    trie: &mut T,
+    store: &mut BenchStore,
+    operation: &WorkloadOp,
+    checksum: &mut ReplayChecksum,
+) {
+    match operation {
+        WorkloadOp::Put { key, value } => {
+            trie.put(key.clone(), value.clone());
+            checksum.record(operation, OpOutcome::Done);
         }
-        "get" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "get");
-            trie.get_ref(&key).map(|value| value.len()).unwrap_or(0)
+        WorkloadOp::Get { key } => checksum.record(operation, OpOutcome::Value(trie.value(key))),
+        WorkloadOp::Delete { key } => {
+            trie.delete(key);
+            checksum.record(operation, OpOutcome::Done);
         }
-        "delete" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "delete");
-            trie.delete(&key);
-            key.len()
-        }
-        "deleterecursive" | "delete_recursive" | "delete-recursive" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "deleteRecursive");
-            trie.delete_recursive(&key);
-            key.len()
+        WorkloadOp::DeleteRecursive { prefix } => {
+            let deleted = trie.delete_recursive(prefix);
+            checksum.record(operation, OpOutcome::Count(deleted));
         }
-        "getvaluelength" | "get_value_length" | "get-value-length" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "getValueLength");
-            trie.get_value_length(&key).unwrap_or(0)
+        WorkloadOp::GetValueLength { key } => {
+            checksum.record(operation, OpOutcome::Length(trie.get_value_length(key)));
         }
-        "getvaluehash" | "get_value_hash" | "get-value-hash" => {
-            let key = decode_required(&operation.key_hex, "keyHex", "getValueHash");
-            trie.get_value_hash(&key)
-                .map(|hash| hash.len())
-                .unwrap_or(0)
+        WorkloadOp::GetValueHash { key } => {
+            checksum.record(operation, OpOutcome::Hash(trie.get_value_hash(key)));
         }
-        "collectkeys" | "collect_keys" | "collect-keys" => {
-            let size = operation.size.unwrap_or(0);
-            trie.collect_keys(size).len()
+        WorkloadOp::CollectKeys { byte_size } => {
+            let keys = trie.collect_keys(*byte_size);
+            checksum.record(operation, OpOutcome::Keys(&keys));
         }
-        "save" => {
-            trie.save_to_store(store);
-            1
+        WorkloadOp::Save => {
+            trie.save(store);
+            checksum.record(operation, OpOutcome::Root(trie.current_root_hash()));
         }
-        "savereload" | "save_reload" | "save-reload" => {
-            trie.save_to_store(store);
+        WorkloadOp::SaveReload => {
+            trie.save(store);
             let root = trie.current_root_hash();
-            let rehydrated =
-                NextUnitrie::from_persisted_root(&root, store).unwrap_or_else(|error| {
-                    panic!("could not rehydrate trie from persisted root: {error}")
-                });
+            let rehydrated = T::reload(&root, store).unwrap_or_else(|error| {
+                panic!("could not rehydrate trie from persisted root: {error}")
+            });
             *trie = rehydrated;
-            root.len()
+            checksum.record(operation, OpOutcome::Root(root));
+        }
+        WorkloadOp::RootHash => {
+            checksum.record(operation, OpOutcome::Root(trie.current_root_hash()));
+        }
+    }
+}
+
+fn parse_operation(operation: &Operation) -> WorkloadOp {
+    let operation_name = operation.op.trim().to_ascii_lowercase();
+    match operation_name.as_str() {
+        "put" => WorkloadOp::Put {
+            key: decode_required(&operation.key_hex, "keyHex", "put"),
+            value: decode_required(&operation.value_hex, "valueHex", "put"),
+        },
+        "get" => WorkloadOp::Get {
+            key: decode_required(&operation.key_hex, "keyHex", "get"),
+        },
+        "delete" => WorkloadOp::Delete {
+            key: decode_required(&operation.key_hex, "keyHex", "delete"),
+        },
+        "deleterecursive" | "delete_recursive" | "delete-recursive" => {
+            WorkloadOp::DeleteRecursive {
+                prefix: decode_required(&operation.key_hex, "keyHex", "deleteRecursive"),
+            }
         }
-        "roothash" | "root_hash" | "root-hash" => trie.current_root_hash().len(),
+        "getvaluelength" | "get_value_length" | "get-value-length" => WorkloadOp::GetValueLength {
+            key: decode_required(&operation.key_hex, "keyHex", "getValueLength"),
+        },
+        "getvaluehash" | "get_value_hash" | "get-value-hash" => WorkloadOp::GetValueHash {
+            key: decode_required(&operation.key_hex, "keyHex", "getValueHash"),
+        },
+        "collectkeys" | "collect_keys" | "collect-keys" => WorkloadOp::CollectKeys {
+            byte_size: operation.size.unwrap_or(0),
+        },
+        "save" => WorkloadOp::Save,
+        "savereload" | "save_reload" | "save-reload" => WorkloadOp::SaveReload,
+        "roothash" | "root_hash" | "root-hash" => WorkloadOp::RootHash,
         _ => panic!("unsupported workload operation: {}", operation.op),
     }
 }
@@ -232,41 +379,48 @@ fn decode_hex_value(raw: &str) -> Vec<u8> {
     decode_hex(&normalized).unwrap_or_else(|error| panic!("invalid hex value '{raw}': {error}"))
 }
 
-fn write_manual_summary(corpus: &Corpus) -> Result<(), String> {
-    let mut workloads = Vec::with_capacity(corpus.workloads.len());
-    for workload in &corpus.workloads {
+fn write_manual_summary(workloads: &[BenchWorkload], compare: bool) -> Result<(), String> {
+    let next = measure::<NextUnitrie>(workloads);
+    write_json(
+        &resolve_output_path(CORE_OUTPUT_ENV, "result_trie_rust_core_summary.json"),
+        &next,
+    )?;
+    if compare {
+        let legacy = measure::<Unitrie>(workloads);
+        write_json(
+            &resolve_output_path(
+                COMPARISON_OUTPUT_ENV,
+                "result_trie_rust_core_comparison.json",
+            ),
+            &ComparisonReport::from_reports(&legacy, &next),
+        )?;
+    }
+    Ok(())
+}
+
+fn measure<T: BenchEngine>(workloads: &[BenchWorkload]) -> BenchReport {
+    let mut report = BenchReport::new(chrono_like_timestamp(), T::NAME);
+    for workload in workloads {
         let mut samples_ns = Vec::with_capacity(30);
-        let mut checksum = 0usize;
+        let mut checksum = [0u8; 32];
         for _ in 0..30 {
             let started = Instant::now();
-            checksum ^= run_workload(workload);
+            checksum = run_workload::<T>(workload);
             samples_ns.push(started.elapsed().as_nanos() as f64);
         }
 
-        samples_ns.sort_by(|left, right| left.partial_cmp(right).unwrap());
-        let avg_ns = samples_ns.iter().sum::<f64>() / samples_ns.len() as f64;
-        let p95_index = ((samples_ns.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
-        let p95_ns = samples_ns[p95_index.min(samples_ns.len() - 1)];
-        let throughput_ops_per_sec = if avg_ns <= 0.0 {
-            0.0
-        } else {
-            1_000_000_000.0 / avg_ns
-        };
-
-        workloads.push(serde_json::json!({
-            "benchmark": workload.name,
-            "engine": "rust(next-core)",
-            "metrics": {
-                "avgMicros": avg_ns / 1_000.0,
-                "p95Micros": p95_ns / 1_000.0,
-                "throughputOpsPerSec": throughput_ops_per_sec
-            },
-            "sampleCount": samples_ns.len(),
-            "checksum": checksum
-        }));
+        report.workloads.push(WorkloadReport {
+            benchmark: workload.name.clone(),
+            engine: T::NAME.to_string(),
+            metrics: WorkloadMetrics::from_samples_ns(&samples_ns),
+            sample_count: samples_ns.len(),
+            checksum: Checksum(checksum.to_vec()),
+        });
     }
+    report
+}
 
-    let output_path = resolve_output_path();
+fn write_json<R: serde::Serialize>(output_path: &Path, report: &R) -> Result<(), String> {
     if let Some(parent) = output_path.parent() {
         fs::create_dir_all(parent).map_err(|error| {
             format!(
@@ -276,22 +430,16 @@ fn write_manual_summary(corpus: &Corpus) -> Result<(), String> {
         })?;
     }
 
-    let payload = serde_json::json!({
-        "generatedAt": chrono_like_timestamp(),
-        "engine": "rust(next-core)",
-        "workloads": workloads
-    });
-
     fs::write(
-        &output_path,
-        serde_json::to_vec_pretty(&payload)
+        output_path,
+        serde_json::to_vec_pretty(report)
             .map_err(|error| format!("could not serialize rust core summary JSON: {error}"))?,
     )
     .map_err(|error| format!("could not write {}: {error}", output_path.display()))
 }
 
-fn resolve_output_path() -> PathBuf {
-    if let Ok(configured) = env::var(CORE_OUTPUT_ENV) {
+fn resolve_output_path(env_name: &str, file_name: &str) -> PathBuf {
+    if let Ok(configured) = env::var(env_name) {
         if !configured.trim().is_empty() {
             return PathBuf::from(configured);
         }
@@ -305,7 +453,7 @@ fn resolve_output_path() -> PathBuf {
         .join("build")
         .join("reports")
         .join("jmh")
-        .join("result_trie_rust_core_summary.json")
+        .join(file_name)
 }
 
 fn chrono_like_timestamp() -> String {
@@ -0,0 +1,318 @@
+use crate::codec_orchid::OrchidCodec;
+use crate::codec_rskip107::ChildEncoding;
+use crate::core_trie::{compute_child_encoding, decode_persisted_node, encode_node_metadata};
+use crate::hash::{is_empty_root, keccak256};
+use crate::node_ref::{CodecConfig, NodeReference, TrieNode, HASH_SIZE};
+use crate::store_adapter::RawStoreAdapter;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AuditIssue {
+    Missing {
+        hash: [u8; HASH_SIZE],
+    },
+    Undecodable {
+        hash: [u8; HASH_SIZE],
+        error: String,
+    },
+    // The stored payload does not hash to the key it is stored under.
+    HashMismatch {
+        hash: [u8; HASH_SIZE],
+        actual: [u8; HASH_SIZE],
+    },
+    // Decoding and re-encoding the payload does not reproduce it byte for byte.
+    Reencoded {
+        hash: [u8; HASH_SIZE],
+        stored: Vec<u8>,
+        reencoded: Vec<u8>,
+    },
+    // The children size stored in the payload disagrees with the size of the
+    // subtrees it references.
+    ChildrenSizeMismatch {
+        hash: [u8; HASH_SIZE],
+        stored: u64,
+        computed: u64,
+    },
+}
+
+impl AuditIssue {
+    pub fn hash(&self) -> &[u8; HASH_SIZE] {
+        match self {
+            Self::Missing { hash }
+            | Self::Undecodable { hash, .. }
+            | Self::HashMismatch { hash, .. }
+            | Self::Reencoded { hash, .. }
+            | Self::ChildrenSizeMismatch { hash, .. } => hash,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AuditReport {
+    pub nodes_checked: usize,
+    pub orchid_nodes: usize,
+    // Nodes whose re-encoding could not be compared because a descendant was
+    // missing or undecodable, so their children size is unknown.
+    pub nodes_skipped: usize,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty() && self.nodes_skipped == 0
+    }
+}
+
+pub fn audit_store<T: RawStoreAdapter>(
+    root_hash: &[u8],
+    store: &mut T,
+) -> Result<AuditReport, String> {
+    audit_store_with_config(root_hash, store, &CodecConfig::CONSENSUS)
+}
+
+// Decodes every node reachable from `root_hash` and re-encodes it with this
+// crate's encoder, reporting payloads that do not survive the round trip.
+pub fn audit_store_with_config<T: RawStoreAdapter>(
+    root_hash: &[u8],
+    store: &mut T,
+    config: &CodecConfig,
+) -> Result<AuditReport, String> {
+    let root: [u8; HASH_SIZE] = root_hash
+        .try_into()
+        .map_err(|_| format!("root hash must be {HASH_SIZE} bytes"))?;
+    let mut report = AuditReport::default();
+    if is_empty_root(&root) {
+        return Ok(report);
+    }
+
+    let mut audited = HashMap::new();
+    audit_node(root, store, config, &mut audited, &mut report)?;
+    Ok(report)
+}
+
+// Returns how a parent would reference the node, or `None` when its subtree
+// could not be fully audited.
+fn audit_node<T: RawStoreAdapter>(
+    hash: [u8; HASH_SIZE],
+    store: &mut T,
+    config: &CodecConfig,
+    audited: &mut HashMap<[u8; HASH_SIZE], Option<(ChildEncoding, u64)>>,
+    report: &mut AuditReport,
+) -> Result<Option<(ChildEncoding, u64)>, String> {
+    if let Some(result) = audited.get(&hash) {
+        return Ok(result.clone());
+    }
+
+    let result = audit_payload(hash, store, config, audited, report)?;
+    audited.insert(hash, result.clone());
+    Ok(result)
+}
+
+fn audit_payload<T: RawStoreAdapter>(
+    hash: [u8; HASH_SIZE],
+    store: &mut T,
+    config: &CodecConfig,
+    audited: &mut HashMap<[u8; HASH_SIZE], Option<(ChildEncoding, u64)>>,
+    report: &mut AuditReport,
+) -> Result<Option<(ChildEncoding, u64)>, String> {
+    let Some(stored) = store.load_raw_node(&hash) else {
+        report.issues.push(AuditIssue::Missing { hash });
+        return Ok(None);
+    };
+    report.nodes_checked += 1;
+
+    let actual = keccak256(&stored);
+    if actual != hash {
+        report
+            .issues
+            .push(AuditIssue::HashMismatch { hash, actual });
+    }
+
+    let node = match decode_persisted_node(&stored, store.codec_hint()) {
+        Ok(node) => node,
+        Err(error) => {
+            report.issues.push(AuditIssue::Undecodable { hash, error });
+            return Ok(None);
+        }
+    };
+
+    if OrchidCodec::is_orchid_message(&stored) {
+        return audit_orchid_node(hash, &stored, &node, store, config, audited, report);
+    }
+
+    let left = audit_child(&node.left, store, config, audited, report)?;
+    let right = audit_child(&node.right, store, config, audited, report)?;
+    let (Some(left), Some(right)) = (left, right) else {
+        report.nodes_skipped += 1;
+        return Ok(None);
+    };
+
+    if let Some(stored_size) = node.children_size {
+        let computed = left.1 + right.1;
+        if stored_size != computed {
+            report.issues.push(AuditIssue::ChildrenSizeMismatch {
+                hash,
+                stored: stored_size,
+                computed,
+            });
+        }
+    }
+
+    let metadata = encode_node_metadata(&node, left, right, config)?;
+    if metadata.serialized != stored {
+        report.issues.push(AuditIssue::Reencoded {
+            hash,
+            stored,
+            reencoded: metadata.serialized.clone(),
+        });
+    }
+    Ok(Some(metadata.into_child_encoding()))
+}
+
+fn audit_child<T: RawStoreAdapter>(
+    reference: &NodeReference,
+    store: &mut T,
+    config: &CodecConfig,
+    audited: &mut HashMap<[u8; HASH_SIZE], Option<(ChildEncoding, u64)>>,
+    report: &mut AuditReport,
+) -> Result<Option<(ChildEncoding, u64)>, String> {
+    match reference {
+        NodeReference::Hashed(hash) => audit_node(*hash, store, config, audited, report),
+        // Embedded children are covered by their parent's byte comparison.
+        reference => compute_child_encoding(reference, config).map(Some),
+    }
+}
+
+// Orchid payloads carry no children size, so they are re-encoded on their own
+// and referenced by hash with their stored length.
+fn audit_orchid_node<T: RawStoreAdapter>(
+    hash: [u8; HASH_SIZE],
+    stored: &[u8],
+    node: &TrieNode,
+    store: &mut T,
+    config: &CodecConfig,
+    audited: &mut HashMap<[u8; HASH_SIZE], Option<(ChildEncoding, u64)>>,
+    report: &mut AuditReport,
+) -> Result<Option<(ChildEncoding, u64)>, String> {
+    report.orchid_nodes += 1;
+    let mut complete = true;
+    for child in [&node.left, &node.right] {
+        if let NodeReference::Hashed(child) = child {
+            complete &= audit_node(*child, store, config, audited, report)?.is_some();
+        }
+    }
+
+    let secure = stored.get(1).is_some_and(|flags| flags & 0x01 == 0x01);
+    let reencoded = OrchidCodec::encode_node(
+        node,
+        hashed_child(&node.left),
+        hashed_child(&node.right),
+        secure,
+    )?;
+    if reencoded != stored {
+        report.issues.push(AuditIssue::Reencoded {
+            hash,
+            stored: stored.to_vec(),
+            reencoded,
+        });
+    }
+    if !complete {
+        return Ok(None);
+    }
+    Ok(Some((ChildEncoding::Hashed(hash), stored.len() as u64)))
+}
+
+fn hashed_child(reference: &NodeReference) -> Option<[u8; HASH_SIZE]> {
+    match reference {
+        NodeReference::Hashed(hash) => Some(*hash),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{audit_store, AuditIssue};
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+
+    fn saved_trie() -> ([u8; 32], MemoryStore) {
+        let mut trie = Unitrie::new();
+        for index in 0u8..64 {
+            trie.put(vec![0x01, index], vec![index; 4]);
+            trie.put(vec![0x02, index], vec![index; 64]);
+        }
+        let mut store = MemoryStore::new();
+        trie.save_to_store(&mut store);
+        (trie.root_hash(), store)
+    }
+
+    #[test]
+    fn audit_of_own_writes_is_clean() {
+        let (root, mut store) = saved_trie();
+        let report = audit_store(&root, &mut store).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.nodes_checked, store.node_count());
+    }
+
+    #[test]
+    fn audit_flags_non_canonical_and_missing_payloads() {
+        let (root, mut store) = saved_trie();
+        let root_payload = store.node(root.as_slice()).unwrap().to_vec();
+
+        // A children size varint widened to four bytes still decodes.
+        let mut padded = root_payload.clone();
+        let size_offset = padded.len() - 3;
+        assert_eq!(padded[size_offset], 0xfd);
+        let size = u16::from_le_bytes([padded[size_offset + 1], padded[size_offset + 2]]);
+        padded.truncate(size_offset);
+        padded.push(0xfe);
+        padded.extend_from_slice(&u32::from(size).to_le_bytes());
+        store.save_raw_node(&root, &padded);
+
+        let report = audit_store(&root, &mut store).unwrap();
+        assert_eq!(
+            report.issues,
+            vec![
+                AuditIssue::HashMismatch {
+                    hash: root,
+                    actual: crate::hash::keccak256(&padded),
+                },
+                AuditIssue::Reencoded {
+                    hash: root,
+                    stored: padded,
+                    reencoded: root_payload,
+                },
+            ]
+        );
+
+        let child = store
+            .nodes()
+            .map(|(hash, _)| hash.to_vec())
+            .find(|hash| hash[..] != root[..])
+            .unwrap();
+        store.remove_node(&child);
+        let report = audit_store(&root, &mut store).unwrap();
+        assert!(report.nodes_skipped > 0);
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, AuditIssue::Missing { hash } if hash[..] == child[..])));
+    }
+
+    #[test]
+    fn audit_cross_checks_stored_children_size() {
+        let (root, mut store) = saved_trie();
+        let mut payload = store.node(root.as_slice()).unwrap().to_vec();
+        let size_offset = payload.len() - 2;
+        let size = u16::from_le_bytes([payload[size_offset], payload[size_offset + 1]]);
+        payload[size_offset..].copy_from_slice(&(size + 1).to_le_bytes());
+        store.save_raw_node(&root, &payload);
+
+        let report = audit_store(&root, &mut store).unwrap();
+        assert!(report.issues.contains(&AuditIssue::ChildrenSizeMismatch {
+            hash: root,
+            stored: u64::from(size) + 1,
+            computed: u64::from(size),
+        }));
+    }
+}
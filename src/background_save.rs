@@ -0,0 +1,420 @@
+use crate::change_set::ChangeSet;
+use crate::store_adapter::RawStoreAdapter;
+use crate::TrieRoot;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FlushReceipt {
+    pub sequence: u64,
+    pub root: TrieRoot,
+    pub nodes_written: usize,
+    pub values_written: usize,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BackgroundSaveError {
+    // The store panicked while writing this flush.
+    FlushFailed { sequence: u64, message: String },
+    // A flush failed earlier, so nothing more is written; snapshots submitted
+    // since are kept with the failed one and returned by `finish`.
+    Stopped { failed_sequence: u64 },
+}
+
+impl fmt::Display for BackgroundSaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FlushFailed { sequence, message } => {
+                write!(f, "background flush {sequence} failed: {message}")
+            }
+            Self::Stopped { failed_sequence } => write!(
+                f,
+                "background saving stopped after flush {failed_sequence} failed"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Completion {
+    sequence: u64,
+    outcome: Result<FlushReceipt, (String, ChangeSet)>,
+}
+
+// Writes save snapshots to a store on its own thread, so the next block can
+// execute while the previous one flushes. Double buffered: one snapshot is
+// written while the trie collects the next block's changes, and submitting
+// before that write is done blocks until it is. The store belongs to the
+// thread until `finish`.
+#[derive(Debug)]
+pub struct BackgroundSaver<S> {
+    jobs: Option<Sender<(u64, ChangeSet)>>,
+    completions: Receiver<Completion>,
+    worker: Option<JoinHandle<S>>,
+    next_sequence: u64,
+    in_flight: bool,
+    receipts: VecDeque<FlushReceipt>,
+    failed_sequence: Option<u64>,
+    // Everything not known to be written once a flush fails.
+    unflushed: Option<ChangeSet>,
+}
+
+impl<S: RawStoreAdapter + Send + 'static> BackgroundSaver<S> {
+    pub fn new(store: S) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (completion_sender, completions) = mpsc::channel();
+        let worker = thread::spawn(move || flush_loop(store, job_receiver, completion_sender));
+        Self {
+            jobs: Some(jobs),
+            completions,
+            worker: Some(worker),
+            next_sequence: 0,
+            in_flight: false,
+            receipts: VecDeque::new(),
+            failed_sequence: None,
+            unflushed: None,
+        }
+    }
+
+    // Queues a snapshot, normally one taken by `UnitrieCore::save_in_background`,
+    // and returns its sequence number. Blocks while an earlier snapshot is still
+    // being written.
+    pub fn submit(&mut self, change_set: ChangeSet) -> Result<u64, BackgroundSaveError> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        if self.in_flight {
+            if let Err(error) = self.receive(true) {
+                self.keep_unflushed(change_set);
+                return Err(error);
+            }
+        }
+        if let Some(failed_sequence) = self.failed_sequence {
+            self.keep_unflushed(change_set);
+            return Err(BackgroundSaveError::Stopped { failed_sequence });
+        }
+
+        let jobs = self.jobs.as_ref().expect("jobs are open until finish");
+        if let Err(mpsc::SendError((_, change_set))) = jobs.send((sequence, change_set)) {
+            // The thread only exits early after reporting a failure.
+            self.keep_unflushed(change_set);
+            return Err(self
+                .receive(true)
+                .err()
+                .unwrap_or(BackgroundSaveError::Stopped {
+                    failed_sequence: sequence,
+                }));
+        }
+        self.in_flight = true;
+        Ok(sequence)
+    }
+
+    // The next completed flush, without blocking.
+    pub fn poll(&mut self) -> Result<Option<FlushReceipt>, BackgroundSaveError> {
+        if self.in_flight {
+            self.receive(false)?;
+        }
+        self.next_receipt()
+    }
+
+    // Blocks until every submitted snapshot is written and returns the
+    // receipts not yet polled.
+    pub fn wait(&mut self) -> Result<Vec<FlushReceipt>, BackgroundSaveError> {
+        if self.in_flight {
+            self.receive(true)?;
+        }
+        if self.receipts.is_empty() {
+            if let Some(failed_sequence) = self.failed_sequence {
+                return Err(BackgroundSaveError::Stopped { failed_sequence });
+            }
+        }
+        Ok(self.receipts.drain(..).collect())
+    }
+
+    pub fn is_flushing(&self) -> bool {
+        self.in_flight
+    }
+
+    // Waits for the thread and hands back the store, along with what was not
+    // written when a flush failed. Writing that change set to a working store
+    // makes the last submitted root loadable.
+    pub fn finish(mut self) -> (S, Option<ChangeSet>) {
+        self.jobs = None;
+        if self.in_flight {
+            let _ = self.receive(true);
+        }
+        let worker = self.worker.take().expect("worker is joined once");
+        let store = worker
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload));
+        (store, self.unflushed.take())
+    }
+
+    fn receive(&mut self, block: bool) -> Result<(), BackgroundSaveError> {
+        let completion = if block {
+            self.completions.recv().ok()
+        } else {
+            match self.completions.try_recv() {
+                Ok(completion) => Some(completion),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => None,
+            }
+        };
+        self.in_flight = false;
+        let Some(Completion { sequence, outcome }) = completion else {
+            return Ok(());
+        };
+        match outcome {
+            Ok(receipt) => {
+                self.receipts.push_back(receipt);
+                Ok(())
+            }
+            Err((message, change_set)) => {
+                self.failed_sequence = Some(sequence);
+                self.keep_unflushed(change_set);
+                Err(BackgroundSaveError::FlushFailed { sequence, message })
+            }
+        }
+    }
+
+    fn next_receipt(&mut self) -> Result<Option<FlushReceipt>, BackgroundSaveError> {
+        match (self.receipts.pop_front(), self.failed_sequence) {
+            (None, Some(failed_sequence)) => Err(BackgroundSaveError::Stopped { failed_sequence }),
+            (receipt, _) => Ok(receipt),
+        }
+    }
+
+    // Later snapshots are appended, so the result still writes children
+    // before their parents and ends at the newest root.
+    fn keep_unflushed(&mut self, change_set: ChangeSet) {
+        match &mut self.unflushed {
+            None => self.unflushed = Some(change_set),
+            Some(unflushed) => {
+                unflushed.root = change_set.root;
+                unflushed.nodes.extend(change_set.nodes);
+                unflushed.values.extend(change_set.values);
+            }
+        }
+    }
+}
+
+impl<S> Drop for BackgroundSaver<S> {
+    fn drop(&mut self) {
+        // Let a pending flush finish rather than abandon it half written.
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn flush_loop<S: RawStoreAdapter>(
+    mut store: S,
+    jobs: Receiver<(u64, ChangeSet)>,
+    completions: Sender<Completion>,
+) -> S {
+    for (sequence, change_set) in jobs {
+        let started = Instant::now();
+        let written = panic::catch_unwind(AssertUnwindSafe(|| {
+            write_change_set(&mut store, &change_set)
+        }));
+        let outcome = match written {
+            Ok(()) => Ok(FlushReceipt {
+                sequence,
+                root: change_set.root,
+                nodes_written: change_set.nodes.len(),
+                values_written: change_set.values.len(),
+                elapsed: started.elapsed(),
+            }),
+            Err(payload) => Err((panic_message(payload.as_ref()), change_set)),
+        };
+        let failed = outcome.is_err();
+        if completions.send(Completion { sequence, outcome }).is_err() || failed {
+            break;
+        }
+    }
+    store
+}
+
+// Values first, then nodes in save order, so the root is written last.
+fn write_change_set<S: RawStoreAdapter>(store: &mut S, change_set: &ChangeSet) {
+    for (hash, value) in &change_set.values {
+        store.save_raw_value(hash, value);
+    }
+    for (hash, node) in &change_set.nodes {
+        store.save_raw_node(hash, node);
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "store panicked".to_string())
+}
+
+// Stands in for the store while a save is captured: writes are only recorded
+// by the wrapping `RecordingStore`, and nothing is known to be stored yet.
+pub(crate) struct DetachedStore;
+
+impl RawStoreAdapter for DetachedStore {
+    fn load_raw_node(&mut self, _hash: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_raw_value(&mut self, _hash: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn save_raw_node(&mut self, _hash: &[u8], _serialized_node: &[u8]) {}
+
+    fn save_raw_value(&mut self, _hash: &[u8], _value: &[u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackgroundSaveError, BackgroundSaver};
+    use crate::change_set::apply_change_set;
+    use crate::flat_store::MemoryFlatStore;
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn blocks_keep_executing_while_earlier_ones_flush() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let mut saver = BackgroundSaver::new(MemoryStore::new());
+            let mut roots = Vec::new();
+            for block in 0u8..4 {
+                for index in 0u8..16 {
+                    core.put(vec![block, index], vec![index; 40]);
+                }
+                assert_eq!(
+                    core.save_in_background(&mut saver).unwrap(),
+                    u64::from(block)
+                );
+                roots.push(core.current_root_hash());
+            }
+            let mut receipts = Vec::new();
+            while let Some(receipt) = saver.poll().unwrap() {
+                receipts.push(receipt);
+            }
+            receipts.extend(saver.wait().unwrap());
+            assert_eq!(receipts.len(), 4);
+            assert_eq!(receipts[3].root, roots[3]);
+            assert!(!saver.is_flushing());
+
+            let (mut store, unflushed) = saver.finish();
+            assert!(unflushed.is_none());
+            for root in &roots {
+                let loaded =
+                    UnitrieCore::from_persisted_root(implementation, root, &mut store).unwrap();
+                assert_eq!(loaded.get(&[0x00, 0x03]), Some(vec![0x03; 40]));
+            }
+        }
+    }
+
+    // Writes until `budget` runs out, then panics.
+    struct FailingStore {
+        inner: MemoryStore,
+        budget: usize,
+    }
+
+    impl RawStoreAdapter for FailingStore {
+        fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+            self.inner.load_raw_node(hash)
+        }
+
+        fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+            self.inner.load_raw_value(hash)
+        }
+
+        fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+            self.budget = self.budget.checked_sub(1).expect("disk full");
+            self.inner.save_raw_node(hash, serialized_node);
+        }
+
+        fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+            self.inner.save_raw_value(hash, value);
+        }
+    }
+
+    #[test]
+    fn failed_flushes_are_reported_and_keep_what_was_not_written() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let store = FailingStore {
+                inner: MemoryStore::new(),
+                budget: 3,
+            };
+            let mut saver = BackgroundSaver::new(store);
+            for index in 0u8..8 {
+                core.put(vec![index; 4], vec![index; 40]);
+            }
+            core.save_in_background(&mut saver).unwrap();
+            assert_eq!(
+                saver.wait(),
+                Err(BackgroundSaveError::FlushFailed {
+                    sequence: 0,
+                    message: "disk full".to_string(),
+                })
+            );
+
+            core.put(vec![0x09; 4], vec![0x09]);
+            assert_eq!(
+                core.save_in_background(&mut saver),
+                Err(BackgroundSaveError::Stopped { failed_sequence: 0 })
+            );
+            let (store, unflushed) = saver.finish();
+            let unflushed = unflushed.unwrap();
+            let root = core.current_root_hash();
+            assert_eq!(unflushed.root, root);
+
+            let mut recovered = store.inner;
+            apply_change_set(&unflushed, &mut recovered).unwrap();
+            let loaded =
+                UnitrieCore::from_persisted_root(implementation, &root, &mut recovered).unwrap();
+            assert_eq!(loaded.get(&[0x09; 4]), Some(vec![0x09]));
+        }
+    }
+
+    #[test]
+    fn observers_run_ahead_of_a_failed_flush_until_recovery() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let flat = core.enable_flat_layer(MemoryFlatStore::new());
+            let store = FailingStore {
+                inner: MemoryStore::new(),
+                budget: 0,
+            };
+            let mut saver = BackgroundSaver::new(store);
+            for index in 0u8..8 {
+                core.put(vec![index; 4], vec![index; 40]);
+            }
+            core.save_in_background(&mut saver).unwrap();
+            let root = core.current_root_hash();
+            // The flat layer moved to the new root before a byte was written.
+            assert_eq!(flat.root(), Some(root));
+            assert_eq!(flat.get(&[0x03; 4]), Some(vec![0x03; 40]));
+            assert!(matches!(
+                saver.wait(),
+                Err(BackgroundSaveError::FlushFailed { sequence: 0, .. })
+            ));
+
+            let (store, unflushed) = saver.finish();
+            let mut recovered = store.inner;
+            assert!(
+                UnitrieCore::from_persisted_root(implementation, &root, &mut recovered).is_err()
+            );
+            apply_change_set(&unflushed.unwrap(), &mut recovered).unwrap();
+            let loaded =
+                UnitrieCore::from_persisted_root(implementation, &root, &mut recovered).unwrap();
+            assert_eq!(loaded.get(&[0x03; 4]), flat.get(&[0x03; 4]));
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use crate::core_trie::empty_node_serialized;
+use crate::hash::{is_empty_root, keccak256};
+use crate::json::to_hex;
+use crate::long_value::{load_chunked, manifest_length};
+use crate::node_ref::HASH_SIZE;
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+use crate::varint;
+use crate::TrieRoot;
+use std::collections::HashMap;
+
+const CHANGE_SET_VERSION: u8 = 1;
+
+// A hash and the payload stored under it.
+pub type ChangeSetEntry = ([u8; HASH_SIZE], Vec<u8>);
+
+// Everything one save wrote: the serialized nodes and long values that were
+// new to the store, in write order, and the root they lead to. Writing them to
+// a replica's store makes `root` loadable there.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ChangeSet {
+    pub root: TrieRoot,
+    pub nodes: Vec<ChangeSetEntry>,
+    pub values: Vec<ChangeSetEntry>,
+}
+
+impl ChangeSet {
+    pub fn new(root: TrieRoot) -> Self {
+        Self {
+            root,
+            ..Self::default()
+        }
+    }
+
+    // True when the save wrote nothing.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.values.is_empty()
+    }
+
+    pub fn payload_bytes(&self) -> usize {
+        self.nodes
+            .iter()
+            .chain(&self.values)
+            .map(|(_, payload)| payload.len())
+            .sum()
+    }
+
+    // Wire format:
+    //   version (1) | root (32) | varint node count | nodes | varint value count | values
+    // where each node or value is hash (32) | varint length | payload.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(1 + HASH_SIZE + self.payload_bytes());
+        encoded.push(CHANGE_SET_VERSION);
+        encoded.extend_from_slice(&self.root);
+        for entries in [&self.nodes, &self.values] {
+            varint::encode_into(entries.len() as u64, &mut encoded);
+            for (hash, payload) in entries {
+                encoded.extend_from_slice(hash);
+                varint::encode_into(payload.len() as u64, &mut encoded);
+                encoded.extend_from_slice(payload);
+            }
+        }
+        encoded
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, String> {
+        let mut offset = 0;
+        let version = *input.first().ok_or("change set is empty")?;
+        if version != CHANGE_SET_VERSION {
+            return Err(format!("unsupported change set version {version}"));
+        }
+        offset += 1;
+        let root = read_hash(input, &mut offset)?;
+        let nodes = read_entries(input, &mut offset)?;
+        let values = read_entries(input, &mut offset)?;
+        if offset != input.len() {
+            return Err(format!(
+                "change set has {} trailing bytes",
+                input.len() - offset
+            ));
+        }
+        Ok(Self {
+            root,
+            nodes,
+            values,
+        })
+    }
+}
+
+// Writes a change set received from another node to `store`, after checking
+// every payload against its hash and that the root is reachable. Nothing is
+// written unless the whole change set checks out.
+pub fn apply_change_set<S: RawStoreAdapter>(
+    change_set: &ChangeSet,
+    store: &mut S,
+) -> Result<(), String> {
+    for (hash, node) in &change_set.nodes {
+        let empty_root = is_empty_root(hash) && *node == empty_node_serialized();
+        if !empty_root && keccak256(node) != *hash {
+            return Err(format!("node {} does not match its hash", to_hex(hash)));
+        }
+    }
+    let mut chunks = ChunkSource {
+        values: HashMap::new(),
+        store,
+    };
+    let mut manifests = Vec::new();
+    for (hash, value) in &change_set.values {
+        if keccak256(value) == *hash {
+            chunks.values.insert(hash, value);
+        } else {
+            manifests.push((hash, value));
+        }
+    }
+    // Extended values are stored as a manifest under the value hash; their
+    // chunks, from the change set or the store, must hash to it.
+    for (hash, manifest) in manifests {
+        let Some(length) = manifest_length(manifest) else {
+            return Err(format!("value {} does not match its hash", to_hex(hash)));
+        };
+        load_chunked(&mut chunks, hash, manifest, length)
+            .map_err(|error| format!("value {}: {error}", to_hex(hash)))?;
+    }
+    let store = chunks.store;
+    let root = &change_set.root;
+    let root_known = is_empty_root(root)
+        || change_set.nodes.iter().any(|(hash, _)| hash == root)
+        || store.load_raw_node(root).is_some();
+    if !root_known {
+        return Err(format!(
+            "root {} is neither in the change set nor in the store",
+            to_hex(root)
+        ));
+    }
+
+    for (hash, value) in &change_set.values {
+        store.save_raw_value(hash, value);
+    }
+    for (hash, node) in &change_set.nodes {
+        store.save_raw_node(hash, node);
+    }
+    Ok(())
+}
+
+// Serves value chunks from a change set being applied, then from the store.
+struct ChunkSource<'a, S> {
+    values: HashMap<&'a [u8], &'a [u8]>,
+    store: &'a mut S,
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for ChunkSource<'_, S> {
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        match self.values.get(hash) {
+            Some(value) => Some(value.to_vec()),
+            None => self.store.load_raw_value(hash),
+        }
+    }
+
+    fn save_raw_node(&mut self, _hash: &[u8], _serialized_node: &[u8]) {}
+
+    fn save_raw_value(&mut self, _hash: &[u8], _value: &[u8]) {}
+}
+
+fn read_hash(input: &[u8], offset: &mut usize) -> Result<[u8; HASH_SIZE], String> {
+    let hash = input
+        .get(*offset..*offset + HASH_SIZE)
+        .ok_or("change set hash is truncated")?;
+    *offset += HASH_SIZE;
+    Ok(hash.try_into().expect("slice has hash length"))
+}
+
+fn read_entries(input: &[u8], offset: &mut usize) -> Result<Vec<ChangeSetEntry>, String> {
+    let remaining = (input.len() - *offset) as u64;
+    let count = varint::decode_from_slice_strict(input, offset, remaining)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let hash = read_hash(input, offset)?;
+        let remaining = (input.len() - *offset) as u64;
+        let length = varint::decode_from_slice_strict(input, offset, remaining)? as usize;
+        let payload = offset
+            .checked_add(length)
+            .and_then(|end| input.get(*offset..end))
+            .ok_or("change set payload is truncated")?;
+        entries.push((hash, payload.to_vec()));
+        *offset += length;
+    }
+    Ok(entries)
+}
+
+// Passes every call through to the wrapped store and keeps a copy of what is
+// written.
+pub(crate) struct RecordingStore<'a, S> {
+    inner: &'a mut S,
+    change_set: ChangeSet,
+}
+
+impl<'a, S: RawStoreAdapter> RecordingStore<'a, S> {
+    pub(crate) fn new(inner: &'a mut S) -> Self {
+        Self {
+            inner,
+            change_set: ChangeSet::default(),
+        }
+    }
+
+    pub(crate) fn finish(self, root: TrieRoot) -> ChangeSet {
+        ChangeSet {
+            root,
+            ..self.change_set
+        }
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for RecordingStore<'_, S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.inner.load_raw_node(hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.inner.load_raw_value(hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        if let Ok(hash) = hash.try_into() {
+            self.change_set.nodes.push((hash, serialized_node.to_vec()));
+        }
+        self.inner.save_raw_node(hash, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        if let Ok(hash) = hash.try_into() {
+            self.change_set.values.push((hash, value.to_vec()));
+        }
+        self.inner.save_raw_value(hash, value);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_change_set, ChangeSet};
+    use crate::node_ref::{CodecConfig, MAX_CONSENSUS_VALUE_LENGTH};
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn change_sets_hold_exactly_what_each_save_wrote() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let mut store = MemoryStore::new();
+            for index in 0u8..8 {
+                core.put(vec![index; 4], vec![index; 64]);
+            }
+            let (stats, first) = core.save_to_store_with_change_set(&mut store);
+            assert_eq!(first.root, core.current_root_hash());
+            assert_eq!(first.nodes.len() as u64, stats.nodes_written);
+            assert_eq!(first.values.len(), 8);
+            assert_eq!(first.nodes.len(), store.node_count());
+
+            core.put(vec![0x07; 4], vec![0x70]);
+            let (_, second) = core.save_to_store_with_change_set(&mut store);
+            assert!(!second.nodes.is_empty() && second.nodes.len() < first.nodes.len());
+            assert!(second.values.is_empty());
+            let (_, unchanged) = core.save_to_store_with_change_set(&mut store);
+            // The legacy engine rewrites its root node on every save.
+            assert!(unchanged.values.is_empty());
+            assert!(unchanged
+                .nodes
+                .iter()
+                .all(|(hash, _)| *hash == unchanged.root));
+
+            let encoded = second.encode();
+            assert_eq!(ChangeSet::decode(&encoded).unwrap(), second);
+            assert!(ChangeSet::decode(&encoded[..encoded.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn decoding_rejects_truncated_input_without_panicking() {
+        let change_set = ChangeSet {
+            root: [0x01; 32],
+            nodes: vec![([0x02; 32], vec![0x20; 3]), ([0x03; 32], Vec::new())],
+            values: vec![([0x04; 32], vec![0x40; 200])],
+        };
+        let encoded = change_set.encode();
+        for end in 0..encoded.len() {
+            assert!(ChangeSet::decode(&encoded[..end]).is_err(), "cut at {end}");
+        }
+
+        // One node whose hash is followed by a length but no payload.
+        let mut missing_payload = vec![0x01];
+        missing_payload.extend_from_slice(&[0x05; 32]);
+        missing_payload.push(0x01);
+        missing_payload.extend_from_slice(&[0x06; 32]);
+        missing_payload.push(0x01);
+        assert!(ChangeSet::decode(&missing_payload).is_err());
+    }
+
+    #[test]
+    fn followers_catch_up_from_verified_change_sets() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut leader = UnitrieCore::new(implementation);
+            let mut follower = UnitrieCore::new(implementation);
+            let mut leader_store = MemoryStore::new();
+            let mut follower_store = MemoryStore::new();
+            for block in 0u8..3 {
+                leader.put(vec![block; 4], vec![block; 64]);
+                leader.delete(&[block.wrapping_sub(1); 4]);
+                let (_, change_set) = leader.save_to_store_with_change_set(&mut leader_store);
+                let shipped = ChangeSet::decode(&change_set.encode()).unwrap();
+                follower
+                    .apply_change_set(&shipped, &mut follower_store)
+                    .unwrap();
+                assert_eq!(follower.current_root_hash(), leader.current_root_hash());
+            }
+            assert_eq!(follower.get(&[0x02; 4]), Some(vec![0x02; 64]));
+
+            let mut tampered = ChangeSet::new(follower.current_root_hash());
+            tampered.values.push(([0x00; 32], vec![0x01]));
+            assert!(apply_change_set(&tampered, &mut follower_store).is_err());
+            let unknown_root = ChangeSet::new([0x42; 32]);
+            assert!(follower
+                .apply_change_set(&unknown_root, &mut MemoryStore::new())
+                .is_err());
+            assert!(follower_store.value(&[0x00; 32]).is_none());
+        }
+    }
+
+    #[test]
+    fn extended_values_travel_as_verified_manifests_and_chunks() {
+        let config = CodecConfig::CONSENSUS.with_extended_values(true);
+        let long: Vec<u8> = (0..MAX_CONSENSUS_VALUE_LENGTH + 2)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut leader = UnitrieCore::new(implementation).with_config(config);
+            let mut follower = UnitrieCore::new(implementation).with_config(config);
+            leader.try_put(vec![0x01], long.clone()).unwrap();
+            leader.put(vec![0x02], vec![0x02; 64]);
+            let (_, change_set) = leader.save_to_store_with_change_set(&mut MemoryStore::new());
+
+            let mut follower_store = MemoryStore::new();
+            follower
+                .apply_change_set(&change_set, &mut follower_store)
+                .unwrap();
+            assert_eq!(follower.current_root_hash(), leader.current_root_hash());
+            assert_eq!(follower.get(&[0x01]), Some(long.clone()));
+
+            // A manifest whose chunks are neither shipped nor stored fails the
+            // whole change set.
+            let mut missing_chunk = change_set.clone();
+            missing_chunk
+                .values
+                .retain(|(_, payload)| payload.len() != 1);
+            assert_eq!(missing_chunk.values.len(), change_set.values.len() - 1);
+            let mut fresh = MemoryStore::new();
+            assert!(apply_change_set(&missing_chunk, &mut fresh).is_err());
+            assert!(fresh.is_empty());
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::core_api::TrieOp;
+use crate::diff::diff_roots;
+use crate::hash::{is_empty_root, Keccak256Hasher};
+use crate::node_ref::HASH_SIZE;
+use crate::store_adapter::RawStoreAdapter;
+use crate::varint;
+use crate::{TrieRoot, UnitrieCore, UnitrieImplementation};
+use std::io::{Read, Write};
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"UTSN";
+pub const SNAPSHOT_VERSION: u8 = 1;
+pub const DELTA_MAGIC: [u8; 4] = *b"UTDL";
+pub const DELTA_VERSION: u8 = 1;
+
+const DELTA_PUT: u8 = 0x01;
+const DELTA_DELETE: u8 = 0x02;
+
+// Layout (version 1):
+//   magic[4] | version u8 | root[32] | entry_count varint
+//   entry_count x (key_len varint | key | value_len varint | value), ascending keys
+//   keccak256 of everything above [32]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SnapshotHeader {
+    pub version: u8,
+    pub root: TrieRoot,
+    pub entry_count: u64,
+}
+
+pub fn write_snapshot<W: Write>(
+    trie: &mut UnitrieCore,
+    writer: W,
+) -> Result<SnapshotHeader, String> {
+    let snapshot = trie.snapshot();
+    let header = SnapshotHeader {
+        version: SNAPSHOT_VERSION,
+        root: snapshot.root,
+        entry_count: snapshot.key_count as u64,
+    };
+
+    let mut writer = HashingWriter::new(writer);
+    writer.write_bytes(&SNAPSHOT_MAGIC)?;
+    writer.write_bytes(&[header.version])?;
+    writer.write_bytes(&header.root)?;
+    writer.write_varint(header.entry_count)?;
+    for (key, value) in trie.iter_prefix(&[]) {
+        writer.write_varint(key.len() as u64)?;
+        writer.write_bytes(key)?;
+        writer.write_varint(value.len() as u64)?;
+        writer.write_bytes(value)?;
+    }
+    writer.finish()?;
+    Ok(header)
+}
+
+pub fn read_snapshot<R: Read>(
+    implementation: UnitrieImplementation,
+    reader: R,
+) -> Result<(UnitrieCore, SnapshotHeader), String> {
+    let mut reader = HashingReader::new(reader);
+    let magic: [u8; 4] = reader.read_array()?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err("snapshot has an invalid magic header".to_string());
+    }
+
+    let [version] = reader.read_array()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("unsupported snapshot version {version}"));
+    }
+
+    let root: TrieRoot = reader.read_array()?;
+    let entry_count = reader.read_varint()?;
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let key_len = reader.read_varint()?;
+        let key = reader.read_vec(key_len)?;
+        let value_len = reader.read_varint()?;
+        let value = reader.read_vec(value_len)?;
+        entries.push((key, value));
+    }
+    reader.verify_checksum()?;
+
+    let mut trie = UnitrieCore::from_sorted_entries(implementation, entries)?;
+    if trie.current_root_hash() != root {
+        return Err("snapshot entries do not match the recorded root hash".to_string());
+    }
+
+    Ok((
+        trie,
+        SnapshotHeader {
+            version,
+            root,
+            entry_count,
+        },
+    ))
+}
+
+// Layout (version 1):
+//   magic[4] | version u8 | old_root[32] | new_root[32] | op_count varint
+//   op_count x (kind u8 | key_len varint | key [| value_len varint | value])
+//   keccak256 of everything above [32]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeltaHeader {
+    pub version: u8,
+    pub old_root: TrieRoot,
+    pub new_root: TrieRoot,
+    pub op_count: u64,
+}
+
+// Writes the entry-level changes that turn the state at `old_root` into the
+// state at `new_root`, both persisted in `store`. The persisted trees are
+// diffed structurally, so subtrees the two roots share are never read.
+pub fn write_delta<T: RawStoreAdapter, W: Write>(
+    old_root: &[u8],
+    new_root: &[u8],
+    store: &mut T,
+    writer: W,
+) -> Result<DeltaHeader, String> {
+    let old_root = root_hash(old_root)?;
+    let new_root = root_hash(new_root)?;
+    // Equal roots are not read by the diff; still refuse one the store lacks.
+    if old_root == new_root && !is_empty_root(&old_root) && store.load_raw_node(&old_root).is_none()
+    {
+        return Err("root node was not found in store".to_string());
+    }
+    let changes = diff_roots(&old_root, &new_root, store)?;
+    let header = DeltaHeader {
+        version: DELTA_VERSION,
+        old_root,
+        new_root,
+        op_count: changes.len() as u64,
+    };
+
+    let mut writer = HashingWriter::new(writer);
+    writer.write_bytes(&DELTA_MAGIC)?;
+    writer.write_bytes(&[header.version])?;
+    writer.write_bytes(&header.old_root)?;
+    writer.write_bytes(&header.new_root)?;
+    writer.write_varint(header.op_count)?;
+    for change in &changes {
+        match change.to_op() {
+            TrieOp::Put { key, value } => {
+                writer.write_bytes(&[DELTA_PUT])?;
+                writer.write_varint(key.len() as u64)?;
+                writer.write_bytes(&key)?;
+                writer.write_varint(value.len() as u64)?;
+                writer.write_bytes(&value)?;
+            }
+            TrieOp::Delete { key } | TrieOp::DeleteRecursive { prefix: key } => {
+                writer.write_bytes(&[DELTA_DELETE])?;
+                writer.write_varint(key.len() as u64)?;
+                writer.write_bytes(&key)?;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(header)
+}
+
+fn root_hash(root: &[u8]) -> Result<TrieRoot, String> {
+    root.try_into()
+        .map_err(|_| format!("root hash must be {HASH_SIZE} bytes"))
+}
+
+// Applies a delta to a trie currently at the delta's old root. The trie is only
+// replaced once the resulting root matches the delta's new root.
+pub fn apply_delta<R: Read>(trie: &mut UnitrieCore, reader: R) -> Result<DeltaHeader, String> {
+    let mut reader = HashingReader::new(reader);
+    let magic: [u8; 4] = reader.read_array()?;
+    if magic != DELTA_MAGIC {
+        return Err("delta has an invalid magic header".to_string());
+    }
+
+    let [version] = reader.read_array()?;
+    if version != DELTA_VERSION {
+        return Err(format!("unsupported delta version {version}"));
+    }
+
+    let old_root: TrieRoot = reader.read_array()?;
+    let new_root: TrieRoot = reader.read_array()?;
+    let op_count = reader.read_varint()?;
+    let mut ops = Vec::new();
+    for _ in 0..op_count {
+        let [kind] = reader.read_array()?;
+        let key_len = reader.read_varint()?;
+        let key = reader.read_vec(key_len)?;
+        match kind {
+            DELTA_PUT => {
+                let value_len = reader.read_varint()?;
+                ops.push(TrieOp::put(key, reader.read_vec(value_len)?));
+            }
+            DELTA_DELETE => ops.push(TrieOp::delete(key)),
+            other => return Err(format!("unknown delta operation 0x{other:02x}")),
+        }
+    }
+    reader.verify_checksum()?;
+
+    if trie.current_root_hash() != old_root {
+        return Err("delta old root does not match the trie's current root".to_string());
+    }
+
+    trie.apply_batch_checked(ops, new_root)?;
+
+    Ok(DeltaHeader {
+        version,
+        old_root,
+        new_root,
+        op_count,
+    })
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Keccak256Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Keccak256Hasher::new(),
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.hasher.update(bytes);
+        self.inner
+            .write_all(bytes)
+            .map_err(|error| format!("could not write snapshot: {error}"))
+    }
+
+    fn write_varint(&mut self, value: u64) -> Result<(), String> {
+        self.write_bytes(&varint::encode(value))
+    }
+
+    fn finish(self) -> Result<(), String> {
+        let Self { mut inner, hasher } = self;
+        let checksum = hasher.finalize();
+        inner
+            .write_all(&checksum)
+            .and_then(|_| inner.flush())
+            .map_err(|error| format!("could not write snapshot checksum: {error}"))
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Keccak256Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Keccak256Hasher::new(),
+        }
+    }
+
+    fn read_raw(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        self.inner
+            .read_exact(buffer)
+            .map_err(|error| format!("snapshot is truncated: {error}"))
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        self.read_raw(buffer)?;
+        self.hasher.update(buffer);
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut bytes = [0u8; N];
+        self.read_into(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_vec(&mut self, length: u64) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        (&mut self.inner)
+            .take(length)
+            .read_to_end(&mut bytes)
+            .map_err(|error| format!("could not read snapshot: {error}"))?;
+        if bytes.len() as u64 != length {
+            return Err("snapshot is truncated".to_string());
+        }
+        self.hasher.update(&bytes);
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let [first] = self.read_array()?;
+        let width = match first {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            _ => return Ok(u64::from(first)),
+        };
+
+        let mut encoded = [0u8; 9];
+        encoded[0] = first;
+        self.read_into(&mut encoded[1..=width])?;
+        varint::decode_from_slice(&encoded[..=width], &mut 0usize)
+    }
+
+    fn verify_checksum(self) -> Result<(), String> {
+        let Self { mut inner, hasher } = self;
+        let expected = hasher.finalize();
+        let mut stored = [0u8; HASH_SIZE];
+        inner
+            .read_exact(&mut stored)
+            .map_err(|error| format!("snapshot checksum is truncated: {error}"))?;
+        if stored != expected {
+            return Err("snapshot checksum mismatch".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, read_snapshot, write_delta, write_snapshot, SNAPSHOT_VERSION};
+    use crate::latency_store::{LatencyConfig, LatencyStore};
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    fn sample_trie(implementation: UnitrieImplementation) -> UnitrieCore {
+        let mut trie = UnitrieCore::new(implementation);
+        trie.put(Vec::new(), b"root-value".to_vec());
+        trie.put(b"k1".to_vec(), b"v1".to_vec());
+        trie.put(b"k2".to_vec(), vec![0x42; 300]);
+        trie.put(vec![0xff; 40], vec![0x01]);
+        trie
+    }
+
+    #[test]
+    fn snapshot_round_trip_preserves_root_and_entries() {
+        for &writer in UnitrieImplementation::enabled() {
+            let mut trie = sample_trie(writer);
+            let mut file = Vec::new();
+            let header = write_snapshot(&mut trie, &mut file).unwrap();
+            assert_eq!(header.version, SNAPSHOT_VERSION);
+            assert_eq!(header.entry_count, 4);
+
+            for &reader in UnitrieImplementation::enabled() {
+                let (mut restored, read_header) = read_snapshot(reader, file.as_slice()).unwrap();
+                assert_eq!(read_header, header);
+                assert_eq!(restored.current_root_hash(), trie.current_root_hash());
+                assert_eq!(restored.get(b"k2"), Some(vec![0x42; 300]));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_rejects_corruption_and_truncation() {
+        let implementation = UnitrieImplementation::default();
+        let mut trie = sample_trie(implementation);
+        let mut file = Vec::new();
+        write_snapshot(&mut trie, &mut file).unwrap();
+
+        let mut corrupted = file.clone();
+        let middle = corrupted.len() / 2;
+        corrupted[middle] ^= 0x01;
+        assert!(read_snapshot(implementation, corrupted.as_slice()).is_err());
+
+        let truncated = &file[..file.len() - 1];
+        assert!(read_snapshot(implementation, truncated).is_err());
+
+        let mut wrong_magic = file.clone();
+        wrong_magic[0] = b'X';
+        assert!(read_snapshot(implementation, wrong_magic.as_slice()).is_err());
+    }
+
+    #[test]
+    fn delta_moves_a_trie_between_persisted_roots() {
+        let mut store = MemoryStore::new();
+        let mut trie = sample_trie(UnitrieImplementation::default());
+        trie.save_to_store(&mut store);
+        let old_root = trie.current_root_hash();
+
+        trie.delete(b"k1");
+        trie.put(b"k2".to_vec(), vec![0x43; 300]);
+        trie.put(b"k3".to_vec(), b"v3".to_vec());
+        trie.save_to_store(&mut store);
+        let new_root = trie.current_root_hash();
+
+        let mut delta = Vec::new();
+        let header = write_delta(&old_root, &new_root, &mut store, &mut delta).unwrap();
+        assert_eq!(header.op_count, 3);
+
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut follower = sample_trie(implementation);
+            apply_delta(&mut follower, delta.as_slice()).unwrap();
+            assert_eq!(follower.current_root_hash(), new_root);
+
+            // Applying the same delta twice must fail without touching the trie.
+            assert!(apply_delta(&mut follower, delta.as_slice()).is_err());
+            assert_eq!(follower.current_root_hash(), new_root);
+        }
+    }
+
+    #[test]
+    fn delta_reads_only_the_paths_that_changed() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie = UnitrieCore::new(implementation);
+            for index in 0u8..64 {
+                trie.put(vec![index; 4], vec![index; 40]);
+            }
+            let mut store = LatencyStore::new(MemoryStore::new(), LatencyConfig::default());
+            trie.save_to_store(&mut store);
+            let old_root = trie.current_root_hash();
+            trie.put(vec![0x05; 4], vec![0x50; 40]);
+            trie.save_to_store(&mut store);
+            let new_root = trie.current_root_hash();
+
+            let reads = store.stats().reads;
+            let mut delta = Vec::new();
+            let header = write_delta(&old_root, &new_root, &mut store, &mut delta).unwrap();
+            assert_eq!(header.op_count, 1);
+            // Loading both roots would read every node of each tree.
+            assert!(store.stats().reads - reads < store.inner().node_count() / 2);
+            assert!(write_delta(&[0x42; 32], &new_root, &mut store, Vec::new()).is_err());
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::json::to_hex;
+use crate::store_adapter::{CodecHint, IterableStore, RawStoreAdapter};
+use std::fmt;
+
+// Stored payloads are `version | crc32 (big-endian) | payload`, the checksum
+// covering the hash the payload is stored under followed by the payload, so a
+// payload written under the wrong key fails too.
+const ENVELOPE_VERSION: u8 = 1;
+const ENVELOPE_HEADER_LENGTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PayloadKind {
+    Node,
+    Value,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CorruptionReason {
+    // Shorter than the envelope header.
+    Truncated { length: usize },
+    UnknownVersion(u8),
+    ChecksumMismatch { stored: u32, computed: u32 },
+}
+
+// A stored payload that exists but cannot be trusted; absence is not an error.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CorruptPayload {
+    pub kind: PayloadKind,
+    pub hash: Vec<u8>,
+    pub reason: CorruptionReason,
+}
+
+impl fmt::Display for CorruptPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            PayloadKind::Node => "node",
+            PayloadKind::Value => "value",
+        };
+        write!(f, "stored {kind} {} is corrupted: ", to_hex(&self.hash))?;
+        match self.reason {
+            CorruptionReason::Truncated { length } => {
+                write!(f, "{length} bytes cannot hold the envelope header")
+            }
+            CorruptionReason::UnknownVersion(version) => {
+                write!(f, "unknown envelope version {version}")
+            }
+            CorruptionReason::ChecksumMismatch { stored, computed } => {
+                write!(
+                    f,
+                    "checksum {computed:08x} does not match stored {stored:08x}"
+                )
+            }
+        }
+    }
+}
+
+// Wraps every payload in a checksummed envelope so disk corruption surfaces at
+// load time. Stores written without the wrapper do not read back through it.
+#[derive(Debug)]
+pub struct ChecksummedStore<S> {
+    inner: S,
+    load_error: Option<CorruptPayload>,
+}
+
+impl<S: RawStoreAdapter> ChecksummedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            load_error: None,
+        }
+    }
+
+    // `Ok(None)` when nothing is stored under `hash`.
+    pub fn load_node_checked(&mut self, hash: &[u8]) -> Result<Option<Vec<u8>>, CorruptPayload> {
+        let stored = self.inner.load_raw_node(hash);
+        open_envelope(PayloadKind::Node, hash, stored)
+    }
+
+    pub fn load_value_checked(&mut self, hash: &[u8]) -> Result<Option<Vec<u8>>, CorruptPayload> {
+        let stored = self.inner.load_raw_value(hash);
+        open_envelope(PayloadKind::Value, hash, stored)
+    }
+
+    // `RawStoreAdapter` loads report corrupted payloads as missing; the last
+    // corruption seen is kept here.
+    pub fn take_load_error(&mut self) -> Option<CorruptPayload> {
+        self.load_error.take()
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn keep_error(&mut self, result: Result<Option<Vec<u8>>, CorruptPayload>) -> Option<Vec<u8>> {
+        result.unwrap_or_else(|error| {
+            self.load_error = Some(error);
+            None
+        })
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for ChecksummedStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let result = self.load_node_checked(hash);
+        self.keep_error(result)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let result = self.load_value_checked(hash);
+        self.keep_error(result)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        self.inner
+            .save_raw_node(hash, &seal_envelope(hash, serialized_node));
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        self.inner.save_raw_value(hash, &seal_envelope(hash, value));
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+impl<S: IterableStore> IterableStore for ChecksummedStore<S> {
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        self.inner.iter_node_hashes()
+    }
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        self.inner.iter_value_hashes()
+    }
+}
+
+pub fn seal_envelope(hash: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(ENVELOPE_HEADER_LENGTH + payload.len());
+    sealed.push(ENVELOPE_VERSION);
+    sealed.extend_from_slice(&checksum(hash, payload).to_be_bytes());
+    sealed.extend_from_slice(payload);
+    sealed
+}
+
+fn open_envelope(
+    kind: PayloadKind,
+    hash: &[u8],
+    stored: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, CorruptPayload> {
+    let Some(mut stored) = stored else {
+        return Ok(None);
+    };
+    let corrupt = |reason| CorruptPayload {
+        kind,
+        hash: hash.to_vec(),
+        reason,
+    };
+    if stored.len() < ENVELOPE_HEADER_LENGTH {
+        return Err(corrupt(CorruptionReason::Truncated {
+            length: stored.len(),
+        }));
+    }
+    if stored[0] != ENVELOPE_VERSION {
+        return Err(corrupt(CorruptionReason::UnknownVersion(stored[0])));
+    }
+    let stored_checksum = u32::from_be_bytes(stored[1..5].try_into().expect("4-byte checksum"));
+    let computed = checksum(hash, &stored[ENVELOPE_HEADER_LENGTH..]);
+    if computed != stored_checksum {
+        return Err(corrupt(CorruptionReason::ChecksumMismatch {
+            stored: stored_checksum,
+            computed,
+        }));
+    }
+    stored.drain(..ENVELOPE_HEADER_LENGTH);
+    Ok(Some(stored))
+}
+
+fn checksum(hash: &[u8], payload: &[u8]) -> u32 {
+    !crc32_update(crc32_update(!0, hash), payload)
+}
+
+// CRC-32 (IEEE 802.3), bytewise with a table built at compile time.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, ChecksummedStore, CorruptionReason, PayloadKind};
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(checksum(b"", b"123456789"), 0xcbf4_3926);
+        assert_eq!(checksum(b"1234", b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn corruption_is_reported_apart_from_absence() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(vec![0x01], vec![0x11; 64]);
+            trie.put(vec![0x02], vec![0x22]);
+            let mut store = ChecksummedStore::new(MemoryStore::new());
+            trie.save_to_store(&mut store);
+            let root = trie.current_root_hash();
+            let loaded =
+                UnitrieCore::from_persisted_root(implementation, &root, &mut store).unwrap();
+            assert_eq!(loaded.get(&[0x01]), Some(vec![0x11; 64]));
+            assert_eq!(store.load_node_checked(&[0x42; 32]), Ok(None));
+
+            let mut sealed = store.inner().node(&root).unwrap().to_vec();
+            *sealed.last_mut().unwrap() ^= 0x01;
+            store.inner_mut().save_raw_node(&root, &sealed);
+            let error = store.load_node_checked(&root).unwrap_err();
+            assert_eq!(error.kind, PayloadKind::Node);
+            assert!(matches!(
+                error.reason,
+                CorruptionReason::ChecksumMismatch { .. }
+            ));
+
+            assert!(UnitrieCore::from_persisted_root(implementation, &root, &mut store).is_err());
+            assert_eq!(store.take_load_error(), Some(error));
+            store.inner_mut().save_raw_node(&root, &[0x01, 0x00]);
+            assert_eq!(
+                store.load_node_checked(&root).unwrap_err().reason,
+                CorruptionReason::Truncated { length: 2 }
+            );
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::node_ref::{NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
 use crate::path::shared_path_serializer;
 
 const ARITY: u8 = 2;
@@ -8,8 +9,32 @@ const MESSAGE_HEADER_LENGTH: usize = 6;
 pub struct OrchidCodec;
 
 impl OrchidCodec {
+    // Checks the header is self-consistent, not only the arity byte: unknown
+    // flag bits, unknown bhashes bits and a length that cannot hold the declared
+    // path, hashes and long value all rule the payload out.
     pub fn is_orchid_message(payload: &[u8]) -> bool {
-        payload.first().copied() == Some(ARITY)
+        if payload.len() < MESSAGE_HEADER_LENGTH || payload[0] != ARITY {
+            return false;
+        }
+
+        let flags = payload[1];
+        if flags & !0x03 != 0 {
+            return false;
+        }
+        let bhashes = u16::from_be_bytes([payload[2], payload[3]]);
+        if bhashes & !0b11 != 0 {
+            return false;
+        }
+
+        let shared_path_bits_length = u16::from_be_bytes([payload[4], payload[5]]) as usize;
+        let fixed_length = MESSAGE_HEADER_LENGTH
+            + shared_path_serializer::calculate_encoded_length(shared_path_bits_length)
+            + HASH_SIZE * bhashes.count_ones() as usize;
+        if (flags & 0x02) == 0x02 {
+            payload.len() == fixed_length + HASH_SIZE
+        } else {
+            payload.len() >= fixed_length
+        }
     }
 
     pub fn decode_node(payload: &[u8]) -> Result<TrieNode, String> {
@@ -33,18 +58,14 @@ impl OrchidCodec {
         let encoded_shared_path_length =
             shared_path_serializer::calculate_encoded_length(shared_path_bits_length);
 
-        let shared_path_bits = if encoded_shared_path_length > 0 {
-            let end = offset + encoded_shared_path_length;
-            if end > payload.len() {
-                return Err("orchid payload shared path is truncated".to_string());
-            }
-            let encoded = &payload[offset..end];
-            offset = end;
-            shared_path_serializer::decode(encoded, shared_path_bits_length)
-        } else {
-            Vec::new()
-        };
-        let shared_path = SharedPath::from_bits(shared_path_bits)?;
+        let end = offset + encoded_shared_path_length;
+        if end > payload.len() {
+            return Err("orchid payload shared path is truncated".to_string());
+        }
+        let shared_path_bits =
+            PathBits::from_packed(payload[offset..end].to_vec(), shared_path_bits_length)?;
+        offset = end;
+        let shared_path = SharedPath::from_path_bits(shared_path_bits);
 
         let left = if (bhashes & 0b01) != 0 {
             NodeReference::hashed(read_hash(payload, &mut offset)?)
@@ -166,7 +187,9 @@ fn read_u16(payload: &[u8], offset: &mut usize) -> Result<u16, String> {
 #[cfg(test)]
 mod tests {
     use super::OrchidCodec;
+    use crate::core_trie::decode_persisted_node;
     use crate::node_ref::{NodeReference, SharedPath, TrieNode, ValueRef};
+    use crate::store_adapter::CodecHint;
 
     #[test]
     fn decode_rejects_wrong_arity() {
@@ -200,4 +223,41 @@ mod tests {
         assert_eq!(encoded[1] & 0x02, 0x02);
         assert_eq!(encoded[1] & 0x01, 0x01);
     }
+
+    #[test]
+    fn detection_checks_header_structure() {
+        let node = TrieNode::new(
+            SharedPath::from_bits(vec![1, 0, 1]).unwrap(),
+            ValueRef::empty(),
+            NodeReference::hashed([0x11; 32]),
+            NodeReference::empty(),
+        );
+        let encoded = OrchidCodec::encode_node(&node, Some([0x11; 32]), None, true).unwrap();
+        assert!(OrchidCodec::is_orchid_message(&encoded));
+
+        // Unknown flag bits, unknown bhashes bits, or a payload too short for the
+        // declared hashes.
+        let mut flagged = encoded.clone();
+        flagged[1] |= 0x80;
+        assert!(!OrchidCodec::is_orchid_message(&flagged));
+        let mut bhashes = encoded.clone();
+        bhashes[2] = 0x01;
+        assert!(!OrchidCodec::is_orchid_message(&bhashes));
+        assert!(!OrchidCodec::is_orchid_message(
+            &encoded[..encoded.len() - 1]
+        ));
+
+        // An arity byte alone is no longer enough; such payloads are refused
+        // unless the store says it holds Orchid nodes.
+        let crafted = [0x02, 0x04, 0x00, 0x00, 0x00, 0x00];
+        assert!(decode_persisted_node(&crafted, CodecHint::Auto).is_err());
+        assert_ne!(
+            decode_persisted_node(&encoded, CodecHint::Rskip107),
+            Ok(node.clone())
+        );
+        assert_eq!(
+            decode_persisted_node(&encoded, CodecHint::Orchid).unwrap(),
+            node
+        );
+    }
 }
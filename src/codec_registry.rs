@@ -0,0 +1,212 @@
+use crate::codec_orchid::OrchidCodec;
+use crate::codec_rskip107::{Rskip107Codec, EXTENDED_VERSION_FLAG, VERSION_FLAG, VERSION_MASK};
+use crate::node_ref::TrieNode;
+use std::sync::{OnceLock, RwLock};
+
+pub type DecodeNodeFn = fn(&[u8]) -> Result<TrieNode, String>;
+
+// A node format, recognised by its first byte: payloads whose first byte has
+// `first_byte & mask == tag` go to `decode`. RSKIP107 keeps its version in the
+// top two bits and Orchid starts with an arity byte of 2, so a future version
+// claims e.g. mask 0b1100_0000, tag 0b1100_0000.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeDecoder {
+    pub name: &'static str,
+    pub mask: u8,
+    pub tag: u8,
+    pub decode: DecodeNodeFn,
+}
+
+impl NodeDecoder {
+    pub fn matches(&self, first_byte: u8) -> bool {
+        first_byte & self.mask == self.tag
+    }
+
+    // True when some first byte would match both.
+    fn overlaps(&self, other: &NodeDecoder) -> bool {
+        (self.tag ^ other.tag) & self.mask & other.mask == 0
+    }
+}
+
+// First-byte dispatch for nodes read with `CodecHint::Auto`. Only the
+// top-level node goes through it; embedded children are decoded by their
+// parent's codec.
+#[derive(Debug, Clone, Default)]
+pub struct CodecRegistry {
+    decoders: Vec<NodeDecoder>,
+}
+
+impl CodecRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Orchid and both RSKIP107 versions.
+    pub fn with_builtin_decoders() -> Self {
+        let mut registry = Self::new();
+        for decoder in [
+            NodeDecoder {
+                name: "orchid",
+                mask: 0xff,
+                tag: 0x02,
+                decode: decode_orchid,
+            },
+            NodeDecoder {
+                name: "rskip107",
+                mask: VERSION_MASK,
+                tag: VERSION_FLAG,
+                decode: Rskip107Codec::decode_node,
+            },
+            NodeDecoder {
+                name: "rskip107-extended",
+                mask: VERSION_MASK,
+                tag: EXTENDED_VERSION_FLAG,
+                decode: Rskip107Codec::decode_node,
+            },
+        ] {
+            registry
+                .register(decoder)
+                .expect("builtin decoders are disjoint");
+        }
+        registry
+    }
+
+    // Fails when a registered decoder already claims some of the same first
+    // bytes, so the decoder chosen for a payload never depends on order.
+    pub fn register(&mut self, decoder: NodeDecoder) -> Result<(), String> {
+        if decoder.mask & decoder.tag != decoder.tag {
+            return Err(format!(
+                "decoder '{}' tag {:#04x} has bits outside its mask {:#04x}",
+                decoder.name, decoder.tag, decoder.mask
+            ));
+        }
+        if let Some(existing) = self
+            .decoders
+            .iter()
+            .find(|existing| existing.overlaps(&decoder))
+        {
+            return Err(format!(
+                "decoder '{}' overlaps the registered '{}' decoder",
+                decoder.name, existing.name
+            ));
+        }
+        self.decoders.push(decoder);
+        Ok(())
+    }
+
+    pub fn decoders(&self) -> &[NodeDecoder] {
+        &self.decoders
+    }
+
+    pub fn decoder_for(&self, payload: &[u8]) -> Option<&NodeDecoder> {
+        let first_byte = *payload.first()?;
+        self.decoders
+            .iter()
+            .find(|decoder| decoder.matches(first_byte))
+    }
+
+    pub fn decode(&self, payload: &[u8]) -> Result<TrieNode, String> {
+        match self.decoder_for(payload) {
+            Some(decoder) => (decoder.decode)(payload),
+            None => Err(format!(
+                "no node decoder registered for first byte {:#04x}",
+                payload.first().copied().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+// An arity byte alone is not enough for Orchid; see `is_orchid_message`.
+fn decode_orchid(payload: &[u8]) -> Result<TrieNode, String> {
+    if !OrchidCodec::is_orchid_message(payload) {
+        return Err("node payload has an Orchid arity byte but no Orchid header".to_string());
+    }
+    OrchidCodec::decode_node(payload)
+}
+
+fn global() -> &'static RwLock<CodecRegistry> {
+    static REGISTRY: OnceLock<RwLock<CodecRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(CodecRegistry::with_builtin_decoders()))
+}
+
+// Adds a decoder to the process-wide registry every `CodecHint::Auto` load
+// consults. Register before the first load that may meet the format.
+pub fn register_node_decoder(decoder: NodeDecoder) -> Result<(), String> {
+    global()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .register(decoder)
+}
+
+pub fn registered_node_decoders() -> Vec<NodeDecoder> {
+    global()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .decoders()
+        .to_vec()
+}
+
+pub(crate) fn decode_registered(payload: &[u8]) -> Result<TrieNode, String> {
+    global()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .decode(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{register_node_decoder, registered_node_decoders, CodecRegistry, NodeDecoder};
+    use crate::codec_rskip107::Rskip107Codec;
+    use crate::core_trie::decode_persisted_node;
+    use crate::node_ref::TrieNode;
+    use crate::store_adapter::CodecHint;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    // A made-up format: one marker byte ahead of an RSKIP107 payload.
+    fn decode_wrapped(payload: &[u8]) -> Result<TrieNode, String> {
+        Rskip107Codec::decode_node(&payload[1..])
+    }
+
+    const WRAPPED: NodeDecoder = NodeDecoder {
+        name: "wrapped",
+        mask: 0xff,
+        tag: 0xfe,
+        decode: decode_wrapped,
+    };
+
+    #[test]
+    fn registries_refuse_overlapping_decoders() {
+        let mut registry = CodecRegistry::with_builtin_decoders();
+        let clash = NodeDecoder {
+            name: "clash",
+            mask: 0xc0,
+            tag: 0x40,
+            ..WRAPPED
+        };
+        assert!(registry.register(clash).is_err());
+        assert!(registry
+            .register(NodeDecoder { tag: 0xc0, ..clash })
+            .is_ok());
+        assert!(registry.register(WRAPPED).is_err());
+        assert!(registry.decode(&[0x00]).is_err());
+        assert_eq!(registry.decoder_for(&[0x55]).unwrap().name, "rskip107");
+    }
+
+    #[test]
+    fn auto_decoding_dispatches_to_registered_formats() {
+        let mut trie = UnitrieCore::new(UnitrieImplementation::default());
+        trie.put(vec![0x01], vec![0x11]);
+        let encoded = trie.encode_root();
+        let wrapped = [&[WRAPPED.tag][..], &encoded].concat();
+        assert!(decode_persisted_node(&wrapped, CodecHint::Auto).is_err());
+
+        register_node_decoder(WRAPPED).unwrap();
+        assert!(registered_node_decoders()
+            .iter()
+            .any(|decoder| decoder.name == WRAPPED.name));
+        assert_eq!(
+            decode_persisted_node(&wrapped, CodecHint::Auto).unwrap(),
+            decode_persisted_node(&encoded, CodecHint::Auto).unwrap()
+        );
+    }
+}
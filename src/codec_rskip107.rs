@@ -1,15 +1,22 @@
-use crate::node_ref::{NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE};
+use crate::node_ref::{
+    CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE,
+    MAX_CONSENSUS_VALUE_LENGTH,
+};
 use crate::path::shared_path_serializer;
 use crate::varint;
+use std::fmt;
 
-const VERSION_FLAG: u8 = 0b0100_0000;
-const VERSION_MASK: u8 = 0b1100_0000;
-const LONG_VALUE_FLAG: u8 = 0b0010_0000;
-const SHARED_PREFIX_FLAG: u8 = 0b0001_0000;
-const LEFT_PRESENT_FLAG: u8 = 0b0000_1000;
-const RIGHT_PRESENT_FLAG: u8 = 0b0000_0100;
-const LEFT_EMBEDDED_FLAG: u8 = 0b0000_0010;
-const RIGHT_EMBEDDED_FLAG: u8 = 0b0000_0001;
+pub(crate) const VERSION_FLAG: u8 = 0b0100_0000;
+// Same layout as version 1, except the long value length is a varint so values
+// past uint24 are representable.
+pub(crate) const EXTENDED_VERSION_FLAG: u8 = 0b1000_0000;
+pub(crate) const VERSION_MASK: u8 = 0b1100_0000;
+pub(crate) const LONG_VALUE_FLAG: u8 = 0b0010_0000;
+pub(crate) const SHARED_PREFIX_FLAG: u8 = 0b0001_0000;
+pub(crate) const LEFT_PRESENT_FLAG: u8 = 0b0000_1000;
+pub(crate) const RIGHT_PRESENT_FLAG: u8 = 0b0000_0100;
+pub(crate) const LEFT_EMBEDDED_FLAG: u8 = 0b0000_0010;
+pub(crate) const RIGHT_EMBEDDED_FLAG: u8 = 0b0000_0001;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ChildEncoding {
@@ -28,68 +35,236 @@ impl ChildEncoding {
     }
 }
 
+// Strict decoding additionally rejects payloads that decode fine but that the
+// encoder would never produce, so two stores holding the same trie are
+// byte-identical.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DecodeOptions {
+    pub strict: bool,
+    pub config: CodecConfig,
+    pub limits: Option<DecodeLimits>,
+}
+
+impl DecodeOptions {
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            config: CodecConfig::CONSENSUS,
+            limits: None,
+        }
+    }
+
+    // Permissive decoding with `DecodeLimits::UNTRUSTED`, for payloads from sync
+    // peers or proofs rather than a local store.
+    pub fn untrusted() -> Self {
+        Self::default().with_limits(DecodeLimits::UNTRUSTED)
+    }
+
+    // `untrusted` for payloads encoded under `config` rather than consensus.
+    pub fn untrusted_for(config: &CodecConfig) -> Self {
+        Self {
+            config: *config,
+            ..Self::default()
+        }
+        .with_limits(DecodeLimits::for_config(config))
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+}
+
+// Caps on what a single payload may make the decoder allocate or recurse into.
+// An embedded depth of 0 rejects embedded children altogether.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecodeLimits {
+    pub max_inline_value_length: usize,
+    pub max_embedded_depth: usize,
+    pub max_shared_path_bits: usize,
+}
+
+impl DecodeLimits {
+    // Consensus nodes inline at most `long_value_threshold` bytes and only embed
+    // terminal children; paths stay well under 512-byte keys.
+    pub const UNTRUSTED: Self = Self {
+        max_inline_value_length: CodecConfig::CONSENSUS.long_value_threshold,
+        max_embedded_depth: 1,
+        max_shared_path_bits: 4096,
+    };
+
+    // `UNTRUSTED` with the inline cap of a trie encoded under `config`.
+    pub fn for_config(config: &CodecConfig) -> Self {
+        Self {
+            max_inline_value_length: config.long_value_threshold,
+            ..Self::UNTRUSTED
+        }
+    }
+}
+
+// Where and why a payload failed to decode. `Display` renders a one-line
+// summary, followed by a hex window when one was attached.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DecodeError {
+    pub message: String,
+    pub offset: usize,
+    pub flags: Option<u8>,
+    pub expected: Option<usize>,
+    pub available: Option<usize>,
+    pub context: Option<String>,
+}
+
+impl DecodeError {
+    pub fn new(message: impl Into<String>, offset: usize) -> Self {
+        Self {
+            message: message.into(),
+            offset,
+            flags: None,
+            expected: None,
+            available: None,
+            context: None,
+        }
+    }
+
+    fn truncated(what: &str, offset: usize, expected: usize, available: usize) -> Self {
+        Self {
+            expected: Some(expected),
+            available: Some(available),
+            ..Self::new(format!("{what} is truncated"), offset)
+        }
+    }
+
+    // Keeps the innermost flag byte when errors bubble out of embedded children.
+    fn with_flags(mut self, flags: u8) -> Self {
+        self.flags.get_or_insert(flags);
+        self
+    }
+
+    pub fn with_context(mut self, payload: &[u8]) -> Self {
+        self.context = Some(hexdump_window(payload, self.offset, 16));
+        self
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)?;
+        if let Some(flags) = self.flags {
+            write!(f, " (flags 0x{flags:02x})")?;
+        }
+        if let (Some(expected), Some(available)) = (self.expected, self.available) {
+            write!(f, " (expected {expected} bytes, {available} available)")?;
+        }
+        if let Some(context) = &self.context {
+            write!(f, "\n{context}")?;
+        }
+        Ok(())
+    }
+}
+
+// Hex bytes within `radius` of `offset`, with the byte at `offset` bracketed.
+pub fn hexdump_window(payload: &[u8], offset: usize, radius: usize) -> String {
+    let start = offset.saturating_sub(radius);
+    let end = offset.saturating_add(radius + 1).min(payload.len());
+    let mut window = format!("{start:04x}:");
+    for (index, byte) in payload.iter().enumerate().take(end).skip(start) {
+        if index == offset {
+            window.push_str(&format!(" [{byte:02x}]"));
+        } else {
+            window.push_str(&format!(" {byte:02x}"));
+        }
+    }
+    if offset >= payload.len() {
+        window.push_str(" [..]");
+    }
+    window
+}
+
+// A node's structure without copying its value, for traversals that only
+// follow children (pruning, verification, proof building). Embedded children
+// stay undecoded; pass their bytes to `decode_header` to descend.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NodeHeader<'a> {
+    pub flags: u8,
+    pub shared_path: SharedPath,
+    pub left: ChildHeader<'a>,
+    pub right: ChildHeader<'a>,
+    pub children_size: Option<u64>,
+    pub value: ValueHeader<'a>,
+}
+
+impl NodeHeader<'_> {
+    pub fn is_terminal(&self) -> bool {
+        self.left == ChildHeader::Empty && self.right == ChildHeader::Empty
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ChildHeader<'a> {
+    Empty,
+    Embedded(&'a [u8]),
+    Hashed([u8; HASH_SIZE]),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ValueHeader<'a> {
+    Empty,
+    Inline(&'a [u8]),
+    Hashed {
+        hash: [u8; HASH_SIZE],
+        length: usize,
+    },
+}
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
 pub struct Rskip107Codec;
 
 impl Rskip107Codec {
     pub fn is_rskip107_message(payload: &[u8]) -> bool {
-        !payload.is_empty() && (payload[0] & VERSION_MASK) == VERSION_FLAG
+        payload.first().is_some_and(|flags| {
+            let version = flags & VERSION_MASK;
+            version == VERSION_FLAG || version == EXTENDED_VERSION_FLAG
+        })
     }
 
     pub fn decode_node(payload: &[u8]) -> Result<TrieNode, String> {
-        if payload.is_empty() {
-            return Err("RSKIP107 node payload is empty".to_string());
-        }
-
-        let flags = payload[0];
-        let has_long_value = (flags & LONG_VALUE_FLAG) == LONG_VALUE_FLAG;
-        let shared_prefix_present = (flags & SHARED_PREFIX_FLAG) == SHARED_PREFIX_FLAG;
-        let left_present = (flags & LEFT_PRESENT_FLAG) == LEFT_PRESENT_FLAG;
-        let right_present = (flags & RIGHT_PRESENT_FLAG) == RIGHT_PRESENT_FLAG;
-        let left_embedded = (flags & LEFT_EMBEDDED_FLAG) == LEFT_EMBEDDED_FLAG;
-        let right_embedded = (flags & RIGHT_EMBEDDED_FLAG) == RIGHT_EMBEDDED_FLAG;
-
-        let mut offset = 1usize;
-        let shared_bits = shared_path_serializer::deserialize_from_slice(
-            payload,
-            &mut offset,
-            shared_prefix_present,
-        )?;
-        let shared_path = SharedPath::from_bits(shared_bits)?;
-
-        let left = if left_present {
-            Self::decode_reference(payload, &mut offset, left_embedded)?
-        } else {
-            NodeReference::Empty
-        };
+        Self::decode_node_with_options(payload, &DecodeOptions::default())
+    }
 
-        let right = if right_present {
-            Self::decode_reference(payload, &mut offset, right_embedded)?
-        } else {
-            NodeReference::Empty
-        };
+    // Strict consensus decoding, discarding the node.
+    pub fn validate_canonical(payload: &[u8]) -> Result<(), String> {
+        Self::decode_node_with_options(payload, &DecodeOptions::strict()).map(|_| ())
+    }
 
-        if left_present || right_present {
-            let _children_size = varint::decode_from_slice(payload, &mut offset)?;
-        }
+    pub fn decode_node_with_options(
+        payload: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<TrieNode, String> {
+        Self::decode_node_reusing(payload, options, &mut DecodeScratch::default())
+    }
 
-        let value = if has_long_value {
-            let hash = read_hash(payload, &mut offset)?;
-            let value_length = read_u24(payload, &mut offset)?;
-            ValueRef::hashed(hash, Some(value_length))
-        } else if offset < payload.len() {
-            let inline = payload[offset..].to_vec();
-            offset = payload.len();
-            ValueRef::inline(inline)
-        } else {
-            ValueRef::empty()
-        };
+    // For callers decoding many payloads with one `DecodeScratch`.
+    pub fn decode_node_reusing(
+        payload: &[u8],
+        options: &DecodeOptions,
+        scratch: &mut DecodeScratch,
+    ) -> Result<TrieNode, String> {
+        decode_node_at(payload, options, scratch).map_err(|error| error.to_string())
+    }
 
-        if offset != payload.len() {
-            return Err("RSKIP107 node payload has trailing data".to_string());
-        }
+    // Permissive, like `decode_node`.
+    pub fn decode_header(payload: &[u8]) -> Result<NodeHeader<'_>, String> {
+        decode_header_at(payload).map_err(|error| error.to_string())
+    }
 
-        Ok(TrieNode::new(shared_path, value, left, right))
+    // Same as `decode_node_with_options`, keeping the structured error and a hex
+    // window around the failing offset.
+    pub fn decode_node_diagnostic(
+        payload: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<TrieNode, DecodeError> {
+        decode_node_at(payload, options, &mut DecodeScratch::default())
+            .map_err(|error| error.with_context(payload))
     }
 
     pub fn encode_node(
@@ -98,7 +273,17 @@ impl Rskip107Codec {
         right: &ChildEncoding,
         children_size: Option<u64>,
     ) -> Result<Vec<u8>, String> {
-        let has_long_value = node.has_long_value();
+        Self::encode_node_with_config(node, left, right, children_size, &CodecConfig::CONSENSUS)
+    }
+
+    pub fn encode_node_with_config(
+        node: &TrieNode,
+        left: &ChildEncoding,
+        right: &ChildEncoding,
+        children_size: Option<u64>,
+        config: &CodecConfig,
+    ) -> Result<Vec<u8>, String> {
+        let has_long_value = node.has_long_value_for(config);
         let left_present = left.is_present();
         let right_present = right.is_present();
 
@@ -106,7 +291,19 @@ impl Rskip107Codec {
             return Err("childrenSize is required for non-terminal node".to_string());
         }
 
-        let mut flags = VERSION_FLAG;
+        let value_length = node.value.len().unwrap_or(0);
+        let extended = has_long_value && value_length > MAX_CONSENSUS_VALUE_LENGTH;
+        if extended && !config.extended_values {
+            return Err(format!(
+                "value of {value_length} bytes does not fit in uint24 and extended values are disabled"
+            ));
+        }
+
+        let mut flags = if extended {
+            EXTENDED_VERSION_FLAG
+        } else {
+            VERSION_FLAG
+        };
         if has_long_value {
             flags |= LONG_VALUE_FLAG;
         }
@@ -128,7 +325,7 @@ impl Rskip107Codec {
 
         let mut encoded = Vec::new();
         encoded.push(flags);
-        shared_path_serializer::serialize_into(node.shared_path.as_bits(), &mut encoded);
+        shared_path_serializer::serialize_packed_into(node.shared_path.bits(), &mut encoded);
 
         Self::encode_reference(left, &mut encoded)?;
         Self::encode_reference(right, &mut encoded)?;
@@ -147,7 +344,11 @@ impl Rskip107Codec {
                 .len()
                 .ok_or_else(|| "long value node missing value length".to_string())?;
             encoded.extend_from_slice(&hash);
-            encoded.extend_from_slice(&encode_u24(length)?);
+            if extended {
+                varint::encode_into(length as u64, &mut encoded);
+            } else {
+                encoded.extend_from_slice(&encode_u24(length)?);
+            }
         } else if let Some(inline) = node.value.inline_bytes() {
             encoded.extend_from_slice(inline);
         }
@@ -155,33 +356,6 @@ impl Rskip107Codec {
         Ok(encoded)
     }
 
-    fn decode_reference(
-        payload: &[u8],
-        offset: &mut usize,
-        embedded: bool,
-    ) -> Result<NodeReference, String> {
-        if embedded {
-            if *offset >= payload.len() {
-                return Err("embedded node length is truncated".to_string());
-            }
-            let length = payload[*offset] as usize;
-            *offset += 1;
-
-            let end = *offset + length;
-            if end > payload.len() {
-                return Err("embedded node payload is truncated".to_string());
-            }
-
-            let node_payload = &payload[*offset..end];
-            *offset = end;
-            let embedded_node = Self::decode_node(node_payload)?;
-            Ok(NodeReference::embedded(embedded_node))
-        } else {
-            let hash = read_hash(payload, offset)?;
-            Ok(NodeReference::hashed(hash))
-        }
-    }
-
     fn encode_reference(reference: &ChildEncoding, output: &mut Vec<u8>) -> Result<(), String> {
         match reference {
             ChildEncoding::Empty => Ok(()),
@@ -204,10 +378,435 @@ impl Rskip107Codec {
     }
 }
 
-fn read_hash(payload: &[u8], offset: &mut usize) -> Result<[u8; HASH_SIZE], String> {
+// Hard cap on embedded nesting, applied with or without `DecodeLimits`.
+// Consensus nodes only embed terminal children, one level deep.
+const MAX_EMBEDDED_NESTING: usize = 64;
+
+// Stack for decoding embedded chains iteratively. Reusing one across decodes,
+// e.g. in a loader, keeps it allocated between payloads.
+#[derive(Debug, Default)]
+pub struct DecodeScratch {
+    stack: Vec<PendingNode>,
+}
+
+// A node whose children are still being decoded. Offsets are absolute in the
+// outermost payload, so errors in embedded children point into the stored
+// bytes. `depth` counts the embedded nodes enclosing this one.
+#[derive(Debug)]
+struct PendingNode {
+    start: usize,
+    end: usize,
+    // Offset of the length byte in the parent, for embedded nodes.
+    length_offset: usize,
+    depth: usize,
+    flags: u8,
+    offset: usize,
+    shared_path: SharedPath,
+    left: Option<NodeReference>,
+    right: Option<NodeReference>,
+}
+
+enum Step {
+    Descend(PendingNode),
+    Done(TrieNode),
+}
+
+fn decode_node_at(
+    payload: &[u8],
+    options: &DecodeOptions,
+    scratch: &mut DecodeScratch,
+) -> Result<TrieNode, DecodeError> {
+    let stack = &mut scratch.stack;
+    stack.clear();
+    // Errors without flags of their own take those of the innermost enclosing
+    // node.
+    decode_chain(payload, options, stack).map_err(|error| {
+        stack
+            .drain(..)
+            .rev()
+            .fold(error, |error, pending| error.with_flags(pending.flags))
+    })
+}
+
+fn decode_chain(
+    payload: &[u8],
+    options: &DecodeOptions,
+    stack: &mut Vec<PendingNode>,
+) -> Result<TrieNode, DecodeError> {
+    stack.push(open_node(payload, 0, payload.len(), 0, 0, options)?);
+    loop {
+        let pending = stack
+            .last_mut()
+            .expect("stack holds the node being decoded");
+        let node = match advance(payload, pending, options)? {
+            Step::Descend(child) => {
+                stack.push(child);
+                continue;
+            }
+            Step::Done(node) => node,
+        };
+
+        let finished = stack.pop().expect("finished node is on the stack");
+        let Some(parent) = stack.last_mut() else {
+            return Ok(node);
+        };
+        if options.strict {
+            let length = finished.end - finished.start;
+            if length > options.config.max_embedded_node_size {
+                return Err(DecodeError::new(
+                    format!(
+                        "non-canonical: {length}-byte embedded child exceeds the {}-byte limit",
+                        options.config.max_embedded_node_size
+                    ),
+                    finished.length_offset,
+                ));
+            }
+            if !node.is_terminal() {
+                return Err(DecodeError::new(
+                    "non-canonical: embedded child is not terminal",
+                    finished.length_offset,
+                ));
+            }
+        }
+        parent.offset = finished.end;
+        let slot = if parent.left.is_none() {
+            &mut parent.left
+        } else {
+            &mut parent.right
+        };
+        *slot = Some(NodeReference::embedded(node));
+    }
+}
+
+// Reads the flags and shared path of the node at `start..end`.
+fn open_node(
+    payload: &[u8],
+    start: usize,
+    end: usize,
+    length_offset: usize,
+    depth: usize,
+    options: &DecodeOptions,
+) -> Result<PendingNode, DecodeError> {
+    let payload = &payload[..end];
+    let Some(&flags) = payload.get(start) else {
+        return Err(DecodeError::new("RSKIP107 node payload is empty", start));
+    };
+    let fail = |message: String, offset: usize| DecodeError::new(message, offset).with_flags(flags);
+
+    let version = flags & VERSION_MASK;
+    if options.strict && version != VERSION_FLAG && version != EXTENDED_VERSION_FLAG {
+        return Err(fail(
+            format!("unknown RSKIP107 version bits {version:#04x}"),
+            start,
+        ));
+    }
+    let extended = version == EXTENDED_VERSION_FLAG;
+    if options.strict && extended && flags & LONG_VALUE_FLAG == 0 {
+        return Err(fail(
+            "non-canonical: extended version without a long value".to_string(),
+            start,
+        ));
+    }
+    let left_present = flags & LEFT_PRESENT_FLAG != 0;
+    let right_present = flags & RIGHT_PRESENT_FLAG != 0;
+    let left_embedded = flags & LEFT_EMBEDDED_FLAG != 0;
+    let right_embedded = flags & RIGHT_EMBEDDED_FLAG != 0;
+    if options.strict && (left_embedded && !left_present || right_embedded && !right_present) {
+        return Err(fail(
+            "non-canonical: embedded flag set for an absent child".to_string(),
+            start,
+        ));
+    }
+
+    let path_start = start + 1;
+    let mut offset = path_start;
+    let shared_prefix_present = flags & SHARED_PREFIX_FLAG != 0;
+    let shared_bits =
+        shared_path_serializer::deserialize_to_packed(payload, &mut offset, shared_prefix_present)
+            .map_err(|message| fail(message, path_start))?;
+    if options.strict && shared_prefix_present {
+        let mut canonical = Vec::new();
+        shared_path_serializer::serialize_packed_into(&shared_bits, &mut canonical);
+        if payload[path_start..offset] != canonical[..] {
+            return Err(fail(
+                "non-canonical: shared path header or padding bits differ from the canonical form"
+                    .to_string(),
+                path_start,
+            ));
+        }
+    }
+    if let Some(limits) = &options.limits {
+        if shared_bits.bit_len() > limits.max_shared_path_bits {
+            return Err(fail(
+                format!(
+                    "{}-bit shared path exceeds the {}-bit limit",
+                    shared_bits.bit_len(),
+                    limits.max_shared_path_bits
+                ),
+                path_start,
+            ));
+        }
+    }
+
+    Ok(PendingNode {
+        start,
+        end,
+        length_offset,
+        depth,
+        flags,
+        offset,
+        shared_path: SharedPath::from_path_bits(shared_bits),
+        left: None,
+        right: None,
+    })
+}
+
+// Decodes children until one is embedded, which the caller decodes first, or
+// finishes the node.
+fn advance(
+    payload: &[u8],
+    pending: &mut PendingNode,
+    options: &DecodeOptions,
+) -> Result<Step, DecodeError> {
+    let flags = pending.flags;
+    let payload = &payload[..pending.end];
+
+    for (present_flag, embedded_flag) in [
+        (LEFT_PRESENT_FLAG, LEFT_EMBEDDED_FLAG),
+        (RIGHT_PRESENT_FLAG, RIGHT_EMBEDDED_FLAG),
+    ] {
+        let slot = if present_flag == LEFT_PRESENT_FLAG {
+            &mut pending.left
+        } else {
+            &mut pending.right
+        };
+        if slot.is_some() {
+            continue;
+        }
+        if flags & present_flag == 0 {
+            *slot = Some(NodeReference::Empty);
+        } else if flags & embedded_flag == 0 {
+            let hash = read_hash(payload, 0, &mut pending.offset)
+                .map_err(|error| error.with_flags(flags))?;
+            *slot = Some(NodeReference::hashed(hash));
+        } else {
+            return open_embedded(payload, pending, options)
+                .map(Step::Descend)
+                .map_err(|error| error.with_flags(flags));
+        }
+    }
+
+    let left = pending.left.take().expect("left child decoded above");
+    let right = pending.right.take().expect("right child decoded above");
+    let has_children = !left.is_empty() || !right.is_empty();
+    let (children_size, value) = read_tail(
+        payload,
+        flags,
+        pending.start,
+        pending.offset,
+        has_children,
+        options,
+    )?;
+    let value = match value {
+        ValueHeader::Empty => ValueRef::empty(),
+        ValueHeader::Inline(inline) => ValueRef::inline(inline.to_vec()),
+        ValueHeader::Hashed { hash, length } => ValueRef::hashed(hash, Some(length)),
+    };
+    let shared_path = std::mem::replace(&mut pending.shared_path, SharedPath::empty());
+    Ok(Step::Done(
+        TrieNode::new(shared_path, value, left, right).with_children_size(children_size),
+    ))
+}
+
+fn decode_header_at(payload: &[u8]) -> Result<NodeHeader<'_>, DecodeError> {
+    let options = DecodeOptions::default();
+    let mut pending = open_node(payload, 0, payload.len(), 0, 0, &options)?;
+    let flags = pending.flags;
+    let mut child = |present_flag: u8, embedded_flag: u8| {
+        if flags & present_flag == 0 {
+            return Ok(ChildHeader::Empty);
+        }
+        if flags & embedded_flag == 0 {
+            return read_hash(payload, 0, &mut pending.offset).map(ChildHeader::Hashed);
+        }
+        let embedded = open_embedded(payload, &mut pending, &options)?;
+        pending.offset = embedded.end;
+        Ok(ChildHeader::Embedded(
+            &payload[embedded.start..embedded.end],
+        ))
+    };
+    let left =
+        child(LEFT_PRESENT_FLAG, LEFT_EMBEDDED_FLAG).map_err(|error| error.with_flags(flags))?;
+    let right =
+        child(RIGHT_PRESENT_FLAG, RIGHT_EMBEDDED_FLAG).map_err(|error| error.with_flags(flags))?;
+    let has_children = left != ChildHeader::Empty || right != ChildHeader::Empty;
+    let (children_size, value) =
+        read_tail(payload, flags, 0, pending.offset, has_children, &options)?;
+    Ok(NodeHeader {
+        flags,
+        shared_path: pending.shared_path,
+        left,
+        right,
+        children_size,
+        value,
+    })
+}
+
+// Children size and value, after the child references. The value is borrowed
+// from `payload`.
+fn read_tail<'a>(
+    payload: &'a [u8],
+    flags: u8,
+    node_start: usize,
+    start_offset: usize,
+    has_children: bool,
+    options: &DecodeOptions,
+) -> Result<(Option<u64>, ValueHeader<'a>), DecodeError> {
+    let fail = |message: String, offset: usize| DecodeError::new(message, offset).with_flags(flags);
+    let mut offset = start_offset;
+    let mut children_size = None;
+    if has_children {
+        let size_start = offset;
+        let decoded = if options.strict {
+            varint::decode_from_slice_strict(payload, &mut offset, u64::MAX).map_err(|message| {
+                fail(
+                    format!("non-canonical: children size {message}"),
+                    size_start,
+                )
+            })?
+        } else {
+            varint::decode_from_slice(payload, &mut offset)
+                .map_err(|message| fail(message, size_start))?
+        };
+        children_size = Some(decoded);
+    }
+
+    let value = if flags & LONG_VALUE_FLAG != 0 {
+        let hash = read_hash(payload, 0, &mut offset).map_err(|error| error.with_flags(flags))?;
+        let value_length = if flags & VERSION_MASK == EXTENDED_VERSION_FLAG {
+            read_extended_length(payload, 0, &mut offset, options)
+                .map_err(|error| error.with_flags(flags))?
+        } else {
+            read_u24(payload, 0, &mut offset).map_err(|error| error.with_flags(flags))?
+        };
+        ValueHeader::Hashed {
+            hash,
+            length: value_length,
+        }
+    } else if offset < payload.len() {
+        if let Some(limits) = &options.limits {
+            let length = payload.len() - offset;
+            if length > limits.max_inline_value_length {
+                return Err(fail(
+                    format!(
+                        "{length}-byte inline value exceeds the {}-byte limit",
+                        limits.max_inline_value_length
+                    ),
+                    offset,
+                ));
+            }
+        }
+        let inline = &payload[offset..];
+        offset = payload.len();
+        ValueHeader::Inline(inline)
+    } else {
+        ValueHeader::Empty
+    };
+
+    if offset != payload.len() {
+        return Err(fail(
+            format!(
+                "RSKIP107 node payload has {} bytes of trailing data",
+                payload.len() - offset
+            ),
+            offset,
+        ));
+    }
+
+    if options.strict {
+        let threshold = options.config.long_value_threshold;
+        match &value {
+            ValueHeader::Hashed { length, .. } if *length <= threshold => {
+                return Err(fail(
+                    format!(
+                        "non-canonical: {length}-byte value stored by hash, threshold is {threshold}"
+                    ),
+                    node_start,
+                ));
+            }
+            ValueHeader::Inline(inline) if inline.len() > threshold => {
+                return Err(fail(
+                    format!(
+                        "non-canonical: {}-byte value stored inline, threshold is {threshold}",
+                        inline.len()
+                    ),
+                    node_start,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((children_size, value))
+}
+
+// Reads an embedded child's length byte and opens the child.
+fn open_embedded(
+    payload: &[u8],
+    pending: &mut PendingNode,
+    options: &DecodeOptions,
+) -> Result<PendingNode, DecodeError> {
+    let length_offset = pending.offset;
+    let max_depth = options.limits.map_or(MAX_EMBEDDED_NESTING, |limits| {
+        limits.max_embedded_depth.min(MAX_EMBEDDED_NESTING)
+    });
+    if pending.depth >= max_depth {
+        return Err(DecodeError::new(
+            format!("embedded children nest deeper than {max_depth}"),
+            length_offset,
+        ));
+    }
+    let Some(&length) = payload.get(length_offset) else {
+        return Err(DecodeError::truncated(
+            "embedded node length",
+            length_offset,
+            1,
+            0,
+        ));
+    };
+    let start = length_offset + 1;
+    let end = start + length as usize;
+    if end > payload.len() {
+        return Err(DecodeError::truncated(
+            "embedded node payload",
+            start,
+            length as usize,
+            payload.len() - start,
+        ));
+    }
+    open_node(
+        payload,
+        start,
+        end,
+        length_offset,
+        pending.depth + 1,
+        options,
+    )
+}
+
+fn read_hash(
+    payload: &[u8],
+    base: usize,
+    offset: &mut usize,
+) -> Result<[u8; HASH_SIZE], DecodeError> {
     let end = *offset + HASH_SIZE;
     if end > payload.len() {
-        return Err("hash payload is truncated".to_string());
+        return Err(DecodeError::truncated(
+            "hash payload",
+            base + *offset,
+            HASH_SIZE,
+            payload.len().saturating_sub(*offset),
+        ));
     }
 
     let mut hash = [0u8; HASH_SIZE];
@@ -216,10 +815,15 @@ fn read_hash(payload: &[u8], offset: &mut usize) -> Result<[u8; HASH_SIZE], Stri
     Ok(hash)
 }
 
-fn read_u24(payload: &[u8], offset: &mut usize) -> Result<usize, String> {
+fn read_u24(payload: &[u8], base: usize, offset: &mut usize) -> Result<usize, DecodeError> {
     let end = *offset + 3;
     if end > payload.len() {
-        return Err("uint24 payload is truncated".to_string());
+        return Err(DecodeError::truncated(
+            "uint24 payload",
+            base + *offset,
+            3,
+            payload.len().saturating_sub(*offset),
+        ));
     }
 
     let value = ((payload[*offset] as usize) << 16)
@@ -229,6 +833,33 @@ fn read_u24(payload: &[u8], offset: &mut usize) -> Result<usize, String> {
     Ok(value)
 }
 
+fn read_extended_length(
+    payload: &[u8],
+    base: usize,
+    offset: &mut usize,
+    options: &DecodeOptions,
+) -> Result<usize, DecodeError> {
+    let start = *offset;
+    let length = if options.strict {
+        varint::decode_from_slice_strict(payload, offset, usize::MAX as u64).map_err(|message| {
+            DecodeError::new(format!("non-canonical: {message}"), base + start)
+        })?
+    } else {
+        varint::decode_from_slice(payload, offset)
+            .map_err(|message| DecodeError::new(message, base + start))?
+    };
+    let length = usize::try_from(length).map_err(|_| {
+        DecodeError::new("extended value length does not fit in memory", base + start)
+    })?;
+    if options.strict && length <= MAX_CONSENSUS_VALUE_LENGTH {
+        return Err(DecodeError::new(
+            format!("non-canonical: {length}-byte value uses the extended version"),
+            base + start,
+        ));
+    }
+    Ok(length)
+}
+
 fn encode_u24(value: usize) -> Result<[u8; 3], String> {
     if value > 0x00ff_ffff {
         return Err("value does not fit in uint24".to_string());
@@ -243,7 +874,11 @@ fn encode_u24(value: usize) -> Result<[u8; 3], String> {
 
 #[cfg(test)]
 mod tests {
-    use super::{ChildEncoding, Rskip107Codec, LONG_VALUE_FLAG, VERSION_FLAG};
+    use super::{
+        ChildEncoding, ChildHeader, DecodeLimits, DecodeOptions, DecodeScratch, Rskip107Codec,
+        ValueHeader, LEFT_EMBEDDED_FLAG, LEFT_PRESENT_FLAG, LONG_VALUE_FLAG, SHARED_PREFIX_FLAG,
+        VERSION_FLAG,
+    };
     use crate::node_ref::{NodeReference, SharedPath, TrieNode, ValueRef};
 
     #[test]
@@ -300,4 +935,216 @@ mod tests {
         );
         assert!(result.is_err());
     }
+
+    #[test]
+    fn decode_preserves_children_size() {
+        let node = TrieNode::new(
+            SharedPath::empty(),
+            ValueRef::empty(),
+            NodeReference::Hashed([3u8; 32]),
+            NodeReference::empty(),
+        );
+        let encoded = Rskip107Codec::encode_node(
+            &node,
+            &ChildEncoding::Hashed([3u8; 32]),
+            &ChildEncoding::Empty,
+            Some(300),
+        )
+        .unwrap();
+
+        let decoded = Rskip107Codec::decode_node(&encoded).unwrap();
+        assert_eq!(decoded.children_size, Some(300));
+        assert_eq!(decoded, node.with_children_size(Some(300)));
+    }
+
+    #[test]
+    fn untrusted_limits_reject_oversized_or_deeply_nested_payloads() {
+        let decode = |payload: &[u8], options: &DecodeOptions| {
+            Rskip107Codec::decode_node_with_options(payload, options).is_ok()
+        };
+        let untrusted = DecodeOptions::untrusted();
+
+        let mut long_inline = vec![VERSION_FLAG];
+        long_inline.extend_from_slice(&[7; 40]);
+        let mut long_path = vec![VERSION_FLAG | SHARED_PREFIX_FLAG, 0xff, 0xfd, 0x88, 0x13];
+        long_path.extend_from_slice(&[0; 625]);
+        let embedded_flags = VERSION_FLAG | LEFT_PRESENT_FLAG | LEFT_EMBEDDED_FLAG;
+        let one_level = vec![embedded_flags, 0x02, VERSION_FLAG, 0x01, 0x00];
+        let mut two_levels = vec![embedded_flags, one_level.len() as u8];
+        two_levels.extend_from_slice(&one_level);
+        two_levels.push(0x00);
+
+        for payload in [&long_inline, &long_path, &two_levels] {
+            assert!(decode(payload, &DecodeOptions::default()));
+            assert!(!decode(payload, &untrusted));
+        }
+        assert!(decode(&one_level, &untrusted));
+        let no_embedding = untrusted.with_limits(DecodeLimits {
+            max_embedded_depth: 0,
+            ..DecodeLimits::UNTRUSTED
+        });
+        assert!(!decode(&one_level, &no_embedding));
+    }
+
+    #[test]
+    fn embedded_chains_decode_iteratively_up_to_the_nesting_cap() {
+        let chain = |levels: usize| {
+            let mut payload = vec![VERSION_FLAG, 0x01];
+            for _ in 0..levels {
+                let mut parent = vec![
+                    VERSION_FLAG | LEFT_PRESENT_FLAG | LEFT_EMBEDDED_FLAG,
+                    payload.len() as u8,
+                ];
+                parent.extend_from_slice(&payload);
+                parent.push(0x00);
+                payload = parent;
+            }
+            payload
+        };
+
+        let mut scratch = DecodeScratch::default();
+        let options = DecodeOptions::default();
+        let mut node =
+            Rskip107Codec::decode_node_reusing(&chain(40), &options, &mut scratch).unwrap();
+        for _ in 0..40 {
+            let NodeReference::Embedded(child) = node.left else {
+                panic!("chain link is not embedded");
+            };
+            node = *child;
+        }
+        assert_eq!(node.value, ValueRef::inline(vec![0x01]));
+
+        let error =
+            Rskip107Codec::decode_node_reusing(&chain(70), &options, &mut scratch).unwrap_err();
+        assert!(error.starts_with("embedded children nest deeper than 64"));
+        assert!(Rskip107Codec::decode_node_reusing(&chain(1), &options, &mut scratch).is_ok());
+    }
+
+    #[test]
+    fn headers_expose_structure_and_borrow_values() {
+        let leaf = TrieNode::new(
+            SharedPath::from_bits(vec![1, 1]).unwrap(),
+            ValueRef::inline(vec![0x05; 3]),
+            NodeReference::empty(),
+            NodeReference::empty(),
+        );
+        let leaf_encoded =
+            Rskip107Codec::encode_node(&leaf, &ChildEncoding::Empty, &ChildEncoding::Empty, None)
+                .unwrap();
+        let parent = TrieNode::new(
+            SharedPath::from_bits(vec![0, 1, 0]).unwrap(),
+            ValueRef::inline(vec![0x07; 20]),
+            NodeReference::embedded(leaf),
+            NodeReference::hashed([0x22; 32]),
+        );
+        let encoded = Rskip107Codec::encode_node(
+            &parent,
+            &ChildEncoding::Embedded(leaf_encoded.clone()),
+            &ChildEncoding::Hashed([0x22; 32]),
+            Some(90),
+        )
+        .unwrap();
+
+        let header = Rskip107Codec::decode_header(&encoded).unwrap();
+        assert_eq!(header.shared_path, parent.shared_path);
+        assert_eq!(header.left, ChildHeader::Embedded(&leaf_encoded));
+        assert_eq!(header.right, ChildHeader::Hashed([0x22; 32]));
+        assert_eq!(header.children_size, Some(90));
+        let ValueHeader::Inline(value) = header.value else {
+            panic!("value is inline");
+        };
+        assert_eq!(value, [0x07; 20]);
+        assert!(std::ptr::eq(value, &encoded[encoded.len() - 20..]));
+
+        let ChildHeader::Embedded(child) = header.left else {
+            panic!("left child is embedded");
+        };
+        assert!(Rskip107Codec::decode_header(child).unwrap().is_terminal());
+        let mut long_value = vec![VERSION_FLAG | LONG_VALUE_FLAG];
+        long_value.extend_from_slice(&[0x33; 32]);
+        long_value.extend_from_slice(&[0x00, 0x01, 0x00]);
+        assert_eq!(
+            Rskip107Codec::decode_header(&long_value).unwrap().value,
+            ValueHeader::Hashed {
+                hash: [0x33; 32],
+                length: 256
+            }
+        );
+        assert!(Rskip107Codec::decode_header(&long_value[..20]).is_err());
+    }
+
+    #[test]
+    fn strict_decoding_rejects_non_canonical_payloads() {
+        let node = TrieNode::new(
+            SharedPath::from_bits(vec![1, 0, 1]).unwrap(),
+            ValueRef::inline(vec![1, 2, 3, 4]),
+            NodeReference::empty(),
+            NodeReference::empty(),
+        );
+        let canonical =
+            Rskip107Codec::encode_node(&node, &ChildEncoding::Empty, &ChildEncoding::Empty, None)
+                .unwrap();
+        assert!(Rskip107Codec::validate_canonical(&canonical).is_ok());
+
+        // Non-zero padding bits after the 3-bit shared path.
+        let mut padded = canonical.clone();
+        padded[2] |= 0x01;
+        assert!(Rskip107Codec::decode_node(&padded).is_ok());
+        assert!(Rskip107Codec::validate_canonical(&padded).is_err());
+
+        // Long-form path length header for a length that has a short form.
+        let long_header = [VERSION_FLAG | SHARED_PREFIX_FLAG, 0xff, 0x03, 0xa0, 0x01];
+        assert!(Rskip107Codec::decode_node(&long_header).is_ok());
+        assert!(Rskip107Codec::validate_canonical(&long_header).is_err());
+
+        // Children size varint padded to the 3-byte form.
+        let mut padded_size = vec![VERSION_FLAG | LEFT_PRESENT_FLAG];
+        padded_size.extend_from_slice(&[0x11; 32]);
+        padded_size.extend_from_slice(&[0xfd, 0x05, 0x00]);
+        assert!(Rskip107Codec::decode_node(&padded_size).is_ok());
+        assert!(Rskip107Codec::validate_canonical(&padded_size).is_err());
+
+        // A long value small enough to be inlined.
+        let mut short_long_value = vec![VERSION_FLAG | LONG_VALUE_FLAG];
+        short_long_value.extend_from_slice(&[0x22; 32]);
+        short_long_value.extend_from_slice(&[0x00, 0x00, 0x04]);
+        assert!(Rskip107Codec::validate_canonical(&short_long_value).is_err());
+
+        // Embedded child that is not terminal.
+        let mut inner = vec![VERSION_FLAG | LEFT_PRESENT_FLAG];
+        inner.extend_from_slice(&[0x33; 32]);
+        inner.push(0x01);
+        let mut parent = vec![VERSION_FLAG | LEFT_PRESENT_FLAG | LEFT_EMBEDDED_FLAG];
+        parent.push(inner.len() as u8);
+        parent.extend_from_slice(&inner);
+        parent.push(0x01);
+        assert!(Rskip107Codec::decode_node(&parent).is_ok());
+        assert!(Rskip107Codec::validate_canonical(&parent).is_err());
+    }
+
+    #[test]
+    fn decode_errors_report_offsets_flags_and_lengths() {
+        let mut payload = vec![VERSION_FLAG | LONG_VALUE_FLAG];
+        payload.extend_from_slice(&[0x44; 20]);
+        let error =
+            Rskip107Codec::decode_node_diagnostic(&payload, &DecodeOptions::default()).unwrap_err();
+        assert_eq!(error.offset, 1);
+        assert_eq!(error.flags, Some(VERSION_FLAG | LONG_VALUE_FLAG));
+        assert_eq!((error.expected, error.available), (Some(32), Some(20)));
+        assert!(error.context.unwrap().starts_with("0000: 60 [44] 44"));
+
+        // Offsets inside embedded children are reported against the outer payload.
+        let parent = [
+            VERSION_FLAG | LEFT_PRESENT_FLAG | LEFT_EMBEDDED_FLAG,
+            0x02,
+            VERSION_FLAG | LONG_VALUE_FLAG,
+            0x00,
+            0x01,
+        ];
+        let message = Rskip107Codec::decode_node(&parent).unwrap_err();
+        assert_eq!(
+            message,
+            "hash payload is truncated at offset 3 (flags 0x60) (expected 32 bytes, 1 available)"
+        );
+    }
 }
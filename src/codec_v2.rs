@@ -0,0 +1,389 @@
+use crate::codec_registry::NodeDecoder;
+use crate::codec_rskip107::{
+    LEFT_PRESENT_FLAG, LONG_VALUE_FLAG, RIGHT_PRESENT_FLAG, SHARED_PREFIX_FLAG, VERSION_MASK,
+};
+use crate::hash::{empty_trie_hash, is_empty_root, keccak256};
+use crate::node_ref::{CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
+use crate::sorted_builder::{SortedTrieBuilder, SubtreeSink};
+use crate::store_adapter::RawStoreAdapter;
+use crate::varint;
+use crate::TrieRoot;
+
+// Experimental successor to RSKIP107, in the version bits RSKIP107 leaves
+// unused. Nothing here is consensus: roots differ from RSKIP107 roots for the
+// same entries and the layout may still change. Layout:
+//
+//   flags | [varint path bits | packed path] | [left hash] | [right hash]
+//         | [varint children size] | [u64 rent timestamp] | value
+//
+// where the value is the inline bytes, or the value hash and a u32 length when
+// LONG_VALUE is set. Children are always referenced by hash.
+pub(crate) const V2_VERSION_FLAG: u8 = 0b1100_0000;
+const RENT_TIMESTAMP_FLAG: u8 = 0b0000_0010;
+const RESERVED_FLAG: u8 = 0b0000_0001;
+
+// Not registered by default; pass to `register_node_decoder` to have
+// `CodecHint::Auto` loads read v2 nodes. The rent timestamp is dropped.
+pub const V2_PROTOTYPE_DECODER: NodeDecoder = NodeDecoder {
+    name: "v2-prototype",
+    mask: VERSION_MASK,
+    tag: V2_VERSION_FLAG,
+    decode: decode_v2_node,
+};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct V2Node {
+    pub node: TrieNode,
+    pub rent_timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct V2SaveOptions {
+    // Only `long_value_threshold` applies; nothing is embedded.
+    pub config: CodecConfig,
+    // Stamped on every node the save writes.
+    pub rent_timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct V2Codec;
+
+impl V2Codec {
+    pub fn is_v2_message(payload: &[u8]) -> bool {
+        payload
+            .first()
+            .is_some_and(|flags| flags & VERSION_MASK == V2_VERSION_FLAG)
+    }
+
+    pub fn encode_node(
+        node: &TrieNode,
+        children_size: Option<u64>,
+        rent_timestamp: Option<u64>,
+        config: &CodecConfig,
+    ) -> Result<Vec<u8>, String> {
+        let left = child_hash(&node.left)?;
+        let right = child_hash(&node.right)?;
+        if (left.is_some() || right.is_some()) != children_size.is_some() {
+            return Err("children size is required exactly for non-terminal nodes".to_string());
+        }
+        let has_long_value = node.has_long_value_for(config);
+
+        let mut flags = V2_VERSION_FLAG;
+        if has_long_value {
+            flags |= LONG_VALUE_FLAG;
+        }
+        if !node.shared_path.is_empty() {
+            flags |= SHARED_PREFIX_FLAG;
+        }
+        if left.is_some() {
+            flags |= LEFT_PRESENT_FLAG;
+        }
+        if right.is_some() {
+            flags |= RIGHT_PRESENT_FLAG;
+        }
+        if rent_timestamp.is_some() {
+            flags |= RENT_TIMESTAMP_FLAG;
+        }
+
+        let mut encoded = vec![flags];
+        if !node.shared_path.is_empty() {
+            let bits = node.shared_path.bits();
+            varint::encode_into(bits.bit_len() as u64, &mut encoded);
+            encoded.extend_from_slice(bits.to_packed_bytes());
+        }
+        for hash in [left, right].into_iter().flatten() {
+            encoded.extend_from_slice(&hash);
+        }
+        if let Some(children_size) = children_size {
+            varint::encode_into(children_size, &mut encoded);
+        }
+        if let Some(timestamp) = rent_timestamp {
+            encoded.extend_from_slice(&timestamp.to_be_bytes());
+        }
+
+        if has_long_value {
+            let hash = node
+                .value
+                .hash()
+                .ok_or("long value node missing value hash")?;
+            let length = node
+                .value
+                .len()
+                .ok_or("long value node missing value length")?;
+            let length = u32::try_from(length)
+                .map_err(|_| format!("value of {length} bytes does not fit in u32"))?;
+            encoded.extend_from_slice(&hash);
+            encoded.extend_from_slice(&length.to_be_bytes());
+        } else if let Some(inline) = node.value.inline_bytes() {
+            encoded.extend_from_slice(inline);
+        }
+        Ok(encoded)
+    }
+
+    pub fn decode_node(payload: &[u8]) -> Result<V2Node, String> {
+        let flags = *payload.first().ok_or("v2 node payload is empty")?;
+        if flags & VERSION_MASK != V2_VERSION_FLAG {
+            return Err(format!("flags {flags:#04x} are not a v2 node"));
+        }
+        if flags & RESERVED_FLAG != 0 {
+            return Err("v2 node sets the reserved flag bit".to_string());
+        }
+        let mut offset = 1;
+
+        let shared_path = if flags & SHARED_PREFIX_FLAG != 0 {
+            let bit_len = read_varint(payload, &mut offset)? as usize;
+            let packed = take(payload, &mut offset, bit_len.div_ceil(8), "shared path")?;
+            SharedPath::from_path_bits(PathBits::from_packed(packed.to_vec(), bit_len)?)
+        } else {
+            SharedPath::empty()
+        };
+        let mut child = |present_flag: u8| -> Result<NodeReference, String> {
+            if flags & present_flag == 0 {
+                return Ok(NodeReference::Empty);
+            }
+            let hash = take(payload, &mut offset, HASH_SIZE, "child hash")?;
+            Ok(NodeReference::hashed(hash.try_into().expect("hash length")))
+        };
+        let left = child(LEFT_PRESENT_FLAG)?;
+        let right = child(RIGHT_PRESENT_FLAG)?;
+        let children_size = if left.is_empty() && right.is_empty() {
+            None
+        } else {
+            Some(read_varint(payload, &mut offset)?)
+        };
+        let rent_timestamp = if flags & RENT_TIMESTAMP_FLAG != 0 {
+            let bytes = take(payload, &mut offset, 8, "rent timestamp")?;
+            Some(u64::from_be_bytes(bytes.try_into().expect("u64 length")))
+        } else {
+            None
+        };
+
+        let value = if flags & LONG_VALUE_FLAG != 0 {
+            let hash = take(payload, &mut offset, HASH_SIZE, "value hash")?;
+            let length = take(payload, &mut offset, 4, "value length")?;
+            let length = u32::from_be_bytes(length.try_into().expect("u32 length"));
+            if offset != payload.len() {
+                return Err(format!(
+                    "v2 node payload has {} bytes of trailing data",
+                    payload.len() - offset
+                ));
+            }
+            ValueRef::hashed(hash.try_into().expect("hash length"), Some(length as usize))
+        } else {
+            ValueRef::inline(payload[offset..].to_vec())
+        };
+
+        Ok(V2Node {
+            node: TrieNode::new(shared_path, value, left, right).with_children_size(children_size),
+            rent_timestamp,
+        })
+    }
+}
+
+fn decode_v2_node(payload: &[u8]) -> Result<TrieNode, String> {
+    V2Codec::decode_node(payload).map(|decoded| decoded.node)
+}
+
+fn child_hash(reference: &NodeReference) -> Result<Option<[u8; HASH_SIZE]>, String> {
+    match reference {
+        NodeReference::Empty => Ok(None),
+        NodeReference::Hashed(hash) => Ok(Some(*hash)),
+        NodeReference::Embedded(_) => Err("v2 nodes cannot embed children".to_string()),
+    }
+}
+
+fn take<'a>(
+    payload: &'a [u8],
+    offset: &mut usize,
+    length: usize,
+    what: &str,
+) -> Result<&'a [u8], String> {
+    let bytes = payload
+        .get(*offset..*offset + length)
+        .ok_or_else(|| format!("v2 node {what} is truncated"))?;
+    *offset += length;
+    Ok(bytes)
+}
+
+fn read_varint(payload: &[u8], offset: &mut usize) -> Result<u64, String> {
+    varint::decode_from_slice_strict(payload, offset, u64::MAX)
+}
+
+// Writes strictly ascending `entries` to `store` as a v2 trie and returns its
+// root. Long values go to the value space as with RSKIP107.
+pub fn save_v2<I, S>(entries: I, store: &mut S, options: &V2SaveOptions) -> Result<TrieRoot, String>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    S: RawStoreAdapter,
+{
+    let mut builder = SortedTrieBuilder::new(V2StoreSink { store, options });
+    for (key, value) in entries {
+        builder.push(key, value)?;
+    }
+    Ok(builder
+        .finish()?
+        .map_or_else(empty_trie_hash, |root| root.hash))
+}
+
+pub type V2Entry = (Vec<u8>, Vec<u8>);
+
+// Every entry under a v2 root, in key order.
+pub fn read_v2_entries<S: RawStoreAdapter>(
+    root: &TrieRoot,
+    store: &mut S,
+) -> Result<Vec<V2Entry>, String> {
+    let mut entries = Vec::new();
+    if is_empty_root(root) {
+        return Ok(entries);
+    }
+    let mut pending = vec![(*root, PathBits::empty())];
+    while let Some((hash, mut path)) = pending.pop() {
+        let payload = store
+            .load_raw_node(&hash)
+            .ok_or_else(|| format!("v2 node {} is missing", crate::json::to_hex(&hash)))?;
+        let node = V2Codec::decode_node(&payload)?.node;
+        path.extend_from(node.shared_path.bits());
+
+        if node.has_value() {
+            if path.bit_len() % 8 != 0 {
+                return Err(format!("value stored at a {}-bit path", path.bit_len()));
+            }
+            let value = match &node.value {
+                ValueRef::Hashed { hash, .. } => store
+                    .load_raw_value(hash)
+                    .ok_or("v2 long value is missing")?,
+                value => value.inline_bytes().unwrap_or_default().to_vec(),
+            };
+            entries.push((path.to_packed_bytes().to_vec(), value));
+        }
+        // Right first, so the left subtree is visited next.
+        for (bit, child) in [(1, &node.right), (0, &node.left)] {
+            if let NodeReference::Hashed(child) = child {
+                let mut child_path = path.clone();
+                child_path.append(bit);
+                pending.push((*child, child_path));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+struct V2Subtree {
+    hash: [u8; HASH_SIZE],
+    size: u64,
+}
+
+struct V2StoreSink<'a, S> {
+    store: &'a mut S,
+    options: &'a V2SaveOptions,
+}
+
+impl<S: RawStoreAdapter> SubtreeSink for V2StoreSink<'_, S> {
+    type Subtree = V2Subtree;
+
+    fn finish(
+        &mut self,
+        shared_path: SharedPath,
+        value: Vec<u8>,
+        left: Option<V2Subtree>,
+        right: Option<V2Subtree>,
+    ) -> Result<V2Subtree, String> {
+        let children_size = (left.is_some() || right.is_some()).then(|| {
+            left.as_ref().map_or(0, |child| child.size)
+                + right.as_ref().map_or(0, |child| child.size)
+        });
+        let reference = |child: Option<V2Subtree>| {
+            child.map_or(NodeReference::Empty, |child| {
+                NodeReference::hashed(child.hash)
+            })
+        };
+        let node = TrieNode::new(
+            shared_path,
+            ValueRef::inline(value),
+            reference(left),
+            reference(right),
+        );
+        let config = &self.options.config;
+        let encoded =
+            V2Codec::encode_node(&node, children_size, self.options.rent_timestamp, config)?;
+
+        let mut external_value_size = 0;
+        if node.has_long_value_for(config) {
+            let value = node.value.inline_bytes().unwrap_or_default();
+            external_value_size = value.len() as u64;
+            self.store.save_raw_value(&keccak256(value), value);
+        }
+        let hash = keccak256(&encoded);
+        self.store.save_raw_node(&hash, &encoded);
+        Ok(V2Subtree {
+            hash,
+            size: children_size.unwrap_or(0) + external_value_size + encoded.len() as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_v2_entries, V2Codec, V2SaveOptions, V2_PROTOTYPE_DECODER};
+    use crate::codec_registry::CodecRegistry;
+    use crate::codec_rskip107::Rskip107Codec;
+    use crate::node_ref::{NodeReference, SharedPath, TrieNode, ValueRef};
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn v2_nodes_round_trip_with_timestamps_and_u32_lengths() {
+        let node = TrieNode::new(
+            SharedPath::from_bits(vec![1, 0, 1, 1, 0, 0, 1, 0, 1]).unwrap(),
+            ValueRef::hashed([0x44; 32], Some(20_000_000)),
+            NodeReference::hashed([0x11; 32]),
+            NodeReference::empty(),
+        )
+        .with_children_size(Some(77));
+        let config = Default::default();
+        let encoded = V2Codec::encode_node(&node, Some(77), Some(1_700_000_000), &config).unwrap();
+        assert!(V2Codec::is_v2_message(&encoded));
+        assert!(!Rskip107Codec::is_rskip107_message(&encoded));
+        let decoded = V2Codec::decode_node(&encoded).unwrap();
+        assert_eq!(decoded.node, node);
+        assert_eq!(decoded.rent_timestamp, Some(1_700_000_000));
+        assert!(Rskip107Codec::validate_canonical(&encoded).is_err());
+
+        let mut registry = CodecRegistry::with_builtin_decoders();
+        registry.register(V2_PROTOTYPE_DECODER).unwrap();
+        assert_eq!(registry.decode(&encoded).unwrap(), node);
+        assert!(V2Codec::decode_node(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn v2_saves_store_a_separate_trie_without_touching_rskip107_roots() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie = UnitrieCore::new(implementation);
+            for index in 0u8..20 {
+                trie.put(vec![index, 0x01], vec![index; 1 + index as usize * 3]);
+            }
+            let consensus_root = trie.current_root_hash();
+            let mut store = MemoryStore::new();
+            let options = V2SaveOptions {
+                rent_timestamp: Some(42),
+                ..V2SaveOptions::default()
+            };
+            let root = trie.save_v2_to_store(&mut store, &options).unwrap();
+
+            assert_ne!(root, consensus_root);
+            assert_eq!(trie.current_root_hash(), consensus_root);
+            let expected: Vec<_> = trie
+                .iter()
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .collect();
+            assert_eq!(read_v2_entries(&root, &mut store).unwrap(), expected);
+            assert!(store
+                .nodes()
+                .all(
+                    |(_, payload)| V2Codec::decode_node(payload).unwrap().rent_timestamp
+                        == Some(42)
+                ));
+        }
+    }
+}
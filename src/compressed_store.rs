@@ -0,0 +1,274 @@
+use crate::node_ref::MAX_CONSENSUS_VALUE_LENGTH;
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+use crate::varint;
+
+// Every stored payload starts with a codec tag and the uncompressed length, so
+// readers never depend on the codec the writer was configured with.
+const PAYLOAD_RAW: u8 = 0x00;
+const PAYLOAD_LZ4: u8 = 0x01;
+const PAYLOAD_SNAPPY: u8 = 0x02;
+const PAYLOAD_ZSTD: u8 = 0x03;
+
+// No stored payload is longer than a consensus value plus a node header:
+// longer values are chunked. Decoders preallocate the header's length, so
+// anything above this is corruption, not a payload worth allocating for.
+const MAX_PAYLOAD_LENGTH: usize = MAX_CONSENSUS_VALUE_LENGTH + 4096;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "snappy")]
+    Snappy,
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CompressionStats {
+    pub payloads: usize,
+    pub compressed_payloads: usize,
+    pub raw_bytes: usize,
+    pub stored_bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct CompressedStore<S> {
+    inner: S,
+    compression: Compression,
+    stats: CompressionStats,
+    load_error: Option<String>,
+}
+
+impl<S: RawStoreAdapter> CompressedStore<S> {
+    pub fn new(inner: S, compression: Compression) -> Self {
+        Self {
+            inner,
+            compression,
+            stats: CompressionStats::default(),
+            load_error: None,
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    // Loads report undecodable payloads as missing; the reason is kept here.
+    pub fn take_load_error(&mut self) -> Option<String> {
+        self.load_error.take()
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn encode(&mut self, payload: &[u8]) -> Vec<u8> {
+        let encoded = encode_payload(self.compression, payload);
+        self.stats.payloads += 1;
+        self.stats.raw_bytes += payload.len();
+        self.stats.stored_bytes += encoded.len();
+        if encoded.first() != Some(&PAYLOAD_RAW) {
+            self.stats.compressed_payloads += 1;
+        }
+        encoded
+    }
+
+    fn decode(&mut self, stored: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        match decode_payload(&stored?) {
+            Ok(payload) => Some(payload),
+            Err(error) => {
+                self.load_error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for CompressedStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let stored = self.inner.load_raw_node(hash);
+        self.decode(stored)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let stored = self.inner.load_raw_value(hash);
+        self.decode(stored)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        let encoded = self.encode(serialized_node);
+        self.inner.save_raw_node(hash, &encoded);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        let encoded = self.encode(value);
+        self.inner.save_raw_value(hash, &encoded);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+// Falls back to a raw payload when the codec fails or does not shrink the input.
+pub fn encode_payload(compression: Compression, payload: &[u8]) -> Vec<u8> {
+    let compressed = compress(compression, payload);
+    let (tag, body) = match &compressed {
+        Some((tag, body)) if body.len() < payload.len() => (*tag, body.as_slice()),
+        _ => (PAYLOAD_RAW, payload),
+    };
+
+    let mut encoded = Vec::with_capacity(1 + varint::size_of(payload.len() as u64) + body.len());
+    encoded.push(tag);
+    varint::encode_into(payload.len() as u64, &mut encoded);
+    encoded.extend_from_slice(body);
+    encoded
+}
+
+pub fn decode_payload(stored: &[u8]) -> Result<Vec<u8>, String> {
+    let Some((&tag, _)) = stored.split_first() else {
+        return Err("compressed payload is empty".to_string());
+    };
+    let mut offset = 1usize;
+    let length = usize::try_from(varint::decode_from_slice(stored, &mut offset)?)
+        .map_err(|_| "compressed payload length does not fit in memory".to_string())?;
+    let body = &stored[offset..];
+    if tag != PAYLOAD_RAW && length > MAX_PAYLOAD_LENGTH {
+        return Err(format!(
+            "compressed payload length {length} exceeds the maximum of {MAX_PAYLOAD_LENGTH}"
+        ));
+    }
+
+    let payload = match tag {
+        PAYLOAD_RAW => body.to_vec(),
+        other => decompress(other, body, length)?,
+    };
+    if payload.len() != length {
+        return Err(format!(
+            "compressed payload decoded to {} bytes, header says {length}",
+            payload.len()
+        ));
+    }
+    Ok(payload)
+}
+
+// Codec arguments are unused when every compression feature is disabled.
+#[allow(unused_variables)]
+fn compress(compression: Compression, payload: &[u8]) -> Option<(u8, Vec<u8>)> {
+    match compression {
+        Compression::None => None,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Some((PAYLOAD_LZ4, lz4_flex::block::compress(payload))),
+        #[cfg(feature = "snappy")]
+        Compression::Snappy => snap::raw::Encoder::new()
+            .compress_vec(payload)
+            .ok()
+            .map(|body| (PAYLOAD_SNAPPY, body)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { level } => zstd::bulk::compress(payload, level)
+            .ok()
+            .map(|body| (PAYLOAD_ZSTD, body)),
+    }
+}
+
+#[allow(unused_variables, unreachable_patterns)]
+fn decompress(tag: u8, body: &[u8], length: usize) -> Result<Vec<u8>, String> {
+    match tag {
+        #[cfg(feature = "lz4")]
+        PAYLOAD_LZ4 => lz4_flex::block::decompress(body, length)
+            .map_err(|error| format!("lz4 payload is corrupted: {error}")),
+        #[cfg(feature = "snappy")]
+        PAYLOAD_SNAPPY => snap::raw::Decoder::new()
+            .decompress_vec(body)
+            .map_err(|error| format!("snappy payload is corrupted: {error}")),
+        #[cfg(feature = "zstd")]
+        PAYLOAD_ZSTD => zstd::bulk::decompress(body, length)
+            .map_err(|error| format!("zstd payload is corrupted: {error}")),
+        PAYLOAD_LZ4 | PAYLOAD_SNAPPY | PAYLOAD_ZSTD => Err(format!(
+            "payload codec 0x{tag:02x} is not enabled in this build"
+        )),
+        other => Err(format!("unknown payload codec 0x{other:02x}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_payload, encode_payload, CompressedStore, Compression};
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::MemoryStore;
+
+    fn enabled_codecs() -> Vec<Compression> {
+        vec![
+            Compression::None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "snappy")]
+            Compression::Snappy,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd { level: 3 },
+        ]
+    }
+
+    #[test]
+    fn compressed_store_round_trips_trie_through_every_codec() {
+        for compression in enabled_codecs() {
+            let mut trie = Unitrie::new();
+            for index in 0u8..32 {
+                trie.put(vec![0x10, index], vec![index; 8]);
+                trie.put(vec![0x20, index], vec![0x42; 200]);
+            }
+            let root = trie.root_hash();
+
+            let mut store = CompressedStore::new(MemoryStore::new(), compression);
+            trie.save_to_store(&mut store);
+            let stats = store.stats();
+            if compression != Compression::None {
+                assert!(stats.compressed_payloads > 0);
+                assert!(stats.stored_bytes < stats.raw_bytes);
+            }
+
+            let mut restored = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+            assert_eq!(restored.root_hash(), root);
+            assert_eq!(restored.get(&[0x20, 7]), Some(vec![0x42; 200]));
+            assert!(store.take_load_error().is_none());
+        }
+    }
+
+    #[test]
+    fn payload_header_rejects_truncated_or_unknown_payloads() {
+        let encoded = encode_payload(Compression::None, b"payload");
+        assert_eq!(decode_payload(&encoded).unwrap(), b"payload");
+
+        assert!(decode_payload(&[]).is_err());
+        assert!(decode_payload(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_payload(&[0x7f, 0x00]).is_err());
+    }
+
+    #[test]
+    fn oversized_length_headers_are_rejected_before_decompressing() {
+        let mut corrupted = vec![0x01];
+        crate::varint::encode_into(1 << 40, &mut corrupted);
+        corrupted.extend_from_slice(&[0x00; 16]);
+        let error = decode_payload(&corrupted).unwrap_err();
+        assert!(error.contains("exceeds the maximum"), "{error}");
+    }
+}
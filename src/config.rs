@@ -0,0 +1,365 @@
+use crate::core_api::EmptyValuePolicy;
+use crate::core_trie::{LoadError, LoadLimits};
+#[cfg(feature = "next")]
+use crate::next::root_history::DEFAULT_ROOT_HISTORY_CAPACITY;
+#[cfg(feature = "next")]
+use crate::next::storage_iteration_cache::DEFAULT_STORAGE_CACHE_CAPACITY;
+use crate::node_ref::CodecConfig;
+use crate::store_adapter::{RawStoreAdapter, SharedStoreAdapter};
+use crate::{UnitrieCore, UnitrieImplementation};
+use std::str::FromStr;
+
+// Only the next engine keeps these caches.
+#[cfg(not(feature = "next"))]
+const DEFAULT_ROOT_HISTORY_CAPACITY: usize = 0;
+#[cfg(not(feature = "next"))]
+const DEFAULT_STORAGE_CACHE_CAPACITY: usize = 0;
+
+// Table read when the input has table headers; other tables are skipped so a
+// host can pass its whole config file.
+const CONFIG_TABLE: &str = "unitrie";
+
+// Everything a host needs to build or load a `UnitrieCore`. Parsed from a TOML
+// table such as:
+//
+//   [unitrie]
+//   implementation = "next"
+//   codec = "consensus"          # or "extended"
+//   storage_cache_capacity = 256
+//   root_history_capacity = 64
+//   load_threads = 4
+//   max_load_depth = 4096
+//   max_load_nodes = 50_000_000
+//   max_load_bytes = 8_000_000_000
+//   verify_hashes = true
+//   empty_values = "delete"     # or "reject"
+//
+// `long_value_threshold` and `max_embedded_node_size` override the codec mode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct UnitrieConfig {
+    pub implementation: UnitrieImplementation,
+    pub codec: CodecConfig,
+    pub storage_cache_capacity: usize,
+    pub root_history_capacity: usize,
+    pub load_threads: usize,
+    pub load_limits: LoadLimits,
+    pub empty_values: EmptyValuePolicy,
+}
+
+impl Default for UnitrieConfig {
+    fn default() -> Self {
+        Self {
+            implementation: UnitrieImplementation::default(),
+            codec: CodecConfig::CONSENSUS,
+            storage_cache_capacity: DEFAULT_STORAGE_CACHE_CAPACITY,
+            root_history_capacity: DEFAULT_ROOT_HISTORY_CAPACITY,
+            load_threads: 1,
+            load_limits: LoadLimits::default(),
+            empty_values: EmptyValuePolicy::default(),
+        }
+    }
+}
+
+impl UnitrieConfig {
+    // Supports the TOML subset config files use: one `key = value` per line with
+    // string, integer and boolean values, `#` comments and table headers.
+    // Unknown keys are rejected so typos do not silently fall back to defaults.
+    pub fn from_toml(input: &str) -> Result<Self, String> {
+        let has_tables = input
+            .lines()
+            .any(|line| strip_comment(line).trim_start().starts_with('['));
+        let mut in_table = !has_tables;
+        let mut config = Self::default();
+        let mut codec = CodecConfig::CONSENSUS;
+
+        for (index, line) in input.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fail = |message: String| format!("line {}: {message}", index + 1);
+
+            if let Some(table) = line.strip_prefix('[') {
+                let table = table
+                    .strip_suffix(']')
+                    .ok_or_else(|| fail("unterminated table header".to_string()))?;
+                in_table = table.trim() == CONFIG_TABLE;
+                continue;
+            }
+            if !in_table {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| fail(format!("expected `key = value`, got `{line}`")))?;
+            let value = TomlValue::parse(value.trim()).map_err(fail)?;
+            config.set(key.trim(), value, &mut codec).map_err(fail)?;
+        }
+
+        config.codec = codec;
+        Ok(config)
+    }
+
+    // A new empty trie with this configuration.
+    pub fn build(&self) -> UnitrieCore {
+        self.configure(UnitrieCore::new(self.implementation).with_config(self.codec))
+    }
+
+    pub fn load<T: RawStoreAdapter>(
+        &self,
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<UnitrieCore, LoadError> {
+        let core = UnitrieCore::from_persisted_root_with_config(
+            self.implementation,
+            root_hash,
+            store,
+            self.load_limits,
+            self.codec,
+        )?;
+        Ok(self.configure(core))
+    }
+
+    // Loads on `load_threads` workers sharing `store`.
+    pub fn load_parallel<S>(&self, root_hash: &[u8], store: &S) -> Result<UnitrieCore, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        let core = UnitrieCore::from_persisted_root_parallel_with_config(
+            self.implementation,
+            root_hash,
+            store,
+            self.load_threads,
+            self.load_limits,
+            self.codec,
+        )?;
+        Ok(self.configure(core))
+    }
+
+    // Everything but the codec, which a load must already decode under.
+    fn configure(&self, core: UnitrieCore) -> UnitrieCore {
+        let mut core = core.with_empty_value_policy(self.empty_values);
+        core.set_storage_cache_capacity(self.storage_cache_capacity);
+        core.set_root_history_capacity(self.root_history_capacity);
+        core
+    }
+
+    fn set(&mut self, key: &str, value: TomlValue, codec: &mut CodecConfig) -> Result<(), String> {
+        match key {
+            "implementation" => {
+                self.implementation = UnitrieImplementation::from_config(&value.string(key)?)?;
+            }
+            "codec" => {
+                let extended_values = match value.string(key)?.as_str() {
+                    "consensus" => false,
+                    "extended" => true,
+                    other => {
+                        return Err(format!(
+                            "unsupported codec '{other}', expected one of: consensus, extended"
+                        ))
+                    }
+                };
+                *codec = codec.with_extended_values(extended_values);
+            }
+            "long_value_threshold" => {
+                *codec = CodecConfig::new(value.integer(key)?, codec.max_embedded_node_size)?
+                    .with_extended_values(codec.extended_values);
+            }
+            "max_embedded_node_size" => {
+                *codec = CodecConfig::new(codec.long_value_threshold, value.integer(key)?)?
+                    .with_extended_values(codec.extended_values);
+            }
+            "storage_cache_capacity" => self.storage_cache_capacity = value.integer(key)?,
+            "root_history_capacity" => self.root_history_capacity = value.integer(key)?,
+            "load_threads" => self.load_threads = value.integer(key)?.max(1),
+            "max_load_depth" => self.load_limits.max_depth = value.integer(key)?,
+            "max_load_nodes" => self.load_limits.max_nodes = value.integer(key)?,
+            "max_load_bytes" => self.load_limits.max_total_bytes = value.integer(key)?,
+            "verify_hashes" => self.load_limits.verify_hashes = value.boolean(key)?,
+            "empty_values" => {
+                self.empty_values = match value.string(key)?.as_str() {
+                    "delete" => EmptyValuePolicy::Delete,
+                    "reject" => EmptyValuePolicy::Reject,
+                    other => {
+                        return Err(format!(
+                            "unsupported empty_values '{other}', expected one of: delete, reject"
+                        ))
+                    }
+                };
+            }
+            other => return Err(format!("unknown unitrie config key '{other}'")),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for UnitrieConfig {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::from_toml(input)
+    }
+}
+
+enum TomlValue {
+    String(String),
+    Integer(u64),
+    Boolean(bool),
+}
+
+impl TomlValue {
+    fn parse(raw: &str) -> Result<Self, String> {
+        if let Some(quoted) = raw.strip_prefix('"') {
+            let text = quoted
+                .strip_suffix('"')
+                .filter(|text| !text.contains(['"', '\\']))
+                .ok_or_else(|| format!("unsupported string value {raw}"))?;
+            return Ok(Self::String(text.to_string()));
+        }
+        match raw {
+            "true" => return Ok(Self::Boolean(true)),
+            "false" => return Ok(Self::Boolean(false)),
+            _ => {}
+        }
+        raw.replace('_', "")
+            .parse()
+            .map(Self::Integer)
+            .map_err(|_| format!("unsupported value `{raw}`"))
+    }
+
+    fn string(self, key: &str) -> Result<String, String> {
+        match self {
+            Self::String(text) => Ok(text),
+            _ => Err(format!("'{key}' must be a string")),
+        }
+    }
+
+    fn integer(self, key: &str) -> Result<usize, String> {
+        match self {
+            Self::Integer(value) => usize::try_from(value)
+                .map_err(|_| format!("'{key}' does not fit in memory: {value}")),
+            _ => Err(format!("'{key}' must be an integer")),
+        }
+    }
+
+    fn boolean(self, key: &str) -> Result<bool, String> {
+        match self {
+            Self::Boolean(value) => Ok(value),
+            _ => Err(format!("'{key}' must be a boolean")),
+        }
+    }
+}
+
+// Config values never contain `#`, so anything after one is a comment.
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#').map_or(line, |(content, _)| content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnitrieConfig;
+    use crate::core_api::EmptyValuePolicy;
+    use crate::hash::keccak256;
+    use crate::node_ref::CodecConfig;
+    use crate::store_adapter::{LockedStore, MemoryStore};
+    use crate::UnitrieImplementation;
+
+    #[test]
+    fn parses_the_unitrie_table_of_a_host_config() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let config: UnitrieConfig = format!(
+                r#"
+                [database]
+                implementation = "ignored"
+
+                [unitrie]
+                implementation = "{implementation}"  # engine
+                codec = "extended"
+                storage_cache_capacity = 16
+                load_threads = 4
+                max_load_nodes = 1_000_000
+                verify_hashes = true
+                empty_values = "reject"
+                "#
+            )
+            .parse()
+            .unwrap();
+
+            assert_eq!(config.implementation, implementation);
+            assert_eq!(
+                config.codec,
+                CodecConfig::CONSENSUS.with_extended_values(true)
+            );
+            assert_eq!(config.storage_cache_capacity, 16);
+            assert_eq!(config.load_threads, 4);
+            assert_eq!(config.load_limits.max_nodes, 1_000_000);
+            assert!(config.load_limits.verify_hashes);
+            assert_eq!(
+                config.root_history_capacity,
+                UnitrieConfig::default().root_history_capacity
+            );
+
+            let core = config.build();
+            assert_eq!(core.implementation(), implementation);
+            assert_eq!(core.config(), &config.codec);
+            assert_eq!(core.empty_value_policy(), EmptyValuePolicy::Reject);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_mistyped_values() {
+        assert_eq!(
+            UnitrieConfig::from_toml(&format!(
+                "implementation = \"{}\"",
+                UnitrieImplementation::default()
+            ))
+            .unwrap(),
+            UnitrieConfig::default()
+        );
+        for input in [
+            "implmentation = \"next\"",
+            "load_threads = \"4\"",
+            "verify_hashes = 1",
+            "codec = \"fast\"",
+            "empty_values = \"ignore\"",
+            "max_embedded_node_size = 300",
+            "[unitrie\nload_threads = 4",
+        ] {
+            assert!(UnitrieConfig::from_toml(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn saves_after_a_configured_load_write_only_the_changed_path() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let config: UnitrieConfig = format!(
+                "implementation = \"{implementation}\"\nlong_value_threshold = 8\nload_threads = 2"
+            )
+            .parse()
+            .unwrap();
+            let store = LockedStore::new(MemoryStore::new());
+            let mut core = config.build();
+            for index in 0u16..200 {
+                core.put(
+                    keccak256(&index.to_be_bytes()).to_vec(),
+                    vec![index as u8; 12],
+                );
+            }
+            core.save_to_store(&mut *store.lock());
+            let root = core.current_root_hash();
+
+            let sequential = config.load(&root, &mut *store.lock()).unwrap();
+            let parallel = config.load_parallel(&root, &store).unwrap();
+            for (mut loaded, value) in [(sequential, 0xe0), (parallel, 0xe1)] {
+                assert_eq!(loaded.config(), &config.codec);
+                assert_eq!(loaded.current_root_hash(), root);
+                loaded.put(keccak256(&7u16.to_be_bytes()).to_vec(), vec![value; 12]);
+                let stats = loaded.save_to_store_with_stats(&mut *store.lock());
+                assert_eq!(stats.values_written, 1);
+                // 200 hashed keys branch about eight levels deep.
+                assert!(stats.nodes_written < 24, "{implementation}: {stats:?}");
+            }
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use crate::node_ref::HASH_SIZE;
+use crate::core_trie::SaveStats;
+use crate::diff::{diff_roots, EntryChange};
+use crate::json::to_hex;
+use crate::key_validation::KeyShapeError;
+use crate::node_ref::{ValueTooLarge, HASH_SIZE};
+use crate::store_adapter::RawStoreAdapter;
+use std::fmt;
+use std::sync::Arc;
 
 pub type TrieRoot = [u8; HASH_SIZE];
 
@@ -8,6 +15,96 @@ pub struct TrieSnapshot {
     pub key_count: usize,
 }
 
+// Outcome of `UnitrieCore::apply_block`.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockStateResult {
+    pub previous_root: TrieRoot,
+    pub root: TrieRoot,
+    pub key_count: usize,
+    pub ops_applied: usize,
+    pub save_stats: SaveStats,
+}
+
+impl TrieSnapshot {
+    // Changes that turn this snapshot into `other`, read from the store both
+    // roots were saved to.
+    pub fn diff<T: RawStoreAdapter>(
+        &self,
+        other: &TrieSnapshot,
+        store: &mut T,
+    ) -> Result<Vec<EntryChange>, String> {
+        diff_roots(&self.root, &other.root, store)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TrieOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+    DeleteRecursive { prefix: Vec<u8> },
+}
+
+impl TrieOp {
+    pub fn put(key: Vec<u8>, value: Vec<u8>) -> Self {
+        Self::Put { key, value }
+    }
+
+    pub fn delete(key: Vec<u8>) -> Self {
+        Self::Delete { key }
+    }
+
+    pub fn delete_recursive(prefix: Vec<u8>) -> Self {
+        Self::DeleteRecursive { prefix }
+    }
+
+    // The key (or prefix) the operation touches.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Put { key, .. } | Self::Delete { key } => key,
+            Self::DeleteRecursive { prefix } => prefix,
+        }
+    }
+}
+
+// What `UnitrieCore::put` does with an empty value. The trie cannot hold one,
+// so RSKj turns it into a delete of the key; `Reject` treats it as a caller
+// bug instead, for code ported from map-like APIs. Deleting on purpose is
+// always available through `delete` and `put_or_delete_on_empty`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum EmptyValuePolicy {
+    #[default]
+    Delete,
+    Reject,
+}
+
+// A write refused by a check the `UnitrieCore` was configured with. The
+// `*_checked` and `try_*` writes return it; the others panic with it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WriteError {
+    // An empty value under `EmptyValuePolicy::Reject`.
+    EmptyValue { key: Vec<u8> },
+    MalformedKey { key: Vec<u8>, error: KeyShapeError },
+    ValueTooLarge { key: Vec<u8>, error: ValueTooLarge },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyValue { key } => write!(
+                f,
+                "empty value for key {} rejected; use delete or put_or_delete_on_empty",
+                to_hex(key)
+            ),
+            Self::MalformedKey { key, error } => {
+                write!(f, "malformed key {}: {error}", to_hex(key))
+            }
+            Self::ValueTooLarge { key, error } => {
+                write!(f, "value for key {} rejected: {error}", to_hex(key))
+            }
+        }
+    }
+}
+
 pub trait TrieStoreReader {
     fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>>;
 
@@ -27,9 +124,13 @@ pub trait TrieEngine {
 
     fn put(&mut self, key: Vec<u8>, value: Vec<u8>);
 
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>>;
+
     fn delete(&mut self, key: &[u8]);
 
-    fn delete_recursive(&mut self, prefix: &[u8]);
+    fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn delete_recursive(&mut self, prefix: &[u8]) -> usize;
 
     fn get_value_length(&self, key: &[u8]) -> Option<usize>;
 
@@ -37,9 +138,516 @@ pub trait TrieEngine {
 
     fn collect_keys(&self, byte_size: usize) -> Vec<Vec<u8>>;
 
-    fn get_storage_keys(&mut self, account_address: &[u8]) -> Vec<Vec<u8>>;
+    fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>>;
+
+    fn count_keys(&self, byte_size: usize) -> usize;
+
+    fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>>;
+
+    fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>>;
+
+    fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>>;
 
     fn current_root_hash(&mut self) -> TrieRoot;
 
     fn snapshot(&mut self) -> TrieSnapshot;
 }
+
+// An engine that saves to and loads from a store, so `UnitrieCoreGeneric` can
+// offer persistence over it.
+pub trait PersistentTrieEngine: TrieEngine + Sized {
+    fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats;
+
+    fn from_persisted_root<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Self, String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmptyValuePolicy, TrieOp, WriteError};
+    use crate::core_trie::{CancellationToken, SaveStats};
+    use crate::hash::{keccak256, EMPTY_TRIE_HASH};
+    use crate::key_scheme::KeyScheme;
+    #[cfg(feature = "next")]
+    use crate::next::core_trie::NextUnitrie;
+    use crate::node_ref::{CodecConfig, ValueTooLarge, MAX_CONSENSUS_VALUE_LENGTH};
+    use crate::storage_keys_packed;
+    use crate::store_adapter::MemoryStore;
+    use crate::value_handle::ValueHandle;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn apply_block_persists_the_new_root_or_rejects_the_whole_block() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let mut store = MemoryStore::new();
+            let ops = vec![
+                TrieOp::put(b"a".to_vec(), vec![1; 50]),
+                TrieOp::put(b"b".to_vec(), vec![2]),
+                TrieOp::delete(b"a".to_vec()),
+            ];
+            let result = core.apply_block(&ops, &mut store).unwrap();
+            assert_eq!(result.ops_applied, 3);
+            assert_eq!(result.key_count, 1);
+            assert_eq!(result.root, core.current_root_hash());
+            assert_ne!(result.previous_root, result.root);
+            assert!(store.node(result.root.as_slice()).is_some());
+
+            let mut oversized = vec![TrieOp::put(b"c".to_vec(), vec![3])];
+            oversized.push(TrieOp::put(
+                b"d".to_vec(),
+                vec![0; CodecConfig::CONSENSUS.max_value_length() + 1],
+            ));
+            assert!(core.apply_block(&oversized, &mut store).is_err());
+            assert_eq!(core.current_root_hash(), result.root);
+            assert_eq!(core.get(b"c"), None);
+        }
+    }
+
+    #[test]
+    fn forgetting_persistence_state_rewrites_everything_to_a_new_store() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for index in 0u8..20 {
+                core.put(vec![index], vec![index; 40]);
+            }
+            let mut first = MemoryStore::new();
+            core.save_to_store(&mut first);
+            assert_eq!(core.persisted_value_count(), 20);
+            assert!(core.persisted_node_count() > 1);
+
+            let mut second = MemoryStore::new();
+            core.save_to_store(&mut second);
+            assert!(second.node_count() < first.node_count());
+            assert_eq!(second.value_count(), 0);
+
+            core.forget_persistence_state();
+            assert_eq!(core.persisted_node_count(), 0);
+            assert_eq!(core.persisted_value_count(), 0);
+            core.save_to_store(&mut second);
+            assert_eq!(second, first);
+        }
+    }
+
+    #[test]
+    fn cancelled_save_leaves_the_store_resumable() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for index in 0u16..3_000 {
+                core.put(index.to_be_bytes().to_vec(), vec![1; 40]);
+            }
+            let root = core.current_root_hash();
+            let mut store = MemoryStore::new();
+
+            let cancel = CancellationToken::new();
+            let mut reports = Vec::new();
+            let result = core.save_to_store_with_progress(
+                &mut store,
+                |done, total| {
+                    reports.push((done, total));
+                    cancel.cancel();
+                },
+                &cancel,
+            );
+            assert!(result.is_err());
+            assert_eq!(reports.len(), 1);
+            assert!(store.node(root.as_slice()).is_none());
+
+            let mut last = (0, 0);
+            core.save_to_store_with_progress(
+                &mut store,
+                |done, total| last = (done, total),
+                &CancellationToken::new(),
+            )
+            .unwrap();
+            assert_eq!(last.0, last.1);
+            assert!(store.node(root.as_slice()).is_some());
+        }
+    }
+
+    #[test]
+    fn storage_keys_are_listed_through_a_shared_reference() {
+        let scheme = KeyScheme::RSK;
+        let account = [0x44u8; 20];
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.put(scheme.storage_key(&account, &[0x01]), vec![0x01]);
+
+            let shared = &core;
+            assert_eq!(shared.get_storage_keys(&account), vec![vec![0x01]]);
+            assert_eq!(shared.get_storage_keys(&account), vec![vec![0x01]]);
+            assert_eq!(
+                *shared.get_storage_keys_packed(&account),
+                storage_keys_packed::encode(&[vec![0x01]])
+            );
+
+            core.put(scheme.storage_key(&account, &[0x02]), vec![0x02]);
+            let mut keys = core.get_storage_keys(&account);
+            keys.sort();
+            assert_eq!(keys, vec![vec![0x01], vec![0x02]]);
+        }
+    }
+
+    #[test]
+    fn save_stats_describe_the_save_that_just_ran() {
+        let mut first_saves = Vec::new();
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for index in 0u8..20 {
+                core.put(vec![index], vec![index; 40]);
+            }
+            let mut store = MemoryStore::new();
+            let first = core.save_to_store_with_stats(&mut store);
+            assert!(!first.skipped_unchanged);
+            first_saves.push(first);
+
+            // Legacy revisits the saved tree and rewrites only the root node;
+            // next knows nothing changed and skips the save.
+            let second = core.save_to_store_with_stats(&mut store);
+            let expected = match implementation {
+                #[cfg(feature = "legacy")]
+                UnitrieImplementation::LegacyV1 => SaveStats {
+                    nodes_visited: 39,
+                    nodes_written: 1,
+                    values_written: 0,
+                    skipped_unchanged: false,
+                },
+                #[cfg(feature = "next")]
+                UnitrieImplementation::Next => SaveStats::UNCHANGED,
+            };
+            assert_eq!(second, expected);
+
+            core.put(vec![0xff], vec![0xff; 40]);
+            let third = core.save_to_store_with_stats(&mut store);
+            assert!(!third.skipped_unchanged);
+            assert_eq!(third.values_written, 1);
+        }
+        assert!(first_saves.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn accounting_accessors_agree_across_implementations() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.put(vec![0x02], vec![0xaa; 3]);
+            core.put(vec![0x01], vec![0xbb; 100]);
+
+            assert_eq!(core.key_count(), 2);
+            assert_eq!(core.snapshot().key_count, 2);
+            assert_eq!(core.total_value_bytes(), 103);
+            assert_eq!(core.keys().collect::<Vec<_>>(), vec![&[0x01][..], &[0x02]]);
+            assert_eq!(core.iter().nth(1), Some((&[0x02][..], &[0xaa; 3][..])));
+            assert_eq!(
+                core.get_ref(&[0x01]),
+                Some(ValueHandle::Borrowed(&[0xbb; 100]))
+            );
+            assert_eq!(core.get_ref(&[0x03]), None);
+        }
+    }
+
+    #[test]
+    fn clear_resets_to_an_empty_trie_and_forgets_bookkeeping() {
+        let account = [0x55u8; 20];
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.put(
+                KeyScheme::RSK.storage_key(&account, &[0x01]),
+                vec![0x01; 40],
+            );
+            assert_eq!(core.get_storage_keys(&account).len(), 1);
+            let mut store = MemoryStore::new();
+            core.save_to_store(&mut store);
+
+            let previous_root = core.clear_returning_root();
+            assert_eq!(core.current_root_hash(), EMPTY_TRIE_HASH);
+            assert_eq!(core.key_count(), 0);
+            assert_eq!(core.persisted_node_count(), 0);
+            assert!(core.get_storage_keys(&account).is_empty());
+
+            core.revert_to_root(&previous_root, &mut store).unwrap();
+            assert_eq!(core.get_storage_keys(&account).len(), 1);
+        }
+    }
+
+    #[cfg(all(feature = "legacy", feature = "next"))]
+    #[test]
+    fn equality_compares_content_across_implementations() {
+        let mut legacy = UnitrieCore::new(UnitrieImplementation::LegacyV1);
+        let mut next = UnitrieCore::new(UnitrieImplementation::Next);
+        for index in 0u8..10 {
+            legacy.put(vec![index], vec![index; 40]);
+            next.put(vec![9 - index], vec![9 - index; 40]);
+        }
+        assert_eq!(legacy, next);
+        assert!(legacy.same_state(&mut next));
+
+        next.put(vec![0x20], vec![0x01]);
+        assert_ne!(legacy, next);
+        assert!(!legacy.same_state(&mut next));
+
+        let mut wide = UnitrieCore::new(UnitrieImplementation::Next)
+            .with_config(CodecConfig::new(64, 44).unwrap());
+        for index in 0u8..10 {
+            wide.put(vec![index], vec![index; 40]);
+        }
+        assert_eq!(legacy, wide);
+        assert!(!legacy.same_state(&mut wide));
+    }
+
+    #[cfg(all(feature = "legacy", feature = "next"))]
+    #[test]
+    fn converting_between_implementations_keeps_state() {
+        let mut core = UnitrieCore::new(UnitrieImplementation::LegacyV1);
+        for index in 0u8..20 {
+            core.put(vec![index], vec![index; 40]);
+        }
+        let mut store = MemoryStore::new();
+        core.save_to_store(&mut store);
+        let root = core.current_root_hash();
+        let persisted = core.persisted_node_count();
+        let expected = core.clone();
+
+        for implementation in [UnitrieImplementation::Next, UnitrieImplementation::LegacyV1] {
+            core.convert_to(implementation);
+            assert_eq!(core.implementation(), implementation);
+            assert_eq!(core, expected);
+            assert_eq!(core.current_root_hash(), root);
+            assert_eq!(core.persisted_node_count(), persisted);
+        }
+
+        core.convert_to(UnitrieImplementation::Next);
+        core.put(vec![0xff], vec![0xff; 40]);
+        let written = store.node_count();
+        core.save_to_store(&mut store);
+        assert!(store.node_count() - written < persisted);
+    }
+
+    #[test]
+    fn delete_account_removes_the_account_with_its_storage_and_code() {
+        let scheme = KeyScheme::RSK;
+        let (removed, kept) = ([0x11u8; 20], [0x22u8; 20]);
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for account in [removed, kept] {
+                core.put(scheme.account_key(&account), vec![0x01]);
+                core.put_code(&account, vec![0x60; 50]);
+                for slot in 0u8..3 {
+                    core.put(scheme.storage_key(&account, &[slot]), vec![slot + 1]);
+                }
+            }
+            let mut expected = UnitrieCore::new(implementation);
+            for (key, value) in core
+                .iter()
+                .filter(|(key, _)| key.starts_with(&scheme.account_key(&kept)))
+            {
+                expected.put(key.to_vec(), value.to_vec());
+            }
+
+            assert_eq!(core.delete_account(&removed), 5);
+            assert_eq!(core.delete_account(&removed), 0);
+            assert_eq!(core.current_root_hash(), expected.current_root_hash());
+        }
+    }
+
+    #[test]
+    fn code_helpers_use_the_code_key_and_hash_long_code() {
+        let account = [0x33u8; 20];
+        let code = vec![0x60; 100];
+        let code_key = [KeyScheme::RSK.account_key(&account), vec![0x80]].concat();
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            assert_eq!(core.get_code(&account), None);
+            assert_eq!(core.get_code_hash(&account), None);
+
+            core.put_code(&account, code.clone());
+            assert_eq!(core.get(&code_key), Some(code.clone()));
+            assert_eq!(core.get_code(&account), Some(code.clone()));
+            assert_eq!(core.get_code_hash(&account), Some(keccak256(&code)));
+
+            core.put_code(&account, Vec::new());
+            assert_eq!(core.key_count(), 0);
+        }
+    }
+
+    #[cfg(feature = "legacy")]
+    #[test]
+    fn legacy_cores_do_not_track_dirty_keys() {
+        let mut legacy = UnitrieCore::new(UnitrieImplementation::LegacyV1);
+        legacy.put(vec![0x01], vec![0x01]);
+        assert_eq!(legacy.dirty_keys(), None);
+        assert!(legacy.has_pending_changes());
+    }
+
+    #[cfg(feature = "next")]
+    #[test]
+    fn dirty_keys_list_what_the_next_save_replicates() {
+        let mut core = UnitrieCore::new(UnitrieImplementation::Next);
+        assert!(!core.has_pending_changes());
+        core.put(vec![0x02], vec![0x02]);
+        core.put(vec![0x01], vec![0x01]);
+        core.apply_batch([
+            TrieOp::delete(vec![0x03]),
+            TrieOp::delete_recursive(vec![0x04]),
+        ]);
+        assert!(core.has_pending_changes());
+        assert_eq!(
+            core.dirty_keys(),
+            Some(vec![vec![0x01], vec![0x02], vec![0x03]])
+        );
+        assert_eq!(core.dirty_prefixes(), Some(vec![vec![0x04]]));
+
+        let mut store = MemoryStore::new();
+        core.save_to_store(&mut store);
+        assert!(!core.has_pending_changes());
+        assert_eq!(core.dirty_keys(), Some(Vec::new()));
+
+        core.clear();
+        assert_eq!(core.dirty_prefixes(), Some(vec![Vec::new()]));
+    }
+
+    #[test]
+    fn empty_value_policy_selects_between_delete_and_reject() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            assert_eq!(core.empty_value_policy(), EmptyValuePolicy::Delete);
+            core.put(vec![0x01], vec![0x11]);
+            core.put(vec![0x01], Vec::new());
+            assert_eq!(core.key_count(), 0);
+
+            let mut strict =
+                UnitrieCore::new(implementation).with_empty_value_policy(EmptyValuePolicy::Reject);
+            strict.put(vec![0x01], vec![0x11]);
+            strict.put_storage_value(&[0x22; 20], &[0x00; 32], vec![0x01]);
+            strict.put_storage_value(&[0x22; 20], &[0x00; 32], Vec::new());
+            strict.put_or_delete_on_empty(vec![0x01], Vec::new());
+            assert_eq!(strict.key_count(), 0);
+
+            strict.put(vec![0x02], vec![0x22]);
+            let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                strict.put(vec![0x02], Vec::new())
+            }));
+            assert!(rejected.is_err());
+            assert_eq!(strict.get(&[0x02]), Some(vec![0x22]));
+        }
+    }
+
+    #[test]
+    fn every_write_path_applies_the_empty_value_policy() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut strict =
+                UnitrieCore::new(implementation).with_empty_value_policy(EmptyValuePolicy::Reject);
+            strict.put(vec![0x02], vec![0x22]);
+            let refused = WriteError::EmptyValue { key: vec![0x02] };
+            assert_eq!(
+                strict.put_checked(vec![0x02], Vec::new()),
+                Err(refused.clone())
+            );
+            assert_eq!(strict.try_put(vec![0x02], Vec::new()), Err(refused.clone()));
+            assert_eq!(
+                strict.update_checked(vec![0x02], |_| Some(Vec::new())),
+                Err(refused.clone())
+            );
+            let batch = [
+                TrieOp::put(vec![0x03], vec![0x33]),
+                TrieOp::put(vec![0x02], Vec::new()),
+            ];
+            assert_eq!(strict.try_apply_batch(batch.clone()), Err(refused));
+            assert!(strict.apply_block(&batch, &mut MemoryStore::new()).is_err());
+            assert!(strict.extend_sorted([(vec![0x04], Vec::new())]).is_err());
+            assert!(strict
+                .put_value_from_reader(vec![0x05], &[][..], 0, &mut MemoryStore::new())
+                .is_err());
+            let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                strict.update(vec![0x02], |_| Some(Vec::new()))
+            }));
+            assert!(rejected.is_err());
+            assert_eq!(strict.key_count(), 1);
+            assert_eq!(strict.get(&[0x02]), Some(vec![0x22]));
+
+            strict.update(vec![0x02], |_| None);
+            assert_eq!(strict.key_count(), 0);
+        }
+    }
+
+    #[test]
+    fn oversized_values_are_refused_when_written() {
+        let oversized = vec![0x01; MAX_CONSENSUS_VALUE_LENGTH + 1];
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.put(vec![0x01], vec![0x11]);
+            let root = core.current_root_hash();
+            let writes: [&dyn Fn(&mut UnitrieCore); 3] = [
+                &|core| core.put(vec![0x02], oversized.clone()),
+                &|core| core.update(vec![0x01], |_| Some(oversized.clone())),
+                &|core| {
+                    core.apply_batch([
+                        TrieOp::put(vec![0x03], vec![0x33]),
+                        TrieOp::put(vec![0x04], oversized.clone()),
+                    ])
+                },
+            ];
+            for write in writes {
+                let rejected =
+                    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| write(&mut core)));
+                assert!(rejected.is_err());
+                assert_eq!(core.current_root_hash(), root);
+            }
+            assert!(core
+                .extend_sorted([(vec![0x05], oversized.clone())])
+                .is_err());
+            assert_eq!(core.current_root_hash(), root);
+
+            let too_large = |key: u8| WriteError::ValueTooLarge {
+                key: vec![key],
+                error: ValueTooLarge {
+                    length: oversized.len(),
+                    max_length: MAX_CONSENSUS_VALUE_LENGTH,
+                },
+            };
+            assert_eq!(
+                core.try_put(vec![0x02], oversized.clone()),
+                Err(too_large(0x02))
+            );
+            assert_eq!(
+                core.update_checked(vec![0x01], |_| Some(oversized.clone())),
+                Err(too_large(0x01))
+            );
+            assert_eq!(
+                core.try_apply_batch([TrieOp::put(vec![0x04], oversized.clone())]),
+                Err(too_large(0x04))
+            );
+            assert_eq!(core.get(&[0x01]), Some(vec![0x11]));
+            assert_eq!(core.current_root_hash(), root);
+        }
+    }
+
+    #[cfg(feature = "next")]
+    #[test]
+    fn next_engine_lists_storage_keys_from_several_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<NextUnitrie>();
+        assert_send_sync::<UnitrieCore>();
+        let scheme = KeyScheme::RSK;
+        let account = [0x45u8; 20];
+        let mut trie = NextUnitrie::new();
+        for slot in 0u8..4 {
+            trie.put(scheme.storage_key(&account, &[slot]), vec![slot + 1]);
+        }
+        let trie = &trie;
+        std::thread::scope(|scope| {
+            let listings: Vec<_> = (0..4)
+                .map(|_| scope.spawn(move || trie.get_storage_keys(&account).len()))
+                .collect();
+            for listing in listings {
+                assert_eq!(listing.join().unwrap(), 4);
+            }
+        });
+    }
+}
@@ -1,37 +1,396 @@
 use crate::codec_orchid::OrchidCodec;
+use crate::codec_registry::decode_registered;
 use crate::codec_rskip107::{ChildEncoding, Rskip107Codec};
-use crate::hash::{empty_trie_hash, keccak256};
+use crate::core_api::{PersistentTrieEngine, TrieEngine, TrieOp, TrieSnapshot};
+use crate::diff::{diff_entries, diff_roots, EntryChange};
+use crate::hash::{empty_trie_hash, is_empty_root, keccak256};
+use crate::key_scheme::KeyScheme;
+use crate::long_value;
 use crate::node_ref::{
-    NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE, LONG_VALUE_THRESHOLD,
-    MAX_EMBEDDED_NODE_SIZE_IN_BYTES,
+    CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef, ValueTooLarge, HASH_SIZE,
 };
-use crate::path::shared_path_serializer;
-use crate::store_adapter::RawStoreAdapter;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::path::path_bits::PathBits;
+use crate::proof::{AccountProof, ProofSizeEstimate, StorageProof, TrieProof};
+use crate::sorted_builder::build_trie_node;
+use crate::storage_keys_packed;
+use crate::store_adapter::{CodecHint, RawStoreAdapter, SharedStoreAdapter};
+use std::collections::btree_map::{self, Entry};
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+type SortedEntries = Vec<(Vec<u8>, Vec<u8>)>;
+
+// Ordered iterator over a contiguous key range of a trie's entries.
+#[derive(Debug, Clone)]
+pub struct EntryRange<'a> {
+    range: btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+}
 
-const SECURE_KEY_SIZE: usize = 10;
-const DOMAIN_PREFIX: [u8; 1] = [0x00];
-const STORAGE_PREFIX: [u8; 1] = [0x00];
+impl<'a> Iterator for EntryRange<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range
+            .next()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+}
 
 #[derive(Debug, Clone)]
 struct MaterializedTrie {
     root_node: Option<TrieNode>,
     root_hash: [u8; HASH_SIZE],
+    summaries: Vec<NodeSummary>,
+}
+
+impl MaterializedTrie {
+    fn build(root_node: Option<TrieNode>, config: &CodecConfig) -> Result<Self, String> {
+        let summaries = match &root_node {
+            None => Vec::new(),
+            Some(node) => summarize_tree(node, config)?,
+        };
+        let root_hash = summaries
+            .last()
+            .map_or_else(empty_trie_hash, |summary| summary.hash);
+        Ok(Self {
+            root_node,
+            root_hash,
+            summaries,
+        })
+    }
+
+    fn empty() -> Self {
+        Self {
+            root_node: None,
+            root_hash: empty_trie_hash(),
+            summaries: Vec::new(),
+        }
+    }
 }
 
+// What hashing learned about one node, so proofs can reference it from a parent
+// or size it without encoding its subtree again. Kept in post-order: children
+// come before their parent and the root is last.
 #[derive(Debug, Clone)]
-struct NodeMetadata {
+struct NodeSummary {
     hash: [u8; HASH_SIZE],
-    serialized: Vec<u8>,
     reference_size: u64,
     embeddable: bool,
+    serialized_len: usize,
+    serialized_zero_bytes: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+// Hashes of long values by key, filled at load and on the first `get_value_hash`
+// so code blobs are not rehashed on every lookup. Writing a key drops its hash.
+// Behind a Mutex rather than a RefCell so the trie stays `Sync`.
+#[derive(Debug, Default)]
+struct ValueHashCache(Mutex<HashMap<Vec<u8>, [u8; HASH_SIZE]>>);
+
+impl Clone for ValueHashCache {
+    fn clone(&self) -> Self {
+        Self::from_map(self.lock().clone())
+    }
+}
+
+impl ValueHashCache {
+    fn from_map(hashes: HashMap<Vec<u8>, [u8; HASH_SIZE]>) -> Self {
+        Self(Mutex::new(hashes))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Vec<u8>, [u8; HASH_SIZE]>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn get_or_compute(&self, key: &[u8], value: &[u8]) -> [u8; HASH_SIZE] {
+        if let Some(hash) = self.lock().get(key) {
+            return *hash;
+        }
+        // Hashed without the lock held; a racing lookup computes the same hash.
+        let hash = keccak256(value);
+        self.lock().insert(key.to_vec(), hash);
+        hash
+    }
+
+    fn hashes(&mut self) -> &mut HashMap<Vec<u8>, [u8; HASH_SIZE]> {
+        self.0
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, hash: [u8; HASH_SIZE]) {
+        self.hashes().insert(key, hash);
+    }
+
+    fn forget(&mut self, key: &[u8]) {
+        self.hashes().remove(key);
+    }
+
+    fn clear(&mut self) {
+        self.hashes().clear();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct NodeMetadata {
+    pub(crate) hash: [u8; HASH_SIZE],
+    pub(crate) serialized: Vec<u8>,
+    pub(crate) reference_size: u64,
+    pub(crate) embeddable: bool,
+}
+
+impl NodeMetadata {
+    // How a parent references this node: embedded inline when small enough,
+    // otherwise by hash.
+    pub(crate) fn into_child_encoding(self) -> (ChildEncoding, u64) {
+        if self.embeddable {
+            (
+                ChildEncoding::Embedded(self.serialized),
+                self.reference_size,
+            )
+        } else {
+            (ChildEncoding::Hashed(self.hash), self.reference_size)
+        }
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+// Describes the save that just completed. `skipped_unchanged` is set when the
+// engine knew nothing changed since its last save and skipped the walk, in which
+// case every count is zero.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct SaveStats {
     pub nodes_visited: u64,
     pub nodes_written: u64,
     pub values_written: u64,
+    pub skipped_unchanged: bool,
+}
+
+impl SaveStats {
+    pub const UNCHANGED: Self = Self {
+        nodes_visited: 0,
+        nodes_written: 0,
+        values_written: 0,
+        skipped_unchanged: true,
+    };
+}
+
+// Nodes below the root a load walks by default. Key length bounds the depth of a
+// well-formed trie far below this.
+pub const DEFAULT_MAX_LOAD_DEPTH: usize = 4096;
+
+// Bounds on what loading a persisted trie may walk and read, for roots that come
+// from an untrusted store or sync peer. Bytes count node payloads and long values
+// read from the store. `verify_hashes` rehashes every node and long value read
+// and checks it against the hash it was referenced by.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LoadLimits {
+    pub max_depth: usize,
+    pub max_nodes: usize,
+    pub max_total_bytes: usize,
+    pub verify_hashes: bool,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_LOAD_DEPTH,
+            max_nodes: usize::MAX,
+            max_total_bytes: usize::MAX,
+            verify_hashes: false,
+        }
+    }
+}
+
+// Shared by every worker of a parallel load, hence the atomics.
+struct LoadBudget {
+    limits: LoadLimits,
+    nodes: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl LoadBudget {
+    fn new(limits: LoadLimits) -> Self {
+        Self {
+            limits,
+            nodes: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    fn charge(&self, nodes: usize, bytes: usize) -> Result<(), LoadError> {
+        let total_nodes = self
+            .nodes
+            .fetch_add(nodes, Ordering::Relaxed)
+            .saturating_add(nodes);
+        if total_nodes > self.limits.max_nodes {
+            return Err(LoadError::NodeLimitExceeded {
+                max_nodes: self.limits.max_nodes,
+            });
+        }
+        let total_bytes = self
+            .bytes
+            .fetch_add(bytes, Ordering::Relaxed)
+            .saturating_add(bytes);
+        if total_bytes > self.limits.max_total_bytes {
+            return Err(LoadError::ByteLimitExceeded {
+                max_total_bytes: self.limits.max_total_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    fn verify(&self, expected: &[u8; HASH_SIZE], payload: &[u8]) -> Result<(), LoadError> {
+        if !self.limits.verify_hashes {
+            return Ok(());
+        }
+        let actual = keccak256(payload);
+        if actual != *expected {
+            return Err(LoadError::HashMismatch {
+                expected: *expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LoadError {
+    DepthLimitExceeded {
+        max_depth: usize,
+    },
+    NodeLimitExceeded {
+        max_nodes: usize,
+    },
+    ByteLimitExceeded {
+        max_total_bytes: usize,
+    },
+    // A node or long value whose keccak is not the hash it was stored under.
+    HashMismatch {
+        expected: [u8; HASH_SIZE],
+        actual: [u8; HASH_SIZE],
+    },
+    // Missing, undecodable or otherwise invalid persisted data.
+    Other(String),
+}
+
+impl From<String> for LoadError {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DepthLimitExceeded { max_depth } => {
+                write!(f, "persisted trie is deeper than {max_depth} nodes")
+            }
+            Self::NodeLimitExceeded { max_nodes } => {
+                write!(f, "persisted trie has more than {max_nodes} nodes")
+            }
+            Self::ByteLimitExceeded { max_total_bytes } => {
+                write!(f, "persisted trie is larger than {max_total_bytes} bytes")
+            }
+            Self::HashMismatch { expected, actual } => write!(
+                f,
+                "payload stored under {} hashes to {}",
+                hex(expected),
+                hex(actual)
+            ),
+            Self::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+// Lets a host abort a running save, e.g. on shutdown. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Nodes between progress callbacks, so the callback stays off the hot path.
+const SAVE_PROGRESS_INTERVAL: u64 = 1024;
+
+struct SaveProgress<'a> {
+    callback: Option<&'a mut dyn FnMut(u64, u64)>,
+    cancel: Option<&'a CancellationToken>,
+    done: u64,
+    total: u64,
+}
+
+impl<'a> SaveProgress<'a> {
+    fn none() -> Self {
+        Self {
+            callback: None,
+            cancel: None,
+            done: 0,
+            total: 0,
+        }
+    }
+
+    fn visit(&mut self) -> Result<(), String> {
+        if self.cancel.is_some_and(CancellationToken::is_cancelled) {
+            return Err(format!(
+                "save cancelled after {} of {} nodes",
+                self.done, self.total
+            ));
+        }
+        self.done += 1;
+        if let Some(callback) = self.callback.as_mut() {
+            if self.done.is_multiple_of(SAVE_PROGRESS_INTERVAL) || self.done == self.total {
+                callback(self.done, self.total);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SaveEstimate {
+    pub nodes: u64,
+    pub values: u64,
+    pub node_bytes: u64,
+    pub value_bytes: u64,
+}
+
+impl SaveEstimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.node_bytes.saturating_add(self.value_bytes)
+    }
+}
+
+// The keys below one bit prefix, committed to as the hash of the trie they
+// form with the prefix stripped. Tries holding the same entries under the
+// prefix agree on the hash whatever else they hold.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SubtreeDigest {
+    // One byte per bit, each 0 or 1.
+    pub prefix_bits: Vec<u8>,
+    pub hash: [u8; HASH_SIZE],
+    pub key_count: usize,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -40,6 +399,9 @@ pub struct Unitrie {
     materialized: Option<MaterializedTrie>,
     persisted_node_hashes: HashSet<[u8; HASH_SIZE]>,
     persisted_value_hashes: HashSet<[u8; HASH_SIZE]>,
+    value_hashes: ValueHashCache,
+    config: CodecConfig,
+    key_scheme: KeyScheme,
 }
 
 impl Unitrie {
@@ -51,47 +413,212 @@ impl Unitrie {
         root_hash: &[u8],
         store: &mut T,
     ) -> Result<Self, String> {
+        Self::from_persisted_root_with_limits(root_hash, store, LoadLimits::default())
+            .map_err(|error| error.to_string())
+    }
+
+    // Loads without recursion, stopping at the first limit the trie exceeds.
+    pub fn from_persisted_root_with_limits<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError> {
+        Self::from_persisted_root_with_config(root_hash, store, limits, CodecConfig::CONSENSUS)
+    }
+
+    // Loads a trie saved under `config`. Unlike loading and then calling
+    // `with_config`, this keeps the loaded nodes known as persisted, so the
+    // next save only writes what changed.
+    pub fn from_persisted_root_with_config<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError> {
         if root_hash.len() != HASH_SIZE {
-            return Err(format!(
+            return Err(LoadError::Other(format!(
                 "root hash must be {HASH_SIZE} bytes, got {}",
                 root_hash.len()
-            ));
+            )));
         }
 
         let mut fixed_root = [0u8; HASH_SIZE];
         fixed_root.copy_from_slice(root_hash);
-        if fixed_root == empty_trie_hash() {
-            return Ok(Self::new());
+        if is_empty_root(&fixed_root) {
+            return Ok(Self::new().with_config(config));
         }
 
-        let root_payload = store
-            .load_raw_node(root_hash)
-            .ok_or_else(|| "root hash not found in store adapter".to_string())?;
-        let root_node = decode_persisted_node(&root_payload)?;
+        let budget = LoadBudget::new(limits);
+        let mut loaded = LoadedEntries::default();
+        let root = load_root_node(&fixed_root, store, &budget, &mut loaded)?;
+        collect_entries(vec![root], store, &mut loaded, &budget)?;
+        Ok(Self::from_loaded(loaded, config))
+    }
+
+    // Eager load that hands subtrees to `threads` workers, all reading through
+    // the shared `store`. The top of the trie is expanded on the calling thread
+    // until there are a few subtrees per worker.
+    pub fn from_persisted_root_parallel<S>(
+        root_hash: &[u8],
+        shared_store: &S,
+        threads: usize,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        Self::from_persisted_root_parallel_with_config(
+            root_hash,
+            shared_store,
+            threads,
+            limits,
+            CodecConfig::CONSENSUS,
+        )
+    }
+
+    // `from_persisted_root_parallel` for a trie saved under `config`; see
+    // `from_persisted_root_with_config`.
+    pub fn from_persisted_root_parallel_with_config<S>(
+        root_hash: &[u8],
+        shared_store: &S,
+        threads: usize,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        const SUBTREES_PER_WORKER: usize = 4;
+
+        let root_hash: [u8; HASH_SIZE] = root_hash.try_into().map_err(|_| {
+            format!(
+                "root hash must be {HASH_SIZE} bytes, got {}",
+                root_hash.len()
+            )
+        })?;
+        if is_empty_root(&root_hash) {
+            return Ok(Self::new().with_config(config));
+        }
 
+        let budget = LoadBudget::new(limits);
+        let mut loaded = LoadedEntries::default();
+        let mut store = shared_store;
+        let mut frontier = VecDeque::new();
+        frontier.push_back(load_root_node(
+            &root_hash,
+            &mut store,
+            &budget,
+            &mut loaded,
+        )?);
+        let target = threads.max(1) * SUBTREES_PER_WORKER;
         let mut node_cache = HashMap::new();
-        let mut persisted_node_hashes = HashSet::new();
-        persisted_node_hashes.insert(fixed_root);
-        let mut persisted_value_hashes = HashSet::new();
-        let mut entries = BTreeMap::new();
-        collect_entries_from_node(
-            &root_node,
-            Vec::new(),
-            store,
-            &mut node_cache,
-            &mut entries,
-            &mut persisted_node_hashes,
-            &mut persisted_value_hashes,
-        )?;
+        let mut children = Vec::new();
+        while frontier.len() < target {
+            let Some(pending) = frontier.pop_front() else {
+                break;
+            };
+            expand_node(
+                pending,
+                &mut store,
+                &mut node_cache,
+                &mut loaded,
+                &budget,
+                &mut children,
+            )?;
+            frontier.extend(children.drain(..));
+        }
+        if threads <= 1 || frontier.is_empty() {
+            collect_entries(frontier.into(), &mut store, &mut loaded, &budget)?;
+            return Ok(Self::from_loaded(loaded, config));
+        }
 
-        Ok(Self {
-            entries,
+        let queue = Mutex::new(Vec::from(frontier));
+        let results: Vec<Result<LoadedEntries, LoadError>> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut store = shared_store;
+                        let mut loaded = LoadedEntries::default();
+                        loop {
+                            let next = queue.lock().expect("load queue lock").pop();
+                            let Some(pending) = next else {
+                                return Ok(loaded);
+                            };
+                            collect_entries(vec![pending], &mut store, &mut loaded, &budget)?;
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("load worker panicked"))
+                .collect()
+        });
+        for result in results {
+            loaded.merge(result?);
+        }
+        Ok(Self::from_loaded(loaded, config))
+    }
+
+    fn from_loaded(loaded: LoadedEntries, config: CodecConfig) -> Self {
+        Self {
+            entries: loaded.entries,
             materialized: None,
-            persisted_node_hashes,
-            persisted_value_hashes,
+            persisted_node_hashes: loaded.persisted_node_hashes,
+            persisted_value_hashes: loaded.value_hashes.values().copied().collect(),
+            value_hashes: ValueHashCache::from_map(loaded.value_hashes),
+            config,
+            key_scheme: KeyScheme::RSK,
+        }
+    }
+
+    // Bulk construction from strictly ascending entries: the map is bulk-built and
+    // the node tree is assembled in one pass instead of per-key insertion.
+    pub fn from_sorted_entries<I>(entries: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let sorted = collect_sorted_entries(entries)?;
+        let root_node = build_trie_node(sorted.iter().cloned())?;
+        let materialized = MaterializedTrie::build(root_node, &CodecConfig::CONSENSUS)?;
+
+        Ok(Self {
+            entries: sorted.into_iter().collect(),
+            materialized: Some(materialized),
+            ..Self::default()
         })
     }
 
+    // Merges strictly ascending entries into the trie; empty values delete, as in `put`.
+    pub fn extend_sorted<I>(&mut self, entries: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let mut incoming = SortedEntries::new();
+        for (key, value) in entries {
+            ensure_ascending(incoming.last().map(|(last, _)| last.as_slice()), &key)?;
+            self.config
+                .check_value_length(value.len())
+                .map_err(|error| error.to_string())?;
+            incoming.push((key, value));
+        }
+
+        let (deletions, insertions): (Vec<_>, Vec<_>) = incoming
+            .into_iter()
+            .partition(|(_, value)| value.is_empty());
+        for (key, _) in deletions {
+            self.value_hashes.forget(&key);
+            self.entries.remove(&key);
+        }
+        for (key, _) in &insertions {
+            self.value_hashes.forget(key);
+        }
+        let mut insertions: BTreeMap<Vec<u8>, Vec<u8>> = insertions.into_iter().collect();
+        self.entries.append(&mut insertions);
+        self.materialized = None;
+        Ok(())
+    }
+
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.entries.get(key).cloned()
     }
@@ -100,51 +627,239 @@ impl Unitrie {
         self.entries.get(key).map(Vec::as_slice)
     }
 
+    // Panics on values too long for the configured encoding; see `try_put`.
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.insert(key, value);
+    }
+
+    // `put` for values that may be too long for the configured encoding,
+    // returning the error instead of panicking.
+    pub fn try_put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ValueTooLarge> {
+        self.config.check_value_length(value.len())?;
+        self.put(key, value);
+        Ok(())
+    }
+
+    // Reads a value of `length` bytes from `reader` straight into `store`:
+    // as one raw payload up to the consensus limit, in chunks past it. The trie
+    // still holds the value, but the next save skips it.
+    pub fn put_value_from_reader<R: Read, T: RawStoreAdapter>(
+        &mut self,
+        key: Vec<u8>,
+        mut reader: R,
+        length: usize,
+        store: &mut T,
+    ) -> Result<(), String> {
+        self.config
+            .check_value_length(length)
+            .map_err(|error| error.to_string())?;
+        if length <= self.config.long_value_threshold {
+            let mut value = vec![0u8; length];
+            reader
+                .read_exact(&mut value)
+                .map_err(|error| format!("failed to read value: {error}"))?;
+            self.put(key, value);
+            return Ok(());
+        }
+
+        let (value_hash, value) = long_value::save_value_from_reader(store, reader, length)?;
+        self.put(key.clone(), value);
+        self.persisted_value_hashes.insert(value_hash);
+        self.value_hashes.insert(key, value_hash);
+        Ok(())
+    }
+
+    // Writes the value without cloning it. Returns `false` when the key is absent.
+    pub fn get_value_to_writer<W: Write>(&self, key: &[u8], mut writer: W) -> io::Result<bool> {
+        match self.entries.get(key) {
+            Some(value) => writer.write_all(value).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // Same semantics as `put` (an empty value deletes the key), returning the
+    // value that was replaced or removed.
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        assert_value_fits(&self.config, &value);
+        self.materialized = None;
+        self.value_hashes.forget(&key);
         if value.is_empty() {
-            self.entries.remove(&key);
+            self.entries.remove(&key)
         } else {
-            self.entries.insert(key, value);
+            self.entries.insert(key, value)
         }
+    }
+
+    // Read-modify-write without cloning the old value: `f` sees the current value
+    // and returns the replacement. `None` or an empty value deletes the key.
+    pub fn update<F>(&mut self, key: Vec<u8>, f: F)
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
         self.materialized = None;
+        self.value_hashes.forget(&key);
+        let config = self.config;
+        match self.entries.entry(key) {
+            Entry::Occupied(mut entry) => match f(Some(entry.get())) {
+                Some(value) if !value.is_empty() => {
+                    assert_value_fits(&config, &value);
+                    *entry.get_mut() = value;
+                }
+                _ => {
+                    entry.remove();
+                }
+            },
+            Entry::Vacant(entry) => {
+                if let Some(value) = f(None).filter(|value| !value.is_empty()) {
+                    assert_value_fits(&config, &value);
+                    entry.insert(value);
+                }
+            }
+        }
     }
 
     pub fn delete(&mut self, key: &[u8]) {
-        self.entries.remove(key);
+        self.delete_returning(key);
+    }
+
+    pub fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
         self.materialized = None;
+        self.value_hashes.forget(key);
+        self.entries.remove(key)
+    }
+
+    // Back to a fresh empty trie with the same config and key scheme. Unlike
+    // `delete_recursive(&[])` this also drops the persistence bookkeeping.
+    pub fn clear(&mut self) {
+        *self = Self {
+            config: self.config,
+            key_scheme: self.key_scheme,
+            ..Self::default()
+        };
     }
 
-    pub fn delete_recursive(&mut self, prefix: &[u8]) {
+    // Returns the number of keys removed.
+    pub fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
         if self.entries.is_empty() {
-            return;
+            return 0;
         }
 
         if prefix.is_empty() {
+            let removed = self.entries.len();
             self.entries.clear();
+            self.value_hashes.clear();
             self.materialized = None;
-            return;
+            return removed;
         }
 
         // Remove lexicographic window [prefix, prefix_upper_bound) which is exactly
-        // the key set that starts with `prefix`.
-        let mut tail = self.entries.split_off(prefix);
-        let removed_any = if let Some(upper_bound) = prefix_upper_bound(prefix) {
-            let mut suffix = tail.split_off(&upper_bound);
-            let removed = !tail.is_empty();
-            self.entries.append(&mut suffix);
-            removed
-        } else {
-            // Prefix made of 0xff bytes has no finite upper bound; everything in tail matches.
-            !tail.is_empty()
+        // the key set that starts with `prefix`. A prefix made of 0xff bytes has no
+        // finite upper bound, so everything in tail matches.
+        let tail = {
+            let mut tail = self.entries.split_off(prefix);
+            if let Some(upper_bound) = prefix_upper_bound(prefix) {
+                let mut suffix = tail.split_off(&upper_bound);
+                self.entries.append(&mut suffix);
+            }
+            tail
         };
 
-        if !removed_any {
-            // Restore original map when nothing was removed.
-            self.entries.append(&mut tail);
-            return;
+        if tail.is_empty() {
+            return 0;
         }
 
+        for key in tail.keys() {
+            self.value_hashes.forget(key);
+        }
         self.materialized = None;
+        tail.len()
+    }
+
+    // Storage cells under the account's storage prefix, keyed as `KeyScheme`
+    // derives them. An empty value clears the cell.
+    pub fn put_storage_value(&mut self, address: &[u8], slot: &[u8], value: Vec<u8>) {
+        let storage_key = self.key_scheme.storage_key(address, slot);
+        self.put(storage_key, value);
+    }
+
+    pub fn get_storage_value(&self, address: &[u8], slot: &[u8]) -> Option<Vec<u8>> {
+        self.get(&self.key_scheme.storage_key(address, slot))
+    }
+
+    // Stores contract code under the account's code key; empty code removes it.
+    pub fn put_code(&mut self, address: &[u8], code: Vec<u8>) {
+        let code_key = self.key_scheme.code_key(address);
+        self.put(code_key, code);
+    }
+
+    pub fn get_code(&self, address: &[u8]) -> Option<Vec<u8>> {
+        self.get(&self.key_scheme.code_key(address))
+    }
+
+    // keccak256 of the code, the hash RSKj reports; long code is not copied.
+    pub fn get_code_hash(&self, address: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.get_value_hash(&self.key_scheme.code_key(address))
+    }
+
+    // Removes the account node together with its storage and code, all of which
+    // live under the account key, as RSKj does for a destroyed account. Returns
+    // the number of keys removed.
+    pub fn delete_account(&mut self, address: &[u8]) -> usize {
+        let account_key = self.key_scheme.account_key(address);
+        self.delete_recursive(&account_key)
+    }
+
+    pub fn apply_batch<I>(&mut self, ops: I)
+    where
+        I: IntoIterator<Item = TrieOp>,
+    {
+        // Checked up front so a rejected batch leaves the trie untouched.
+        let ops: Vec<TrieOp> = ops.into_iter().collect();
+        for op in &ops {
+            if let TrieOp::Put { value, .. } = op {
+                assert_value_fits(&self.config, value);
+            }
+        }
+        for op in ops {
+            match op {
+                TrieOp::Put { key, value } => {
+                    self.insert(key, value);
+                }
+                TrieOp::Delete { key } => {
+                    self.delete_returning(&key);
+                }
+                TrieOp::DeleteRecursive { prefix } => {
+                    self.delete_recursive(&prefix);
+                }
+            }
+        }
+    }
+
+    // Switches to another persisted root, returning the changes applied. When the
+    // current root is in `store` as well, only subtrees that differ between the
+    // two roots are read; otherwise the target is loaded in full.
+    pub fn revert_to_root<T: RawStoreAdapter>(
+        &mut self,
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Vec<EntryChange>, String> {
+        let current = self.root_hash();
+        if current[..] == *root_hash {
+            return Ok(Vec::new());
+        }
+
+        if store.load_raw_node(&current).is_some() {
+            let changes = diff_roots(&current, root_hash, store)?;
+            self.apply_batch(changes.iter().map(EntryChange::to_op));
+            if self.root_hash()[..] == *root_hash {
+                return Ok(changes);
+            }
+        }
+
+        let target = Unitrie::from_persisted_root(root_hash, store)?.with_config(self.config);
+        let changes = diff_entries(self.iter_prefix(&[]), target.iter_prefix(&[]));
+        *self = target;
+        Ok(changes)
     }
 
     pub fn get_value_length(&self, key: &[u8]) -> Option<usize> {
@@ -152,7 +867,11 @@ impl Unitrie {
     }
 
     pub fn get_value_hash(&self, key: &[u8]) -> Option<[u8; HASH_SIZE]> {
-        self.entries.get(key).map(|value| keccak256(value))
+        let value = self.entries.get(key)?;
+        if value.len() <= self.config.long_value_threshold {
+            return Some(keccak256(value));
+        }
+        Some(self.value_hashes.get_or_compute(key, value))
     }
 
     // Matches Java semantics: collect keys with exactly `byte_size` bytes.
@@ -166,70 +885,392 @@ impl Unitrie {
             .collect()
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
-        self.entries.keys()
+    // Page of `collect_keys` results strictly after `start_after`, in key order.
+    pub fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        let collect_all = byte_size == i32::MAX as usize;
+        self.keys_after(start_after)
+            .filter(|key| collect_all || key.len() == byte_size)
+            .take(limit)
+            .cloned()
+            .collect()
     }
 
-    // Matches MutableTrieImpl storage key extraction:
-    // accountStoragePrefixKey = [0x00] + secure(addr)[0..10] + addr + [0x00]
-    // storage key payload starts after the secure subkey prefix (10 bytes).
-    pub fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
-        let account_storage_prefix_key = account_storage_prefix_key(account_address);
-
+    pub fn count_keys(&self, byte_size: usize) -> usize {
+        if byte_size == i32::MAX as usize {
+            return self.entries.len();
+        }
         self.entries
             .keys()
-            .filter_map(|key| {
-                if !key.starts_with(&account_storage_prefix_key) {
-                    return None;
-                }
+            .filter(|key| key.len() == byte_size)
+            .count()
+    }
 
-                let storage_key_payload = &key[account_storage_prefix_key.len()..];
-                if storage_key_payload.len() < SECURE_KEY_SIZE {
-                    return None;
-                }
+    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.entries.keys()
+    }
 
-                Some(storage_key_payload[SECURE_KEY_SIZE..].to_vec())
-            })
-            .collect()
+    // All entries in key order.
+    pub fn iter(&self) -> EntryRange<'_> {
+        EntryRange {
+            range: self.entries.range::<[u8], _>(..),
+        }
     }
 
-    pub fn root_hash(&mut self) -> [u8; HASH_SIZE] {
-        self.materialize().root_hash
+    // All entries whose key starts with `prefix`, in key order.
+    pub fn iter_prefix(&self, prefix: &[u8]) -> EntryRange<'_> {
+        let upper = match prefix_upper_bound(prefix) {
+            Some(upper_bound) => Bound::Excluded(upper_bound),
+            None => Bound::Unbounded,
+        };
+        EntryRange {
+            range: self
+                .entries
+                .range::<[u8], _>((Bound::Included(prefix), upper.as_ref().map(Vec::as_slice))),
+        }
     }
 
-    pub fn current_root_hash(&mut self) -> [u8; HASH_SIZE] {
-        self.root_hash()
+    // `iter_prefix` resuming strictly after `start_after`.
+    pub fn iter_prefix_after(&self, prefix: &[u8], start_after: Option<&[u8]>) -> EntryRange<'_> {
+        let upper = prefix_upper_bound(prefix);
+        let lower = match start_after {
+            Some(key) if key >= prefix => Bound::Excluded(key),
+            _ => Bound::Included(prefix),
+        };
+        let range = match (lower, upper.as_deref()) {
+            // Past the end of the prefix; an excluded bound may not equal the upper one.
+            (Bound::Excluded(key), Some(upper)) if key >= upper => self
+                .entries
+                .range::<[u8], _>((Bound::Included(upper), Bound::Excluded(upper))),
+            (lower, upper) => self
+                .entries
+                .range::<[u8], _>((lower, upper.map_or(Bound::Unbounded, Bound::Excluded))),
+        };
+        EntryRange { range }
     }
 
-    pub fn key_count(&self) -> usize {
-        self.entries.len()
+    // Accounts under this trie's `KeyScheme`, with addresses decoded from their keys.
+    pub fn accounts_iter(&self) -> AccountIter<'_> {
+        AccountIter::new(&self.entries, self.key_scheme)
     }
 
-    pub fn save_to_store<T: RawStoreAdapter>(&mut self, store: &mut T) {
-        let _ = self.save_to_store_with_stats(store);
+    pub fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.iter_prefix(prefix)
+            .take(limit)
+            .map(|(key, _)| key.to_vec())
+            .collect()
     }
 
-    pub fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+    pub fn keys_after(&self, start_after: Option<&[u8]>) -> impl Iterator<Item = &Vec<u8>> {
+        let lower = match start_after {
+            Some(key) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+        self.entries
+            .range::<[u8], _>((lower, Bound::Unbounded))
+            .map(|(key, _)| key)
+    }
+
+    // Matches MutableTrieImpl storage key extraction under the trie's
+    // `KeyScheme`: the storage key follows the account storage prefix key and
+    // the secure subkey prefix.
+    pub fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
+        let scheme = self.key_scheme;
+        let account_storage_prefix_key = scheme.account_storage_prefix_key(account_address);
+
+        self.entries
+            .keys()
+            .filter_map(|key| {
+                scheme
+                    .strip_storage_prefix(&account_storage_prefix_key, key)
+                    .map(<[u8]>::to_vec)
+            })
+            .collect()
+    }
+
+    // Storage keys in the `storage_keys_packed` wire form.
+    pub fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        Arc::new(storage_keys_packed::encode(
+            &self.get_storage_keys(account_address),
+        ))
+    }
+
+    // Switches the encoding parameters. Cached hashes and persistence bookkeeping
+    // were computed under the old parameters, so they are dropped.
+    pub fn with_config(mut self, config: CodecConfig) -> Self {
+        if config != self.config {
+            self.config = config;
+            self.materialized = None;
+            self.persisted_node_hashes.clear();
+            self.persisted_value_hashes.clear();
+        }
+        self
+    }
+
+    pub fn config(&self) -> &CodecConfig {
+        &self.config
+    }
+
+    // Only changes how account and storage keys are laid out; stored entries and
+    // root hashes are unaffected.
+    pub fn with_key_scheme(mut self, key_scheme: KeyScheme) -> Self {
+        self.key_scheme = key_scheme;
+        self
+    }
+
+    pub fn key_scheme(&self) -> &KeyScheme {
+        &self.key_scheme
+    }
+
+    // Node and value hashes this trie believes are already in the store; saves
+    // skip writing them.
+    pub fn persisted_node_count(&self) -> usize {
+        self.persisted_node_hashes.len()
+    }
+
+    pub fn persisted_value_count(&self) -> usize {
+        self.persisted_value_hashes.len()
+    }
+
+    // The next save writes every node and value again, e.g. after switching to
+    // another store or pruning the current one.
+    pub fn forget_persistence_state(&mut self) {
+        self.persisted_node_hashes.clear();
+        self.persisted_value_hashes.clear();
+    }
+
+    pub fn root_hash(&mut self) -> [u8; HASH_SIZE] {
+        self.materialize().root_hash
+    }
+
+    pub fn current_root_hash(&mut self) -> [u8; HASH_SIZE] {
+        self.root_hash()
+    }
+
+    // Root hash comparison: cheap once both roots are cached, but tries holding
+    // the same entries under different `CodecConfig`s compare unequal.
+    pub fn same_state(&mut self, other: &mut Self) -> bool {
+        self.root_hash() == other.root_hash()
+    }
+
+    // The in-memory root node, children embedded down to the leaves. `None` for
+    // an empty trie.
+    pub fn root_node(&mut self) -> Option<TrieNode> {
+        self.materialize().root_node.clone()
+    }
+
+    // The serialized root message (RSKj's `toMessage`). Except for the empty
+    // trie, its keccak is `root_hash`.
+    pub fn encode_root(&mut self) -> Vec<u8> {
+        let config = self.config;
+        match &self.materialize().root_node {
+            None => empty_node_serialized(),
+            Some(node) => {
+                compute_node_metadata(node, &config)
+                    .expect("materialized node generated from entries should be encodable")
+                    .serialized
+            }
+        }
+    }
+
+    // Proves the presence or absence of `key` against `root_hash`.
+    pub fn get_proof(&mut self, key: &[u8]) -> TrieProof {
+        self.get_proofs(&[key]).pop().expect("one proof per key")
+    }
+
+    // `get_proof` for each key. Only nodes on the keys' paths are encoded, once
+    // each, from the hashes the last materialization computed.
+    pub fn get_proofs<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Vec<TrieProof> {
+        let config = self.config;
+        let materialized = self.materialize();
+        let nodes = match &materialized.root_node {
+            None => vec![Vec::new(); keys.len()],
+            Some(root) => collect_proof_nodes(root, &materialized.summaries, keys, &config)
+                .expect("materialized node generated from entries should be encodable"),
+        };
+        keys.iter()
+            .zip(nodes)
+            .map(|(key, nodes)| {
+                let key = key.as_ref();
+                TrieProof::new(key.to_vec(), nodes)
+                    .with_value(self.long_value(key).map(<[u8]>::to_vec))
+            })
+            .collect()
+    }
+
+    // The value under `key` when nodes only commit to its hash.
+    fn long_value(&self, key: &[u8]) -> Option<&[u8]> {
+        self.get_ref(key)
+            .filter(|value| value.len() > self.config.long_value_threshold)
+    }
+
+    // Proofs for an account and some of its storage slots under this trie's
+    // `KeyScheme`, sharing a single hashing pass.
+    pub fn get_account_proof<K: AsRef<[u8]>>(
+        &mut self,
+        address: &[u8],
+        storage_keys: &[K],
+    ) -> AccountProof {
+        let scheme = self.key_scheme;
+        let mut keys = vec![scheme.account_key(address)];
+        keys.extend(
+            storage_keys
+                .iter()
+                .map(|storage_key| scheme.storage_key(address, storage_key.as_ref())),
+        );
+
+        let mut proofs = self.get_proofs(&keys).into_iter();
+        let account_proof = proofs.next().expect("one proof per key");
+        let storage_proofs = storage_keys
+            .iter()
+            .zip(proofs)
+            .map(|(storage_key, proof)| StorageProof {
+                storage_key: storage_key.as_ref().to_vec(),
+                value: self.get(&proof.key),
+                proof,
+            })
+            .collect();
+        AccountProof {
+            address: address.to_vec(),
+            account_state: self.get(&account_proof.key),
+            account_proof,
+            storage_proofs,
+        }
+    }
+
+    // Size of `get_proof(key)` without building it.
+    pub fn estimate_proof_size(&mut self, key: &[u8]) -> ProofSizeEstimate {
+        self.estimate_multiproof_size(&[key])
+    }
+
+    // Size of a proof for all `keys` that lists nodes shared by their paths once.
+    pub fn estimate_multiproof_size<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> ProofSizeEstimate {
+        let mut estimate = ProofSizeEstimate::default();
+        for key in keys {
+            estimate.add_key(key.as_ref(), self.long_value(key.as_ref()));
+        }
+        let materialized = self.materialize();
+        if let Some(root) = &materialized.root_node {
+            let summaries = &materialized.summaries;
+            let mut seen = HashSet::new();
+            for key in keys {
+                for (_, index) in proof_path_nodes(root, summaries, key.as_ref()) {
+                    if seen.insert(index) {
+                        let summary = &summaries[index];
+                        estimate.add_node(summary.serialized_len, summary.serialized_zero_bytes);
+                    }
+                }
+            }
+        }
+        estimate
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Digests of the non-empty subtrees `depth` bits below the root, in key
+    // order; at depth 0 the only digest is the root's. Keys shorter than
+    // `depth` bits belong to no subtree.
+    pub fn subtree_digests(&mut self, depth: usize) -> Vec<SubtreeDigest> {
+        let config = self.config;
+        match &self.materialize().root_node {
+            None => Vec::new(),
+            Some(root) => collect_subtree_digests(root, depth, &config)
+                .expect("materialized node generated from entries should be encodable"),
+        }
+    }
+
+    // Walks every entry; long values count at their full length.
+    pub fn total_value_bytes(&self) -> u64 {
+        self.entries.values().map(|value| value.len() as u64).sum()
+    }
+
+    pub fn save_to_store<T: RawStoreAdapter>(&mut self, store: &mut T) {
+        let _ = self.save_to_store_with_stats(store);
+    }
+
+    pub fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+        self.save_with_progress(store, &mut SaveProgress::none())
+            .expect("persisting node generated from in-memory entries should not fail")
+    }
+
+    // `save_to_store_with_stats` reporting `(nodes_done, nodes_total)` every few
+    // thousand nodes and stopping at the next node once `cancel` is set. Nodes are
+    // written children first and the root last, so a cancelled save leaves the
+    // previous root intact; its written nodes are kept and skipped on the next save.
+    pub fn save_to_store_with_progress<T, F>(
+        &mut self,
+        store: &mut T,
+        mut progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<SaveStats, String>
+    where
+        T: RawStoreAdapter,
+        F: FnMut(u64, u64),
+    {
+        let total = match &self.materialize().root_node {
+            None => 1,
+            Some(node) => count_nodes(node),
+        };
+        let mut state = SaveProgress {
+            callback: Some(&mut progress),
+            cancel: Some(cancel),
+            done: 0,
+            total,
+        };
+        self.save_with_progress(store, &mut state)
+    }
+
+    fn save_with_progress<T: RawStoreAdapter>(
+        &mut self,
+        store: &mut T,
+        progress: &mut SaveProgress<'_>,
+    ) -> Result<SaveStats, String> {
         if self.entries.is_empty() {
-            let empty_node_serialized = Rskip107Codec::encode_node(
-                &TrieNode::empty(),
-                &ChildEncoding::Empty,
-                &ChildEncoding::Empty,
-                None,
-            )
-            .expect("empty trie node encoding should never fail");
+            progress.visit()?;
             let empty_hash = empty_trie_hash();
-            store.save_raw_node(&empty_hash, &empty_node_serialized);
+            store.save_raw_node(&empty_hash, &empty_node_serialized());
             self.persisted_node_hashes.insert(empty_hash);
-            self.materialized = Some(MaterializedTrie {
-                root_node: None,
-                root_hash: empty_hash,
-            });
-            return SaveStats {
+            self.materialized = Some(MaterializedTrie::empty());
+            return Ok(SaveStats {
                 nodes_visited: 1,
                 nodes_written: 1,
-                values_written: 0,
-            };
+                ..SaveStats::default()
+            });
+        }
+
+        self.materialize();
+        let materialized = self.materialized.take().expect("materialized trie");
+        let root_node = materialized
+            .root_node
+            .as_ref()
+            .expect("non-empty trie must have root node");
+
+        let persisted = persist_tree(
+            root_node,
+            store,
+            &mut self.persisted_node_hashes,
+            &mut self.persisted_value_hashes,
+            &self.config,
+            progress,
+        );
+        self.materialized = Some(materialized);
+        Ok(persisted?.1)
+    }
+
+    // Dry run of `save_to_store_with_stats`: walks the same persistence path against
+    // a counting sink and copies of the dedup sets, so the real store and the
+    // persistence bookkeeping are left untouched.
+    pub fn estimate_save(&mut self) -> SaveEstimate {
+        let mut sink = CountingStore::default();
+        if self.entries.is_empty() {
+            sink.save_raw_node(&empty_trie_hash(), &empty_node_serialized());
+            return sink.estimate;
         }
 
         let root_node = self
@@ -238,132 +1279,375 @@ impl Unitrie {
             .as_ref()
             .expect("non-empty trie must have root node")
             .clone();
-
-        let (root_metadata, save_stats) = persist_node_recursive(
+        let mut persisted_node_hashes = self.persisted_node_hashes.clone();
+        let mut persisted_value_hashes = self.persisted_value_hashes.clone();
+        persist_tree(
             &root_node,
-            store,
-            &mut self.persisted_node_hashes,
-            &mut self.persisted_value_hashes,
-            true,
+            &mut sink,
+            &mut persisted_node_hashes,
+            &mut persisted_value_hashes,
+            &self.config,
+            &mut SaveProgress::none(),
         )
         .expect("persisting node generated from in-memory entries should not fail");
-        self.materialized = Some(MaterializedTrie {
-            root_node: Some(root_node),
-            root_hash: root_metadata.hash,
-        });
-        save_stats
+        sink.estimate
     }
 
     fn materialize(&mut self) -> &MaterializedTrie {
         if self.materialized.is_none() {
             let root_node = build_root_node(&self.entries);
-            let root_hash = match root_node.as_ref() {
-                None => empty_trie_hash(),
-                Some(node) => {
-                    compute_node_metadata(node)
-                        .expect("materialized node generated from entries should be encodable")
-                        .hash
-                }
+            self.materialized = Some(
+                MaterializedTrie::build(root_node, &self.config)
+                    .expect("materialized node generated from entries should be encodable"),
+            );
+        }
+
+        self.materialized.as_ref().expect("materialized trie")
+    }
+}
+
+// Accounts in key order as `(address, account value)`, from
+// `Unitrie::accounts_iter`. Each account's storage and code keys sort right
+// after it and are skipped in one seek.
+#[derive(Debug, Clone)]
+pub struct AccountIter<'a> {
+    entries: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    key_scheme: KeyScheme,
+    upper: Option<Vec<u8>>,
+    range: btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+}
+
+impl<'a> AccountIter<'a> {
+    fn new(entries: &'a BTreeMap<Vec<u8>, Vec<u8>>, key_scheme: KeyScheme) -> Self {
+        let upper = prefix_upper_bound(key_scheme.domain_prefix);
+        let range = Self::range(entries, key_scheme.domain_prefix, upper.as_deref());
+        Self {
+            entries,
+            key_scheme,
+            upper,
+            range,
+        }
+    }
+
+    fn range(
+        entries: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+        start: &[u8],
+        upper: Option<&[u8]>,
+    ) -> btree_map::Range<'a, Vec<u8>, Vec<u8>> {
+        let upper = upper.map_or(Bound::Unbounded, Bound::Excluded);
+        entries.range::<[u8], _>((Bound::Included(start), upper))
+    }
+}
+
+impl<'a> Iterator for AccountIter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (key, value) in self.range.by_ref() {
+            let Some(address) = self.key_scheme.account_address(key) else {
+                continue;
             };
+            match prefix_upper_bound(key) {
+                Some(next) => {
+                    self.range = Self::range(self.entries, &next, self.upper.as_deref());
+                }
+                None => self.range = Self::range(self.entries, key, Some(key)),
+            }
+            return Some((address, value.as_slice()));
+        }
+        None
+    }
+}
 
-            self.materialized = Some(MaterializedTrie {
-                root_node,
-                root_hash,
-            });
+// Content equality: the same entries, regardless of caches, persistence
+// bookkeeping or encoding parameters.
+impl PartialEq for Unitrie {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl Eq for Unitrie {}
+
+impl TrieEngine for Unitrie {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(key)
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.put(key, value);
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.delete(key);
+    }
+
+    fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.delete_returning(key)
+    }
+
+    fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
+        self.delete_recursive(prefix)
+    }
+
+    fn get_value_length(&self, key: &[u8]) -> Option<usize> {
+        self.get_value_length(key)
+    }
+
+    fn get_value_hash(&self, key: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.get_value_hash(key)
+    }
+
+    fn collect_keys(&self, byte_size: usize) -> Vec<Vec<u8>> {
+        self.collect_keys(byte_size)
+    }
+
+    fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        self.collect_keys_page(byte_size, start_after, limit)
+    }
+
+    fn count_keys(&self, byte_size: usize) -> usize {
+        self.count_keys(byte_size)
+    }
+
+    fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.collect_keys_with_prefix(prefix, limit)
+    }
+
+    fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
+        self.get_storage_keys(account_address)
+    }
+
+    fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        self.get_storage_keys_packed(account_address)
+    }
+
+    fn current_root_hash(&mut self) -> [u8; HASH_SIZE] {
+        self.current_root_hash()
+    }
+
+    fn snapshot(&mut self) -> TrieSnapshot {
+        TrieSnapshot {
+            root: self.current_root_hash(),
+            key_count: self.key_count(),
         }
+    }
+}
 
-        self.materialized.as_ref().expect("materialized trie")
+impl PersistentTrieEngine for Unitrie {
+    fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+        self.save_to_store_with_stats(store)
     }
+
+    fn from_persisted_root<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Self, String> {
+        Self::from_persisted_root(root_hash, store)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CountingStore {
+    estimate: SaveEstimate,
 }
 
-fn decode_persisted_node(payload: &[u8]) -> Result<TrieNode, String> {
-    if OrchidCodec::is_orchid_message(payload) {
-        return OrchidCodec::decode_node(payload);
+impl RawStoreAdapter for CountingStore {
+    fn save_raw_node(&mut self, _hash: &[u8], serialized_node: &[u8]) {
+        self.estimate.nodes += 1;
+        self.estimate.node_bytes += serialized_node.len() as u64;
     }
 
-    Rskip107Codec::decode_node(payload)
+    fn save_raw_value(&mut self, _hash: &[u8], value: &[u8]) {
+        self.estimate.values += 1;
+        self.estimate.value_bytes += value.len() as u64;
+    }
+}
+
+pub fn encode_root(trie: &mut Unitrie) -> Vec<u8> {
+    trie.encode_root()
+}
+
+// Hashes a node whose subtree is fully in memory; nodes with hashed children
+// cannot be encoded without their subtree sizes.
+pub fn hash_node(node: &TrieNode) -> Result<[u8; HASH_SIZE], String> {
+    hash_node_with_config(node, &CodecConfig::CONSENSUS)
 }
 
-fn collect_entries_from_node<T: RawStoreAdapter>(
+pub fn hash_node_with_config(
     node: &TrieNode,
-    prefix_bits: Vec<u8>,
-    store: &mut T,
-    node_cache: &mut HashMap<[u8; HASH_SIZE], TrieNode>,
-    entries: &mut BTreeMap<Vec<u8>, Vec<u8>>,
-    persisted_node_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-    persisted_value_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-) -> Result<(), String> {
-    let mut full_bits = prefix_bits;
-    full_bits.extend_from_slice(node.shared_path.as_bits());
+    config: &CodecConfig,
+) -> Result<[u8; HASH_SIZE], String> {
+    Ok(compute_node_metadata(node, config)?.hash)
+}
 
-    if node.value.has_value() {
-        if let ValueRef::Hashed { hash, .. } = &node.value {
-            persisted_value_hashes.insert(*hash);
-        }
-        let value = resolve_node_value(&node.value, store)?;
-        entries.insert(shared_path_serializer::encode(&full_bits), value);
+pub(crate) fn empty_node_serialized() -> Vec<u8> {
+    Rskip107Codec::encode_node(
+        &TrieNode::empty(),
+        &ChildEncoding::Empty,
+        &ChildEncoding::Empty,
+        None,
+    )
+    .expect("empty trie node encoding should never fail")
+}
+
+// Values the encoding cannot represent would otherwise only fail once the trie
+// is hashed, far from the write that caused it.
+fn assert_value_fits(config: &CodecConfig, value: &[u8]) {
+    if let Err(error) = config.check_value_length(value.len()) {
+        panic!("{error}");
     }
+}
 
-    collect_child_entries(
-        &node.left,
-        0,
-        &full_bits,
-        store,
-        node_cache,
-        entries,
-        persisted_node_hashes,
-        persisted_value_hashes,
-    )?;
-    collect_child_entries(
-        &node.right,
-        1,
-        &full_bits,
-        store,
-        node_cache,
-        entries,
-        persisted_node_hashes,
-        persisted_value_hashes,
-    )?;
+pub(crate) fn decode_persisted_node(payload: &[u8], hint: CodecHint) -> Result<TrieNode, String> {
+    match hint {
+        CodecHint::Orchid => OrchidCodec::decode_node(payload),
+        CodecHint::Rskip107 => Rskip107Codec::decode_node(payload),
+        CodecHint::Auto => decode_registered(payload),
+    }
+}
+
+// A node still to be walked, with the key bits leading to it.
+struct PendingNode {
+    node: TrieNode,
+    prefix_bits: PathBits,
+    depth: usize,
+}
+
+// What a load has gathered so far; workers of a parallel load each fill their own.
+#[derive(Default)]
+struct LoadedEntries {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    persisted_node_hashes: HashSet<[u8; HASH_SIZE]>,
+    value_hashes: HashMap<Vec<u8>, [u8; HASH_SIZE]>,
+}
+
+impl LoadedEntries {
+    // Workers walk disjoint subtrees, so their keys never collide.
+    fn merge(&mut self, mut other: LoadedEntries) {
+        self.entries.append(&mut other.entries);
+        self.persisted_node_hashes
+            .extend(other.persisted_node_hashes);
+        self.value_hashes.extend(other.value_hashes);
+    }
+}
+
+fn load_root_node<T: RawStoreAdapter>(
+    root_hash: &[u8; HASH_SIZE],
+    store: &mut T,
+    budget: &LoadBudget,
+    loaded: &mut LoadedEntries,
+) -> Result<PendingNode, LoadError> {
+    let root_payload = store
+        .load_raw_node(root_hash)
+        .ok_or_else(|| "root hash not found in store adapter".to_string())?;
+    budget.charge(0, root_payload.len())?;
+    budget.verify(root_hash, &root_payload)?;
+    let node = decode_persisted_node(&root_payload, store.codec_hint())?;
+    loaded.persisted_node_hashes.insert(*root_hash);
+    Ok(PendingNode {
+        node,
+        prefix_bits: PathBits::empty(),
+        depth: 0,
+    })
+}
+
+// Depth-first walk with an explicit stack, so deep or adversarial tries cannot
+// overflow the call stack.
+fn collect_entries<T: RawStoreAdapter>(
+    mut stack: Vec<PendingNode>,
+    store: &mut T,
+    loaded: &mut LoadedEntries,
+    budget: &LoadBudget,
+) -> Result<(), LoadError> {
+    let mut node_cache = HashMap::new();
+    let mut children = Vec::new();
+    while let Some(pending) = stack.pop() {
+        expand_node(
+            pending,
+            store,
+            &mut node_cache,
+            loaded,
+            budget,
+            &mut children,
+        )?;
+        stack.append(&mut children);
+    }
     Ok(())
 }
 
-fn collect_child_entries<T: RawStoreAdapter>(
-    reference: &NodeReference,
-    implicit_bit: u8,
-    parent_bits: &[u8],
+// Records the node's value and pushes its children, right before left.
+fn expand_node<T: RawStoreAdapter>(
+    pending: PendingNode,
     store: &mut T,
     node_cache: &mut HashMap<[u8; HASH_SIZE], TrieNode>,
-    entries: &mut BTreeMap<Vec<u8>, Vec<u8>>,
-    persisted_node_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-    persisted_value_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-) -> Result<(), String> {
-    let child = match reference {
-        NodeReference::Empty => return Ok(()),
-        NodeReference::Embedded(node) => node.as_ref().clone(),
-        NodeReference::Hashed(hash) => {
-            persisted_node_hashes.insert(*hash);
-            load_node_by_hash(hash, store, node_cache)?
+    loaded: &mut LoadedEntries,
+    budget: &LoadBudget,
+    children: &mut Vec<PendingNode>,
+) -> Result<(), LoadError> {
+    let PendingNode {
+        node,
+        prefix_bits,
+        depth,
+    } = pending;
+    budget.charge(1, 0)?;
+    let mut full_bits = prefix_bits;
+    full_bits.extend_from(node.shared_path.bits());
+
+    if node.value.has_value() {
+        let key = full_bits.to_packed_bytes().to_vec();
+        if let ValueRef::Hashed { hash, length } = &node.value {
+            // Charged before the value is read, so an oversized one never is.
+            budget.charge(0, length.unwrap_or_default())?;
+            loaded.value_hashes.insert(key.clone(), *hash);
         }
-    };
+        let value = resolve_node_value(&node.value, store)?;
+        if let ValueRef::Hashed { hash, .. } = &node.value {
+            budget.verify(hash, &value)?;
+        }
+        loaded.entries.insert(key, value);
+    }
 
-    let mut child_prefix = parent_bits.to_vec();
-    child_prefix.push(implicit_bit);
-    collect_entries_from_node(
-        &child,
-        child_prefix,
-        store,
-        node_cache,
-        entries,
-        persisted_node_hashes,
-        persisted_value_hashes,
-    )
+    for (implicit_bit, reference) in [(1u8, &node.right), (0u8, &node.left)] {
+        let child = match reference {
+            NodeReference::Empty => continue,
+            NodeReference::Embedded(child) => child.as_ref().clone(),
+            NodeReference::Hashed(hash) => {
+                loaded.persisted_node_hashes.insert(*hash);
+                load_node_by_hash(hash, store, node_cache, budget)?
+            }
+        };
+        if depth >= budget.limits.max_depth {
+            return Err(LoadError::DepthLimitExceeded {
+                max_depth: budget.limits.max_depth,
+            });
+        }
+        let mut child_prefix = full_bits.clone();
+        child_prefix.append(implicit_bit);
+        children.push(PendingNode {
+            node: child,
+            prefix_bits: child_prefix,
+            depth: depth + 1,
+        });
+    }
+    Ok(())
 }
 
 fn load_node_by_hash<T: RawStoreAdapter>(
     hash: &[u8; HASH_SIZE],
     store: &mut T,
     node_cache: &mut HashMap<[u8; HASH_SIZE], TrieNode>,
-) -> Result<TrieNode, String> {
+    budget: &LoadBudget,
+) -> Result<TrieNode, LoadError> {
     if let Some(node) = node_cache.get(hash) {
         return Ok(node.clone());
     }
@@ -371,7 +1655,9 @@ fn load_node_by_hash<T: RawStoreAdapter>(
     let payload = store
         .load_raw_node(hash)
         .ok_or_else(|| format!("referenced node {} was not found in store", hex(hash)))?;
-    let node = decode_persisted_node(&payload)?;
+    budget.charge(0, payload.len())?;
+    budget.verify(hash, &payload)?;
+    let node = decode_persisted_node(&payload, store.codec_hint())?;
     node_cache.insert(*hash, node.clone());
     Ok(node)
 }
@@ -383,288 +1669,575 @@ fn resolve_node_value<T: RawStoreAdapter>(
     match value {
         ValueRef::Empty => Ok(Vec::new()),
         ValueRef::Inline(bytes) => Ok(bytes.clone()),
-        ValueRef::Hashed { hash, .. } => store
-            .load_raw_value(hash)
+        ValueRef::Hashed { hash, length } => long_value::load_value(store, hash, *length)?
             .ok_or_else(|| format!("long value {} was not found in store", hex(hash))),
     }
 }
 
-fn build_root_node(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Option<TrieNode> {
-    if entries.is_empty() {
-        return None;
+// The node tree of a fixed entry set, readable from many threads at once;
+// backs `frozen::FrozenTrie`.
+#[derive(Debug)]
+pub(crate) struct FrozenNodes {
+    materialized: MaterializedTrie,
+    config: CodecConfig,
+}
+
+impl FrozenNodes {
+    pub(crate) fn build(entries: &BTreeMap<Vec<u8>, Vec<u8>>, config: CodecConfig) -> Self {
+        Self {
+            materialized: MaterializedTrie::build(build_root_node(entries), &config)
+                .expect("frozen node generated from entries should be encodable"),
+            config,
+        }
     }
 
-    let bit_entries: Vec<(Vec<u8>, Vec<u8>)> = entries
-        .iter()
-        .map(|(key, value)| {
-            (
-                shared_path_serializer::decode(key, key.len() * 8),
-                value.clone(),
-            )
-        })
-        .collect();
+    pub(crate) fn root_hash(&self) -> [u8; HASH_SIZE] {
+        self.materialized.root_hash
+    }
+
+    pub(crate) fn proofs<K: AsRef<[u8]>>(
+        &self,
+        entries: &BTreeMap<Vec<u8>, Vec<u8>>,
+        keys: &[K],
+    ) -> Vec<TrieProof> {
+        let nodes = match &self.materialized.root_node {
+            None => vec![Vec::new(); keys.len()],
+            Some(root) => {
+                collect_proof_nodes(root, &self.materialized.summaries, keys, &self.config)
+                    .expect("frozen node generated from entries should be encodable")
+            }
+        };
+        keys.iter()
+            .zip(nodes)
+            .map(|(key, nodes)| {
+                let key = key.as_ref();
+                let long_value = entries
+                    .get(key)
+                    .filter(|value| value.len() > self.config.long_value_threshold);
+                TrieProof::new(key.to_vec(), nodes).with_value(long_value.cloned())
+            })
+            .collect()
+    }
 
-    Some(build_node(bit_entries, 0))
+    // Nothing is known to be stored, so every node and long value is written.
+    pub(crate) fn save<T: RawStoreAdapter>(&self, store: &mut T) -> SaveStats {
+        let Some(root_node) = &self.materialized.root_node else {
+            store.save_raw_node(&empty_trie_hash(), &empty_node_serialized());
+            return SaveStats {
+                nodes_visited: 1,
+                nodes_written: 1,
+                ..SaveStats::default()
+            };
+        };
+        persist_tree(
+            root_node,
+            store,
+            &mut HashSet::new(),
+            &mut HashSet::new(),
+            &self.config,
+            &mut SaveProgress::none(),
+        )
+        .map(|(_, stats)| stats)
+        .expect("persisting frozen nodes should not fail")
+    }
 }
 
-fn build_node(entries: Vec<(Vec<u8>, Vec<u8>)>, depth: usize) -> TrieNode {
-    let shared_len = longest_common_suffix_length(&entries, depth);
-    let node_depth = depth + shared_len;
+fn build_root_node(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Option<TrieNode> {
+    build_trie_node(
+        entries
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone())),
+    )
+    .expect("BTreeMap keys are strictly ascending")
+}
 
-    let shared_path_bits = entries
-        .first()
-        .map(|(bits, _)| bits[depth..node_depth].to_vec())
-        .unwrap_or_default();
+fn compute_node_metadata(node: &TrieNode, config: &CodecConfig) -> Result<NodeMetadata, String> {
+    encode_in_memory_tree(node, &mut HashingVisitor { config })
+}
 
-    let mut value: Option<Vec<u8>> = None;
-    let mut left_entries = Vec::new();
-    let mut right_entries = Vec::new();
+// Encodes `node` given its already-encoded children. Only the node's path and value
+// are read; terminality is derived from the child encodings.
+pub(crate) fn encode_node_metadata(
+    node: &TrieNode,
+    (left_encoding, left_size): (ChildEncoding, u64),
+    (right_encoding, right_size): (ChildEncoding, u64),
+    config: &CodecConfig,
+) -> Result<NodeMetadata, String> {
+    let is_terminal = !left_encoding.is_present() && !right_encoding.is_present();
+    let children_size = if is_terminal {
+        None
+    } else {
+        Some(left_size + right_size)
+    };
+
+    let serialized = Rskip107Codec::encode_node_with_config(
+        node,
+        &left_encoding,
+        &right_encoding,
+        children_size,
+        config,
+    )?;
+    let hash = keccak256(&serialized);
+    let external_value_size = if node.has_long_value_for(config) {
+        node.value_length() as u64
+    } else {
+        0
+    };
+
+    let reference_size = children_size.unwrap_or(0) + external_value_size + serialized.len() as u64;
+    let embeddable = is_terminal && serialized.len() <= config.max_embedded_node_size;
+
+    Ok(NodeMetadata {
+        hash,
+        serialized,
+        reference_size,
+        embeddable,
+    })
+}
 
-    for (bits, node_value) in entries {
-        if bits.len() == node_depth {
-            value = Some(node_value);
+pub(crate) fn compute_child_encoding(
+    reference: &NodeReference,
+    config: &CodecConfig,
+) -> Result<(ChildEncoding, u64), String> {
+    match reference {
+        NodeReference::Empty => Ok((ChildEncoding::Empty, 0)),
+        NodeReference::Embedded(child) => {
+            Ok(compute_node_metadata(child, config)?.into_child_encoding())
+        }
+        NodeReference::Hashed(_) => {
+            Err("cannot compute node metadata with unresolved hashed node reference".to_string())
+        }
+    }
+}
+
+fn collect_subtree_digests(
+    root: &TrieNode,
+    depth: usize,
+    config: &CodecConfig,
+) -> Result<Vec<SubtreeDigest>, String> {
+    let mut digests = Vec::new();
+    // Right children are pushed first so subtrees come out in key order.
+    let mut stack = vec![(root, PathBits::empty())];
+    while let Some((node, mut prefix)) = stack.pop() {
+        let path = node.shared_path.bits();
+        let start = prefix.bit_len();
+        if start + path.bit_len() >= depth {
+            let split = depth - start;
+            prefix.extend_from(&path.slice(0..split));
+            // Only the node's path and value are encoded; its children as they are.
+            let subtree_root = TrieNode::new(
+                SharedPath::from_path_bits(path.slice(split..path.bit_len())),
+                node.value.clone(),
+                NodeReference::Empty,
+                NodeReference::Empty,
+            );
+            let metadata = encode_node_metadata(
+                &subtree_root,
+                compute_child_encoding(&node.left, config)?,
+                compute_child_encoding(&node.right, config)?,
+                config,
+            )?;
+            digests.push(SubtreeDigest {
+                prefix_bits: prefix.to_bits_vec(),
+                hash: metadata.hash,
+                key_count: count_values(node),
+            });
             continue;
         }
 
-        let next_bit = bits[node_depth];
-        if next_bit == 0 {
-            left_entries.push((bits, node_value));
-        } else {
-            right_entries.push((bits, node_value));
+        prefix.extend_from(path);
+        for (bit, child) in [(1u8, &node.right), (0u8, &node.left)] {
+            if let NodeReference::Embedded(child) = child {
+                let mut child_prefix = prefix.clone();
+                child_prefix.append(bit);
+                stack.push((child, child_prefix));
+            }
         }
     }
+    Ok(digests)
+}
 
-    let left_reference = if left_entries.is_empty() {
-        NodeReference::empty()
-    } else {
-        NodeReference::embedded(build_node(left_entries, node_depth + 1))
-    };
-
-    let right_reference = if right_entries.is_empty() {
-        NodeReference::empty()
-    } else {
-        NodeReference::embedded(build_node(right_entries, node_depth + 1))
-    };
+fn count_values(node: &TrieNode) -> usize {
+    let mut count = 0usize;
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if node.has_value() {
+            count += 1;
+        }
+        for reference in [&node.left, &node.right] {
+            if let NodeReference::Embedded(child) = reference {
+                stack.push(child);
+            }
+        }
+    }
+    count
+}
 
-    TrieNode::new(
-        SharedPath::from_bits(shared_path_bits).expect("generated path bits must be binary"),
-        ValueRef::inline(value.unwrap_or_default()),
-        left_reference,
-        right_reference,
-    )
+// In-memory nodes below `node`, itself included; hashed children are not descended.
+fn count_nodes(node: &TrieNode) -> u64 {
+    let mut count = 0u64;
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        count += 1;
+        for reference in [&node.left, &node.right] {
+            if let NodeReference::Embedded(child) = reference {
+                stack.push(child);
+            }
+        }
+    }
+    count
 }
 
-fn longest_common_suffix_length(entries: &[(Vec<u8>, Vec<u8>)], depth: usize) -> usize {
-    if entries.is_empty() {
-        return 0;
+// Callbacks for `encode_in_memory_tree`.
+trait EncodeVisitor {
+    fn enter(&mut self) -> Result<(), String> {
+        Ok(())
     }
 
-    let first = &entries[0].0;
-    if depth >= first.len() {
-        return 0;
+    fn hashed_child(&mut self, hash: &[u8; HASH_SIZE]) -> Result<(ChildEncoding, u64), String>;
+
+    fn finish(
+        &mut self,
+        node: &TrieNode,
+        left: (ChildEncoding, u64),
+        right: (ChildEncoding, u64),
+        is_root: bool,
+    ) -> Result<NodeMetadata, String>;
+}
+
+// Encodes `root` children first using an explicit stack, so deep tries cannot
+// overflow the call stack.
+fn encode_in_memory_tree<V: EncodeVisitor>(
+    root: &TrieNode,
+    visitor: &mut V,
+) -> Result<NodeMetadata, String> {
+    struct Frame<'a> {
+        node: &'a TrieNode,
+        children: Vec<(ChildEncoding, u64)>,
     }
 
-    let max_common_len = entries
-        .iter()
-        .map(|(bits, _)| bits.len().saturating_sub(depth))
-        .min()
-        .unwrap_or(0);
+    visitor.enter()?;
+    let mut stack = vec![Frame {
+        node: root,
+        children: Vec::with_capacity(2),
+    }];
+    loop {
+        let frame = stack
+            .last_mut()
+            .expect("stack holds the root until it is finished");
+        if frame.children.len() < 2 {
+            let node = frame.node;
+            let reference = if frame.children.is_empty() {
+                &node.left
+            } else {
+                &node.right
+            };
+            match reference {
+                NodeReference::Empty => frame.children.push((ChildEncoding::Empty, 0)),
+                NodeReference::Hashed(hash) => {
+                    let encoding = visitor.hashed_child(hash)?;
+                    frame.children.push(encoding);
+                }
+                NodeReference::Embedded(child) => {
+                    visitor.enter()?;
+                    stack.push(Frame {
+                        node: child,
+                        children: Vec::with_capacity(2),
+                    });
+                }
+            }
+            continue;
+        }
 
-    for idx in 0..max_common_len {
-        let bit = first[depth + idx];
-        if entries.iter().any(|(bits, _)| bits[depth + idx] != bit) {
-            return idx;
+        let frame = stack.pop().expect("frame was just inspected");
+        let mut children = frame.children.into_iter();
+        let left = children.next().expect("left child encoded");
+        let right = children.next().expect("right child encoded");
+        let metadata = visitor.finish(frame.node, left, right, stack.is_empty())?;
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(metadata.into_child_encoding()),
+            None => return Ok(metadata),
         }
     }
+}
 
-    max_common_len
+// Nodes a proof of `key` lists, each with its summary index: the root, then
+// the hashed nodes visited down to the node holding the key or the point where
+// the key leaves the trie. Embedded nodes travel inside their parent.
+fn proof_path_nodes<'a>(
+    root: &'a TrieNode,
+    summaries: &[NodeSummary],
+    key: &[u8],
+) -> Vec<(&'a TrieNode, usize)> {
+    let key_bits = PathBits::from_key_bytes(key);
+    let root_index = summaries.len() - 1;
+    let mut path = Vec::new();
+    let mut node = root;
+    let mut index = root_index;
+    let mut offset = 0usize;
+    loop {
+        if index == root_index || !summaries[index].embeddable {
+            path.push((node, index));
+        }
+        let shared_path = node.shared_path.bits();
+        if !key_bits.matches_at(offset, shared_path) {
+            return path;
+        }
+        offset += shared_path.bit_len();
+        let (child, child_index) = match key_bits.get_bit(offset) {
+            None => return path,
+            Some(0) => (&node.left, summaries[index].left),
+            Some(_) => (&node.right, summaries[index].right),
+        };
+        match (child, child_index) {
+            (NodeReference::Embedded(child), Some(child_index)) => {
+                node = child;
+                index = child_index;
+                offset += 1;
+            }
+            _ => return path,
+        }
+    }
 }
 
-fn compute_node_metadata(node: &TrieNode) -> Result<NodeMetadata, String> {
-    let (left_encoding, left_size) = compute_child_encoding(&node.left)?;
-    let (right_encoding, right_size) = compute_child_encoding(&node.right)?;
+// Serialized nodes of each key's proof. A node on several paths is encoded once.
+fn collect_proof_nodes<K: AsRef<[u8]>>(
+    root: &TrieNode,
+    summaries: &[NodeSummary],
+    keys: &[K],
+    config: &CodecConfig,
+) -> Result<Vec<Vec<Vec<u8>>>, String> {
+    let mut serialized: HashMap<usize, Vec<u8>> = HashMap::new();
+    keys.iter()
+        .map(|key| {
+            let mut nodes = Vec::new();
+            for (node, index) in proof_path_nodes(root, summaries, key.as_ref()) {
+                let bytes = match serialized.entry(index) {
+                    hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                    hash_map::Entry::Vacant(entry) => entry.insert(encode_path_node(
+                        node,
+                        &summaries[index],
+                        summaries,
+                        config,
+                    )?),
+                };
+                nodes.push(bytes.clone());
+            }
+            Ok(nodes)
+        })
+        .collect()
+}
 
-    let children_size = if node.is_terminal() {
-        None
-    } else {
-        Some(left_size + right_size)
-    };
+// Encodes one node from its children's summaries. Only embedded children are
+// encoded again, and those are leaves.
+fn encode_path_node(
+    node: &TrieNode,
+    summary: &NodeSummary,
+    summaries: &[NodeSummary],
+    config: &CodecConfig,
+) -> Result<Vec<u8>, String> {
+    let left = summarized_child_encoding(&node.left, summary.left, summaries, config)?;
+    let right = summarized_child_encoding(&node.right, summary.right, summaries, config)?;
+    Ok(encode_node_metadata(node, left, right, config)?.serialized)
+}
 
-    let serialized =
-        Rskip107Codec::encode_node(node, &left_encoding, &right_encoding, children_size)?;
-    let hash = keccak256(&serialized);
-    let external_value_size = if node.has_long_value() {
-        node.value_length() as u64
-    } else {
-        0
+fn summarized_child_encoding(
+    reference: &NodeReference,
+    index: Option<usize>,
+    summaries: &[NodeSummary],
+    config: &CodecConfig,
+) -> Result<(ChildEncoding, u64), String> {
+    match (reference, index) {
+        (NodeReference::Embedded(_), Some(index)) if !summaries[index].embeddable => Ok((
+            ChildEncoding::Hashed(summaries[index].hash),
+            summaries[index].reference_size,
+        )),
+        _ => compute_child_encoding(reference, config),
+    }
+}
+
+struct HashingVisitor<'a> {
+    config: &'a CodecConfig,
+}
+
+impl EncodeVisitor for HashingVisitor<'_> {
+    fn hashed_child(&mut self, _hash: &[u8; HASH_SIZE]) -> Result<(ChildEncoding, u64), String> {
+        Err("cannot compute node metadata with unresolved hashed node reference".to_string())
+    }
+
+    fn finish(
+        &mut self,
+        node: &TrieNode,
+        left: (ChildEncoding, u64),
+        right: (ChildEncoding, u64),
+        _is_root: bool,
+    ) -> Result<NodeMetadata, String> {
+        encode_node_metadata(node, left, right, self.config)
+    }
+}
+
+fn summarize_tree(root: &TrieNode, config: &CodecConfig) -> Result<Vec<NodeSummary>, String> {
+    let mut visitor = SummaryVisitor {
+        config,
+        summaries: Vec::new(),
+        unclaimed: Vec::new(),
     };
+    encode_in_memory_tree(root, &mut visitor)?;
+    Ok(visitor.summaries)
+}
+
+// Hashes the whole tree, recording a `NodeSummary` per node.
+struct SummaryVisitor<'a> {
+    config: &'a CodecConfig,
+    summaries: Vec<NodeSummary>,
+    // Finished nodes whose parent has not finished yet, right child on top.
+    unclaimed: Vec<usize>,
+}
+
+impl EncodeVisitor for SummaryVisitor<'_> {
+    fn hashed_child(&mut self, _hash: &[u8; HASH_SIZE]) -> Result<(ChildEncoding, u64), String> {
+        Err("cannot compute node metadata with unresolved hashed node reference".to_string())
+    }
 
-    let reference_size = children_size.unwrap_or(0) + external_value_size + serialized.len() as u64;
+    fn finish(
+        &mut self,
+        node: &TrieNode,
+        left: (ChildEncoding, u64),
+        right: (ChildEncoding, u64),
+        _is_root: bool,
+    ) -> Result<NodeMetadata, String> {
+        let metadata = encode_node_metadata(node, left, right, self.config)?;
+        let right = matches!(node.right, NodeReference::Embedded(_))
+            .then(|| self.unclaimed.pop().expect("right child was summarized"));
+        let left = matches!(node.left, NodeReference::Embedded(_))
+            .then(|| self.unclaimed.pop().expect("left child was summarized"));
+        self.unclaimed.push(self.summaries.len());
+        self.summaries.push(NodeSummary {
+            hash: metadata.hash,
+            reference_size: metadata.reference_size,
+            embeddable: metadata.embeddable,
+            serialized_len: metadata.serialized.len(),
+            serialized_zero_bytes: metadata
+                .serialized
+                .iter()
+                .filter(|byte| **byte == 0)
+                .count(),
+            left,
+            right,
+        });
+        Ok(metadata)
+    }
+}
 
-    Ok(NodeMetadata {
-        hash,
-        serialized: serialized.clone(),
-        reference_size,
-        embeddable: node.is_terminal() && serialized.len() <= MAX_EMBEDDED_NODE_SIZE_IN_BYTES,
-    })
+struct PersistVisitor<'a, 'p, T> {
+    store: &'a mut T,
+    persisted_node_hashes: &'a mut HashSet<[u8; HASH_SIZE]>,
+    persisted_value_hashes: &'a mut HashSet<[u8; HASH_SIZE]>,
+    config: &'a CodecConfig,
+    progress: &'a mut SaveProgress<'p>,
+    stats: SaveStats,
 }
 
-fn compute_child_encoding(reference: &NodeReference) -> Result<(ChildEncoding, u64), String> {
-    match reference {
-        NodeReference::Empty => Ok((ChildEncoding::Empty, 0)),
-        NodeReference::Embedded(child) => {
-            let metadata = compute_node_metadata(child)?;
-            if metadata.embeddable {
-                Ok((
-                    ChildEncoding::Embedded(metadata.serialized),
-                    metadata.reference_size,
-                ))
-            } else {
-                Ok((
-                    ChildEncoding::Hashed(metadata.hash),
-                    metadata.reference_size,
-                ))
+impl<T: RawStoreAdapter> EncodeVisitor for PersistVisitor<'_, '_, T> {
+    fn enter(&mut self) -> Result<(), String> {
+        self.progress.visit()?;
+        self.stats.nodes_visited += 1;
+        Ok(())
+    }
+
+    fn hashed_child(&mut self, hash: &[u8; HASH_SIZE]) -> Result<(ChildEncoding, u64), String> {
+        self.persisted_node_hashes.insert(*hash);
+        Ok((ChildEncoding::Hashed(*hash), 0))
+    }
+
+    fn finish(
+        &mut self,
+        node: &TrieNode,
+        left: (ChildEncoding, u64),
+        right: (ChildEncoding, u64),
+        is_root: bool,
+    ) -> Result<NodeMetadata, String> {
+        let metadata = encode_node_metadata(node, left, right, self.config)?;
+
+        if let Some(inline_value) = node.value.inline_bytes() {
+            if inline_value.len() > self.config.long_value_threshold {
+                let value_hash = keccak256(inline_value);
+                // Values this trie has not written may still be in the store, e.g.
+                // the same contract code saved by another trie or block.
+                if self.persisted_value_hashes.insert(value_hash)
+                    && self.store.contains_raw_value(&value_hash) != Some(true)
+                {
+                    long_value::save_value(self.store, &value_hash, inline_value);
+                    self.stats.values_written = self.stats.values_written.saturating_add(1);
+                }
             }
         }
-        NodeReference::Hashed(_) => {
-            Err("cannot compute node metadata with unresolved hashed node reference".to_string())
+
+        if is_root || !metadata.embeddable {
+            let should_write = if is_root {
+                true
+            } else {
+                self.persisted_node_hashes.insert(metadata.hash)
+            };
+
+            if should_write {
+                self.store
+                    .save_raw_node(&metadata.hash, &metadata.serialized);
+                self.stats.nodes_written = self.stats.nodes_written.saturating_add(1);
+            }
+
+            if is_root {
+                self.persisted_node_hashes.insert(metadata.hash);
+            }
         }
+
+        Ok(metadata)
     }
 }
 
-fn persist_node_recursive<T: RawStoreAdapter>(
-    node: &TrieNode,
+fn persist_tree<T: RawStoreAdapter>(
+    root: &TrieNode,
     store: &mut T,
     persisted_node_hashes: &mut HashSet<[u8; HASH_SIZE]>,
     persisted_value_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-    is_root: bool,
+    config: &CodecConfig,
+    progress: &mut SaveProgress<'_>,
 ) -> Result<(NodeMetadata, SaveStats), String> {
-    let (left_encoding, left_size, left_stats) = persist_child_reference(
-        &node.left,
-        store,
-        persisted_node_hashes,
-        persisted_value_hashes,
-    )?;
-    let (right_encoding, right_size, right_stats) = persist_child_reference(
-        &node.right,
+    let mut visitor = PersistVisitor {
         store,
         persisted_node_hashes,
         persisted_value_hashes,
-    )?;
-
-    let children_size = if node.is_terminal() {
-        None
-    } else {
-        Some(left_size + right_size)
-    };
-    let serialized =
-        Rskip107Codec::encode_node(node, &left_encoding, &right_encoding, children_size)?;
-    let hash = keccak256(&serialized);
-    let mut save_stats = SaveStats {
-        nodes_visited: 1 + left_stats.nodes_visited + right_stats.nodes_visited,
-        nodes_written: left_stats.nodes_written + right_stats.nodes_written,
-        values_written: left_stats.values_written + right_stats.values_written,
-    };
-
-    if let Some(inline_value) = node.value.inline_bytes() {
-        if inline_value.len() > LONG_VALUE_THRESHOLD {
-            let value_hash = keccak256(inline_value);
-            if persisted_value_hashes.insert(value_hash) {
-                store.save_raw_value(&value_hash, inline_value);
-                save_stats.values_written = save_stats.values_written.saturating_add(1);
-            }
-        }
-    }
-
-    let embeddable = node.is_terminal() && serialized.len() <= MAX_EMBEDDED_NODE_SIZE_IN_BYTES;
-    if is_root || !embeddable {
-        let should_write = if is_root {
-            true
-        } else {
-            persisted_node_hashes.insert(hash)
-        };
-
-        if should_write {
-            store.save_raw_node(&hash, &serialized);
-            save_stats.nodes_written = save_stats.nodes_written.saturating_add(1);
-        }
-
-        if is_root {
-            persisted_node_hashes.insert(hash);
-        }
-    }
-
-    let external_value_size = if node.has_long_value() {
-        node.value_length() as u64
-    } else {
-        0
+        config,
+        progress,
+        stats: SaveStats::default(),
     };
-    let reference_size = children_size.unwrap_or(0) + external_value_size + serialized.len() as u64;
-
-    Ok((
-        NodeMetadata {
-            hash,
-            serialized,
-            reference_size,
-            embeddable,
-        },
-        save_stats,
-    ))
+    let metadata = encode_in_memory_tree(root, &mut visitor)?;
+    Ok((metadata, visitor.stats))
 }
 
-fn persist_child_reference<T: RawStoreAdapter>(
-    reference: &NodeReference,
-    store: &mut T,
-    persisted_node_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-    persisted_value_hashes: &mut HashSet<[u8; HASH_SIZE]>,
-) -> Result<(ChildEncoding, u64, SaveStats), String> {
-    match reference {
-        NodeReference::Empty => Ok((ChildEncoding::Empty, 0, SaveStats::default())),
-        NodeReference::Embedded(child) => {
-            let (child_metadata, child_stats) = persist_node_recursive(
-                child,
-                store,
-                persisted_node_hashes,
-                persisted_value_hashes,
-                false,
-            )?;
-            if child_metadata.embeddable {
-                Ok((
-                    ChildEncoding::Embedded(child_metadata.serialized),
-                    child_metadata.reference_size,
-                    child_stats,
-                ))
-            } else {
-                Ok((
-                    ChildEncoding::Hashed(child_metadata.hash),
-                    child_metadata.reference_size,
-                    child_stats,
-                ))
-            }
-        }
-        NodeReference::Hashed(hash) => {
-            persisted_node_hashes.insert(*hash);
-            Ok((ChildEncoding::Hashed(*hash), 0, SaveStats::default()))
+fn collect_sorted_entries<I>(entries: I) -> Result<SortedEntries, String>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    let mut sorted = SortedEntries::new();
+    for (key, value) in entries {
+        ensure_ascending(sorted.last().map(|(last, _)| last.as_slice()), &key)?;
+        if !value.is_empty() {
+            sorted.push((key, value));
         }
     }
+    Ok(sorted)
 }
 
-fn account_storage_prefix_key(account_address: &[u8]) -> Vec<u8> {
-    let mut key = Vec::with_capacity(
-        DOMAIN_PREFIX.len() + SECURE_KEY_SIZE + account_address.len() + STORAGE_PREFIX.len(),
-    );
-    key.extend_from_slice(&DOMAIN_PREFIX);
-    key.extend_from_slice(&secure_key_prefix(account_address));
-    key.extend_from_slice(account_address);
-    key.extend_from_slice(&STORAGE_PREFIX);
-    key
-}
-
-fn secure_key_prefix(key: &[u8]) -> [u8; SECURE_KEY_SIZE] {
-    let hash = keccak256(key);
-    let mut prefix = [0u8; SECURE_KEY_SIZE];
-    prefix.copy_from_slice(&hash[..SECURE_KEY_SIZE]);
-    prefix
+fn ensure_ascending(previous: Option<&[u8]>, key: &[u8]) -> Result<(), String> {
+    match previous {
+        Some(previous) if key <= previous => Err(format!(
+            "sorted entries must be strictly ascending: {} follows {}",
+            hex(key),
+            hex(previous)
+        )),
+        _ => Ok(()),
+    }
 }
 
 fn hex(bytes: &[u8]) -> String {
@@ -694,10 +2267,14 @@ fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
 
 #[cfg(test)]
 mod tests {
-    use super::Unitrie;
-    use crate::hash::empty_trie_hash;
-    use crate::store_adapter::RawStoreAdapter;
+    use super::{hash_node, LoadError, LoadLimits, SubtreeDigest, Unitrie};
+    use crate::hash::{empty_trie_hash, keccak256};
+    use crate::node_ref::CodecConfig;
+    use crate::proof::verified_get;
+    use crate::sorted_builder::compute_root_with_config;
+    use crate::store_adapter::{RawStoreAdapter, SharedStoreAdapter};
     use std::collections::HashMap;
+    use std::thread;
 
     #[derive(Default)]
     struct InMemoryStore {
@@ -721,6 +2298,10 @@ mod tests {
         fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
             self.values.insert(hash.to_vec(), value.to_vec());
         }
+
+        fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+            Some(self.values.contains_key(hash))
+        }
     }
 
     #[test]
@@ -747,6 +2328,45 @@ mod tests {
         assert_eq!(trie.get(b"acct:2:aa").as_deref(), Some(b"v3".as_slice()));
     }
 
+    #[test]
+    fn insert_and_delete_return_previous_values() {
+        let mut trie = Unitrie::new();
+        assert_eq!(trie.insert(b"k".to_vec(), b"v1".to_vec()), None);
+        assert_eq!(
+            trie.insert(b"k".to_vec(), b"v2".to_vec()),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(trie.insert(b"k".to_vec(), Vec::new()), Some(b"v2".to_vec()));
+        assert!(trie.get(b"k").is_none());
+
+        trie.put(b"k".to_vec(), b"v3".to_vec());
+        assert_eq!(trie.delete_returning(b"k"), Some(b"v3".to_vec()));
+        assert_eq!(trie.delete_returning(b"k"), None);
+
+        trie.put(b"acct:1".to_vec(), b"a".to_vec());
+        trie.put(b"acct:2".to_vec(), b"b".to_vec());
+        trie.put(b"other".to_vec(), b"c".to_vec());
+        assert_eq!(trie.delete_recursive(b"acct:"), 2);
+        assert_eq!(trie.delete_recursive(b"acct:"), 0);
+        assert_eq!(trie.delete_recursive(&[]), 1);
+    }
+
+    #[test]
+    fn update_applies_read_modify_write() {
+        let mut trie = Unitrie::new();
+        let increment =
+            |current: Option<&[u8]>| Some(vec![current.map_or(0, |value| value[0]) + 1]);
+
+        trie.update(b"counter".to_vec(), increment);
+        trie.update(b"counter".to_vec(), increment);
+        assert_eq!(trie.get(b"counter"), Some(vec![2]));
+
+        trie.update(b"counter".to_vec(), |_| None);
+        assert!(trie.get(b"counter").is_none());
+        trie.update(b"absent".to_vec(), |_| Some(Vec::new()));
+        assert_eq!(trie.key_count(), 0);
+    }
+
     #[test]
     fn delete_recursive_handles_boundary_prefixes() {
         let mut trie = Unitrie::new();
@@ -845,6 +2465,325 @@ mod tests {
         assert_eq!(all_keys.len(), 3);
     }
 
+    #[test]
+    fn collect_keys_page_walks_all_matching_keys() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..10 {
+            trie.put(vec![index], vec![0xaa]);
+            trie.put(vec![index, index], vec![0xbb]);
+        }
+
+        let mut paged = Vec::new();
+        let mut cursor: Option<Vec<u8>> = None;
+        loop {
+            let page = trie.collect_keys_page(1, cursor.as_deref(), 3);
+            if page.is_empty() {
+                break;
+            }
+            cursor = page.last().cloned();
+            paged.extend(page);
+        }
+
+        assert_eq!(paged, trie.collect_keys(1));
+        assert_eq!(trie.count_keys(1), 10);
+        assert_eq!(trie.count_keys(2), 10);
+        assert_eq!(trie.count_keys(i32::MAX as usize), 20);
+    }
+
+    #[test]
+    fn prefix_iteration_is_scoped_to_the_prefix() {
+        let mut trie = Unitrie::new();
+        trie.put(vec![0x01], vec![0x01]);
+        trie.put(vec![0x01, 0x00], vec![0x02]);
+        trie.put(vec![0x01, 0xff], vec![0x03]);
+        trie.put(vec![0x02], vec![0x04]);
+        trie.put(vec![0xff, 0xff], vec![0x05]);
+
+        assert_eq!(
+            trie.collect_keys_with_prefix(&[0x01], usize::MAX),
+            vec![vec![0x01], vec![0x01, 0x00], vec![0x01, 0xff]]
+        );
+        assert_eq!(trie.collect_keys_with_prefix(&[0x01], 1), vec![vec![0x01]]);
+        assert_eq!(
+            trie.iter_prefix(&[0xff]).collect::<Vec<_>>(),
+            vec![([0xff, 0xff].as_slice(), [0x05].as_slice())]
+        );
+        assert_eq!(trie.iter_prefix(&[]).count(), 5);
+        assert_eq!(trie.iter_prefix(&[0x03]).count(), 0);
+    }
+
+    #[test]
+    fn revert_to_root_restores_an_earlier_saved_state() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..100 {
+            trie.put(vec![index, 1], vec![index; 40]);
+        }
+        let mut store = InMemoryStore::default();
+        let earlier = trie.root_hash();
+        trie.save_to_store(&mut store);
+        let snapshot = trie.clone();
+
+        trie.put(vec![3, 1], vec![0xff; 3]);
+        trie.delete(&[4, 1]);
+        trie.save_to_store(&mut store);
+        let later = trie.root_hash();
+
+        // Both roots are saved: only the differing keys change.
+        let changes = trie.revert_to_root(&earlier, &mut store).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(trie.root_hash(), earlier);
+        assert_eq!(trie.get(&[4, 1]), snapshot.get(&[4, 1]));
+
+        // Unsaved current state falls back to a full load.
+        trie.put(vec![200], vec![1]);
+        let changes = trie.revert_to_root(&later, &mut store).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(trie.root_hash(), later);
+        assert_eq!(trie.get(&[200]), None);
+    }
+
+    #[test]
+    fn deep_tries_save_and_load_without_recursion() {
+        // Every key is a prefix of the next, so the trie is a 3000-node chain.
+        let mut trie = Unitrie::new();
+        for length in 1..=3_000usize {
+            trie.put(vec![0x5a; length], vec![1]);
+        }
+        let root = trie.root_hash();
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+
+        let loaded = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+        assert_eq!(loaded.key_count(), 3_000);
+        assert_eq!(
+            Unitrie::from_persisted_root_with_limits(
+                &root,
+                &mut store,
+                LoadLimits {
+                    max_depth: 100,
+                    ..LoadLimits::default()
+                }
+            )
+            .unwrap_err(),
+            LoadError::DepthLimitExceeded { max_depth: 100 }
+        );
+    }
+
+    #[test]
+    fn load_limits_bound_nodes_and_bytes_read() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..50 {
+            trie.put(vec![index, 0], vec![index; 200]);
+        }
+        let root = trie.root_hash();
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+
+        let load = |store: &mut InMemoryStore, limits| {
+            Unitrie::from_persisted_root_with_limits(&root, store, limits)
+                .map(|trie| trie.key_count())
+        };
+        assert_eq!(load(&mut store, LoadLimits::default()), Ok(50));
+        let few_nodes = LoadLimits {
+            max_nodes: 10,
+            ..LoadLimits::default()
+        };
+        assert_eq!(
+            load(&mut store, few_nodes),
+            Err(LoadError::NodeLimitExceeded { max_nodes: 10 })
+        );
+        // The long values alone are 10_000 bytes.
+        let few_bytes = LoadLimits {
+            max_total_bytes: 5_000,
+            ..LoadLimits::default()
+        };
+        assert_eq!(
+            load(&mut store, few_bytes),
+            Err(LoadError::ByteLimitExceeded {
+                max_total_bytes: 5_000
+            })
+        );
+    }
+
+    #[test]
+    fn verified_load_reports_the_tampered_hash() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..40 {
+            trie.put(vec![index, 0], vec![index; 20]);
+        }
+        trie.put(vec![0xff], vec![0xee; 64]);
+        let root = trie.root_hash();
+        let verified = LoadLimits {
+            verify_hashes: true,
+            ..LoadLimits::default()
+        };
+
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+        assert!(Unitrie::from_persisted_root_with_limits(&root, &mut store, verified).is_ok());
+
+        // A different long value under the same hash decodes fine unverified.
+        let value_hash = keccak256(&[0xee; 64]);
+        store.values.insert(value_hash.to_vec(), vec![0xdd; 64]);
+        assert!(Unitrie::from_persisted_root(&root, &mut store).is_ok());
+        assert_eq!(
+            Unitrie::from_persisted_root_with_limits(&root, &mut store, verified).unwrap_err(),
+            LoadError::HashMismatch {
+                expected: value_hash,
+                actual: keccak256(&[0xdd; 64]),
+            }
+        );
+
+        // Same for a node payload swapped for another valid node.
+        let mut store = InMemoryStore::default();
+        trie.forget_persistence_state();
+        trie.save_to_store(&mut store);
+        let (victim, other) = {
+            let mut hashes = store.nodes.keys().filter(|hash| hash[..] != root[..]);
+            (
+                hashes.next().unwrap().clone(),
+                hashes.next().unwrap().clone(),
+            )
+        };
+        let swapped = store.nodes[&other].clone();
+        store.nodes.insert(victim.clone(), swapped);
+        match Unitrie::from_persisted_root_with_limits(&root, &mut store, verified) {
+            Err(LoadError::HashMismatch { expected, .. }) => assert_eq!(expected[..], victim[..]),
+            other => panic!("expected a hash mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parallel_load_matches_sequential_load() {
+        struct ReadOnlyStore<'a>(&'a InMemoryStore);
+
+        impl SharedStoreAdapter for ReadOnlyStore<'_> {
+            fn load_raw_node(&self, hash: &[u8]) -> Option<Vec<u8>> {
+                self.0.nodes.get(hash).cloned()
+            }
+
+            fn load_raw_value(&self, hash: &[u8]) -> Option<Vec<u8>> {
+                self.0.values.get(hash).cloned()
+            }
+
+            fn save_raw_node(&self, _hash: &[u8], _serialized_node: &[u8]) {}
+
+            fn save_raw_value(&self, _hash: &[u8], _value: &[u8]) {}
+        }
+
+        let mut trie = Unitrie::new();
+        for index in 0u16..500 {
+            trie.put(
+                index.to_be_bytes().to_vec(),
+                vec![index as u8; 1 + index as usize % 60],
+            );
+        }
+        let root = trie.root_hash();
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+        let sequential = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+
+        for threads in [1, 4] {
+            let mut parallel = Unitrie::from_persisted_root_parallel(
+                &root,
+                &ReadOnlyStore(&store),
+                threads,
+                LoadLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(parallel.root_hash(), root);
+            assert!(parallel.iter_prefix(&[]).eq(sequential.iter_prefix(&[])));
+            assert_eq!(
+                parallel.persisted_node_count(),
+                sequential.persisted_node_count()
+            );
+            assert_eq!(
+                parallel.persisted_value_count(),
+                sequential.persisted_value_count()
+            );
+        }
+    }
+
+    #[test]
+    fn values_already_in_the_store_are_not_rewritten() {
+        let code = vec![0x60; 4_000];
+        let mut store = InMemoryStore::default();
+        let mut first = Unitrie::new();
+        first.put(vec![1], code.clone());
+        assert_eq!(first.save_to_store_with_stats(&mut store).values_written, 1);
+
+        // A different trie holding the same code only writes its nodes.
+        let mut second = Unitrie::new();
+        second.put(vec![2], code.clone());
+        second.put(vec![3], code);
+        let stats = second.save_to_store_with_stats(&mut store);
+        assert_eq!(stats.values_written, 0);
+        assert!(stats.nodes_written > 0);
+        assert_eq!(store.values.len(), 1);
+    }
+
+    #[test]
+    fn value_hashes_come_from_the_store_and_follow_writes() {
+        let mut trie = Unitrie::new();
+        trie.put(vec![1], vec![0x11; 100]);
+        trie.put(vec![2], vec![0x22; 8]);
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+
+        let mut loaded = Unitrie::from_persisted_root(&trie.root_hash(), &mut store).unwrap();
+        assert_eq!(loaded.value_hashes.lock().len(), 1);
+        assert_eq!(loaded.get_value_hash(&[1]), Some(keccak256(&[0x11; 100])));
+        assert_eq!(loaded.get_value_hash(&[2]), Some(keccak256(&[0x22; 8])));
+
+        loaded.put(vec![1], vec![0x33; 100]);
+        assert_eq!(loaded.get_value_hash(&[1]), Some(keccak256(&[0x33; 100])));
+        loaded.delete(&[1]);
+        assert_eq!(loaded.get_value_hash(&[1]), None);
+        assert!(loaded.value_hashes.lock().is_empty());
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn value_hashes_are_cached_from_several_threads() {
+        assert_send_sync::<Unitrie>();
+        let mut trie = Unitrie::new();
+        for index in 0u8..4 {
+            trie.put(vec![index], vec![index; 100]);
+        }
+        let trie = &trie;
+        thread::scope(|scope| {
+            let lookups: Vec<_> = (0u8..4)
+                .map(|index| scope.spawn(move || trie.get_value_hash(&[index])))
+                .collect();
+            for (index, lookup) in (0u8..4).zip(lookups) {
+                assert_eq!(lookup.join().unwrap(), Some(keccak256(&[index; 100])));
+            }
+        });
+        assert_eq!(trie.value_hashes.lock().len(), 4);
+    }
+
+    #[test]
+    fn root_message_and_hash_match_saved_root() {
+        let mut trie = Unitrie::new();
+        // The empty trie keeps its fixed RLP-based hash rather than hashing its
+        // message.
+        assert_eq!(trie.encode_root(), vec![0x40]);
+        assert!(trie.root_node().is_none());
+
+        for index in 0u8..16 {
+            trie.put(vec![index, 0xaa], vec![index; 48]);
+        }
+        let root = trie.root_hash();
+        let message = trie.encode_root();
+        assert_eq!(keccak256(&message), root);
+        assert_eq!(hash_node(&trie.root_node().unwrap()).unwrap(), root);
+
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+        assert_eq!(store.nodes.get(root.as_slice()), Some(&message));
+    }
+
     #[test]
     fn save_and_load_from_persisted_root_round_trip() {
         let mut trie = Unitrie::new();
@@ -866,4 +2805,154 @@ mod tests {
         );
         assert_eq!(loaded.root_hash(), root_hash);
     }
+
+    #[test]
+    fn sorted_construction_matches_incremental_puts() {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0u8..64)
+            .map(|index| (vec![index / 3, index], vec![index; 1 + index as usize]))
+            .collect();
+
+        let mut incremental = Unitrie::new();
+        for (key, value) in &entries {
+            incremental.put(key.clone(), value.clone());
+        }
+
+        let (head, tail) = entries.split_at(20);
+        let mut bulk = Unitrie::from_sorted_entries(head.to_vec()).unwrap();
+        bulk.extend_sorted(tail.to_vec()).unwrap();
+        assert_eq!(bulk.key_count(), entries.len());
+        assert_eq!(bulk.root_hash(), incremental.root_hash());
+
+        let unsorted = vec![(vec![0x02], vec![0x01]), (vec![0x01], vec![0x01])];
+        assert!(Unitrie::from_sorted_entries(unsorted.clone()).is_err());
+        assert!(bulk.extend_sorted(unsorted).is_err());
+    }
+
+    #[test]
+    fn estimate_save_matches_actual_save_without_writing() {
+        let mut trie = Unitrie::new();
+        trie.put(vec![0xaa], vec![0x01, 0x02, 0x03]);
+        trie.put(vec![0xab], vec![0x09; 40]);
+        trie.put(vec![0xac, 0x01], vec![0x07; 64]);
+
+        let estimate = trie.estimate_save();
+        let mut store = InMemoryStore::default();
+        let stats = trie.save_to_store_with_stats(&mut store);
+
+        assert_eq!(estimate.nodes, stats.nodes_written);
+        assert_eq!(estimate.values, stats.values_written);
+        assert_eq!(
+            estimate.node_bytes,
+            store
+                .nodes
+                .values()
+                .map(|node| node.len() as u64)
+                .sum::<u64>()
+        );
+        assert_eq!(
+            estimate.value_bytes,
+            store
+                .values
+                .values()
+                .map(|value| value.len() as u64)
+                .sum::<u64>()
+        );
+
+        let after_save = trie.estimate_save();
+        assert_eq!(after_save.values, 0);
+        assert_eq!(after_save.nodes, 1);
+    }
+
+    #[test]
+    fn custom_codec_config_changes_encoding_and_round_trips() {
+        let entries = vec![
+            (vec![0x01], vec![0x11; 16]),
+            (vec![0x02], vec![0x22; 4]),
+            (vec![0x03, 0x04], vec![0x33; 40]),
+        ];
+        let mut consensus = Unitrie::new();
+        for (key, value) in &entries {
+            consensus.put(key.clone(), value.clone());
+        }
+
+        let config = CodecConfig::new(8, 0).unwrap();
+        let mut custom = consensus.clone().with_config(config);
+        let root = custom.root_hash();
+        assert_ne!(root, consensus.root_hash());
+        assert_eq!(compute_root_with_config(entries, config).unwrap(), root);
+
+        let mut store = InMemoryStore::default();
+        let stats = custom.save_to_store_with_stats(&mut store);
+        assert_eq!(stats.values_written, 2);
+        assert_eq!(stats.nodes_written, 5);
+
+        let mut restored = Unitrie::from_persisted_root(&root, &mut store)
+            .unwrap()
+            .with_config(config);
+        assert_eq!(restored.root_hash(), root);
+        assert_eq!(restored.get(&[0x01]), Some(vec![0x11; 16]));
+
+        assert!(CodecConfig::new(32, 256).is_err());
+        assert!(CodecConfig::default().is_consensus());
+    }
+
+    #[test]
+    fn subtree_digests_commit_to_each_prefix() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..24 {
+            trie.put(vec![index % 3, index], vec![index; 1 + index as usize]);
+        }
+        assert_eq!(trie.subtree_digests(0)[0].hash, trie.root_hash());
+
+        let digests = trie.subtree_digests(8);
+        let prefixes: Vec<Vec<u8>> = digests.iter().map(|d| d.prefix_bits.clone()).collect();
+        assert_eq!(
+            prefixes,
+            (0u8..3)
+                .map(|byte| (0..8).rev().map(|bit| (byte >> bit) & 1).collect())
+                .collect::<Vec<Vec<u8>>>()
+        );
+        assert_eq!(digests.iter().map(|d| d.key_count).sum::<usize>(), 24);
+
+        // The digest of a byte prefix is the root of the trie of its suffixes.
+        let mut suffixes = Unitrie::new();
+        for (key, value) in trie.iter().filter(|(key, _)| key[0] == 1) {
+            suffixes.put(key[1..].to_vec(), value.to_vec());
+        }
+        assert_eq!(digests[1].hash, suffixes.root_hash());
+
+        let mut other = trie.clone();
+        other.put(vec![2, 0xff], vec![0x01]);
+        let changed: Vec<&SubtreeDigest> = other
+            .subtree_digests(8)
+            .iter()
+            .zip(&digests)
+            .filter(|(left, right)| left != right)
+            .map(|(_, right)| right)
+            .collect::<Vec<_>>();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].prefix_bits, vec![0, 0, 0, 0, 0, 0, 1, 0]);
+        assert!(Unitrie::new().subtree_digests(4).is_empty());
+    }
+
+    #[test]
+    fn proofs_list_saved_path_nodes_and_verify_for_every_key() {
+        let mut trie = Unitrie::new();
+        for index in 0u16..300 {
+            let key = keccak256(&index.to_be_bytes())[..1 + usize::from(index % 6)].to_vec();
+            trie.put(key, vec![index as u8; 1 + usize::from(index % 50)]);
+        }
+        let mut store = InMemoryStore::default();
+        trie.save_to_store(&mut store);
+        let root = trie.root_hash();
+
+        let mut keys: Vec<Vec<u8>> = trie.keys().cloned().collect();
+        keys.extend([vec![0x00], vec![0xff; 3], Vec::new()]);
+        for (key, proof) in keys.iter().zip(trie.get_proofs(&keys)) {
+            assert_eq!(verified_get(&root, key, &proof), Ok(trie.get(key)));
+            for node in &proof.nodes {
+                assert_eq!(store.nodes.get(keccak256(node).as_slice()), Some(node));
+            }
+        }
+    }
 }
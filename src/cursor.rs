@@ -0,0 +1,281 @@
+use crate::json::{from_hex, to_hex};
+use crate::node_ref::HASH_SIZE;
+use crate::varint;
+use crate::TrieRoot;
+use std::fmt;
+
+const CURSOR_VERSION: u8 = 1;
+
+// Which paged scan a cursor belongs to, with the scan's parameters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ScanKind {
+    Entries,
+    // `collect_keys` for a key byte size, `i32::MAX` meaning all keys.
+    KeysOfSize(usize),
+    // Storage keys of the account with this address.
+    StorageKeys(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CursorError {
+    Malformed(String),
+    // A page must hold at least one item.
+    ZeroLimit,
+    // The cursor was issued under another root; pages would no longer line up.
+    RootMismatch {
+        cursor: TrieRoot,
+        current: TrieRoot,
+    },
+    ScanMismatch {
+        cursor: ScanKind,
+        requested: ScanKind,
+    },
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(reason) => write!(f, "malformed cursor: {reason}"),
+            Self::ZeroLimit => f.write_str("page limit must be at least 1"),
+            Self::RootMismatch { cursor, current } => write!(
+                f,
+                "cursor was issued under root {} but the trie is at {}",
+                to_hex(cursor),
+                to_hex(current)
+            ),
+            Self::ScanMismatch { cursor, requested } => {
+                write!(f, "cursor belongs to a {cursor:?} scan, not {requested:?}")
+            }
+        }
+    }
+}
+
+// Where a paged scan stopped: the root it read, the scan and the last trie key
+// returned. Resuming under the same root continues strictly after that key, so
+// a token stored across restarts yields the same pages.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IterCursor {
+    root: TrieRoot,
+    scan: ScanKind,
+    last_key: Vec<u8>,
+}
+
+impl IterCursor {
+    pub fn root(&self) -> &TrieRoot {
+        &self.root
+    }
+
+    pub fn scan(&self) -> &ScanKind {
+        &self.scan
+    }
+
+    // The full trie key, also for storage key scans.
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    // Layout, hex encoded:
+    //   version (1) | root (32) | scan tag (1) [| varint size | address] | varint key length | key
+    pub fn to_token(&self) -> String {
+        let mut encoded = Vec::with_capacity(HASH_SIZE + self.last_key.len() + 8);
+        encoded.push(CURSOR_VERSION);
+        encoded.extend_from_slice(&self.root);
+        match &self.scan {
+            ScanKind::Entries => encoded.push(0),
+            ScanKind::KeysOfSize(size) => {
+                encoded.push(1);
+                varint::encode_into(*size as u64, &mut encoded);
+            }
+            ScanKind::StorageKeys(address) => {
+                encoded.push(2);
+                varint::encode_into(address.len() as u64, &mut encoded);
+                encoded.extend_from_slice(address);
+            }
+        }
+        varint::encode_into(self.last_key.len() as u64, &mut encoded);
+        encoded.extend_from_slice(&self.last_key);
+        to_hex(&encoded)
+    }
+
+    pub fn from_token(token: &str) -> Result<Self, CursorError> {
+        let input = from_hex(token).map_err(CursorError::Malformed)?;
+        decode(&input).map_err(CursorError::Malformed)
+    }
+
+    // The key to resume after, once the cursor is known to fit this scan.
+    pub(crate) fn resume_key(
+        &self,
+        root: &TrieRoot,
+        scan: &ScanKind,
+    ) -> Result<&[u8], CursorError> {
+        if self.scan != *scan {
+            return Err(CursorError::ScanMismatch {
+                cursor: self.scan.clone(),
+                requested: scan.clone(),
+            });
+        }
+        if self.root != *root {
+            return Err(CursorError::RootMismatch {
+                cursor: self.root,
+                current: *root,
+            });
+        }
+        Ok(&self.last_key)
+    }
+}
+
+fn decode(input: &[u8]) -> Result<IterCursor, String> {
+    let version = *input.first().ok_or("cursor is empty")?;
+    if version != CURSOR_VERSION {
+        return Err(format!("unsupported cursor version {version}"));
+    }
+    let root: TrieRoot = input
+        .get(1..1 + HASH_SIZE)
+        .ok_or("cursor root is truncated")?
+        .try_into()
+        .expect("slice has hash length");
+    let mut offset = 1 + HASH_SIZE;
+    let tag = *input.get(offset).ok_or("cursor scan is truncated")?;
+    offset += 1;
+    let scan = match tag {
+        0 => ScanKind::Entries,
+        1 => ScanKind::KeysOfSize(
+            varint::decode_from_slice_strict(input, &mut offset, u64::MAX)? as usize,
+        ),
+        2 => ScanKind::StorageKeys(read_bytes(input, &mut offset)?),
+        tag => return Err(format!("unknown cursor scan {tag}")),
+    };
+    let last_key = read_bytes(input, &mut offset)?;
+    if offset != input.len() {
+        return Err(format!(
+            "cursor has {} trailing bytes",
+            input.len() - offset
+        ));
+    }
+    Ok(IterCursor {
+        root,
+        scan,
+        last_key,
+    })
+}
+
+fn read_bytes(input: &[u8], offset: &mut usize) -> Result<Vec<u8>, String> {
+    let length = varint::decode_from_slice_strict(input, offset, u64::MAX)? as usize;
+    let bytes = offset
+        .checked_add(length)
+        .and_then(|end| input.get(*offset..end))
+        .ok_or("cursor bytes are truncated")?
+        .to_vec();
+    *offset += length;
+    Ok(bytes)
+}
+
+// One page of a scan; `next` is `None` once the scan is exhausted.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<IterCursor>,
+}
+
+pub type EntryPage = Page<(Vec<u8>, Vec<u8>)>;
+
+// Takes up to `limit` items from `(trie key, item)` pairs; callers reject a
+// zero limit, which would leave no key to resume after.
+pub(crate) fn paginate<T, I>(entries: I, limit: usize, root: TrieRoot, scan: ScanKind) -> Page<T>
+where
+    I: IntoIterator<Item = (Vec<u8>, T)>,
+{
+    let mut entries = entries.into_iter();
+    let mut items = Vec::new();
+    let mut last_key = None;
+    for (key, item) in entries.by_ref().take(limit) {
+        items.push(item);
+        last_key = Some(key);
+    }
+    let next = match last_key {
+        Some(last_key) if entries.next().is_some() => Some(IterCursor {
+            root,
+            scan,
+            last_key,
+        }),
+        _ => None,
+    };
+    Page { items, next }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CursorError, IterCursor, ScanKind};
+    use crate::key_scheme::KeyScheme;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn cursors_page_through_scans_and_survive_a_round_trip() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let address = [0x44u8; 20];
+            for slot in 0u8..10 {
+                core.put_storage_value(&address, &[slot], vec![slot + 1]);
+            }
+            core.put(KeyScheme::RSK.account_key(&address), vec![0x01]);
+
+            let mut storage_keys = Vec::new();
+            let mut cursor: Option<IterCursor> = None;
+            loop {
+                let page = core
+                    .storage_keys_page(&address, cursor.as_ref(), 3)
+                    .unwrap();
+                storage_keys.extend(page.items);
+                // Tokens stand in for a restart between requests.
+                match page.next {
+                    Some(next) => cursor = Some(IterCursor::from_token(&next.to_token()).unwrap()),
+                    None => break,
+                }
+            }
+            assert_eq!(storage_keys, core.get_storage_keys(&address));
+
+            let first = core.iter_page(None, 4).unwrap();
+            let rest = core.iter_page(first.next.as_ref(), 100).unwrap();
+            assert!(rest.next.is_none());
+            let all: Vec<_> = core
+                .iter()
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .collect();
+            assert_eq!([first.items, rest.items].concat(), all);
+
+            let keys = core
+                .collect_keys_page_with_cursor(i32::MAX as usize, None, 5)
+                .unwrap();
+            let cursor = keys.next.unwrap();
+            assert!(matches!(
+                core.iter_page(Some(&cursor), 5),
+                Err(CursorError::ScanMismatch { .. })
+            ));
+            core.put(vec![0x01], vec![0x01]);
+            assert!(matches!(
+                core.collect_keys_page_with_cursor(i32::MAX as usize, Some(&cursor), 5),
+                Err(CursorError::RootMismatch { .. })
+            ));
+            assert_eq!(core.iter_page(None, 0), Err(CursorError::ZeroLimit));
+            assert_eq!(
+                core.storage_keys_page(&address, None, 0),
+                Err(CursorError::ZeroLimit)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        assert!(IterCursor::from_token("0x").is_err());
+        assert!(IterCursor::from_token("not hex").is_err());
+        let cursor = IterCursor {
+            root: [0x07; 32],
+            scan: ScanKind::KeysOfSize(32),
+            last_key: vec![0x01, 0x02],
+        };
+        let token = cursor.to_token();
+        assert_eq!(IterCursor::from_token(&token), Ok(cursor));
+        assert!(IterCursor::from_token(&format!("{token}00")).is_err());
+        assert!(IterCursor::from_token(&token[..token.len() - 2]).is_err());
+    }
+}
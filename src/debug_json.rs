@@ -0,0 +1,262 @@
+use crate::codec_rskip107::{
+    ChildEncoding, EXTENDED_VERSION_FLAG, LEFT_EMBEDDED_FLAG, LEFT_PRESENT_FLAG, LONG_VALUE_FLAG,
+    RIGHT_EMBEDDED_FLAG, RIGHT_PRESENT_FLAG, SHARED_PREFIX_FLAG, VERSION_FLAG,
+};
+use crate::core_trie::compute_child_encoding;
+use crate::json::{from_hex, to_hex, JsonValue};
+use crate::node_ref::{
+    CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE,
+    MAX_CONSENSUS_VALUE_LENGTH,
+};
+
+// Human-readable form of a node for bug reports and diffs, e.g.
+//
+//   {
+//     "flags": "0x5c",
+//     "sharedPath": { "length": 3, "bits": "101", "hex": "0xa0" },
+//     "value": { "mode": "inline", "length": 2, "data": "0xbeef" },
+//     "left": { "kind": "hashed", "hash": "0x…" },
+//     "right": { "kind": "node", "embedded": true, "node": { … } },
+//     "childrenSize": 81
+//   }
+//
+// `flags` is the RSKIP107 flags byte the node encodes with under the consensus
+// codec; it is derived, so it is ignored when parsing. In-memory children are
+// rendered in full, `embedded` telling whether the encoding inlines them.
+impl TrieNode {
+    pub fn to_debug_json(&self) -> String {
+        node_json(self).render_pretty()
+    }
+
+    pub fn from_debug_json(input: &str) -> Result<TrieNode, String> {
+        parse_node(&JsonValue::parse(input)?)
+    }
+}
+
+fn node_json(node: &TrieNode) -> JsonValue {
+    let bits: String = node
+        .shared_path
+        .to_bits_vec()
+        .iter()
+        .map(|bit| if *bit == 0 { '0' } else { '1' })
+        .collect();
+    JsonValue::object([
+        ("flags", JsonValue::string(format!("0x{:02x}", flags(node)))),
+        (
+            "sharedPath",
+            JsonValue::object([
+                ("length", JsonValue::Number(node.shared_path.len() as u64)),
+                ("bits", JsonValue::String(bits)),
+                (
+                    "hex",
+                    JsonValue::String(to_hex(&node.shared_path.encoded())),
+                ),
+            ]),
+        ),
+        ("value", value_json(&node.value)),
+        ("left", child_json(&node.left)),
+        ("right", child_json(&node.right)),
+        (
+            "childrenSize",
+            node.children_size
+                .map_or(JsonValue::Null, JsonValue::Number),
+        ),
+    ])
+}
+
+fn value_json(value: &ValueRef) -> JsonValue {
+    match value {
+        ValueRef::Empty => JsonValue::object([("mode", JsonValue::string("empty"))]),
+        ValueRef::Inline(data) => JsonValue::object([
+            ("mode", JsonValue::string("inline")),
+            ("length", JsonValue::Number(data.len() as u64)),
+            ("data", JsonValue::String(to_hex(data))),
+        ]),
+        ValueRef::Hashed { hash, length } => JsonValue::object([
+            ("mode", JsonValue::string("hashed")),
+            ("hash", JsonValue::String(to_hex(hash))),
+            (
+                "length",
+                length.map_or(JsonValue::Null, |length| JsonValue::Number(length as u64)),
+            ),
+        ]),
+    }
+}
+
+fn child_json(child: &NodeReference) -> JsonValue {
+    match child {
+        NodeReference::Empty => JsonValue::object([("kind", JsonValue::string("empty"))]),
+        NodeReference::Hashed(hash) => JsonValue::object([
+            ("kind", JsonValue::string("hashed")),
+            ("hash", JsonValue::String(to_hex(hash))),
+        ]),
+        NodeReference::Embedded(node) => JsonValue::object([
+            ("kind", JsonValue::string("node")),
+            ("embedded", JsonValue::Bool(is_embedded(child))),
+            ("node", node_json(node)),
+        ]),
+    }
+}
+
+fn flags(node: &TrieNode) -> u8 {
+    let has_long_value = node.has_long_value();
+    let mut flags = if has_long_value && node.value_length() > MAX_CONSENSUS_VALUE_LENGTH {
+        EXTENDED_VERSION_FLAG
+    } else {
+        VERSION_FLAG
+    };
+    if has_long_value {
+        flags |= LONG_VALUE_FLAG;
+    }
+    if !node.shared_path.is_empty() {
+        flags |= SHARED_PREFIX_FLAG;
+    }
+    if !node.left.is_empty() {
+        flags |= LEFT_PRESENT_FLAG;
+    }
+    if !node.right.is_empty() {
+        flags |= RIGHT_PRESENT_FLAG;
+    }
+    if is_embedded(&node.left) {
+        flags |= LEFT_EMBEDDED_FLAG;
+    }
+    if is_embedded(&node.right) {
+        flags |= RIGHT_EMBEDDED_FLAG;
+    }
+    flags
+}
+
+// Only small terminal nodes are inlined, so deeper subtrees are never encoded.
+fn is_embedded(child: &NodeReference) -> bool {
+    match child {
+        NodeReference::Embedded(node) if node.is_terminal() => matches!(
+            compute_child_encoding(child, &CodecConfig::CONSENSUS),
+            Ok((ChildEncoding::Embedded(_), _))
+        ),
+        _ => false,
+    }
+}
+
+fn parse_node(json: &JsonValue) -> Result<TrieNode, String> {
+    let shared_path = json.expect_field("sharedPath")?;
+    let bits = shared_path
+        .expect_field("bits")?
+        .as_str()?
+        .chars()
+        .map(|bit| match bit {
+            '0' => Ok(0),
+            '1' => Ok(1),
+            other => Err(format!("shared path bit '{other}' is not 0 or 1")),
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    let length = shared_path.expect_field("length")?.as_u64()?;
+    if length != bits.len() as u64 {
+        return Err(format!(
+            "shared path length {length} does not match its {} bits",
+            bits.len()
+        ));
+    }
+
+    let children_size = match json.expect_field("childrenSize")? {
+        JsonValue::Null => None,
+        size => Some(size.as_u64()?),
+    };
+    Ok(TrieNode::new(
+        SharedPath::from_bits(bits)?,
+        parse_value(json.expect_field("value")?)?,
+        parse_child(json.expect_field("left")?)?,
+        parse_child(json.expect_field("right")?)?,
+    )
+    .with_children_size(children_size))
+}
+
+fn parse_value(json: &JsonValue) -> Result<ValueRef, String> {
+    match json.expect_field("mode")?.as_str()? {
+        "empty" => Ok(ValueRef::Empty),
+        "inline" => {
+            let data = from_hex(json.expect_field("data")?.as_str()?)?;
+            if data.is_empty() {
+                return Err("inline value must not be empty".to_string());
+            }
+            Ok(ValueRef::Inline(data))
+        }
+        "hashed" => {
+            let length = match json.expect_field("length")? {
+                JsonValue::Null => None,
+                length => Some(
+                    usize::try_from(length.as_u64()?)
+                        .map_err(|_| "value length does not fit in memory".to_string())?,
+                ),
+            };
+            Ok(ValueRef::hashed(parse_hash(json)?, length))
+        }
+        other => Err(format!(
+            "unsupported value mode '{other}', expected one of: empty, inline, hashed"
+        )),
+    }
+}
+
+fn parse_child(json: &JsonValue) -> Result<NodeReference, String> {
+    match json.expect_field("kind")?.as_str()? {
+        "empty" => Ok(NodeReference::Empty),
+        "hashed" => Ok(NodeReference::Hashed(parse_hash(json)?)),
+        "node" => Ok(NodeReference::embedded(parse_node(
+            json.expect_field("node")?,
+        )?)),
+        other => Err(format!(
+            "unsupported child kind '{other}', expected one of: empty, hashed, node"
+        )),
+    }
+}
+
+fn parse_hash(json: &JsonValue) -> Result<[u8; HASH_SIZE], String> {
+    let hash = from_hex(json.expect_field("hash")?.as_str()?)?;
+    hash.as_slice()
+        .try_into()
+        .map_err(|_| format!("hash must be {HASH_SIZE} bytes, got {}", hash.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec_rskip107::Rskip107Codec;
+    use crate::core_trie::Unitrie;
+    use crate::node_ref::TrieNode;
+
+    #[test]
+    fn debug_json_round_trips_and_reports_the_encoded_flags() {
+        let mut trie = Unitrie::new();
+        trie.put(vec![0x10, 0x01], vec![0xaa; 3]);
+        trie.put(vec![0x10, 0x02], vec![0xbb; 40]);
+        trie.put(vec![0x90], vec![0xcc; 2]);
+        for index in 0u8..6 {
+            trie.put(vec![0x10, 0x80, index], vec![index + 1; 20]);
+        }
+        let root = trie.root_node().unwrap();
+        let json = root.to_debug_json();
+        assert_eq!(TrieNode::from_debug_json(&json).unwrap(), root);
+
+        let payload = trie.encode_root();
+        let decoded = Rskip107Codec::decode_node(&payload).unwrap();
+        let json = decoded.to_debug_json();
+        assert_eq!(TrieNode::from_debug_json(&json).unwrap(), decoded);
+        assert!(json.contains(&format!("\"flags\": \"0x{:02x}\"", payload[0])));
+        assert!(json.contains("\"kind\": \"hashed\""));
+    }
+
+    #[test]
+    fn debug_json_rejects_malformed_fields() {
+        let json = TrieNode::empty().to_debug_json();
+        assert_eq!(TrieNode::from_debug_json(&json).unwrap(), TrieNode::empty());
+        for (from, to) in [
+            ("\"bits\": \"\"", "\"bits\": \"012\""),
+            ("\"length\": 0", "\"length\": 2"),
+            ("\"mode\": \"empty\"", "\"mode\": \"packed\""),
+            (
+                "\"kind\": \"empty\"",
+                "\"kind\": \"hashed\", \"hash\": \"0x00\"",
+            ),
+        ] {
+            assert!(TrieNode::from_debug_json(&json.replacen(from, to, 1)).is_err());
+        }
+    }
+}
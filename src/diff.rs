@@ -0,0 +1,365 @@
+use crate::core_api::TrieOp;
+use crate::core_trie::decode_persisted_node;
+use crate::hash::is_empty_root;
+use crate::long_value;
+use crate::node_ref::{NodeReference, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
+use crate::store_adapter::RawStoreAdapter;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EntryChange {
+    Added {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Removed {
+        key: Vec<u8>,
+        old_value: Vec<u8>,
+    },
+    Modified {
+        key: Vec<u8>,
+        old_value: Vec<u8>,
+        new_value: Vec<u8>,
+    },
+}
+
+impl EntryChange {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Added { key, .. } | Self::Removed { key, .. } | Self::Modified { key, .. } => key,
+        }
+    }
+
+    // The operation that turns the old state into the new one for this key.
+    pub fn to_op(&self) -> TrieOp {
+        match self {
+            Self::Added { key, value } => TrieOp::put(key.clone(), value.clone()),
+            Self::Modified { key, new_value, .. } => TrieOp::put(key.clone(), new_value.clone()),
+            Self::Removed { key, .. } => TrieOp::delete(key.clone()),
+        }
+    }
+}
+
+// Merge-join of two ascending entry streams, yielding changes in key order.
+pub fn diff_entries<'a, L, R>(old: L, new: R) -> Vec<EntryChange>
+where
+    L: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    R: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+{
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    let mut changes = Vec::new();
+
+    loop {
+        let ordering = match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key),
+        };
+
+        match ordering {
+            Ordering::Less => {
+                let (key, old_value) = next_entry(&mut old);
+                changes.push(EntryChange::Removed {
+                    key: key.to_vec(),
+                    old_value: old_value.to_vec(),
+                });
+            }
+            Ordering::Greater => {
+                let (key, value) = next_entry(&mut new);
+                changes.push(EntryChange::Added {
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                });
+            }
+            Ordering::Equal => {
+                let (key, old_value) = next_entry(&mut old);
+                let (_, new_value) = next_entry(&mut new);
+                if old_value != new_value {
+                    changes.push(EntryChange::Modified {
+                        key: key.to_vec(),
+                        old_value: old_value.to_vec(),
+                        new_value: new_value.to_vec(),
+                    });
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+// Diffs two persisted tries, walking them side by side one key bit at a time
+// and skipping any subtree whose hash is the same on both sides, so the cost
+// follows the size of the change rather than the size of the state.
+pub fn diff_roots<T: RawStoreAdapter>(
+    old_root: &[u8],
+    new_root: &[u8],
+    store: &mut T,
+) -> Result<Vec<EntryChange>, String> {
+    let mut changes = Vec::new();
+    if old_root == new_root {
+        return Ok(changes);
+    }
+
+    let old = root_cursor(old_root, store)?;
+    let new = root_cursor(new_root, store)?;
+    let mut walk = RootDiff {
+        store,
+        prefix: PathBits::empty(),
+        changes: &mut changes,
+    };
+    walk.diff_at(old, new)?;
+    Ok(changes)
+}
+
+// A position inside a node: `offset` bits of its shared path have been consumed.
+// `hash` is set only at the top of a hashed node.
+#[derive(Clone)]
+struct Cursor {
+    node: Rc<TrieNode>,
+    offset: usize,
+    hash: Option<[u8; HASH_SIZE]>,
+}
+
+fn root_cursor<T: RawStoreAdapter>(root: &[u8], store: &mut T) -> Result<Option<Cursor>, String> {
+    let hash: [u8; HASH_SIZE] = root
+        .try_into()
+        .map_err(|_| format!("root hash must be {HASH_SIZE} bytes"))?;
+    if is_empty_root(&hash) {
+        return Ok(None);
+    }
+    load_cursor(hash, store).map(Some)
+}
+
+fn load_cursor<T: RawStoreAdapter>(hash: [u8; HASH_SIZE], store: &mut T) -> Result<Cursor, String> {
+    let payload = store
+        .load_raw_node(&hash)
+        .ok_or_else(|| "diffed node was not found in store".to_string())?;
+    Ok(Cursor {
+        node: Rc::new(decode_persisted_node(&payload, store.codec_hint())?),
+        offset: 0,
+        hash: Some(hash),
+    })
+}
+
+struct RootDiff<'a, T> {
+    store: &'a mut T,
+    prefix: PathBits,
+    changes: &'a mut Vec<EntryChange>,
+}
+
+impl<T: RawStoreAdapter> RootDiff<'_, T> {
+    fn diff_at(&mut self, old: Option<Cursor>, new: Option<Cursor>) -> Result<(), String> {
+        if let (Some(old), Some(new)) = (&old, &new) {
+            if old.hash.is_some() && old.hash == new.hash {
+                return Ok(());
+            }
+        }
+
+        let old_value = old.as_ref().and_then(value_here);
+        let new_value = new.as_ref().and_then(value_here);
+        self.diff_values(old_value, new_value)?;
+
+        for bit in [0u8, 1] {
+            let old_child = self.child(old.as_ref(), bit)?;
+            let new_child = self.child(new.as_ref(), bit)?;
+            if old_child.is_some() || new_child.is_some() {
+                self.prefix.append(bit);
+                self.diff_at(old_child, new_child)?;
+                self.prefix.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn child(&mut self, cursor: Option<&Cursor>, bit: u8) -> Result<Option<Cursor>, String> {
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+        let path = cursor.node.shared_path.bits();
+        if cursor.offset < path.bit_len() {
+            return Ok((path.get_bit(cursor.offset) == Some(bit)).then(|| Cursor {
+                node: Rc::clone(&cursor.node),
+                offset: cursor.offset + 1,
+                hash: None,
+            }));
+        }
+
+        let reference = if bit == 0 {
+            &cursor.node.left
+        } else {
+            &cursor.node.right
+        };
+        match reference {
+            NodeReference::Empty => Ok(None),
+            NodeReference::Embedded(child) => Ok(Some(Cursor {
+                node: Rc::new(child.as_ref().clone()),
+                offset: 0,
+                hash: None,
+            })),
+            NodeReference::Hashed(hash) => load_cursor(*hash, self.store).map(Some),
+        }
+    }
+
+    fn diff_values(
+        &mut self,
+        old: Option<&ValueRef>,
+        new: Option<&ValueRef>,
+    ) -> Result<(), String> {
+        if let (
+            Some(ValueRef::Hashed { hash: old, .. }),
+            Some(ValueRef::Hashed { hash: new, .. }),
+        ) = (old, new)
+        {
+            if old == new {
+                return Ok(());
+            }
+        }
+
+        let resolved = (self.resolve(old)?, self.resolve(new)?);
+        let key = || self.prefix.to_packed_bytes().to_vec();
+        let change = match resolved {
+            (None, None) => return Ok(()),
+            (Some(old_value), None) => EntryChange::Removed {
+                key: key(),
+                old_value,
+            },
+            (None, Some(value)) => EntryChange::Added { key: key(), value },
+            (Some(old_value), Some(new_value)) if old_value != new_value => EntryChange::Modified {
+                key: key(),
+                old_value,
+                new_value,
+            },
+            _ => return Ok(()),
+        };
+        self.changes.push(change);
+        Ok(())
+    }
+
+    fn resolve(&mut self, value: Option<&ValueRef>) -> Result<Option<Vec<u8>>, String> {
+        match value {
+            None | Some(ValueRef::Empty) => Ok(None),
+            Some(ValueRef::Inline(bytes)) => Ok(Some(bytes.clone())),
+            Some(ValueRef::Hashed { hash, length }) => {
+                long_value::load_value(self.store, hash, *length)?
+                    .map(Some)
+                    .ok_or_else(|| "diffed long value was not found in store".to_string())
+            }
+        }
+    }
+}
+
+// The value stored exactly at the cursor, once the whole shared path is consumed.
+fn value_here(cursor: &Cursor) -> Option<&ValueRef> {
+    (cursor.offset == cursor.node.shared_path.len() && cursor.node.value.has_value())
+        .then_some(&cursor.node.value)
+}
+
+fn next_entry<'a, I>(entries: &mut Peekable<I>) -> (&'a [u8], &'a [u8])
+where
+    I: Iterator<Item = (&'a [u8], &'a [u8])>,
+{
+    entries.next().expect("peeked entry must exist")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_entries, diff_roots, EntryChange};
+    use crate::core_trie::Unitrie;
+    use crate::latency_store::{LatencyConfig, LatencyStore};
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn diff_reports_added_removed_and_modified_keys() {
+        let old: Vec<(&[u8], &[u8])> = vec![(b"a", b"1"), (b"b", b"2"), (b"c", b"3")];
+        let new: Vec<(&[u8], &[u8])> = vec![(b"b", b"2"), (b"c", b"4"), (b"d", b"5")];
+
+        assert_eq!(
+            diff_entries(old, new),
+            vec![
+                EntryChange::Removed {
+                    key: b"a".to_vec(),
+                    old_value: b"1".to_vec()
+                },
+                EntryChange::Modified {
+                    key: b"c".to_vec(),
+                    old_value: b"3".to_vec(),
+                    new_value: b"4".to_vec()
+                },
+                EntryChange::Added {
+                    key: b"d".to_vec(),
+                    value: b"5".to_vec()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn persisted_root_diff_matches_entry_diff_and_skips_shared_subtrees() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..200 {
+            trie.put(vec![index, 0x01], vec![index; 8]);
+            trie.put(vec![index, 0x02, 0x03], vec![index; 64]);
+        }
+        let mut store = LatencyStore::new(MemoryStore::new(), LatencyConfig::default());
+        let old_root = trie.root_hash();
+        trie.save_to_store(&mut store);
+        let old = trie.clone();
+
+        trie.delete(&[7, 0x01]);
+        trie.put(vec![90, 0x02, 0x03], vec![0xee; 70]);
+        trie.put(vec![90, 0x02], vec![0x01]);
+        trie.put(vec![250], vec![0x02; 40]);
+        let new_root = trie.root_hash();
+        trie.save_to_store(&mut store);
+
+        let reads = store.stats().reads;
+        let changes = diff_roots(&old_root, &new_root, &mut store).unwrap();
+        assert_eq!(
+            changes,
+            diff_entries(old.iter_prefix(&[]), trie.iter_prefix(&[]))
+        );
+        assert_eq!(changes.len(), 4);
+        assert!(store.stats().reads - reads < store.inner().node_count() / 4);
+        assert!(diff_roots(&new_root, &new_root, &mut store)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn snapshots_and_cores_diff_the_same_way() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.put(b"alpha".to_vec(), b"1".to_vec());
+            core.put(b"beta".to_vec(), vec![2; 50]);
+            let mut store = MemoryStore::new();
+            core.save_to_store(&mut store);
+            let before = core.snapshot();
+            let old = core.clone();
+
+            core.delete(b"alpha");
+            core.put(b"gamma".to_vec(), b"3".to_vec());
+            core.save_to_store(&mut store);
+            let after = core.snapshot();
+
+            let expected = vec![
+                EntryChange::Removed {
+                    key: b"alpha".to_vec(),
+                    old_value: b"1".to_vec(),
+                },
+                EntryChange::Added {
+                    key: b"gamma".to_vec(),
+                    value: b"3".to_vec(),
+                },
+            ];
+            assert_eq!(old.diff(&core), expected);
+            assert_eq!(before.diff(&after, &mut store).unwrap(), expected);
+        }
+    }
+}
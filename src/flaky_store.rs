@@ -0,0 +1,218 @@
+use crate::rng::SeededRng;
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+
+// Failure rates are in parts per million so schedules stay integer-only and
+// reproducible across platforms.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct FlakyConfig {
+    pub seed: u64,
+    pub drop_write_ppm: u32,
+    pub corrupt_read_ppm: u32,
+    pub fail_every_nth_read: Option<usize>,
+}
+
+impl FlakyConfig {
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    pub fn drop_writes(mut self, ppm: u32) -> Self {
+        self.drop_write_ppm = ppm;
+        self
+    }
+
+    pub fn corrupt_reads(mut self, ppm: u32) -> Self {
+        self.corrupt_read_ppm = ppm;
+        self
+    }
+
+    pub fn fail_every_nth_read(mut self, nth: usize) -> Self {
+        self.fail_every_nth_read = Some(nth);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct FlakyStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub failed_reads: usize,
+    pub corrupted_reads: usize,
+    pub dropped_writes: usize,
+}
+
+// Wraps a store and injects failures from a seeded schedule: the same seed and
+// call sequence always fail the same operations.
+#[derive(Debug)]
+pub struct FlakyStore<S> {
+    inner: S,
+    config: FlakyConfig,
+    rng: SeededRng,
+    enabled: bool,
+    stats: FlakyStats,
+}
+
+impl<S: RawStoreAdapter> FlakyStore<S> {
+    pub fn new(inner: S, config: FlakyConfig) -> Self {
+        Self {
+            inner,
+            config,
+            rng: SeededRng::new(config.seed),
+            enabled: true,
+            stats: FlakyStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> FlakyConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> FlakyStats {
+        self.stats
+    }
+
+    // Disabled stores pass every call through, e.g. while seeding fixtures.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn roll(&mut self, ppm: u32) -> bool {
+        ppm > 0 && self.rng.next() % 1_000_000 < u64::from(ppm)
+    }
+
+    fn flaky_read(&mut self, loaded: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        if !self.enabled {
+            return loaded;
+        }
+
+        self.stats.reads += 1;
+        if self
+            .config
+            .fail_every_nth_read
+            .is_some_and(|nth| nth > 0 && self.stats.reads.is_multiple_of(nth))
+        {
+            self.stats.failed_reads += 1;
+            return None;
+        }
+
+        let mut payload = loaded?;
+        if !payload.is_empty() && self.roll(self.config.corrupt_read_ppm) {
+            let index = (self.rng.next() % payload.len() as u64) as usize;
+            payload[index] ^= 0xff;
+            self.stats.corrupted_reads += 1;
+        }
+        Some(payload)
+    }
+
+    fn should_drop_write(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.stats.writes += 1;
+        let dropped = self.roll(self.config.drop_write_ppm);
+        if dropped {
+            self.stats.dropped_writes += 1;
+        }
+        dropped
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for FlakyStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let loaded = self.inner.load_raw_node(hash);
+        self.flaky_read(loaded)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let loaded = self.inner.load_raw_value(hash);
+        self.flaky_read(loaded)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        if !self.should_drop_write() {
+            self.inner.save_raw_node(hash, serialized_node);
+        }
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        if !self.should_drop_write() {
+            self.inner.save_raw_value(hash, value);
+        }
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlakyConfig, FlakyStore};
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::MemoryStore;
+
+    fn sample_trie() -> Unitrie {
+        let mut trie = Unitrie::new();
+        for index in 0u8..64 {
+            trie.put(vec![index, index], vec![index; 48]);
+        }
+        trie
+    }
+
+    #[test]
+    fn schedules_are_reproducible_for_a_seed() {
+        let run = |seed| {
+            let config = FlakyConfig::with_seed(seed)
+                .drop_writes(200_000)
+                .corrupt_reads(200_000);
+            let mut store = FlakyStore::new(MemoryStore::new(), config);
+            let mut trie = sample_trie();
+            trie.save_to_store(&mut store);
+            let root = trie.root_hash();
+            let loaded = Unitrie::from_persisted_root(&root, &mut store).is_ok();
+            (store.stats(), loaded)
+        };
+
+        let (stats, loaded) = run(7);
+        assert_eq!(run(7), (stats, loaded));
+        assert!(stats.dropped_writes > 0);
+        assert!(!loaded);
+    }
+
+    #[test]
+    fn nth_read_failures_surface_as_missing_nodes() {
+        let mut store = FlakyStore::new(
+            MemoryStore::new(),
+            FlakyConfig::with_seed(1).fail_every_nth_read(3),
+        );
+        let mut trie = sample_trie();
+        trie.save_to_store(&mut store);
+        let root = trie.root_hash();
+
+        assert!(Unitrie::from_persisted_root(&root, &mut store).is_err());
+        assert!(store.stats().failed_reads > 0);
+
+        store.set_enabled(false);
+        assert!(Unitrie::from_persisted_root(&root, &mut store).is_ok());
+    }
+}
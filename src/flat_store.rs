@@ -0,0 +1,196 @@
+use crate::core_api::TrieOp;
+use crate::core_trie::SaveStats;
+use crate::observer::TrieObserver;
+use crate::TrieRoot;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+// Persisted key -> value map mirroring the trie state at `flat_root`.
+pub trait FlatStore {
+    fn flat_get(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn flat_put(&mut self, key: &[u8], value: &[u8]);
+
+    fn flat_delete(&mut self, key: &[u8]);
+
+    fn flat_delete_prefix(&mut self, prefix: &[u8]);
+
+    fn flat_root(&mut self) -> Option<TrieRoot>;
+
+    fn set_flat_root(&mut self, root: &TrieRoot);
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFlatStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    root: Option<TrieRoot>,
+}
+
+impl MemoryFlatStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FlatStore for MemoryFlatStore {
+    fn flat_get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn flat_put(&mut self, key: &[u8], value: &[u8]) {
+        self.entries.insert(key.to_vec(), value.to_vec());
+    }
+
+    fn flat_delete(&mut self, key: &[u8]) {
+        self.entries.remove(key);
+    }
+
+    fn flat_delete_prefix(&mut self, prefix: &[u8]) {
+        self.entries.retain(|key, _| !key.starts_with(prefix));
+    }
+
+    fn flat_root(&mut self) -> Option<TrieRoot> {
+        self.root
+    }
+
+    fn set_flat_root(&mut self, root: &TrieRoot) {
+        self.root = Some(*root);
+    }
+}
+
+#[derive(Debug)]
+struct FlatState<F> {
+    flat: F,
+    pending: Vec<TrieOp>,
+}
+
+// Keeps a flat store in step with a trie: mutations are buffered as they happen
+// and written out when the trie is saved, so the flat view always matches a
+// persisted root.
+#[derive(Debug)]
+pub struct FlatLayer<F> {
+    state: Mutex<FlatState<F>>,
+}
+
+impl<F: FlatStore> FlatLayer<F> {
+    pub fn new(flat: F) -> Self {
+        Self {
+            state: Mutex::new(FlatState {
+                flat,
+                pending: Vec::new(),
+            }),
+        }
+    }
+
+    // Reads the value as of the last save.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.state().flat.flat_get(key)
+    }
+
+    pub fn root(&self) -> Option<TrieRoot> {
+        self.state().flat.flat_root()
+    }
+
+    pub fn pending_changes(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    // Replaces the flat contents with `entries`, the full state at `root`.
+    pub fn rebuild<'a, I>(&self, root: &TrieRoot, entries: I)
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let mut state = self.state();
+        state.pending.clear();
+        state.flat.flat_delete_prefix(&[]);
+        for (key, value) in entries {
+            state.flat.flat_put(key, value);
+        }
+        state.flat.set_flat_root(root);
+    }
+
+    pub fn into_inner(self) -> F {
+        self.state
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .flat
+    }
+
+    fn state(&self) -> MutexGuard<'_, FlatState<F>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<F: FlatStore + Send> TrieObserver for FlatLayer<F> {
+    fn on_put(&self, key: &[u8], value: &[u8]) {
+        self.state()
+            .pending
+            .push(TrieOp::put(key.to_vec(), value.to_vec()));
+    }
+
+    fn on_delete(&self, key: &[u8]) {
+        self.state().pending.push(TrieOp::delete(key.to_vec()));
+    }
+
+    fn on_delete_recursive(&self, prefix: &[u8]) {
+        self.state()
+            .pending
+            .push(TrieOp::delete_recursive(prefix.to_vec()));
+    }
+
+    fn on_save(&self, root: &TrieRoot, _stats: &SaveStats) {
+        let mut state = self.state();
+        let FlatState { flat, pending } = &mut *state;
+        for op in pending.drain(..) {
+            match op {
+                TrieOp::Put { key, value } => flat.flat_put(&key, &value),
+                TrieOp::Delete { key } => flat.flat_delete(&key),
+                TrieOp::DeleteRecursive { prefix } => flat.flat_delete_prefix(&prefix),
+            }
+        }
+        flat.set_flat_root(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryFlatStore;
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn flat_layer_tracks_the_last_saved_state() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(b"acct-1".to_vec(), b"one".to_vec());
+            trie.put(b"acct-2".to_vec(), b"two".to_vec());
+
+            let layer = trie.enable_flat_layer(MemoryFlatStore::new());
+            assert_eq!(layer.root(), Some(trie.current_root_hash()));
+            assert_eq!(layer.get(b"acct-1"), Some(b"one".to_vec()));
+
+            trie.put(b"acct-3".to_vec(), vec![0x33; 50]);
+            trie.delete_recursive(b"acct-1");
+            trie.put(b"acct-1".to_vec(), b"again".to_vec());
+            assert_eq!(layer.get(b"acct-3"), None);
+            assert_eq!(layer.pending_changes(), 3);
+
+            trie.save_to_store(&mut MemoryStore::new());
+            assert_eq!(layer.pending_changes(), 0);
+            assert_eq!(layer.root(), Some(trie.current_root_hash()));
+            for key in [&b"acct-1"[..], b"acct-2", b"acct-3"] {
+                assert_eq!(layer.get(key), trie.get(key));
+            }
+        }
+    }
+}
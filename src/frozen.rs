@@ -0,0 +1,153 @@
+use crate::core_trie::{FrozenNodes, SaveStats};
+use crate::node_ref::CodecConfig;
+use crate::proof::TrieProof;
+use crate::store_adapter::RawStoreAdapter;
+use crate::TrieRoot;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+// An immutable copy of a trie's state at one root, from
+// `UnitrieCore::freeze`. Clones share the copy and it is `Send + Sync`, so
+// proof serving, iteration and persistence can run on other threads while the
+// trie it came from moves on to the next block.
+#[derive(Debug, Clone)]
+pub struct FrozenTrie {
+    state: Arc<FrozenState>,
+}
+
+#[derive(Debug)]
+struct FrozenState {
+    root: TrieRoot,
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    config: CodecConfig,
+    // Built by the first proof or save, on whichever thread asks.
+    nodes: OnceLock<FrozenNodes>,
+}
+
+impl FrozenTrie {
+    pub(crate) fn new(
+        root: TrieRoot,
+        entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        config: CodecConfig,
+    ) -> Self {
+        Self {
+            state: Arc::new(FrozenState {
+                root,
+                entries,
+                config,
+                nodes: OnceLock::new(),
+            }),
+        }
+    }
+
+    pub fn root(&self) -> TrieRoot {
+        self.state.root
+    }
+
+    pub fn config(&self) -> &CodecConfig {
+        &self.state.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.state.entries.get(key).map(Vec::as_slice)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
+        self.state
+            .entries
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+        self.state
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    // Proves `key` against `root()`.
+    pub fn get_proof(&self, key: &[u8]) -> TrieProof {
+        self.get_proofs(&[key]).pop().expect("one proof per key")
+    }
+
+    pub fn get_proofs<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<TrieProof> {
+        self.nodes().proofs(&self.state.entries, keys)
+    }
+
+    // Writes every node under `root()`, including ones the source trie already
+    // saved; stores are keyed by hash, so those are just written again.
+    pub fn save_to_store<T: RawStoreAdapter>(&self, store: &mut T) -> SaveStats {
+        self.nodes().save(store)
+    }
+
+    fn nodes(&self) -> &FrozenNodes {
+        self.state.nodes.get_or_init(|| {
+            let nodes = FrozenNodes::build(&self.state.entries, self.state.config);
+            debug_assert_eq!(nodes.root_hash(), self.state.root);
+            nodes
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrozenTrie;
+    use crate::proof::verified_get;
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn frozen_state_serves_other_threads_while_the_trie_moves_on() {
+        assert_send_sync::<FrozenTrie>();
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for index in 0u8..32 {
+                core.put(vec![index % 4, index], vec![index; 48]);
+            }
+            let frozen = core.freeze();
+            let root = frozen.root();
+            assert_eq!(root, core.current_root_hash());
+
+            let worker = {
+                let frozen = frozen.clone();
+                thread::spawn(move || {
+                    let proof = frozen.get_proof(&[0x01, 0x05]);
+                    let mut store = MemoryStore::new();
+                    frozen.save_to_store(&mut store);
+                    (proof, frozen.iter_prefix(&[0x02]).count(), store)
+                })
+            };
+            core.put(vec![0x01, 0x05], vec![0x99]);
+            core.delete(&[0x02, 0x02]);
+            assert_ne!(core.current_root_hash(), root);
+
+            let (proof, prefixed, mut store) = worker.join().unwrap();
+            assert_eq!(
+                verified_get(&root, &[0x01, 0x05], &proof).unwrap(),
+                Some(vec![0x05; 48])
+            );
+            assert_eq!(prefixed, 8);
+            assert_eq!(frozen.len(), 32);
+            assert_eq!(frozen.get(&[0x02, 0x02]), Some(&[0x02; 48][..]));
+            let loaded =
+                UnitrieCore::from_persisted_root(implementation, &root, &mut store).unwrap();
+            assert_eq!(loaded.get(&[0x01, 0x05]), Some(vec![0x05; 48]));
+        }
+    }
+}
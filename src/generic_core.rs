@@ -0,0 +1,259 @@
+use crate::core_api::{PersistentTrieEngine, TrieEngine, TrieOp, TrieSnapshot};
+use crate::core_trie::SaveStats;
+#[cfg(feature = "legacy")]
+use crate::core_trie::Unitrie;
+#[cfg(feature = "next")]
+use crate::next::core_trie::NextUnitrie;
+use crate::node_ref::HASH_SIZE;
+use crate::observer::{Observers, TrieObserver};
+use crate::store_adapter::RawStoreAdapter;
+use crate::TrieRoot;
+use std::sync::Arc;
+
+// Front end over a single, statically known engine: calls are monomorphized
+// instead of going through `UnitrieCore`'s enum dispatch, so custom engines can
+// be plugged in at no cost. It offers the `TrieEngine` API, observers, and save
+// and load for a `PersistentTrieEngine`.
+//
+// `UnitrieCore` is not an alias of this type: it picks its engine at runtime
+// and keeps the features built on the concrete engines (key filters and
+// validation, the empty value policy, proofs, checkpoints, background saves).
+// It implements `TrieEngine`, so it can be wrapped here as well.
+#[derive(Debug, Clone, Default)]
+pub struct UnitrieCoreGeneric<E> {
+    engine: E,
+    observers: Observers,
+}
+
+#[cfg(feature = "legacy")]
+pub type LegacyUnitrieCore = UnitrieCoreGeneric<Unitrie>;
+
+#[cfg(feature = "next")]
+pub type NextUnitrieCore = UnitrieCoreGeneric<NextUnitrie>;
+
+impl<E: TrieEngine> UnitrieCoreGeneric<E> {
+    pub fn new(engine: E) -> Self {
+        Self {
+            engine,
+            observers: Observers::default(),
+        }
+    }
+
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    // Mutations made through the engine directly are not seen by observers.
+    pub fn engine_mut(&mut self) -> &mut E {
+        &mut self.engine
+    }
+
+    pub fn into_engine(self) -> E {
+        self.engine
+    }
+
+    pub fn add_observer(&mut self, observer: Arc<dyn TrieObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    pub fn observer_count(&self) -> usize {
+        self.observers.len()
+    }
+
+    pub fn apply_batch<I>(&mut self, ops: I)
+    where
+        I: IntoIterator<Item = TrieOp>,
+    {
+        for op in ops {
+            self.observers.notify_op(&op);
+            match op {
+                TrieOp::Put { key, value } => self.engine.put(key, value),
+                TrieOp::Delete { key } => self.engine.delete(&key),
+                TrieOp::DeleteRecursive { prefix } => {
+                    self.engine.delete_recursive(&prefix);
+                }
+            }
+        }
+    }
+}
+
+impl<E: PersistentTrieEngine> UnitrieCoreGeneric<E> {
+    pub fn from_persisted_root<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Self, String> {
+        E::from_persisted_root(root_hash, store).map(Self::new)
+    }
+
+    pub fn save_to_store<T: RawStoreAdapter>(&mut self, store: &mut T) {
+        let _ = self.save_to_store_with_stats(store);
+    }
+
+    pub fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+        let stats = self.engine.save_to_store_with_stats(store);
+        if !self.observers.is_empty() {
+            let root = self.engine.current_root_hash();
+            self.observers.notify_save(&root, &stats);
+        }
+        stats
+    }
+}
+
+impl<E: TrieEngine> TrieEngine for UnitrieCoreGeneric<E> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.engine.get(key)
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.observers.notify_put(&key, &value);
+        self.engine.put(key, value);
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.observers.notify_put(&key, &value);
+        self.engine.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.observers.notify_delete(key);
+        self.engine.delete(key);
+    }
+
+    fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.observers.notify_delete(key);
+        self.engine.delete_returning(key)
+    }
+
+    fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
+        self.observers.notify_delete_recursive(prefix);
+        self.engine.delete_recursive(prefix)
+    }
+
+    fn get_value_length(&self, key: &[u8]) -> Option<usize> {
+        self.engine.get_value_length(key)
+    }
+
+    fn get_value_hash(&self, key: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.engine.get_value_hash(key)
+    }
+
+    fn collect_keys(&self, byte_size: usize) -> Vec<Vec<u8>> {
+        self.engine.collect_keys(byte_size)
+    }
+
+    fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        self.engine.collect_keys_page(byte_size, start_after, limit)
+    }
+
+    fn count_keys(&self, byte_size: usize) -> usize {
+        self.engine.count_keys(byte_size)
+    }
+
+    fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.engine.collect_keys_with_prefix(prefix, limit)
+    }
+
+    fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
+        self.engine.get_storage_keys(account_address)
+    }
+
+    fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        self.engine.get_storage_keys_packed(account_address)
+    }
+
+    fn current_root_hash(&mut self) -> TrieRoot {
+        self.engine.current_root_hash()
+    }
+
+    fn snapshot(&mut self) -> TrieSnapshot {
+        self.engine.snapshot()
+    }
+}
+
+#[cfg(all(test, feature = "legacy", feature = "next"))]
+mod tests {
+    use super::{LegacyUnitrieCore, NextUnitrieCore, UnitrieCoreGeneric};
+    use crate::core_api::{TrieEngine, TrieOp};
+    use crate::core_trie::SaveStats;
+    use crate::observer::TrieObserver;
+    use crate::store_adapter::MemoryStore;
+    use crate::{TrieRoot, UnitrieCore, UnitrieImplementation};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct CountingObserver(AtomicUsize);
+
+    impl TrieObserver for CountingObserver {
+        fn on_put(&self, _key: &[u8], _value: &[u8]) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_delete(&self, _key: &[u8]) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Default)]
+    struct SavingObserver(Mutex<Vec<TrieRoot>>);
+
+    impl TrieObserver for SavingObserver {
+        fn on_save(&self, root: &TrieRoot, _stats: &SaveStats) {
+            self.0.lock().unwrap().push(*root);
+        }
+    }
+
+    fn exercise<E: TrieEngine>(trie: &mut E) -> [u8; 32] {
+        for index in 0u8..30 {
+            trie.put(vec![0x01, index], vec![index; 40]);
+        }
+        trie.delete(&[0x01, 3]);
+        assert_eq!(trie.insert(vec![0x01, 4], vec![0x44]), Some(vec![4; 40]));
+        trie.current_root_hash()
+    }
+
+    #[test]
+    fn generic_cores_match_the_enum_dispatched_core() {
+        let expected = exercise(&mut UnitrieCore::new(UnitrieImplementation::LegacyV1));
+
+        let observer = Arc::new(CountingObserver::default());
+        let mut legacy = LegacyUnitrieCore::default();
+        legacy.add_observer(observer.clone());
+        assert_eq!(exercise(&mut legacy), expected);
+        assert_eq!(observer.0.load(Ordering::Relaxed), 32);
+
+        assert_eq!(exercise(&mut NextUnitrieCore::default()), expected);
+        let mut wrapped = UnitrieCoreGeneric::new(UnitrieCore::new(UnitrieImplementation::Next));
+        assert_eq!(exercise(&mut wrapped), expected);
+
+        wrapped.apply_batch([TrieOp::delete_recursive(vec![0x01])]);
+        assert_eq!(wrapped.snapshot().key_count, 0);
+        assert_eq!(wrapped.into_engine().key_count(), 0);
+    }
+
+    #[test]
+    fn generic_cores_save_and_load_across_engines() {
+        let observer = Arc::new(SavingObserver::default());
+        let mut legacy = LegacyUnitrieCore::default();
+        legacy.add_observer(observer.clone());
+        let root = exercise(&mut legacy);
+        let mut store = MemoryStore::new();
+        let stats = legacy.save_to_store_with_stats(&mut store);
+        assert!(stats.nodes_written > 0);
+        assert_eq!(*observer.0.lock().unwrap(), vec![root]);
+
+        let mut loaded = NextUnitrieCore::from_persisted_root(&root, &mut store).unwrap();
+        assert_eq!(loaded.current_root_hash(), root);
+        assert_eq!(loaded.get(&[0x01, 4]), Some(vec![0x44]));
+        assert!(NextUnitrieCore::from_persisted_root(&[0xab; 32], &mut store).is_err());
+    }
+}
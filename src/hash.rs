@@ -2,6 +2,12 @@ use tiny_keccak::{Hasher, Keccak};
 
 pub const EMPTY_TRIE_RLP: [u8; 1] = [0x80];
 
+// keccak256(EMPTY_TRIE_RLP), checked against the hasher in tests.
+pub const EMPTY_TRIE_HASH: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
 pub fn keccak256(input: &[u8]) -> [u8; 32] {
     let mut hasher = Keccak::v256();
     hasher.update(input);
@@ -10,18 +16,73 @@ pub fn keccak256(input: &[u8]) -> [u8; 32] {
     output
 }
 
+// Hashes every input with one set-up hasher; the single entry point lets a
+// multi-buffer backend replace the per-input loop without touching callers.
+pub fn keccak256_batch(inputs: &[&[u8]]) -> Vec<[u8; 32]> {
+    let template = Keccak::v256();
+    inputs
+        .iter()
+        .map(|input| {
+            let mut hasher = template.clone();
+            hasher.update(input);
+            let mut output = [0u8; 32];
+            hasher.finalize(&mut output);
+            output
+        })
+        .collect()
+}
+
+// Incremental keccak-256 for inputs that arrive in pieces.
+#[derive(Clone)]
+pub struct Keccak256Hasher {
+    inner: Keccak,
+}
+
+impl Default for Keccak256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keccak256Hasher {
+    pub fn new() -> Self {
+        Self {
+            inner: Keccak::v256(),
+        }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        self.inner.update(input);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        let mut output = [0u8; 32];
+        self.inner.finalize(&mut output);
+        output
+    }
+}
+
 pub fn empty_trie_hash() -> [u8; 32] {
-    keccak256(&EMPTY_TRIE_RLP)
+    EMPTY_TRIE_HASH
+}
+
+pub fn is_empty_root(hash: &[u8]) -> bool {
+    hash == EMPTY_TRIE_HASH
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{empty_trie_hash, keccak256};
+    use super::{
+        empty_trie_hash, is_empty_root, keccak256, keccak256_batch, Keccak256Hasher, EMPTY_TRIE_RLP,
+    };
 
     #[test]
     fn empty_hash_is_stable() {
         let hash = empty_trie_hash();
-        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, keccak256(&EMPTY_TRIE_RLP));
+        assert!(is_empty_root(&hash));
+        assert!(!is_empty_root(&hash[..31]));
+        assert!(!is_empty_root(&keccak256(b"rsk")));
     }
 
     #[test]
@@ -30,4 +91,17 @@ mod tests {
         let second = keccak256(b"rsk");
         assert_eq!(first, second);
     }
+
+    #[test]
+    fn batch_and_streaming_match_single_shot() {
+        let inputs: [&[u8]; 3] = [b"", b"rsk", &[0x42; 300]];
+        let expected: Vec<[u8; 32]> = inputs.iter().map(|input| keccak256(input)).collect();
+        assert_eq!(keccak256_batch(&inputs), expected);
+
+        let mut hasher = Keccak256Hasher::new();
+        for piece in inputs[2].chunks(7) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finalize(), expected[2]);
+    }
 }
@@ -0,0 +1,135 @@
+use crate::core_trie::Unitrie;
+use crate::node_ref::HASH_SIZE;
+
+// Trie over an ordered list, keyed by the RLP encoding of each item's index,
+// as RSKj builds the transactions and receipts tries of a block header.
+// Items are stored as given, e.g. encoded transactions or receipts.
+#[derive(Debug, Clone, Default)]
+pub struct IndexTrie {
+    trie: Unitrie,
+    len: usize,
+}
+
+impl IndexTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_items<I>(items: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut index_trie = Self::new();
+        for item in items {
+            index_trie.push(item)?;
+        }
+        Ok(index_trie)
+    }
+
+    // Empty items are not representable, since an empty value deletes the key,
+    // and are refused without changing the list.
+    pub fn push(&mut self, item: Vec<u8>) -> Result<(), String> {
+        if item.is_empty() {
+            return Err(format!("index trie item {} is empty", self.len));
+        }
+        self.trie.put(index_key(self.len), item);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> Option<Vec<u8>> {
+        self.trie.get(&index_key(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The empty trie hash for an empty list.
+    pub fn root_hash(&mut self) -> [u8; HASH_SIZE] {
+        self.trie.root_hash()
+    }
+
+    pub fn into_unitrie(self) -> Unitrie {
+        self.trie
+    }
+}
+
+// Root of the trie of `items`, e.g. a block's `txTrieRoot` or `receiptTrieRoot`.
+pub fn ordered_list_root<I>(items: I) -> Result<[u8; HASH_SIZE], String>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    IndexTrie::from_items(items).map(|mut index_trie| index_trie.root_hash())
+}
+
+// RLP encoding of `index` as an integer: 0 is 0x80, 1..=0x7f encode as
+// themselves and larger values as a length byte and big-endian bytes.
+pub fn index_key(index: usize) -> Vec<u8> {
+    let bytes = (index as u64).to_be_bytes();
+    let significant = &bytes[bytes.iter().take_while(|byte| **byte == 0).count()..];
+    match significant {
+        [] => vec![0x80],
+        [byte] if *byte < 0x80 => vec![*byte],
+        _ => {
+            let mut key = Vec::with_capacity(1 + significant.len());
+            key.push(0x80 + significant.len() as u8);
+            key.extend_from_slice(significant);
+            key
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index_key, ordered_list_root, IndexTrie};
+    use crate::core_trie::Unitrie;
+    use crate::hash::empty_trie_hash;
+
+    #[test]
+    fn index_keys_are_rlp_encoded_integers() {
+        assert_eq!(index_key(0), vec![0x80]);
+        assert_eq!(index_key(1), vec![0x01]);
+        assert_eq!(index_key(0x7f), vec![0x7f]);
+        assert_eq!(index_key(0x80), vec![0x81, 0x80]);
+        assert_eq!(index_key(0x0100), vec![0x82, 0x01, 0x00]);
+        assert_eq!(index_key(0x01_0000), vec![0x83, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn list_roots_match_a_trie_keyed_by_index() {
+        assert_eq!(ordered_list_root(Vec::new()), Ok(empty_trie_hash()));
+
+        let items: Vec<Vec<u8>> = (0..300u32)
+            .map(|index| index.to_be_bytes().repeat(1 + index as usize % 20))
+            .collect();
+        let mut expected = Unitrie::new();
+        for (index, item) in items.iter().enumerate() {
+            expected.put(index_key(index), item.clone());
+        }
+
+        let mut index_trie = IndexTrie::from_items(items.clone()).unwrap();
+        assert_eq!(index_trie.len(), 300);
+        assert_eq!(index_trie.get(129), Some(items[129].clone()));
+        assert_eq!(index_trie.get(300), None);
+        assert_eq!(index_trie.root_hash(), expected.root_hash());
+        assert_eq!(ordered_list_root(items), Ok(expected.root_hash()));
+    }
+
+    #[test]
+    fn empty_items_are_refused() {
+        let mut index_trie = IndexTrie::new();
+        index_trie.push(vec![0x01]).unwrap();
+        assert!(index_trie.push(Vec::new()).is_err());
+        assert_eq!(index_trie.len(), 1);
+        index_trie.push(vec![0x02]).unwrap();
+        assert_eq!(index_trie.get(1), Some(vec![0x02]));
+
+        assert!(IndexTrie::from_items([vec![0x01], Vec::new()]).is_err());
+        assert!(ordered_list_root([Vec::new()]).is_err());
+    }
+}
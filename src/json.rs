@@ -0,0 +1,273 @@
+// Minimal JSON for the crate's own debug and interchange formats, which only
+// hold objects, arrays, unsigned integers, booleans, null and strings without
+// escapes (hex, bit strings and identifiers).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<JsonValue>),
+    // Fields in insertion order, so rendered output is stable and diffable.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub(crate) fn string(text: impl Into<String>) -> Self {
+        Self::String(text.into())
+    }
+
+    pub(crate) fn object<const N: usize>(fields: [(&str, JsonValue); N]) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn parse(input: &str) -> Result<Self, String> {
+        let mut parser = Parser { input, offset: 0 };
+        let value = parser.value(0)?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected data"));
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn field(&self, name: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // `field` for fields that must be present.
+    pub(crate) fn expect_field(&self, name: &str) -> Result<&JsonValue, String> {
+        self.field(name)
+            .ok_or_else(|| format!("JSON object has no \"{name}\""))
+    }
+
+    pub(crate) fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Self::String(text) => Ok(text),
+            other => Err(format!("expected a JSON string, got {}", other.render())),
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Result<u64, String> {
+        match self {
+            Self::Number(number) => Ok(*number),
+            other => Err(format!("expected a JSON number, got {}", other.render())),
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Result<&[JsonValue], String> {
+        match self {
+            Self::Array(items) => Ok(items),
+            other => Err(format!("expected a JSON array, got {}", other.render())),
+        }
+    }
+
+    // Compact, on one line.
+    pub(crate) fn render(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output, None, 0);
+        output
+    }
+
+    // Two-space indentation, one field or item per line.
+    pub(crate) fn render_pretty(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output, Some(2), 0);
+        output
+    }
+
+    fn write(&self, output: &mut String, indent: Option<usize>, depth: usize) {
+        let newline = |output: &mut String, depth: usize| {
+            if let Some(width) = indent {
+                output.push('\n');
+                output.push_str(&" ".repeat(width * depth));
+            }
+        };
+        match self {
+            Self::Null => output.push_str("null"),
+            Self::Bool(value) => output.push_str(if *value { "true" } else { "false" }),
+            Self::Number(number) => output.push_str(&number.to_string()),
+            Self::String(text) => {
+                output.push('"');
+                output.push_str(text);
+                output.push('"');
+            }
+            Self::Array(items) if items.is_empty() => output.push_str("[]"),
+            Self::Array(items) => {
+                output.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        output.push(',');
+                    }
+                    newline(output, depth + 1);
+                    item.write(output, indent, depth + 1);
+                }
+                newline(output, depth);
+                output.push(']');
+            }
+            Self::Object(fields) if fields.is_empty() => output.push_str("{}"),
+            Self::Object(fields) => {
+                output.push('{');
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        output.push(',');
+                    }
+                    newline(output, depth + 1);
+                    output.push('"');
+                    output.push_str(name);
+                    output.push_str(if indent.is_some() { "\": " } else { "\":" });
+                    value.write(output, indent, depth + 1);
+                }
+                newline(output, depth);
+                output.push('}');
+            }
+        }
+    }
+}
+
+// Nested documents this deep are not ours; refusing them bounds recursion.
+const MAX_DEPTH: usize = 2048;
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at offset {} of JSON input", self.offset)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.input[self.offset..];
+        let trimmed = rest.trim_start();
+        self.offset += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn consume(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.offset += expected.len_utf8();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.consume(expected) {
+            return Ok(());
+        }
+        Err(self.error(&format!("expected '{expected}'")))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<JsonValue, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("JSON nests too deeply"));
+        }
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => self.string().map(JsonValue::String),
+            Some(_) => self.literal(),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        if self.consume('}') {
+            return Ok(JsonValue::Object(fields));
+        }
+        loop {
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value(depth + 1)?));
+            if self.consume('}') {
+                return Ok(JsonValue::Object(fields));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        if self.consume(']') {
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.value(depth + 1)?);
+            if self.consume(']') {
+                return Ok(JsonValue::Array(items));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let rest = &self.input[self.offset..];
+        let length = rest
+            .find(['"', '\\'])
+            .filter(|index| rest[*index..].starts_with('"'))
+            .ok_or_else(|| self.error("unsupported string"))?;
+        self.offset += length + 1;
+        Ok(rest[..length].to_string())
+    }
+
+    fn literal(&mut self) -> Result<JsonValue, String> {
+        let rest = &self.input[self.offset..];
+        let length = rest
+            .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let value = match &rest[..length] {
+            "null" => JsonValue::Null,
+            "true" => JsonValue::Bool(true),
+            "false" => JsonValue::Bool(false),
+            number => JsonValue::Number(
+                number
+                    .parse()
+                    .map_err(|_| self.error(&format!("unsupported value `{number}`")))?,
+            ),
+        };
+        self.offset += length;
+        Ok(value)
+    }
+}
+
+// Lowercase with a 0x prefix, the form RPC and RSKj tooling use.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(2 + bytes.len() * 2);
+    output.push_str("0x");
+    for byte in bytes {
+        output.push_str(&format!("{byte:02x}"));
+    }
+    output
+}
+
+pub(crate) fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("hex string '{text}' has no 0x prefix"))?;
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return Err(format!("invalid hex string '{text}'"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&digits[index..index + 2], 16)
+                .map_err(|_| format!("invalid hex string '{text}'"))
+        })
+        .collect()
+}
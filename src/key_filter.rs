@@ -0,0 +1,213 @@
+use crate::core_api::TrieOp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// One shard per leading key byte, so deletions only invalidate the shards their
+// prefix can reach. The empty key lives in shard 0.
+const SHARD_COUNT: usize = 256;
+const BITS_PER_KEY: usize = 10;
+const HASH_COUNT: u64 = 7;
+const MIN_SHARD_WORDS: usize = 1;
+
+#[derive(Debug, Clone, Default)]
+struct FilterShard {
+    words: Vec<u64>,
+    keys: usize,
+    // Set after deletions or when inserts outgrow the sizing: the shard still
+    // never rejects a present key, it just admits more absent ones.
+    stale: bool,
+}
+
+impl FilterShard {
+    fn with_capacity(keys: usize) -> Self {
+        let words = (keys * BITS_PER_KEY).div_ceil(64).max(MIN_SHARD_WORDS);
+        Self {
+            words: vec![0; words],
+            keys: 0,
+            stale: false,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.words.len() * 64 / BITS_PER_KEY
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        let bits = self.words.len() as u64 * 64;
+        for bit in probe_bits(key, bits) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.keys += 1;
+        if self.keys > self.capacity() {
+            self.stale = true;
+        }
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        let bits = self.words.len() as u64 * 64;
+        probe_bits(key, bits).all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+// Bloom filter over trie keys. It may report absent keys as present, never the
+// other way around.
+#[derive(Debug, Clone)]
+pub struct KeyFilter {
+    shards: Vec<FilterShard>,
+}
+
+impl KeyFilter {
+    pub fn from_keys<'a, I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut by_shard: Vec<Vec<&[u8]>> = vec![Vec::new(); SHARD_COUNT];
+        for key in keys {
+            by_shard[shard_index(key)].push(key);
+        }
+
+        Self {
+            shards: by_shard
+                .into_iter()
+                .map(|keys| build_shard(&keys))
+                .collect(),
+        }
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.shards[shard_index(key)].may_contain(key)
+    }
+
+    pub fn stale_shards(&self) -> Vec<u8> {
+        (0..SHARD_COUNT)
+            .filter(|index| self.shards[*index].stale)
+            .map(|index| index as u8)
+            .collect()
+    }
+
+    pub(crate) fn record_put(&mut self, key: &[u8], value: &[u8]) {
+        if value.is_empty() {
+            self.record_delete(key);
+        } else {
+            self.shards[shard_index(key)].insert(key);
+        }
+    }
+
+    pub(crate) fn record_delete(&mut self, key: &[u8]) {
+        self.shards[shard_index(key)].stale = true;
+    }
+
+    pub(crate) fn record_delete_recursive(&mut self, prefix: &[u8]) {
+        match prefix.first() {
+            Some(first) => self.shards[*first as usize].stale = true,
+            None => self.shards.iter_mut().for_each(|shard| shard.stale = true),
+        }
+    }
+
+    pub(crate) fn record_op(&mut self, op: &TrieOp) {
+        match op {
+            TrieOp::Put { key, value } => self.record_put(key, value),
+            TrieOp::Delete { key } => self.record_delete(key),
+            TrieOp::DeleteRecursive { prefix } => self.record_delete_recursive(prefix),
+        }
+    }
+
+    // Rebuilds stale shards from the keys currently stored under them.
+    pub(crate) fn refresh<'a, F, I>(&mut self, mut keys_in_shard: F) -> usize
+    where
+        F: FnMut(u8) -> I,
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let stale = self.stale_shards();
+        for index in &stale {
+            let keys: Vec<&[u8]> = keys_in_shard(*index).into_iter().collect();
+            self.shards[*index as usize] = build_shard(&keys);
+        }
+        stale.len()
+    }
+}
+
+fn build_shard(keys: &[&[u8]]) -> FilterShard {
+    let mut shard = FilterShard::with_capacity(keys.len());
+    for key in keys {
+        shard.insert(key);
+    }
+    shard
+}
+
+fn shard_index(key: &[u8]) -> usize {
+    key.first().map_or(0, |first| *first as usize)
+}
+
+// Kirsch-Mitzenmacher double hashing over a single 64-bit hash.
+fn probe_bits(key: &[u8], bits: u64) -> impl Iterator<Item = u64> {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    (0..HASH_COUNT).map(move |index| first.wrapping_add(index.wrapping_mul(second)) % bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyFilter;
+    use crate::core_api::TrieOp;
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn filter_never_rejects_present_keys_and_rejects_most_absent_ones() {
+        let keys: Vec<Vec<u8>> = (0u16..2_000)
+            .map(|index| index.to_be_bytes().to_vec())
+            .collect();
+        let filter = KeyFilter::from_keys(keys.iter().map(Vec::as_slice));
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+
+        let false_positives = (2_000u16..12_000)
+            .filter(|index| filter.may_contain(&index.to_be_bytes()))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn deletions_only_invalidate_reachable_shards() {
+        let mut filter = KeyFilter::from_keys([&b"\x01a"[..], b"\x02b"]);
+        filter.record_delete(b"\x01a");
+        filter.record_delete_recursive(b"\x02");
+        assert_eq!(filter.stale_shards(), vec![0x01, 0x02]);
+
+        let refreshed = filter.refresh(|_| Vec::<&[u8]>::new());
+        assert_eq!(refreshed, 2);
+        assert!(filter.stale_shards().is_empty());
+        assert!(!filter.may_contain(b"\x01a"));
+    }
+
+    #[test]
+    fn filtered_trie_answers_like_an_unfiltered_one() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(Vec::new(), b"root".to_vec());
+            trie.put(vec![0x01, 0x02], b"a".to_vec());
+            trie.enable_key_filter();
+
+            trie.apply_batch([
+                TrieOp::put(vec![0x05; 40], b"b".to_vec()),
+                TrieOp::put(vec![0x06], b"c".to_vec()),
+            ]);
+            trie.delete(&[0x01, 0x02]);
+            trie.update(vec![0x07], |_| Some(b"d".to_vec()));
+
+            assert_eq!(trie.get(&[]), Some(b"root".to_vec()));
+            assert_eq!(trie.get(&[0x05; 40]), Some(b"b".to_vec()));
+            assert_eq!(trie.get(&[0x07]), Some(b"d".to_vec()));
+            assert_eq!(trie.get(&[0x01, 0x02]), None);
+            assert_eq!(trie.key_filter().unwrap().stale_shards(), vec![0x01]);
+
+            trie.save_to_store(&mut MemoryStore::new());
+            let filter = trie.key_filter().unwrap();
+            assert!(filter.stale_shards().is_empty());
+            assert!(filter.may_contain(&[]));
+            assert!(!filter.may_contain(&[0x01, 0x02]));
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::hash::keccak256;
+use crate::node_ref::HASH_SIZE;
+
+// Layout of account, code and storage keys:
+//   account key = domain prefix + secure(addr) + addr
+//   code key    = account key + code prefix
+//   storage key = account key + storage prefix + secure(key) + key
+// where secure(x) is the first `secure_key_size` bytes of keccak256(x).
+// `KeyScheme::RSK` matches RSKj's TrieKeyMapper.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct KeyScheme {
+    pub domain_prefix: &'static [u8],
+    pub storage_prefix: &'static [u8],
+    pub code_prefix: &'static [u8],
+    pub secure_key_size: usize,
+}
+
+impl KeyScheme {
+    pub const RSK: Self = Self {
+        domain_prefix: &[0x00],
+        storage_prefix: &[0x00],
+        code_prefix: &[0x80],
+        secure_key_size: 10,
+    };
+
+    // Uses RSK's code prefix; see `with_code_prefix`.
+    pub fn new(
+        domain_prefix: &'static [u8],
+        storage_prefix: &'static [u8],
+        secure_key_size: usize,
+    ) -> Result<Self, String> {
+        if secure_key_size > HASH_SIZE {
+            return Err(format!(
+                "secure key size {secure_key_size} exceeds the {HASH_SIZE}-byte hash"
+            ));
+        }
+
+        Ok(Self {
+            domain_prefix,
+            storage_prefix,
+            code_prefix: Self::RSK.code_prefix,
+            secure_key_size,
+        })
+    }
+
+    pub fn with_code_prefix(mut self, code_prefix: &'static [u8]) -> Self {
+        self.code_prefix = code_prefix;
+        self
+    }
+
+    pub fn secure_key_prefix(&self, key: &[u8]) -> Vec<u8> {
+        keccak256(key)[..self.secure_key_size].to_vec()
+    }
+
+    pub fn account_key(&self, account_address: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(
+            self.domain_prefix.len() + self.secure_key_size + account_address.len(),
+        );
+        key.extend_from_slice(self.domain_prefix);
+        key.extend_from_slice(&self.secure_key_prefix(account_address));
+        key.extend_from_slice(account_address);
+        key
+    }
+
+    // Inverse of `account_key`: the address when `key` is an account key. Keys
+    // extending an account key, such as its storage keys, do not match.
+    pub fn account_address<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let rest = key.strip_prefix(self.domain_prefix)?;
+        if rest.len() <= self.secure_key_size {
+            return None;
+        }
+        let (secure_key_prefix, address) = rest.split_at(self.secure_key_size);
+        (keccak256(address)[..self.secure_key_size] == *secure_key_prefix).then_some(address)
+    }
+
+    pub fn code_key(&self, account_address: &[u8]) -> Vec<u8> {
+        let mut key = self.account_key(account_address);
+        key.extend_from_slice(self.code_prefix);
+        key
+    }
+
+    pub fn account_storage_prefix_key(&self, account_address: &[u8]) -> Vec<u8> {
+        let mut key = self.account_key(account_address);
+        key.extend_from_slice(self.storage_prefix);
+        key
+    }
+
+    pub fn storage_key(&self, account_address: &[u8], storage_key: &[u8]) -> Vec<u8> {
+        let mut key = self.account_storage_prefix_key(account_address);
+        key.extend_from_slice(&self.secure_key_prefix(storage_key));
+        key.extend_from_slice(storage_key);
+        key
+    }
+
+    // Recovers the storage key from a full trie key that starts with
+    // `account_storage_prefix_key`; `None` when it is too short to hold one.
+    pub fn strip_storage_prefix<'a>(
+        &self,
+        account_storage_prefix_key: &[u8],
+        key: &'a [u8],
+    ) -> Option<&'a [u8]> {
+        let payload = key.strip_prefix(account_storage_prefix_key)?;
+        payload.get(self.secure_key_size..)
+    }
+
+    pub fn bridge_account_key(&self) -> Vec<u8> {
+        self.account_key(&BRIDGE_ADDRESS)
+    }
+
+    pub fn remasc_account_key(&self) -> Vec<u8> {
+        self.account_key(&REMASC_ADDRESS)
+    }
+
+    pub fn bridge_storage_key(&self, name: &str) -> Result<Vec<u8>, String> {
+        let slot = named_storage_slot(name)?;
+        Ok(self.storage_key(&BRIDGE_ADDRESS, storage_slot_bytes(&slot)))
+    }
+
+    pub fn remasc_storage_key(&self, name: &str) -> Result<Vec<u8>, String> {
+        let slot = named_storage_slot(name)?;
+        Ok(self.storage_key(&REMASC_ADDRESS, storage_slot_bytes(&slot)))
+    }
+}
+
+// Precompiled contracts keeping state in the trie. Their accounts and storage
+// use the regular secure layout; what sets them apart is that their slots are
+// names rather than hashes, see `named_storage_slot`.
+pub const BRIDGE_ADDRESS: [u8; 20] = precompile_address(0x06);
+pub const REMASC_ADDRESS: [u8; 20] = precompile_address(0x08);
+
+const fn precompile_address(index: u8) -> [u8; 20] {
+    let mut address = [0u8; 20];
+    address[17] = 0x01;
+    address[19] = index;
+    address
+}
+
+// RSKj's `DataWord.fromString`: the UTF-8 name right-aligned in a 32-byte word,
+// as the bridge and REMASC name their slots.
+pub fn named_storage_slot(name: &str) -> Result<[u8; HASH_SIZE], String> {
+    let bytes = name.as_bytes();
+    if bytes.len() > HASH_SIZE {
+        return Err(format!(
+            "storage slot name '{name}' is longer than {HASH_SIZE} bytes"
+        ));
+    }
+    let mut slot = [0u8; HASH_SIZE];
+    slot[HASH_SIZE - bytes.len()..].copy_from_slice(bytes);
+    Ok(slot)
+}
+
+// RSKj's `DataWord.getByteArrayForStorage`: the word without its leading zero
+// bytes, one zero byte for a zero word. RSKj keys storage slots by this form.
+pub fn storage_slot_bytes(slot: &[u8]) -> &[u8] {
+    let start = slot
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(slot.len().saturating_sub(1));
+    &slot[start..]
+}
+
+// RSKj's `DataWord.fromLongString`, for names that do not fit in a word.
+pub fn hashed_storage_slot(name: &str) -> [u8; HASH_SIZE] {
+    keccak256(name.as_bytes())
+}
+
+impl Default for KeyScheme {
+    fn default() -> Self {
+        Self::RSK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        hashed_storage_slot, named_storage_slot, storage_slot_bytes, KeyScheme, BRIDGE_ADDRESS,
+        REMASC_ADDRESS,
+    };
+    use crate::core_trie::Unitrie;
+    use crate::hash::keccak256;
+    use crate::json::to_hex;
+
+    #[test]
+    fn rsk_scheme_matches_rskj_key_layout() {
+        let account = [0x11u8; 20];
+        let storage_key = [0x22u8; 32];
+
+        let mut expected = vec![0x00];
+        expected.extend_from_slice(&keccak256(&account)[..10]);
+        expected.extend_from_slice(&account);
+        expected.push(0x00);
+        expected.extend_from_slice(&keccak256(&storage_key)[..10]);
+        expected.extend_from_slice(&storage_key);
+
+        assert_eq!(KeyScheme::RSK.storage_key(&account, &storage_key), expected);
+        assert!(KeyScheme::new(&[0x00], &[0x00], 33).is_err());
+    }
+
+    #[test]
+    fn storage_keys_follow_the_configured_scheme() {
+        let scheme = KeyScheme::new(&[0x07], &[0x01, 0x02], 4).unwrap();
+        let account = [0x33u8; 20];
+        let mut trie = Unitrie::new().with_key_scheme(scheme);
+        trie.put(scheme.account_key(&account), vec![0x01]);
+        trie.put(scheme.storage_key(&account, &[0xaa]), vec![0x02]);
+        trie.put(KeyScheme::RSK.storage_key(&account, &[0xbb]), vec![0x03]);
+
+        assert_eq!(trie.get_storage_keys(&account), vec![vec![0xaa]]);
+        let trie = trie.with_key_scheme(KeyScheme::RSK);
+        assert_eq!(trie.get_storage_keys(&account), vec![vec![0xbb]]);
+    }
+
+    #[test]
+    fn accounts_iter_yields_accounts_and_skips_their_subtrees() {
+        let scheme = KeyScheme::RSK;
+        let mut trie = Unitrie::new();
+        let mut expected = Vec::new();
+        for index in 0u8..5 {
+            let account = [index; 20];
+            trie.put(scheme.account_key(&account), vec![index + 1]);
+            trie.put(scheme.storage_key(&account, &[0xaa]), vec![0x02]);
+            expected.push((account.to_vec(), vec![index + 1]));
+        }
+        // A storage key whose account node is missing is not an account.
+        trie.put(scheme.storage_key(&[0x77; 20], &[0xaa]), vec![0x03]);
+        trie.put(vec![0x01, 0x02], vec![0x04]);
+        expected.sort();
+
+        let mut accounts: Vec<(Vec<u8>, Vec<u8>)> = trie
+            .accounts_iter()
+            .map(|(address, value)| (address.to_vec(), value.to_vec()))
+            .collect();
+        accounts.sort();
+        assert_eq!(accounts, expected);
+        assert_eq!(
+            scheme.account_address(&scheme.account_key(&[0x07; 20])),
+            Some(&[0x07u8; 20][..])
+        );
+        assert_eq!(
+            scheme.account_address(&scheme.storage_key(&[0x07; 20], &[1])),
+            None
+        );
+    }
+
+    #[test]
+    fn precompile_keys_use_named_slots_under_the_secure_layout() {
+        let scheme = KeyScheme::RSK;
+        assert_eq!(
+            BRIDGE_ADDRESS,
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x00, 0x06]
+        );
+        assert_eq!(REMASC_ADDRESS[17..], [0x01, 0x00, 0x08]);
+
+        let slot = named_storage_slot("rewardBalance").unwrap();
+        assert_eq!(&slot[..19], &[0u8; 19]);
+        assert_eq!(&slot[19..], b"rewardBalance");
+        assert!(named_storage_slot(&"x".repeat(33)).is_err());
+        assert_eq!(hashed_storage_slot("btcUTXOs"), keccak256(b"btcUTXOs"));
+        assert_eq!(storage_slot_bytes(&slot), b"rewardBalance");
+        assert_eq!(storage_slot_bytes(&[0; 32]), [0]);
+
+        // Keys as RSKj's TrieKeyMapper builds them, computed outside this crate.
+        assert_eq!(
+            to_hex(&scheme.bridge_account_key()),
+            "0x00f2d268943739790e0c740000000000000000000000000000000000010006"
+        );
+        assert_eq!(
+            to_hex(&scheme.remasc_account_key()),
+            "0x00dbb7d9576084ede8a4330000000000000000000000000000000000010008"
+        );
+        assert_eq!(
+            to_hex(&scheme.bridge_storage_key("newFederation").unwrap()),
+            "0x00f2d268943739790e0c740000000000000000000000000000000000010006\
+             00693ff1b81ad21620b50b6e657746656465726174696f6e"
+        );
+        assert_eq!(
+            to_hex(&scheme.remasc_storage_key("rewardBalance").unwrap()),
+            "0x00dbb7d9576084ede8a4330000000000000000000000000000000000010008\
+             006ef1b2e44d47cb46e2ba72657761726442616c616e6365"
+        );
+
+        let mut trie = Unitrie::new();
+        trie.put(scheme.bridge_account_key(), vec![0x01]);
+        trie.put(scheme.remasc_account_key(), vec![0x01]);
+        for name in ["newFederation", "oldFederation"] {
+            trie.put(scheme.bridge_storage_key(name).unwrap(), vec![0x02]);
+        }
+        trie.put(
+            scheme.remasc_storage_key("rewardBalance").unwrap(),
+            vec![0x03],
+        );
+
+        let mut bridge_slots = trie.get_storage_keys(&BRIDGE_ADDRESS);
+        bridge_slots.sort();
+        assert_eq!(
+            bridge_slots,
+            vec![b"newFederation".to_vec(), b"oldFederation".to_vec()]
+        );
+        assert_eq!(
+            trie.get_storage_keys(&REMASC_ADDRESS),
+            vec![b"rewardBalance".to_vec()]
+        );
+
+        let mut accounts: Vec<&[u8]> = trie.accounts_iter().map(|(address, _)| address).collect();
+        accounts.sort();
+        assert_eq!(accounts, vec![&BRIDGE_ADDRESS[..], &REMASC_ADDRESS[..]]);
+    }
+}
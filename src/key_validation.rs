@@ -0,0 +1,238 @@
+use crate::json::to_hex;
+use crate::key_scheme::KeyScheme;
+use std::fmt;
+
+const ADDRESS_SIZE: usize = 20;
+const MAX_STORAGE_KEY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KeyKind {
+    Account,
+    Code,
+    Storage,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum KeyShapeError {
+    MissingDomainPrefix,
+    // Too short to hold the secure prefix and an address.
+    TruncatedAccount { length: usize },
+    AccountPrefixMismatch,
+    // Bytes after the account key that are neither the code key suffix nor a
+    // storage key.
+    UnknownSuffix { suffix: Vec<u8> },
+    StorageKeyLength { length: usize },
+    StoragePrefixMismatch,
+}
+
+impl fmt::Display for KeyShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingDomainPrefix => f.write_str("key does not start with the domain prefix"),
+            Self::TruncatedAccount { length } => {
+                write!(f, "{length}-byte key is too short for an account key")
+            }
+            Self::AccountPrefixMismatch => {
+                f.write_str("secure prefix does not match the account address")
+            }
+            Self::UnknownSuffix { suffix } => {
+                write!(
+                    f,
+                    "unexpected bytes {} after the account key",
+                    to_hex(suffix)
+                )
+            }
+            Self::StorageKeyLength { length } => write!(
+                f,
+                "storage key is {length} bytes, expected 1 to {MAX_STORAGE_KEY_SIZE}"
+            ),
+            Self::StoragePrefixMismatch => {
+                f.write_str("secure prefix does not match the storage key")
+            }
+        }
+    }
+}
+
+// Checks that keys have one of the shapes `KeyScheme` builds: an account key,
+// its code key or one of its storage keys, with both secure prefixes matching.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct KeyValidator {
+    scheme: KeyScheme,
+    address_size: usize,
+}
+
+impl KeyValidator {
+    pub fn new(scheme: KeyScheme) -> Self {
+        Self {
+            scheme,
+            address_size: ADDRESS_SIZE,
+        }
+    }
+
+    pub fn with_address_size(mut self, address_size: usize) -> Self {
+        self.address_size = address_size;
+        self
+    }
+
+    pub fn scheme(&self) -> &KeyScheme {
+        &self.scheme
+    }
+
+    pub fn validate(&self, key: &[u8]) -> Result<KeyKind, KeyShapeError> {
+        let scheme = &self.scheme;
+        let rest = key
+            .strip_prefix(scheme.domain_prefix)
+            .ok_or(KeyShapeError::MissingDomainPrefix)?;
+        let account_size = scheme.secure_key_size + self.address_size;
+        if rest.len() < account_size {
+            return Err(KeyShapeError::TruncatedAccount { length: key.len() });
+        }
+        let (account, suffix) = rest.split_at(account_size);
+        let (secure_prefix, address) = account.split_at(scheme.secure_key_size);
+        if scheme.secure_key_prefix(address) != secure_prefix {
+            return Err(KeyShapeError::AccountPrefixMismatch);
+        }
+
+        if suffix.is_empty() {
+            return Ok(KeyKind::Account);
+        }
+        if suffix == scheme.code_prefix {
+            return Ok(KeyKind::Code);
+        }
+        let Some(storage) = suffix.strip_prefix(scheme.storage_prefix) else {
+            return Err(KeyShapeError::UnknownSuffix {
+                suffix: suffix.to_vec(),
+            });
+        };
+        let storage_key = storage
+            .get(scheme.secure_key_size..)
+            .filter(|storage_key| (1..=MAX_STORAGE_KEY_SIZE).contains(&storage_key.len()))
+            .ok_or(KeyShapeError::StorageKeyLength {
+                length: storage.len().saturating_sub(scheme.secure_key_size),
+            })?;
+        if scheme.secure_key_prefix(storage_key) != storage[..scheme.secure_key_size] {
+            return Err(KeyShapeError::StoragePrefixMismatch);
+        }
+        Ok(KeyKind::Storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyKind, KeyShapeError, KeyValidator};
+    use crate::core_api::{TrieOp, WriteError};
+    use crate::key_scheme::KeyScheme;
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn classifies_well_formed_keys_and_rejects_malformed_ones() {
+        let validator = KeyValidator::new(KeyScheme::RSK);
+        let address = [0x11u8; 20];
+        let scheme = KeyScheme::RSK;
+        let account = scheme.account_key(&address);
+        assert_eq!(validator.validate(&account), Ok(KeyKind::Account));
+        assert_eq!(
+            validator.validate(&scheme.code_key(&address)),
+            Ok(KeyKind::Code)
+        );
+        assert_eq!(
+            validator.validate(&scheme.storage_key(&address, &[0x01])),
+            Ok(KeyKind::Storage)
+        );
+
+        let mut wrong_prefix = account.clone();
+        wrong_prefix[1] ^= 0xff;
+        let mut wrong_slot = scheme.storage_key(&address, &[0x22; 32]);
+        *wrong_slot.last_mut().unwrap() ^= 0xff;
+        let cases = [
+            (vec![0x01; 31], KeyShapeError::MissingDomainPrefix),
+            (
+                account[..30].to_vec(),
+                KeyShapeError::TruncatedAccount { length: 30 },
+            ),
+            (wrong_prefix, KeyShapeError::AccountPrefixMismatch),
+            (
+                [account.clone(), vec![0x42]].concat(),
+                KeyShapeError::UnknownSuffix { suffix: vec![0x42] },
+            ),
+            (
+                scheme.account_storage_prefix_key(&address),
+                KeyShapeError::StorageKeyLength { length: 0 },
+            ),
+            (wrong_slot, KeyShapeError::StoragePrefixMismatch),
+        ];
+        for (key, error) in cases {
+            assert_eq!(validator.validate(&key), Err(error));
+        }
+    }
+
+    #[test]
+    fn validating_cores_reject_malformed_keys_at_put_time() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.enable_key_validation();
+            let address = [0x33u8; 20];
+            core.put_checked(KeyScheme::RSK.account_key(&address), vec![0x01])
+                .unwrap();
+            core.put_storage_value(&address, &[0x05], vec![0x02]);
+            assert_eq!(
+                core.put_checked(vec![0x01, 0x02], vec![0x03]),
+                Err(WriteError::MalformedKey {
+                    key: vec![0x01, 0x02],
+                    error: KeyShapeError::MissingDomainPrefix
+                })
+            );
+            let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                core.put(vec![0x00; 4], vec![0x04])
+            }));
+            assert!(rejected.is_err());
+            assert_eq!(core.key_count(), 2);
+
+            core.disable_key_validation();
+            core.put(vec![0x01, 0x02], vec![0x03]);
+            assert_eq!(core.key_count(), 3);
+        }
+    }
+
+    #[test]
+    fn every_write_path_checks_keys() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            core.enable_key_validation();
+            let account = KeyScheme::RSK.account_key(&[0x34u8; 20]);
+            let malformed = vec![0x00; 4];
+
+            let batch = [
+                TrieOp::put(account.clone(), vec![0x01]),
+                TrieOp::put(malformed.clone(), vec![0x02]),
+            ];
+            assert!(matches!(
+                core.try_apply_batch(batch.clone()),
+                Err(WriteError::MalformedKey { key, .. }) if key == malformed
+            ));
+            assert_eq!(core.key_count(), 0);
+            let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                core.apply_batch(batch.clone())
+            }));
+            assert!(rejected.is_err());
+            assert!(core.apply_block(&batch, &mut MemoryStore::new()).is_err());
+            assert!(core
+                .extend_sorted([(malformed.clone(), vec![0x03])])
+                .is_err());
+            let blob = [0x04u8; 8];
+            assert!(core
+                .put_value_from_reader(malformed.clone(), &blob[..], 8, &mut MemoryStore::new())
+                .is_err());
+            let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                core.update(malformed.clone(), |_| Some(vec![0x05]))
+            }));
+            assert!(rejected.is_err());
+            assert_eq!(core.key_count(), 0);
+
+            core.try_apply_batch([TrieOp::put(account.clone(), vec![0x01])])
+                .unwrap();
+            assert_eq!(core.get(&account), Some(vec![0x01]));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::rng::SeededRng;
+use crate::store_adapter::{CodecHint, IterableStore, RawStoreAdapter};
+use std::time::{Duration, Instant};
+
+// Simulated per-call latencies. Each call waits its base latency plus a
+// uniformly drawn extra of up to `jitter`, from a seeded schedule.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct LatencyConfig {
+    pub seed: u64,
+    pub read_latency: Duration,
+    pub write_latency: Duration,
+    pub jitter: Duration,
+}
+
+impl LatencyConfig {
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    pub fn read_latency(mut self, latency: Duration) -> Self {
+        self.read_latency = latency;
+        self
+    }
+
+    pub fn write_latency(mut self, latency: Duration) -> Self {
+        self.write_latency = latency;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.read_latency.is_zero() && self.write_latency.is_zero() && self.jitter.is_zero()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct LatencyStats {
+    pub reads: usize,
+    pub writes: usize,
+    // Sum of the scheduled delays, independent of how long waiting overshot.
+    pub simulated_delay: Duration,
+}
+
+// Wraps a store and delays every read and write, so benchmarks of
+// persistence-heavy workloads see disk-like costs instead of HashMap speeds.
+// Delays busy-wait: sleeping overshoots the microsecond latencies of SSDs by
+// more than the latency itself.
+#[derive(Debug)]
+pub struct LatencyStore<S> {
+    inner: S,
+    config: LatencyConfig,
+    rng: SeededRng,
+    stats: LatencyStats,
+}
+
+impl<S: RawStoreAdapter> LatencyStore<S> {
+    pub fn new(inner: S, config: LatencyConfig) -> Self {
+        Self {
+            inner,
+            config,
+            rng: SeededRng::new(config.seed),
+            stats: LatencyStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> LatencyConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn delay(&mut self, base: Duration) {
+        let jitter_nanos = self.config.jitter.as_nanos() as u64;
+        let jitter = if jitter_nanos == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos(self.rng.next() % (jitter_nanos + 1))
+        };
+        let delay = base + jitter;
+        if delay.is_zero() {
+            return;
+        }
+        self.stats.simulated_delay += delay;
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn delay_read(&mut self) {
+        self.stats.reads += 1;
+        self.delay(self.config.read_latency);
+    }
+
+    fn delay_write(&mut self) {
+        self.stats.writes += 1;
+        self.delay(self.config.write_latency);
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for LatencyStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.delay_read();
+        self.inner.load_raw_node(hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.delay_read();
+        self.inner.load_raw_value(hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        self.delay_write();
+        self.inner.save_raw_node(hash, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        self.delay_write();
+        self.inner.save_raw_value(hash, value);
+    }
+
+    // An existence check is a read to the backing store.
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.delay_read();
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+// Enumeration is tooling, not part of the simulated workload: no delay.
+impl<S: IterableStore> IterableStore for LatencyStore<S> {
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        self.inner.iter_node_hashes()
+    }
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        self.inner.iter_value_hashes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyConfig, LatencyStore};
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::MemoryStore;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn delays_every_call_on_a_reproducible_schedule() {
+        let run = |seed| {
+            let config = LatencyConfig::with_seed(seed)
+                .read_latency(Duration::from_micros(20))
+                .write_latency(Duration::from_micros(50))
+                .jitter(Duration::from_micros(10));
+            let mut store = LatencyStore::new(MemoryStore::new(), config);
+            let mut trie = Unitrie::new();
+            for index in 0u8..16 {
+                trie.put(vec![index, index], vec![index; 48]);
+            }
+
+            let started = Instant::now();
+            trie.save_to_store(&mut store);
+            let root = trie.root_hash();
+            Unitrie::from_persisted_root(&root, &mut store).unwrap();
+            let elapsed = started.elapsed();
+
+            let stats = store.stats();
+            assert!(elapsed >= stats.simulated_delay);
+            stats
+        };
+
+        let stats = run(3);
+        assert_eq!(run(3), stats);
+        assert!(stats.reads > 0 && stats.writes > 0);
+        let base = Duration::from_micros(20) * stats.reads as u32
+            + Duration::from_micros(50) * stats.writes as u32;
+        let max_jitter = Duration::from_micros(10) * (stats.reads + stats.writes) as u32;
+        assert!(stats.simulated_delay >= base);
+        assert!(stats.simulated_delay <= base + max_jitter);
+    }
+}
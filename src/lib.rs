@@ -1,37 +1,125 @@
+#[cfg(not(any(feature = "legacy", feature = "next")))]
+compile_error!("enable at least one of the `legacy` and `next` features");
+
+pub mod audit;
+pub mod background_save;
+pub mod change_set;
+pub mod checkpoint;
+pub mod checksummed_store;
 pub mod codec_orchid;
+pub mod codec_registry;
 pub mod codec_rskip107;
+pub mod codec_v2;
+pub mod compressed_store;
+pub mod config;
 pub mod core_api;
 pub mod core_trie;
+pub mod cursor;
+pub mod debug_json;
+pub mod diff;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod flaky_store;
+pub mod flat_store;
+pub mod frozen;
+pub mod generic_core;
 pub mod hash;
+pub mod index_trie;
+mod json;
+pub mod key_filter;
+pub mod key_scheme;
+pub mod key_validation;
+pub mod latency_store;
+#[doc(hidden)]
+pub mod long_value;
+pub mod namespaced_store;
+#[cfg(feature = "next")]
+#[doc(hidden)]
 pub mod next;
 pub mod node_ref;
+pub mod observer;
+pub mod orphans;
+#[doc(hidden)]
 pub mod path;
+pub mod prefetch;
+pub mod preimage;
+pub mod prelude;
+pub mod proof;
+pub mod reporting;
+mod rng;
+#[doc(hidden)]
+pub mod sorted_builder;
 pub mod storage_keys_packed;
 pub mod store_adapter;
+pub mod trie_builder;
+pub mod typed_store;
+pub mod value_handle;
+pub mod value_index;
+pub mod value_sizes;
+#[doc(hidden)]
 pub mod varint;
+pub mod wal_store;
+pub mod workload;
 
 use std::fmt;
+use std::io::{self, Read, Write};
 
-use crate::core_api::TrieSnapshot;
-use crate::core_trie::{SaveStats, Unitrie};
+use crate::background_save::{BackgroundSaveError, BackgroundSaver, DetachedStore};
+use crate::change_set::{ChangeSet, RecordingStore};
+use crate::checkpoint::{DeltaHeader, SnapshotHeader};
+use crate::codec_v2::V2SaveOptions;
+use crate::core_api::{
+    BlockStateResult, EmptyValuePolicy, TrieEngine, TrieOp, TrieSnapshot, WriteError,
+};
+#[cfg(feature = "legacy")]
+use crate::core_trie::Unitrie;
+use crate::core_trie::{
+    AccountIter, CancellationToken, EntryRange, LoadError, LoadLimits, SaveEstimate, SaveStats,
+    SubtreeDigest,
+};
+use crate::cursor::{CursorError, EntryPage, IterCursor, Page, ScanKind};
+use crate::diff::{diff_entries, EntryChange};
+use crate::flat_store::{FlatLayer, FlatStore};
+use crate::frozen::FrozenTrie;
+use crate::key_filter::KeyFilter;
+use crate::key_scheme::KeyScheme;
+use crate::key_validation::KeyValidator;
+#[cfg(feature = "next")]
 use crate::next::core_trie::NextUnitrie;
-use crate::node_ref::HASH_SIZE;
+use crate::node_ref::{CodecConfig, TrieNode, HASH_SIZE};
+use crate::observer::{Observers, TrieObserver};
+use crate::prefetch::{PrefetchCache, PrefetchStats};
+use crate::preimage::PreimageRegistry;
+use crate::proof::{AccountProof, ProofSizeEstimate, TrieProof};
+use crate::value_handle::ValueHandle;
+use crate::value_index::ValueHashIndex;
+use crate::value_sizes::ValueSizeReport;
+use std::sync::Arc;
 
-pub use crate::store_adapter::RawStoreAdapter;
+#[cfg(feature = "next")]
+pub use crate::next::root_history::{RootHistory, RootRecord};
+pub use crate::store_adapter::{RawStoreAdapter, SharedStoreAdapter};
 
 pub type TrieRoot = [u8; HASH_SIZE];
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum UnitrieImplementation {
+    #[cfg(feature = "legacy")]
     LegacyV1,
+    #[cfg(feature = "next")]
     Next,
 }
 
 impl UnitrieImplementation {
     pub fn from_config(value: &str) -> Result<Self, String> {
         match value.trim().to_ascii_lowercase().as_str() {
+            #[cfg(feature = "legacy")]
             "legacy-v1" => Ok(Self::LegacyV1),
+            #[cfg(feature = "next")]
             "next" => Ok(Self::Next),
+            #[cfg(not(feature = "legacy"))]
+            "legacy-v1" => Err(not_compiled_in("legacy-v1")),
+            #[cfg(not(feature = "next"))]
+            "next" => Err(not_compiled_in("next")),
             other => Err(format!(
                 "unsupported unitrie implementation '{other}', expected one of: legacy-v1, next"
             )),
@@ -40,10 +128,37 @@ impl UnitrieImplementation {
 
     pub fn as_config_name(self) -> &'static str {
         match self {
+            #[cfg(feature = "legacy")]
             Self::LegacyV1 => "legacy-v1",
+            #[cfg(feature = "next")]
             Self::Next => "next",
         }
     }
+
+    // The engines compiled into this build, legacy first.
+    pub fn enabled() -> &'static [Self] {
+        &[
+            #[cfg(feature = "legacy")]
+            Self::LegacyV1,
+            #[cfg(feature = "next")]
+            Self::Next,
+        ]
+    }
+}
+
+#[cfg(not(all(feature = "legacy", feature = "next")))]
+fn not_compiled_in(name: &str) -> String {
+    format!("unitrie implementation '{name}' is not compiled in; enable its cargo feature")
+}
+
+// The legacy engine when it is compiled in, since it is the consensus reference.
+impl Default for UnitrieImplementation {
+    fn default() -> Self {
+        #[cfg(feature = "legacy")]
+        return Self::LegacyV1;
+        #[cfg(not(feature = "legacy"))]
+        return Self::Next;
+    }
 }
 
 impl fmt::Display for UnitrieImplementation {
@@ -53,8 +168,11 @@ impl fmt::Display for UnitrieImplementation {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 enum UnitrieCoreInner {
+    #[cfg(feature = "legacy")]
     Legacy(Unitrie),
+    #[cfg(feature = "next")]
     Next(NextUnitrie),
 }
 
@@ -62,18 +180,28 @@ enum UnitrieCoreInner {
 pub struct UnitrieCore {
     implementation: UnitrieImplementation,
     inner: UnitrieCoreInner,
+    observers: Observers,
+    key_filter: Option<KeyFilter>,
+    key_validator: Option<KeyValidator>,
+    empty_values: EmptyValuePolicy,
 }
 
 impl UnitrieCore {
     pub fn new(implementation: UnitrieImplementation) -> Self {
         let inner = match implementation {
+            #[cfg(feature = "legacy")]
             UnitrieImplementation::LegacyV1 => UnitrieCoreInner::Legacy(Unitrie::new()),
+            #[cfg(feature = "next")]
             UnitrieImplementation::Next => UnitrieCoreInner::Next(NextUnitrie::new()),
         };
 
         Self {
             implementation,
             inner,
+            observers: Observers::default(),
+            key_filter: None,
+            key_validator: None,
+            empty_values: EmptyValuePolicy::default(),
         }
     }
 
@@ -82,123 +210,1473 @@ impl UnitrieCore {
         root_hash: &[u8],
         store: &mut T,
     ) -> Result<Self, String> {
+        Self::from_persisted_root_with_limits(
+            implementation,
+            root_hash,
+            store,
+            LoadLimits::default(),
+        )
+        .map_err(|error| error.to_string())
+    }
+
+    pub fn from_persisted_root_with_limits<T: RawStoreAdapter>(
+        implementation: UnitrieImplementation,
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError> {
+        Self::from_persisted_root_with_config(
+            implementation,
+            root_hash,
+            store,
+            limits,
+            CodecConfig::CONSENSUS,
+        )
+    }
+
+    // Loads a trie saved under `config`. Calling `with_config` after a load
+    // would drop what the engine knows is already persisted.
+    pub fn from_persisted_root_with_config<T: RawStoreAdapter>(
+        implementation: UnitrieImplementation,
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError> {
         let inner = match implementation {
+            #[cfg(feature = "legacy")]
+            UnitrieImplementation::LegacyV1 => UnitrieCoreInner::Legacy(
+                Unitrie::from_persisted_root_with_config(root_hash, store, limits, config)?,
+            ),
+            #[cfg(feature = "next")]
+            UnitrieImplementation::Next => UnitrieCoreInner::Next(
+                NextUnitrie::from_persisted_root_with_config(root_hash, store, limits, config)?,
+            ),
+        };
+
+        Ok(Self {
+            implementation,
+            inner,
+            observers: Observers::default(),
+            key_filter: None,
+            key_validator: None,
+            empty_values: EmptyValuePolicy::default(),
+        })
+    }
+
+    pub fn from_persisted_root_parallel<S>(
+        implementation: UnitrieImplementation,
+        root_hash: &[u8],
+        store: &S,
+        threads: usize,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        Self::from_persisted_root_parallel_with_config(
+            implementation,
+            root_hash,
+            store,
+            threads,
+            limits,
+            CodecConfig::CONSENSUS,
+        )
+    }
+
+    pub fn from_persisted_root_parallel_with_config<S>(
+        implementation: UnitrieImplementation,
+        root_hash: &[u8],
+        store: &S,
+        threads: usize,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        let inner = match implementation {
+            #[cfg(feature = "legacy")]
+            UnitrieImplementation::LegacyV1 => {
+                UnitrieCoreInner::Legacy(Unitrie::from_persisted_root_parallel_with_config(
+                    root_hash, store, threads, limits, config,
+                )?)
+            }
+            #[cfg(feature = "next")]
+            UnitrieImplementation::Next => {
+                UnitrieCoreInner::Next(NextUnitrie::from_persisted_root_parallel_with_config(
+                    root_hash, store, threads, limits, config,
+                )?)
+            }
+        };
+
+        Ok(Self {
+            implementation,
+            inner,
+            observers: Observers::default(),
+            key_filter: None,
+            key_validator: None,
+            empty_values: EmptyValuePolicy::default(),
+        })
+    }
+
+    pub fn from_sorted_entries<I>(
+        implementation: UnitrieImplementation,
+        entries: I,
+    ) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let inner = match implementation {
+            #[cfg(feature = "legacy")]
             UnitrieImplementation::LegacyV1 => {
-                UnitrieCoreInner::Legacy(Unitrie::from_persisted_root(root_hash, store)?)
+                UnitrieCoreInner::Legacy(Unitrie::from_sorted_entries(entries)?)
             }
+            #[cfg(feature = "next")]
             UnitrieImplementation::Next => {
-                UnitrieCoreInner::Next(NextUnitrie::from_persisted_root(root_hash, store)?)
+                UnitrieCoreInner::Next(NextUnitrie::from_sorted_entries(entries)?)
             }
         };
 
         Ok(Self {
             implementation,
             inner,
+            observers: Observers::default(),
+            key_filter: None,
+            key_validator: None,
+            empty_values: EmptyValuePolicy::default(),
         })
     }
 
+    pub fn extend_sorted<I>(&mut self, entries: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        if !self.has_hooks() && !self.has_write_checks() {
+            return match &mut self.inner {
+                #[cfg(feature = "legacy")]
+                UnitrieCoreInner::Legacy(trie) => trie.extend_sorted(entries),
+                #[cfg(feature = "next")]
+                UnitrieCoreInner::Next(trie) => trie.extend_sorted(entries),
+            };
+        }
+
+        let entries: Vec<_> = entries.into_iter().collect();
+        for (key, value) in &entries {
+            self.validate_value(key, value)
+                .and_then(|()| self.validate_key(key))
+                .map_err(|error| error.to_string())?;
+        }
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.extend_sorted(entries.iter().cloned())?,
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.extend_sorted(entries.iter().cloned())?,
+        }
+        for (key, value) in &entries {
+            self.record_put(key, value);
+        }
+        Ok(())
+    }
+
+    pub fn add_observer(&mut self, observer: Arc<dyn TrieObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    pub fn observer_count(&self) -> usize {
+        self.observers.len()
+    }
+
+    // Builds a value-hash index over the current entries and keeps it in sync
+    // with later mutations.
+    pub fn enable_value_index(&mut self) -> Arc<ValueHashIndex> {
+        let index = Arc::new(ValueHashIndex::from_trie(self));
+        self.observers.push(index.clone());
+        index
+    }
+
+    // Records the addresses and storage slots behind current and future keys,
+    // so their secure prefixes can be reversed.
+    pub fn enable_preimage_registry(&mut self) -> Arc<PreimageRegistry> {
+        let registry = Arc::new(PreimageRegistry::from_trie(self));
+        self.observers.push(registry.clone());
+        registry
+    }
+
+    // Attaches a flat key -> value view that is refreshed on every save. A flat
+    // store that does not match the current root is rebuilt first.
+    pub fn enable_flat_layer<F>(&mut self, mut flat: F) -> Arc<FlatLayer<F>>
+    where
+        F: FlatStore + Send + 'static,
+    {
+        let root = self.current_root_hash();
+        let in_sync = flat.flat_root() == Some(root);
+        let layer = Arc::new(FlatLayer::new(flat));
+        if !in_sync {
+            layer.rebuild(&root, self.iter_prefix(&[]));
+        }
+        self.observers.push(layer.clone());
+        layer
+    }
+
+    pub fn enable_key_filter(&mut self) {
+        self.key_filter = Some(KeyFilter::from_keys(self.keys_with_prefix(&[])));
+    }
+
+    pub fn disable_key_filter(&mut self) {
+        self.key_filter = None;
+    }
+
+    pub fn key_filter(&self) -> Option<&KeyFilter> {
+        self.key_filter.as_ref()
+    }
+
+    // Rebuilds filter shards invalidated by deletions; also done on every save.
+    pub fn refresh_key_filter(&mut self) -> usize {
+        let Some(mut filter) = self.key_filter.take() else {
+            return 0;
+        };
+        let refreshed = filter.refresh(|first| {
+            let empty_key = (first == 0 && self.stored_value(&[]).is_some()).then_some(&[][..]);
+            empty_key.into_iter().chain(self.keys_with_prefix(&[first]))
+        });
+        self.key_filter = Some(filter);
+        refreshed
+    }
+
+    // Validates keys against the current key scheme; see `KeyValidator`.
+    pub fn enable_key_validation(&mut self) {
+        self.key_validator = Some(KeyValidator::new(*self.key_scheme()));
+    }
+
+    pub fn set_key_validator(&mut self, validator: KeyValidator) {
+        self.key_validator = Some(validator);
+    }
+
+    pub fn disable_key_validation(&mut self) {
+        self.key_validator = None;
+    }
+
+    pub fn key_validator(&self) -> Option<&KeyValidator> {
+        self.key_validator.as_ref()
+    }
+
+    fn validate_key(&self, key: &[u8]) -> Result<(), WriteError> {
+        match &self.key_validator {
+            Some(validator) => {
+                validator
+                    .validate(key)
+                    .map(|_| ())
+                    .map_err(|error| WriteError::MalformedKey {
+                        key: key.to_vec(),
+                        error,
+                    })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_key(&self, key: &[u8]) {
+        if let Err(error) = self.validate_key(key) {
+            panic!("{error}");
+        }
+    }
+
+    // The checks `put` would run on a batched write.
+    fn validate_op(&self, op: &TrieOp) -> Result<(), WriteError> {
+        match op {
+            TrieOp::Put { key, value } => {
+                self.validate_value(key, value)?;
+                self.validate_key(key)
+            }
+            TrieOp::Delete { .. } | TrieOp::DeleteRecursive { .. } => Ok(()),
+        }
+    }
+
+    fn has_write_checks(&self) -> bool {
+        self.key_validator.is_some() || self.empty_values == EmptyValuePolicy::Reject
+    }
+
+    fn might_contain(&self, key: &[u8]) -> bool {
+        self.key_filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(key))
+    }
+
+    fn has_hooks(&self) -> bool {
+        !self.observers.is_empty() || self.key_filter.is_some()
+    }
+
+    fn validate_value(&self, key: &[u8], value: &[u8]) -> Result<(), WriteError> {
+        validate_value(self.empty_values, self.config(), key, value)
+    }
+
+    fn check_empty_value(&self, key: &[u8], value: &[u8]) {
+        if let Err(error) = self.validate_value(key, value) {
+            panic!("{error}");
+        }
+    }
+
+    fn record_put(&mut self, key: &[u8], value: &[u8]) {
+        if let Some(filter) = &mut self.key_filter {
+            filter.record_put(key, value);
+        }
+        self.observers.notify_put(key, value);
+    }
+
+    fn record_delete(&mut self, key: &[u8]) {
+        if let Some(filter) = &mut self.key_filter {
+            filter.record_delete(key);
+        }
+        self.observers.notify_delete(key);
+    }
+
+    fn record_op(&mut self, op: &TrieOp) {
+        if let Some(filter) = &mut self.key_filter {
+            filter.record_op(op);
+        }
+        self.observers.notify_op(op);
+    }
+
+    // Non-consensus parameters change every root hash; see `CodecConfig`.
+    pub fn with_config(mut self, config: CodecConfig) -> Self {
+        self.inner = match self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => UnitrieCoreInner::Legacy(trie.with_config(config)),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => UnitrieCoreInner::Next(trie.with_config(config)),
+        };
+        self
+    }
+
+    pub fn config(&self) -> &CodecConfig {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.config(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.config(),
+        }
+    }
+
+    pub fn with_empty_value_policy(mut self, policy: EmptyValuePolicy) -> Self {
+        self.empty_values = policy;
+        self
+    }
+
+    pub fn set_empty_value_policy(&mut self, policy: EmptyValuePolicy) {
+        self.empty_values = policy;
+    }
+
+    pub fn empty_value_policy(&self) -> EmptyValuePolicy {
+        self.empty_values
+    }
+
+    pub fn with_key_scheme(mut self, key_scheme: KeyScheme) -> Self {
+        self.inner = match self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => {
+                UnitrieCoreInner::Legacy(trie.with_key_scheme(key_scheme))
+            }
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => {
+                UnitrieCoreInner::Next(trie.with_key_scheme(key_scheme))
+            }
+        };
+        self
+    }
+
+    pub fn key_scheme(&self) -> &KeyScheme {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.key_scheme(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.key_scheme(),
+        }
+    }
+
+    // Switches implementation in place. Entries, config, key scheme, value hash
+    // cache and persistence bookkeeping carry over; next-only state such as the
+    // root history is dropped when leaving `Next`.
+    #[cfg_attr(
+        not(all(feature = "legacy", feature = "next")),
+        allow(unused_variables)
+    )]
+    pub fn convert_to(&mut self, implementation: UnitrieImplementation) {
+        // With a single engine compiled in, `implementation` is always the current one.
+        #[cfg(all(feature = "legacy", feature = "next"))]
+        if implementation != self.implementation {
+            let inner =
+                std::mem::replace(&mut self.inner, UnitrieCoreInner::Legacy(Unitrie::new()));
+            self.inner = match inner {
+                UnitrieCoreInner::Legacy(trie) => {
+                    UnitrieCoreInner::Next(NextUnitrie::from_unitrie(trie))
+                }
+                UnitrieCoreInner::Next(trie) => UnitrieCoreInner::Legacy(trie.into_unitrie()),
+            };
+            self.implementation = implementation;
+        }
+    }
+
     pub fn implementation(&self) -> UnitrieImplementation {
         self.implementation
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if !self.might_contain(key) {
+            return None;
+        }
         match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.get(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.get(key),
         }
     }
 
-    pub fn get_ref(&self, key: &[u8]) -> Option<&[u8]> {
+    // Both engines keep every value in memory, so these handles are always
+    // borrowed; `get_stored_ref` is the lazy counterpart.
+    pub fn get_ref(&self, key: &[u8]) -> Option<ValueHandle<'_>> {
+        if !self.might_contain(key) {
+            return None;
+        }
+        self.stored_value(key).map(ValueHandle::Borrowed)
+    }
+
+    // Reads `key` under a persisted root without loading the trie; long values
+    // stay in the store as `ValueHandle::Stored` until loaded.
+    pub fn get_stored_ref<T: RawStoreAdapter>(
+        root: &TrieRoot,
+        key: &[u8],
+        store: &mut T,
+    ) -> Result<Option<ValueHandle<'static>>, String> {
+        crate::value_handle::stored_value_ref(root, key, store)
+    }
+
+    // Bypasses the key filter, for lookups racing the filter's own bookkeeping.
+    fn stored_value(&self, key: &[u8]) -> Option<&[u8]> {
         match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.get_ref(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.get_ref(key),
         }
     }
 
+    // Panics on an empty value under `EmptyValuePolicy::Reject`, on a value
+    // too long for the configured encoding, and on a malformed key when key
+    // validation is enabled; `put_checked` returns those as errors.
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.check_empty_value(&key, &value);
+        self.put_or_delete_on_empty(key, value);
+    }
+
+    // Deletes `key` when `value` is empty, whatever the empty value policy.
+    pub fn put_or_delete_on_empty(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.check_key(&key);
+        self.put_unchecked(key, value);
+    }
+
+    // Like `put`, but returns refused writes as errors instead of panicking.
+    pub fn put_checked(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), WriteError> {
+        self.validate_key(&key)?;
+        self.validate_value(&key, &value)?;
+        self.put_unchecked(key, value);
+        Ok(())
+    }
+
+    fn put_unchecked(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.record_put(&key, &value);
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.put(key, value),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.put(key, value),
         }
     }
 
+    // Same as `put_checked`, named after the engines' `try_put`.
+    pub fn try_put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), WriteError> {
+        self.put_checked(key, value)
+    }
+
+    pub fn put_value_from_reader<R: Read, T: RawStoreAdapter>(
+        &mut self,
+        key: Vec<u8>,
+        reader: R,
+        length: usize,
+        store: &mut T,
+    ) -> Result<(), String> {
+        if length == 0 {
+            self.validate_value(&key, &[])
+                .map_err(|error| error.to_string())?;
+        }
+        self.validate_key(&key).map_err(|error| error.to_string())?;
+        let observed_key = self.has_hooks().then(|| key.clone());
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => {
+                trie.put_value_from_reader(key, reader, length, store)
+            }
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.put_value_from_reader(key, reader, length, store),
+        }?;
+
+        if let Some(key) = observed_key {
+            match self.stored_value(&key).map(<[u8]>::to_vec) {
+                Some(value) => self.record_put(&key, &value),
+                None => self.record_delete(&key),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_value_to_writer<W: Write>(&self, key: &[u8], writer: W) -> io::Result<bool> {
+        if !self.might_contain(key) {
+            return Ok(false);
+        }
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.get_value_to_writer(key, writer),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.get_value_to_writer(key, writer),
+        }
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.check_empty_value(&key, &value);
+        self.check_key(&key);
+        self.record_put(&key, &value);
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.insert(key, value),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.insert(key, value),
+        }
+    }
+
+    // Panics like `put` when `f` returns a value `put` would refuse, after
+    // leaving the old value in place; `update_checked` returns the error.
+    pub fn update<F>(&mut self, key: Vec<u8>, f: F)
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        if let Err(error) = self.update_checked(key, f) {
+            panic!("{error}");
+        }
+    }
+
+    // Like `update`, but returns a refused write as an error. The key keeps
+    // its old value.
+    pub fn update_checked<F>(&mut self, key: Vec<u8>, f: F) -> Result<(), WriteError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        self.validate_key(&key)?;
+        let observed_key = self.has_hooks().then(|| key.clone());
+        let (empty_values, config) = (self.empty_values, *self.config());
+        let checked_key = key.clone();
+        let mut rejected = None;
+        let checked = |current: Option<&[u8]>| match f(current) {
+            Some(value) => match validate_value(empty_values, &config, &checked_key, &value) {
+                Ok(()) => Some(value),
+                Err(error) => {
+                    rejected = Some(error);
+                    current.map(<[u8]>::to_vec)
+                }
+            },
+            None => None,
+        };
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.update(key, checked),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.update(key, checked),
+        }
+        if let Some(error) = rejected {
+            return Err(error);
+        }
+
+        if let Some(key) = observed_key {
+            match self.stored_value(&key).map(<[u8]>::to_vec) {
+                Some(value) => self.record_put(&key, &value),
+                None => self.record_delete(&key),
+            }
+        }
+        Ok(())
+    }
+
     pub fn delete(&mut self, key: &[u8]) {
+        self.record_delete(key);
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.delete(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.delete(key),
         }
     }
 
-    pub fn delete_recursive(&mut self, key: &[u8]) {
+    pub fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.record_delete(key);
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.delete_returning(key),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.delete_returning(key),
+        }
+    }
+
+    // Observers see the clear as a delete of every key.
+    pub fn clear(&mut self) {
+        if self.key_filter.is_some() {
+            self.key_filter = Some(KeyFilter::from_keys(std::iter::empty()));
+        }
+        self.observers.notify_delete_recursive(&[]);
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.clear(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.clear(),
+        }
+    }
+
+    // Returns the root before clearing, e.g. to revert to it later if it was saved.
+    pub fn clear_returning_root(&mut self) -> TrieRoot {
+        let previous_root = self.current_root_hash();
+        self.clear();
+        previous_root
+    }
+
+    pub fn delete_recursive(&mut self, key: &[u8]) -> usize {
+        if let Some(filter) = &mut self.key_filter {
+            filter.record_delete_recursive(key);
+        }
+        self.observers.notify_delete_recursive(key);
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.delete_recursive(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.delete_recursive(key),
         }
     }
 
+    pub fn put_storage_value(&mut self, address: &[u8], slot: &[u8], value: Vec<u8>) {
+        let storage_key = self.key_scheme().storage_key(address, slot);
+        self.put_or_delete_on_empty(storage_key, value);
+    }
+
+    pub fn get_storage_value(&self, address: &[u8], slot: &[u8]) -> Option<Vec<u8>> {
+        self.get(&self.key_scheme().storage_key(address, slot))
+    }
+
+    pub fn put_code(&mut self, address: &[u8], code: Vec<u8>) {
+        let code_key = self.key_scheme().code_key(address);
+        self.put_or_delete_on_empty(code_key, code);
+    }
+
+    pub fn get_code(&self, address: &[u8]) -> Option<Vec<u8>> {
+        self.get(&self.key_scheme().code_key(address))
+    }
+
+    pub fn get_code_hash(&self, address: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.get_value_hash(&self.key_scheme().code_key(address))
+    }
+
+    // Goes through `delete_recursive`, so observers and the key filter see one
+    // recursive delete of the account key.
+    pub fn delete_account(&mut self, address: &[u8]) -> usize {
+        let account_key = self.key_scheme().account_key(address);
+        self.delete_recursive(&account_key)
+    }
+
+    // Panics on an op `put` would refuse; see `try_apply_batch`.
+    pub fn apply_batch<I>(&mut self, ops: I)
+    where
+        I: IntoIterator<Item = TrieOp>,
+    {
+        if let Err(error) = self.try_apply_batch(ops) {
+            panic!("{error}");
+        }
+    }
+
+    // Checks every op before applying any, so a refused batch leaves the trie
+    // untouched.
+    pub fn try_apply_batch<I>(&mut self, ops: I) -> Result<(), WriteError>
+    where
+        I: IntoIterator<Item = TrieOp>,
+    {
+        let ops: Vec<TrieOp> = ops.into_iter().collect();
+        for op in &ops {
+            self.validate_op(op)?;
+        }
+        for op in &ops {
+            self.record_op(op);
+        }
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.apply_batch(ops),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.apply_batch(ops),
+        }
+        Ok(())
+    }
+
+    // A block's state transition in one call: apply `ops`, hash and persist the
+    // dirty nodes. Ops are validated first, so a rejected block leaves the trie
+    // untouched.
+    pub fn apply_block<T: RawStoreAdapter>(
+        &mut self,
+        ops: &[TrieOp],
+        store: &mut T,
+    ) -> Result<BlockStateResult, String> {
+        for (index, op) in ops.iter().enumerate() {
+            self.validate_op(op)
+                .map_err(|error| format!("block op {index} rejected: {error}"))?;
+        }
+
+        let previous_root = self.current_root_hash();
+        self.apply_batch(ops.iter().cloned());
+        let save_stats = self.save_to_store_with_stats(store);
+        let snapshot = self.snapshot();
+        Ok(BlockStateResult {
+            previous_root,
+            root: snapshot.root,
+            key_count: snapshot.key_count,
+            ops_applied: ops.len(),
+            save_stats,
+        })
+    }
+
+    // Applies `ops` on a scratch copy and only adopts the result (and notifies
+    // observers) when it reaches `expected_root`.
+    pub(crate) fn apply_batch_checked(
+        &mut self,
+        ops: Vec<TrieOp>,
+        expected_root: TrieRoot,
+    ) -> Result<(), String> {
+        let mut candidate = Self {
+            implementation: self.implementation,
+            inner: self.inner.clone(),
+            observers: Observers::default(),
+            key_filter: None,
+            key_validator: self.key_validator,
+            empty_values: self.empty_values,
+        };
+        candidate
+            .try_apply_batch(ops.iter().cloned())
+            .map_err(|error| error.to_string())?;
+        if candidate.current_root_hash() != expected_root {
+            return Err("applying delta did not produce the recorded new root".to_string());
+        }
+
+        self.inner = candidate.inner;
+        for op in &ops {
+            self.record_op(op);
+        }
+        Ok(())
+    }
+
     pub fn get_value_length(&self, key: &[u8]) -> Option<usize> {
+        if !self.might_contain(key) {
+            return None;
+        }
         match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.get_value_length(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.get_value_length(key),
         }
     }
 
     pub fn get_value_hash(&self, key: &[u8]) -> Option<TrieRoot> {
+        if !self.might_contain(key) {
+            return None;
+        }
         match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.get_value_hash(key),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.get_value_hash(key),
         }
     }
 
     pub fn collect_keys(&self, byte_size: usize) -> Vec<Vec<u8>> {
         match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.collect_keys(byte_size),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.collect_keys(byte_size),
         }
     }
 
-    pub fn get_storage_keys(&mut self, account_address: &[u8]) -> Vec<Vec<u8>> {
-        match &mut self.inner {
+    pub fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.collect_keys_page(byte_size, start_after, limit),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.collect_keys_page(byte_size, start_after, limit),
+        }
+    }
+
+    // `collect_keys_page` resumable from an `IterCursor` token.
+    pub fn collect_keys_page_with_cursor(
+        &mut self,
+        byte_size: usize,
+        cursor: Option<&IterCursor>,
+        limit: usize,
+    ) -> Result<Page<Vec<u8>>, CursorError> {
+        let scan = ScanKind::KeysOfSize(byte_size);
+        let (root, start_after) = self.resume_scan(cursor, &scan, limit)?;
+        let keys = self.collect_keys_page(byte_size, start_after.as_deref(), limit + 1);
+        Ok(cursor::paginate(
+            keys.into_iter().map(|key| (key.clone(), key)),
+            limit,
+            root,
+            scan,
+        ))
+    }
+
+    // Histogram of value lengths plus the `top_n` largest values, for tracking
+    // down state bloat.
+    pub fn value_size_report(&self, top_n: usize) -> ValueSizeReport {
+        let validator = KeyValidator::new(*self.key_scheme());
+        value_sizes::value_size_report(self.iter(), top_n, &validator, |key, value| {
+            self.get_value_hash(key)
+                .unwrap_or_else(|| crate::hash::keccak256(value))
+        })
+    }
+
+    pub fn count_keys(&self, byte_size: usize) -> usize {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.count_keys(byte_size),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.count_keys(byte_size),
+        }
+    }
+
+    pub fn iter_prefix(&self, prefix: &[u8]) -> EntryRange<'_> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.iter_prefix(prefix),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.iter_prefix(prefix),
+        }
+    }
+
+    pub fn iter_prefix_after(&self, prefix: &[u8], start_after: Option<&[u8]>) -> EntryRange<'_> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.iter_prefix_after(prefix, start_after),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.iter_prefix_after(prefix, start_after),
+        }
+    }
+
+    // A page of `iter`, resumable from the returned cursor.
+    pub fn iter_page(
+        &mut self,
+        cursor: Option<&IterCursor>,
+        limit: usize,
+    ) -> Result<EntryPage, CursorError> {
+        let scan = ScanKind::Entries;
+        let (root, start_after) = self.resume_scan(cursor, &scan, limit)?;
+        let entries = self
+            .iter_prefix_after(&[], start_after.as_deref())
+            .map(|(key, value)| (key.to_vec(), (key.to_vec(), value.to_vec())));
+        Ok(cursor::paginate(entries, limit, root, scan))
+    }
+
+    // A page of `get_storage_keys`, in trie key order like it.
+    pub fn storage_keys_page(
+        &mut self,
+        account_address: &[u8],
+        cursor: Option<&IterCursor>,
+        limit: usize,
+    ) -> Result<Page<Vec<u8>>, CursorError> {
+        let scan = ScanKind::StorageKeys(account_address.to_vec());
+        let (root, start_after) = self.resume_scan(cursor, &scan, limit)?;
+        let scheme = *self.key_scheme();
+        let prefix = scheme.account_storage_prefix_key(account_address);
+        let keys = self
+            .iter_prefix_after(&prefix, start_after.as_deref())
+            .filter_map(|(key, _)| {
+                scheme
+                    .strip_storage_prefix(&prefix, key)
+                    .map(|storage_key| (key.to_vec(), storage_key.to_vec()))
+            });
+        Ok(cursor::paginate(keys, limit, root, scan))
+    }
+
+    fn resume_scan(
+        &mut self,
+        cursor: Option<&IterCursor>,
+        scan: &ScanKind,
+        limit: usize,
+    ) -> Result<(TrieRoot, Option<Vec<u8>>), CursorError> {
+        if limit == 0 {
+            return Err(CursorError::ZeroLimit);
+        }
+        let root = self.current_root_hash();
+        let start_after = cursor
+            .map(|cursor| cursor.resume_key(&root, scan).map(<[u8]>::to_vec))
+            .transpose()?;
+        Ok((root, start_after))
+    }
+
+    pub fn keys_with_prefix(&self, prefix: &[u8]) -> impl Iterator<Item = &[u8]> {
+        self.iter_prefix(prefix).map(|(key, _)| key)
+    }
+
+    pub fn iter(&self) -> EntryRange<'_> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.iter(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.iter(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn accounts_iter(&self) -> AccountIter<'_> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.accounts_iter(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.accounts_iter(),
+        }
+    }
+
+    pub fn key_count(&self) -> usize {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.key_count(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.key_count(),
+        }
+    }
+
+    pub fn total_value_bytes(&self) -> u64 {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.total_value_bytes(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.total_value_bytes(),
+        }
+    }
+
+    pub fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.collect_keys_with_prefix(prefix, limit),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.collect_keys_with_prefix(prefix, limit),
+        }
+    }
+
+    pub fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.get_storage_keys(account_address),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.get_storage_keys(account_address),
         }
     }
 
+    pub fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.get_storage_keys_packed(account_address),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.get_storage_keys_packed(account_address),
+        }
+    }
+
+    pub fn revert_to_root<T: RawStoreAdapter>(
+        &mut self,
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Vec<EntryChange>, String> {
+        let changes = match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.revert_to_root(root_hash, store),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.revert_to_root(root_hash, store),
+        }?;
+        if self.has_hooks() {
+            for change in &changes {
+                self.record_op(&change.to_op());
+            }
+        }
+        Ok(changes)
+    }
+
+    // Tuning knobs of the next engine; the legacy engine has neither cache.
+    #[cfg_attr(not(feature = "next"), allow(unused_variables))]
+    pub fn set_root_history_capacity(&mut self, capacity: usize) {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => {}
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.set_root_history_capacity(capacity),
+        }
+    }
+
+    #[cfg_attr(not(feature = "next"), allow(unused_variables))]
+    pub fn set_storage_cache_capacity(&mut self, capacity: usize) {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => {}
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.set_storage_cache_capacity(capacity),
+        }
+    }
+
+    // Only the next implementation keeps a root history.
+    #[cfg(feature = "next")]
+    pub fn root_history(&self) -> Option<&RootHistory> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => None,
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => Some(trie.root_history()),
+        }
+    }
+
+    // Changes that turn this trie's entries into `other`'s, in key order.
+    pub fn diff(&self, other: &UnitrieCore) -> Vec<EntryChange> {
+        diff_entries(self.iter_prefix(&[]), other.iter_prefix(&[]))
+    }
+
+    pub fn root_node(&mut self) -> Option<TrieNode> {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.root_node(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.root_node(),
+        }
+    }
+
+    pub fn encode_root(&mut self) -> Vec<u8> {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.encode_root(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.encode_root(),
+        }
+    }
+
+    pub fn get_proof(&mut self, key: &[u8]) -> TrieProof {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.get_proof(key),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.get_proof(key),
+        }
+    }
+
+    pub fn get_proofs<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Vec<TrieProof> {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.get_proofs(keys),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.get_proofs(keys),
+        }
+    }
+
+    pub fn get_account_proof<K: AsRef<[u8]>>(
+        &mut self,
+        address: &[u8],
+        storage_keys: &[K],
+    ) -> AccountProof {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.get_account_proof(address, storage_keys),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.get_account_proof(address, storage_keys),
+        }
+    }
+
+    pub fn subtree_digests(&mut self, depth: usize) -> Vec<SubtreeDigest> {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.subtree_digests(depth),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.subtree_digests(depth),
+        }
+    }
+
+    pub fn estimate_proof_size(&mut self, key: &[u8]) -> ProofSizeEstimate {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.estimate_proof_size(key),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.estimate_proof_size(key),
+        }
+    }
+
+    pub fn estimate_multiproof_size<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> ProofSizeEstimate {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.estimate_multiproof_size(keys),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.estimate_multiproof_size(keys),
+        }
+    }
+
     pub fn root_hash(&mut self) -> TrieRoot {
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.root_hash(),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.root_hash(),
         }
     }
 
     pub fn current_root_hash(&mut self) -> TrieRoot {
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.current_root_hash(),
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.current_root_hash(),
         }
     }
 
+    // Fast path for shadow-mode checks; works across implementations. See
+    // `Unitrie::same_state` for why equal content can still compare unequal.
+    pub fn same_state(&mut self, other: &mut Self) -> bool {
+        self.current_root_hash() == other.current_root_hash()
+    }
+
     pub fn save_to_store<T: RawStoreAdapter>(&mut self, store: &mut T) {
         self.save_to_store_with_stats(store);
     }
 
     pub fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
-        match &mut self.inner {
+        let stats = match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => trie.save_to_store_with_stats(store),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.save_to_store_with_stats(store),
+        };
+
+        self.refresh_key_filter();
+        if !self.observers.is_empty() {
+            let root = self.current_root_hash();
+            self.observers.notify_save(&root, &stats);
+        }
+        stats
+    }
+
+    // Saves like `save_to_store_with_stats` and also returns what was written,
+    // for replication layers shipping the delta to replicas.
+    pub fn save_to_store_with_change_set<T: RawStoreAdapter>(
+        &mut self,
+        store: &mut T,
+    ) -> (SaveStats, ChangeSet) {
+        let mut recorder = RecordingStore::new(store);
+        let stats = self.save_to_store_with_stats(&mut recorder);
+        let root = self.current_root_hash();
+        (stats, recorder.finish(root))
+    }
+
+    // Takes what a save would write and hands it to `saver`'s thread, returning
+    // the flush's sequence number. The trie counts as saved once this returns;
+    // the store has the root once the flush's receipt arrives.
+    //
+    // Observers, the flat layer included, hear `on_save` here, when the snapshot
+    // is taken, not when it is written: their state can run ahead of the store.
+    // If a flush fails, the change set from `BackgroundSaver::finish` holds
+    // everything not written, and applying it to the store with
+    // `apply_change_set` brings the store up to the root observers last saw.
+    pub fn save_in_background<S>(
+        &mut self,
+        saver: &mut BackgroundSaver<S>,
+    ) -> Result<u64, BackgroundSaveError>
+    where
+        S: RawStoreAdapter + Send + 'static,
+    {
+        let (_, change_set) = self.save_to_store_with_change_set(&mut DetachedStore);
+        saver.submit(change_set)
+    }
+
+    // Writes the current entries in the experimental v2 encoding and returns
+    // that root. The trie itself, its root and its save bookkeeping stay
+    // RSKIP107; see `codec_v2`.
+    pub fn save_v2_to_store<T: RawStoreAdapter>(
+        &self,
+        store: &mut T,
+        options: &V2SaveOptions,
+    ) -> Result<TrieRoot, String> {
+        let entries = self
+            .iter()
+            .map(|(key, value)| (key.to_vec(), value.to_vec()));
+        crate::codec_v2::save_v2(entries, store, options)
+    }
+
+    // Follower side of `save_to_store_with_change_set`: verifies and writes a
+    // leader's change set, then moves this trie to its root.
+    pub fn apply_change_set<T: RawStoreAdapter>(
+        &mut self,
+        change_set: &ChangeSet,
+        store: &mut T,
+    ) -> Result<Vec<EntryChange>, String> {
+        crate::change_set::apply_change_set(change_set, store)?;
+        self.revert_to_root(&change_set.root, store)
+    }
+
+    // Observers only hear about saves that completed.
+    pub fn save_to_store_with_progress<T, F>(
+        &mut self,
+        store: &mut T,
+        progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<SaveStats, String>
+    where
+        T: RawStoreAdapter,
+        F: FnMut(u64, u64),
+    {
+        let stats = match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => {
+                trie.save_to_store_with_progress(store, progress, cancel)?
+            }
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => {
-                trie.save_to_store(store);
-                trie.last_save_stats()
+                trie.save_to_store_with_progress(store, progress, cancel)?
             }
+        };
+
+        self.refresh_key_filter();
+        if !self.observers.is_empty() {
+            let root = self.current_root_hash();
+            self.observers.notify_save(&root, &stats);
+        }
+        Ok(stats)
+    }
+
+    // Loads the persisted nodes along the paths of `keys` under the current root.
+    pub fn prefetch<T: RawStoreAdapter>(
+        &mut self,
+        keys: &[Vec<u8>],
+        store: &mut T,
+    ) -> Result<(PrefetchCache, PrefetchStats), String> {
+        let root = self.current_root_hash();
+        prefetch::prefetch(&root, keys, store)
+    }
+
+    pub fn estimate_save(&mut self) -> SaveEstimate {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.estimate_save(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.estimate_save(),
+        }
+    }
+
+    // Keys written or deleted since the last save, sorted. The legacy engine
+    // does not track mutations and returns `None`.
+    pub fn dirty_keys(&self) -> Option<Vec<Vec<u8>>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => None,
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => Some(trie.dirty_keys()),
         }
     }
 
+    // Prefixes deleted recursively since the last save; see `dirty_keys`.
+    pub fn dirty_prefixes(&self) -> Option<Vec<Vec<u8>>> {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => None,
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => Some(trie.dirty_prefixes()),
+        }
+    }
+
+    // Always true for the legacy engine, which cannot tell.
+    pub fn has_pending_changes(&self) -> bool {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(_) => true,
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.has_pending_changes(),
+        }
+    }
+
+    pub fn persisted_node_count(&self) -> usize {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.persisted_node_count(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.persisted_node_count(),
+        }
+    }
+
+    pub fn persisted_value_count(&self) -> usize {
+        match &self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.persisted_value_count(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.persisted_value_count(),
+        }
+    }
+
+    pub fn forget_persistence_state(&mut self) {
+        match &mut self.inner {
+            #[cfg(feature = "legacy")]
+            UnitrieCoreInner::Legacy(trie) => trie.forget_persistence_state(),
+            #[cfg(feature = "next")]
+            UnitrieCoreInner::Next(trie) => trie.forget_persistence_state(),
+        }
+    }
+
+    pub fn write_snapshot<W: Write>(&mut self, writer: W) -> Result<SnapshotHeader, String> {
+        checkpoint::write_snapshot(self, writer)
+    }
+
+    pub fn read_snapshot<R: Read>(
+        implementation: UnitrieImplementation,
+        reader: R,
+    ) -> Result<(Self, SnapshotHeader), String> {
+        checkpoint::read_snapshot(implementation, reader)
+    }
+
+    pub fn apply_delta<R: Read>(&mut self, reader: R) -> Result<DeltaHeader, String> {
+        checkpoint::apply_delta(self, reader)
+    }
+
     pub fn snapshot(&mut self) -> TrieSnapshot {
         match &mut self.inner {
+            #[cfg(feature = "legacy")]
             UnitrieCoreInner::Legacy(trie) => TrieSnapshot {
                 root: trie.current_root_hash(),
                 key_count: trie.key_count(),
             },
+            #[cfg(feature = "next")]
             UnitrieCoreInner::Next(trie) => trie.snapshot(),
         }
     }
+
+    // Copies the current state into an immutable `FrozenTrie` other threads can
+    // read, prove against and save; this trie stays writable.
+    pub fn freeze(&mut self) -> FrozenTrie {
+        let root = self.current_root_hash();
+        let entries = self
+            .iter()
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect();
+        FrozenTrie::new(root, entries, *self.config())
+    }
+}
+
+// The value checks of `put`, outside `UnitrieCore` so `update_checked` can run
+// them while the engine is borrowed.
+fn validate_value(
+    empty_values: EmptyValuePolicy,
+    config: &CodecConfig,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), WriteError> {
+    if value.is_empty() && empty_values == EmptyValuePolicy::Reject {
+        return Err(WriteError::EmptyValue { key: key.to_vec() });
+    }
+    config
+        .check_value_length(value.len())
+        .map_err(|error| WriteError::ValueTooLarge {
+            key: key.to_vec(),
+            error,
+        })
+}
+
+// Content equality across implementations; observers and key filters are ignored.
+impl PartialEq for UnitrieCore {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_count() == other.key_count() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for UnitrieCore {}
+
+impl TrieEngine for UnitrieCore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(key)
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.put(key, value);
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.insert(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.delete(key);
+    }
+
+    fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.delete_returning(key)
+    }
+
+    fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
+        self.delete_recursive(prefix)
+    }
+
+    fn get_value_length(&self, key: &[u8]) -> Option<usize> {
+        self.get_value_length(key)
+    }
+
+    fn get_value_hash(&self, key: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.get_value_hash(key)
+    }
+
+    fn collect_keys(&self, byte_size: usize) -> Vec<Vec<u8>> {
+        self.collect_keys(byte_size)
+    }
+
+    fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        self.collect_keys_page(byte_size, start_after, limit)
+    }
+
+    fn count_keys(&self, byte_size: usize) -> usize {
+        self.count_keys(byte_size)
+    }
+
+    fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.collect_keys_with_prefix(prefix, limit)
+    }
+
+    fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
+        self.get_storage_keys(account_address)
+    }
+
+    fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        self.get_storage_keys_packed(account_address)
+    }
+
+    fn current_root_hash(&mut self) -> [u8; HASH_SIZE] {
+        self.current_root_hash()
+    }
+
+    fn snapshot(&mut self) -> TrieSnapshot {
+        self.snapshot()
+    }
 }
@@ -0,0 +1,300 @@
+use crate::hash::{keccak256, keccak256_batch, Keccak256Hasher};
+use crate::node_ref::{HASH_SIZE, MAX_CONSENSUS_VALUE_LENGTH};
+use crate::store_adapter::RawStoreAdapter;
+use crate::varint;
+use std::io::{Read, Write};
+
+pub const VALUE_CHUNK_SIZE: usize = 1 << 20;
+const MANIFEST_MAGIC: &[u8; 4] = b"UTCV";
+
+// Values past uint24 are stored as chunks keyed by their own hash, plus a
+// manifest under the value hash listing them in order. Smaller values keep the
+// plain one-payload layout every existing store holds.
+pub fn save_value<T: RawStoreAdapter>(
+    store: &mut T,
+    value_hash: &[u8; HASH_SIZE],
+    value: &[u8],
+) -> usize {
+    if value.len() <= MAX_CONSENSUS_VALUE_LENGTH {
+        store.save_raw_value(value_hash, value);
+        return 1;
+    }
+
+    let chunk_count = value.len().div_ceil(VALUE_CHUNK_SIZE);
+    let mut manifest = Vec::with_capacity(
+        MANIFEST_MAGIC.len() + 2 * varint::size_of(u64::MAX) + chunk_count * HASH_SIZE,
+    );
+    manifest.extend_from_slice(MANIFEST_MAGIC);
+    varint::encode_into(value.len() as u64, &mut manifest);
+    varint::encode_into(chunk_count as u64, &mut manifest);
+    let chunks: Vec<&[u8]> = value.chunks(VALUE_CHUNK_SIZE).collect();
+    for (chunk, chunk_hash) in chunks.iter().zip(keccak256_batch(&chunks)) {
+        store.save_raw_value(&chunk_hash, chunk);
+        manifest.extend_from_slice(&chunk_hash);
+    }
+    store.save_raw_value(value_hash, &manifest);
+    chunk_count + 1
+}
+
+// Reads exactly `length` bytes. Values within the consensus limit are saved as
+// one plain payload, like `save_value` does; longer ones have each chunk saved
+// as it arrives and the manifest once the value hash is known. The bytes are
+// returned because the trie keeps values in memory.
+pub fn save_value_from_reader<T: RawStoreAdapter, R: Read>(
+    store: &mut T,
+    mut reader: R,
+    length: usize,
+) -> Result<([u8; HASH_SIZE], Vec<u8>), String> {
+    if length <= MAX_CONSENSUS_VALUE_LENGTH {
+        let mut value = vec![0u8; length];
+        reader
+            .read_exact(&mut value)
+            .map_err(|error| format!("failed to read value: {error}"))?;
+        let value_hash = keccak256(&value);
+        store.save_raw_value(&value_hash, &value);
+        return Ok((value_hash, value));
+    }
+
+    let chunk_count = length.div_ceil(VALUE_CHUNK_SIZE);
+    let mut value = Vec::with_capacity(length);
+    let mut chunk_hashes = Vec::with_capacity(chunk_count * HASH_SIZE);
+    let mut hasher = Keccak256Hasher::new();
+    while value.len() < length {
+        let start = value.len();
+        let end = (start + VALUE_CHUNK_SIZE).min(length);
+        value.resize(end, 0);
+        reader
+            .read_exact(&mut value[start..end])
+            .map_err(|error| format!("failed to read value chunk at byte {start}: {error}"))?;
+        let chunk = &value[start..end];
+        hasher.update(chunk);
+        let chunk_hash = keccak256(chunk);
+        store.save_raw_value(&chunk_hash, chunk);
+        chunk_hashes.extend_from_slice(&chunk_hash);
+    }
+
+    let value_hash = hasher.finalize();
+    let mut manifest = Vec::with_capacity(MANIFEST_MAGIC.len() + 18 + chunk_hashes.len());
+    manifest.extend_from_slice(MANIFEST_MAGIC);
+    varint::encode_into(length as u64, &mut manifest);
+    varint::encode_into(chunk_count as u64, &mut manifest);
+    manifest.extend_from_slice(&chunk_hashes);
+    store.save_raw_value(&value_hash, &manifest);
+    Ok((value_hash, value))
+}
+
+// Copies a stored value to `writer` one chunk at a time. Returns `false` when
+// the value is not in the store; a chunked value whose bytes do not hash to
+// `value_hash` is reported after it has been written.
+pub fn write_value_to<T: RawStoreAdapter, W: Write>(
+    store: &mut T,
+    value_hash: &[u8; HASH_SIZE],
+    expected_length: usize,
+    mut writer: W,
+) -> Result<bool, String> {
+    let Some(stored) = store.load_raw_value(value_hash) else {
+        return Ok(false);
+    };
+    let write_error = |error: std::io::Error| format!("failed to write value: {error}");
+    if stored.len() == expected_length {
+        writer.write_all(&stored).map_err(write_error)?;
+        return Ok(true);
+    }
+
+    let chunk_hashes = parse_manifest(&stored, expected_length)?;
+    let mut hasher = Keccak256Hasher::new();
+    let mut written = 0usize;
+    for chunk_hash in chunk_hashes.chunks_exact(HASH_SIZE) {
+        let chunk = store
+            .load_raw_value(chunk_hash)
+            .ok_or_else(|| "value chunk was not found in store".to_string())?;
+        hasher.update(&chunk);
+        written += chunk.len();
+        writer.write_all(&chunk).map_err(write_error)?;
+    }
+    let actual = hasher.finalize();
+    if written != expected_length || actual != *value_hash {
+        return Err("chunked value does not match its hash".to_string());
+    }
+    Ok(true)
+}
+
+// `expected_length` is the length recorded in the node, when the codec has one.
+// A payload of that length is the value itself; anything else must be a
+// manifest whose chunks reassemble to the expected hash.
+pub fn load_value<T: RawStoreAdapter>(
+    store: &mut T,
+    value_hash: &[u8; HASH_SIZE],
+    expected_length: Option<usize>,
+) -> Result<Option<Vec<u8>>, String> {
+    let Some(stored) = store.load_raw_value(value_hash) else {
+        return Ok(None);
+    };
+    match expected_length {
+        Some(length) if stored.len() != length => {
+            load_chunked(store, value_hash, &stored, length).map(Some)
+        }
+        _ => Ok(Some(stored)),
+    }
+}
+
+pub(crate) fn load_chunked<T: RawStoreAdapter>(
+    store: &mut T,
+    value_hash: &[u8; HASH_SIZE],
+    manifest: &[u8],
+    expected_length: usize,
+) -> Result<Vec<u8>, String> {
+    let chunk_hashes = parse_manifest(manifest, expected_length)?;
+    let mut value = Vec::with_capacity(expected_length);
+    for chunk_hash in chunk_hashes.chunks_exact(HASH_SIZE) {
+        let chunk = store
+            .load_raw_value(chunk_hash)
+            .ok_or_else(|| "value chunk was not found in store".to_string())?;
+        value.extend_from_slice(&chunk);
+    }
+    if value.len() != expected_length || keccak256(&value) != *value_hash {
+        return Err("chunked value does not match its hash".to_string());
+    }
+    Ok(value)
+}
+
+// The length a manifest declares, `None` for payloads that are not one.
+pub(crate) fn manifest_length(payload: &[u8]) -> Option<usize> {
+    if !payload.starts_with(MANIFEST_MAGIC) {
+        return None;
+    }
+    let mut offset = MANIFEST_MAGIC.len();
+    let length = varint::decode_from_slice(payload, &mut offset).ok()?;
+    usize::try_from(length).ok()
+}
+
+// The concatenated chunk hashes a manifest lists, `None` for payloads that are
+// not one.
+pub(crate) fn manifest_chunks(payload: &[u8]) -> Option<&[u8]> {
+    parse_manifest(payload, manifest_length(payload)?).ok()
+}
+
+// Returns the concatenated chunk hashes.
+fn parse_manifest(manifest: &[u8], expected_length: usize) -> Result<&[u8], String> {
+    if !manifest.starts_with(MANIFEST_MAGIC) {
+        return Err("chunked value manifest has no magic".to_string());
+    }
+    let mut offset = MANIFEST_MAGIC.len();
+    let length = varint::decode_from_slice(manifest, &mut offset)?;
+    let chunk_count = varint::decode_from_slice(manifest, &mut offset)?;
+    if length != expected_length as u64 {
+        return Err(format!(
+            "chunked value manifest declares {length} bytes, node says {expected_length}"
+        ));
+    }
+    if chunk_count != expected_length.div_ceil(VALUE_CHUNK_SIZE) as u64
+        || manifest.len() - offset != chunk_count as usize * HASH_SIZE
+    {
+        return Err("chunked value manifest has the wrong number of chunks".to_string());
+    }
+    Ok(&manifest[offset..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_value_to, VALUE_CHUNK_SIZE};
+    use crate::core_trie::Unitrie;
+    use crate::hash::keccak256;
+    use crate::node_ref::{CodecConfig, ValueTooLarge, MAX_CONSENSUS_VALUE_LENGTH};
+    use crate::store_adapter::MemoryStore;
+    use std::io::Cursor;
+
+    #[test]
+    fn oversized_values_are_rejected_or_chunked() {
+        let oversized: Vec<u8> = (0..MAX_CONSENSUS_VALUE_LENGTH + 2)
+            .map(|index| (index % 251) as u8)
+            .collect();
+
+        let mut consensus = Unitrie::new();
+        assert_eq!(
+            consensus.try_put(vec![1], oversized.clone()),
+            Err(ValueTooLarge {
+                length: oversized.len(),
+                max_length: MAX_CONSENSUS_VALUE_LENGTH,
+            })
+        );
+        assert_eq!(consensus.get(&[1]), None);
+
+        let mut trie =
+            Unitrie::new().with_config(CodecConfig::CONSENSUS.with_extended_values(true));
+        trie.try_put(vec![1], oversized.clone()).unwrap();
+        trie.put(vec![2], vec![7; 40]);
+        let root = trie.root_hash();
+        let mut store = MemoryStore::new();
+        trie.save_to_store(&mut store);
+
+        // One manifest and 17 chunks for the oversized value, one plain payload
+        // for the small one.
+        let chunks = oversized.len().div_ceil(VALUE_CHUNK_SIZE);
+        assert_eq!(store.value_count(), 1 + chunks + 1);
+        assert!(store
+            .values()
+            .all(|(_, value)| value.len() <= VALUE_CHUNK_SIZE));
+
+        let restored = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+        assert_eq!(restored.get(&[1]), Some(oversized));
+        assert_eq!(restored.get(&[2]), Some(vec![7; 40]));
+    }
+
+    #[test]
+    fn streamed_values_within_the_consensus_limit_are_stored_raw() {
+        for length in [40, 3 * VALUE_CHUNK_SIZE + 17] {
+            let blob: Vec<u8> = (0..length).map(|index| (index % 251) as u8).collect();
+            let mut store = MemoryStore::new();
+            let mut trie = Unitrie::new();
+            trie.put_value_from_reader(vec![5], Cursor::new(&blob), blob.len(), &mut store)
+                .unwrap();
+            assert_eq!(store.value(&keccak256(&blob)), Some(&blob[..]));
+
+            let root = trie.root_hash();
+            trie.save_to_store(&mut store);
+            assert_eq!(store.value_count(), 1);
+            let restored = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+            assert_eq!(restored.get(&[5]), Some(blob));
+        }
+    }
+
+    #[test]
+    fn streamed_values_reach_the_store_in_chunks() {
+        let blob: Vec<u8> = (0..MAX_CONSENSUS_VALUE_LENGTH + 17)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        let chunks = blob.len().div_ceil(VALUE_CHUNK_SIZE);
+        let mut store = MemoryStore::new();
+        let mut trie =
+            Unitrie::new().with_config(CodecConfig::CONSENSUS.with_extended_values(true));
+        trie.put_value_from_reader(vec![5], Cursor::new(&blob), blob.len(), &mut store)
+            .unwrap();
+        assert_eq!(store.value_count(), chunks + 1);
+        assert!(store
+            .values()
+            .all(|(_, value)| value.len() <= VALUE_CHUNK_SIZE));
+
+        // The next save does not rewrite the value as a single payload.
+        let root = trie.root_hash();
+        trie.save_to_store(&mut store);
+        assert_eq!(store.value_count(), chunks + 1);
+
+        let mut copy = Vec::new();
+        assert!(trie.get_value_to_writer(&[5], &mut copy).unwrap());
+        assert_eq!(copy, blob);
+
+        let restored = Unitrie::from_persisted_root(&root, &mut store).unwrap();
+        assert_eq!(restored.get(&[5]).as_ref(), Some(&blob));
+
+        let mut streamed = Vec::new();
+        assert!(write_value_to(&mut store, &keccak256(&blob), blob.len(), &mut streamed).unwrap());
+        assert_eq!(streamed, blob);
+
+        let short = Cursor::new(vec![1u8; 10]);
+        assert!(trie
+            .put_value_from_reader(vec![6], short, 100, &mut store)
+            .is_err());
+        assert_eq!(trie.get(&[6]), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::store_adapter::{CodecHint, IterableStore, RawStoreAdapter};
+use crate::varint;
+
+// Prefixes every hash with a length-delimited namespace tag, so logical spaces
+// sharing one physical store can never address each other's entries.
+#[derive(Debug)]
+pub struct NamespacedStore<S> {
+    inner: S,
+    namespace: Vec<u8>,
+    prefix: Vec<u8>,
+}
+
+impl<S: RawStoreAdapter> NamespacedStore<S> {
+    pub fn new(inner: S, namespace: impl Into<Vec<u8>>) -> Self {
+        let namespace = namespace.into();
+        let prefix = namespace_prefix(&namespace);
+        Self {
+            inner,
+            namespace,
+            prefix,
+        }
+    }
+
+    pub fn namespace(&self) -> &[u8] {
+        &self.namespace
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    // The physical key under which `hash` is stored for this namespace.
+    pub fn physical_key(&self, hash: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.prefix.len() + hash.len());
+        key.extend_from_slice(&self.prefix);
+        key.extend_from_slice(hash);
+        key
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for NamespacedStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let key = self.physical_key(hash);
+        self.inner.load_raw_node(&key)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let key = self.physical_key(hash);
+        self.inner.load_raw_value(&key)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        let key = self.physical_key(hash);
+        self.inner.save_raw_node(&key, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        let key = self.physical_key(hash);
+        self.inner.save_raw_value(&key, value);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        let key = self.physical_key(hash);
+        self.inner.contains_raw_value(&key)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+// Lists this namespace's hashes only, with the namespace prefix stripped.
+impl<S: IterableStore> IterableStore for NamespacedStore<S> {
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        Box::new(strip_namespace(self.inner.iter_node_hashes(), &self.prefix))
+    }
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        Box::new(strip_namespace(
+            self.inner.iter_value_hashes(),
+            &self.prefix,
+        ))
+    }
+}
+
+fn strip_namespace<'a>(
+    keys: impl Iterator<Item = Vec<u8>> + 'a,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = Vec<u8>> + 'a {
+    keys.filter_map(move |key| key.strip_prefix(prefix).map(<[u8]>::to_vec))
+}
+
+fn namespace_prefix(namespace: &[u8]) -> Vec<u8> {
+    let mut prefix = varint::encode(namespace.len() as u64);
+    prefix.extend_from_slice(namespace);
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NamespacedStore;
+    use crate::core_trie::Unitrie;
+    use crate::orphans::find_orphans;
+    use crate::store_adapter::{IterableStore, MemoryStore, RawStoreAdapter};
+
+    #[test]
+    fn namespaces_share_a_store_without_seeing_each_other() {
+        let mut store = MemoryStore::new();
+        let mut trie = Unitrie::new();
+        trie.put(b"account".to_vec(), vec![0x11; 64]);
+        trie.put(b"balance".to_vec(), b"10".to_vec());
+        let root = trie.root_hash();
+
+        trie.save_to_store(&mut NamespacedStore::new(&mut store, "state"));
+        trie.save_to_store(&mut NamespacedStore::new(&mut store, "fixtures"));
+        assert!(store.load_raw_node(&root).is_none());
+
+        let mut state = NamespacedStore::new(&mut store, "state");
+        let restored = Unitrie::from_persisted_root(&root, &mut state).unwrap();
+        assert_eq!(restored.get(b"account"), Some(vec![0x11; 64]));
+
+        let mut other = NamespacedStore::new(&mut store, "receipts");
+        assert!(Unitrie::from_persisted_root(&root, &mut other).is_err());
+    }
+
+    #[test]
+    fn namespace_tags_are_length_delimited() {
+        let short = NamespacedStore::new(MemoryStore::new(), "a");
+        let long = NamespacedStore::new(MemoryStore::new(), "ab");
+        assert_ne!(short.physical_key(b"bc"), long.physical_key(b"c"));
+    }
+
+    #[test]
+    fn namespaces_list_only_their_own_hashes() {
+        let mut store = MemoryStore::new();
+        let mut state = Unitrie::new();
+        state.put(b"account".to_vec(), vec![0x11; 64]);
+        state.save_to_store(&mut NamespacedStore::new(&mut store, "state"));
+        let mut fixtures = Unitrie::new();
+        fixtures.put(b"balance".to_vec(), b"10".to_vec());
+        fixtures.save_to_store(&mut NamespacedStore::new(&mut store, "fixtures"));
+
+        let root = fixtures.root_hash();
+        let mut namespace = NamespacedStore::new(&mut store, "fixtures");
+        assert_eq!(namespace.iter_value_hashes().count(), 0);
+        let report = find_orphans(&mut namespace, &[root]).unwrap();
+        assert_eq!((report.missing, report.orphan_count()), (0, 0));
+        assert_eq!(report.reachable_nodes, namespace.iter_node_hashes().count());
+
+        let namespace = NamespacedStore::new(&mut store, "state");
+        assert_eq!(namespace.iter_value_hashes().count(), 1);
+    }
+}
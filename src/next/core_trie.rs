@@ -1,25 +1,64 @@
-use crate::core_api::{TrieEngine, TrieSnapshot};
-use crate::core_trie::{SaveStats, Unitrie};
+use crate::core_api::{PersistentTrieEngine, TrieEngine, TrieOp, TrieSnapshot};
+use crate::core_trie::{
+    AccountIter, CancellationToken, EntryRange, LoadError, LoadLimits, SaveEstimate, SaveStats,
+    SubtreeDigest, Unitrie,
+};
+use crate::diff::EntryChange;
+use crate::key_scheme::KeyScheme;
 use crate::next::hashing::IncrementalHashState;
-use crate::next::iter::collect_exact_size_keys;
-use crate::next::mutation::MutationGeneration;
-use crate::next::node_arena::NodeArena;
+use crate::next::iter::{
+    collect_exact_size_keys, collect_exact_size_keys_page, count_exact_size_keys,
+};
+use crate::next::mutation::{DirtyKeys, MutationGeneration};
+use crate::next::node::NextValueRef;
+use crate::next::node_tree::NodeTree;
 use crate::next::persistence::IncrementalPersistence;
+use crate::next::root_history::{RootHistory, RootRecord};
 use crate::next::storage_iteration_cache::StorageIterationCache;
-use crate::node_ref::HASH_SIZE;
+use crate::node_ref::{CodecConfig, TrieNode, ValueTooLarge, HASH_SIZE};
+use crate::proof::{AccountProof, ProofSizeEstimate, TrieProof};
 use crate::storage_keys_packed;
-use crate::store_adapter::RawStoreAdapter;
-use std::sync::Arc;
+use crate::store_adapter::{RawStoreAdapter, SharedStoreAdapter};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[derive(Debug, Default, Clone)]
 pub struct NextUnitrie {
+    // Entries, persistence and proofs; the root hash comes from `nodes`.
     inner: Unitrie,
-    node_arena: NodeArena,
+    nodes: NodeTree,
+    dirty_keys: DirtyKeys,
     hash_state: IncrementalHashState,
     persistence: IncrementalPersistence,
-    storage_iteration_cache: StorageIterationCache,
+    // Behind a Mutex so storage key listings can be cached from `&self` while
+    // the trie stays `Sync`.
+    storage_iteration_cache: SharedStorageIterationCache,
     mutation_generation: MutationGeneration,
     last_save_stats: SaveStats,
+    root_history: RootHistory,
+}
+
+#[derive(Debug, Default)]
+struct SharedStorageIterationCache(Mutex<StorageIterationCache>);
+
+impl Clone for SharedStorageIterationCache {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl SharedStorageIterationCache {
+    fn lock(&self) -> MutexGuard<'_, StorageIterationCache> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn get_mut(&mut self) -> &mut StorageIterationCache {
+        self.0
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 impl NextUnitrie {
@@ -31,13 +70,141 @@ impl NextUnitrie {
         root_hash: &[u8],
         store: &mut T,
     ) -> Result<Self, String> {
-        let inner = Unitrie::from_persisted_root(root_hash, store)?;
+        Self::from_persisted_root_with_limits(root_hash, store, LoadLimits::default())
+            .map_err(|error| error.to_string())
+    }
+
+    pub fn from_persisted_root_with_limits<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError> {
+        Self::from_persisted_root_with_config(root_hash, store, limits, CodecConfig::CONSENSUS)
+    }
+
+    pub fn from_persisted_root_with_config<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError> {
+        let inner = Unitrie::from_persisted_root_with_config(root_hash, store, limits, config)?;
+        Ok(Self::from_loaded(inner))
+    }
+
+    pub fn from_persisted_root_parallel<S>(
+        root_hash: &[u8],
+        store: &S,
+        threads: usize,
+        limits: LoadLimits,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        Self::from_persisted_root_parallel_with_config(
+            root_hash,
+            store,
+            threads,
+            limits,
+            CodecConfig::CONSENSUS,
+        )
+    }
+
+    pub fn from_persisted_root_parallel_with_config<S>(
+        root_hash: &[u8],
+        store: &S,
+        threads: usize,
+        limits: LoadLimits,
+        config: CodecConfig,
+    ) -> Result<Self, LoadError>
+    where
+        S: SharedStoreAdapter + ?Sized,
+    {
+        let inner = Unitrie::from_persisted_root_parallel_with_config(
+            root_hash, store, threads, limits, config,
+        )?;
+        Ok(Self::from_loaded(inner))
+    }
+
+    // Wraps a legacy trie, keeping its caches and persistence bookkeeping. The
+    // root hash is computed on first use.
+    pub fn from_unitrie(inner: Unitrie) -> Self {
         let mut this = Self {
             inner,
             ..Self::default()
         };
+        this.rebuild_nodes();
+        this
+    }
+
+    // Drops the next-only state: dirty keys, root history and cached storage keys.
+    pub fn into_unitrie(self) -> Unitrie {
+        self.inner
+    }
+
+    fn from_loaded(inner: Unitrie) -> Self {
+        let mut this = Self {
+            inner,
+            ..Self::default()
+        };
+        this.rebuild_nodes();
         this.hash_state.update(this.inner.current_root_hash());
-        Ok(this)
+        this
+    }
+
+    pub fn from_sorted_entries<I>(entries: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let inner = Unitrie::from_sorted_entries(entries)?;
+        Ok(Self::from_loaded(inner))
+    }
+
+    pub fn with_config(mut self, config: CodecConfig) -> Self {
+        if config != *self.inner.config() {
+            self.inner = self.inner.with_config(config);
+            // Which values nodes hold inline depends on the config.
+            self.rebuild_nodes();
+            self.hash_state.invalidate();
+            self.persistence = IncrementalPersistence::default();
+        }
+        self
+    }
+
+    pub fn config(&self) -> &CodecConfig {
+        self.inner.config()
+    }
+
+    // Cached storage key listings were extracted under the old scheme.
+    pub fn with_key_scheme(mut self, key_scheme: KeyScheme) -> Self {
+        if key_scheme != *self.inner.key_scheme() {
+            self.inner = self.inner.with_key_scheme(key_scheme);
+            self.bump_mutation_generation();
+        }
+        self
+    }
+
+    pub fn key_scheme(&self) -> &KeyScheme {
+        self.inner.key_scheme()
+    }
+
+    pub fn extend_sorted<I>(&mut self, entries: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        self.bump_mutation_generation();
+        self.hash_state.invalidate();
+        let mut written = Vec::new();
+        let result = self.inner.extend_sorted(
+            entries
+                .into_iter()
+                .inspect(|(key, _)| written.push(key.clone())),
+        );
+        for key in &written {
+            self.dirty_keys.mark(key);
+            self.sync_node(key);
+        }
+        result
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
@@ -49,24 +216,144 @@ impl NextUnitrie {
     }
 
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
-        self.bump_mutation_generation();
-        self.node_arena.mark_dirty_key(&key);
-        self.hash_state.invalidate();
-        self.inner.put(key, value);
+        self.record_mutation(&key);
+        self.inner.put(key.clone(), value);
+        self.sync_node(&key);
+    }
+
+    pub fn try_put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ValueTooLarge> {
+        self.config().check_value_length(value.len())?;
+        self.put(key, value);
+        Ok(())
+    }
+
+    pub fn put_value_from_reader<R: Read, T: RawStoreAdapter>(
+        &mut self,
+        key: Vec<u8>,
+        reader: R,
+        length: usize,
+        store: &mut T,
+    ) -> Result<(), String> {
+        self.record_mutation(&key);
+        let result = self
+            .inner
+            .put_value_from_reader(key.clone(), reader, length, store);
+        self.sync_node(&key);
+        result
+    }
+
+    pub fn get_value_to_writer<W: Write>(&self, key: &[u8], writer: W) -> io::Result<bool> {
+        self.inner.get_value_to_writer(key, writer)
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.record_mutation(&key);
+        let previous = self.inner.insert(key.clone(), value);
+        self.sync_node(&key);
+        previous
+    }
+
+    pub fn update<F>(&mut self, key: Vec<u8>, f: F)
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        self.record_mutation(&key);
+        self.inner.update(key.clone(), f);
+        self.sync_node(&key);
     }
 
     pub fn delete(&mut self, key: &[u8]) {
+        self.record_mutation(key);
+        self.inner.delete(key);
+        self.nodes.remove(key);
+    }
+
+    pub fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.record_mutation(key);
+        let previous = self.inner.delete_returning(key);
+        self.nodes.remove(key);
+        previous
+    }
+
+    // Resets everything but the config and the root history, whose roots stay
+    // valid in the stores they were saved to.
+    pub fn clear(&mut self) {
+        let had_entries = self.inner.key_count() > 0;
+        self.inner.clear();
+        self.nodes = NodeTree::new();
+        self.dirty_keys.clear();
+        if had_entries {
+            self.dirty_keys.mark_prefix(&[]);
+        }
+        self.hash_state.invalidate();
+        self.persistence = IncrementalPersistence::default();
+        self.storage_iteration_cache.get_mut().clear();
+        self.mutation_generation = MutationGeneration::default();
+        self.last_save_stats = SaveStats::default();
+    }
+
+    pub fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
         self.bump_mutation_generation();
-        self.node_arena.mark_dirty_key(key);
+        self.dirty_keys.mark_prefix(prefix);
         self.hash_state.invalidate();
-        self.inner.delete(key);
+        self.nodes.remove_prefix(prefix);
+        self.inner.delete_recursive(prefix)
+    }
+
+    pub fn put_storage_value(&mut self, address: &[u8], slot: &[u8], value: Vec<u8>) {
+        let storage_key = self.key_scheme().storage_key(address, slot);
+        self.put(storage_key, value);
     }
 
-    pub fn delete_recursive(&mut self, prefix: &[u8]) {
+    pub fn get_storage_value(&self, address: &[u8], slot: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get_storage_value(address, slot)
+    }
+
+    pub fn put_code(&mut self, address: &[u8], code: Vec<u8>) {
+        let code_key = self.key_scheme().code_key(address);
+        self.put(code_key, code);
+    }
+
+    pub fn get_code(&self, address: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get_code(address)
+    }
+
+    pub fn get_code_hash(&self, address: &[u8]) -> Option<[u8; HASH_SIZE]> {
+        self.inner.get_code_hash(address)
+    }
+
+    pub fn delete_account(&mut self, address: &[u8]) -> usize {
+        let account_key = self.key_scheme().account_key(address);
+        self.delete_recursive(&account_key)
+    }
+
+    // Applies all operations with a single generation bump and hash invalidation.
+    pub fn apply_batch<I>(&mut self, ops: I)
+    where
+        I: IntoIterator<Item = TrieOp>,
+    {
+        let mut ops = ops.into_iter().peekable();
+        if ops.peek().is_none() {
+            return;
+        }
+
         self.bump_mutation_generation();
-        self.node_arena.mark_dirty_key(prefix);
         self.hash_state.invalidate();
-        self.inner.delete_recursive(prefix);
+        let mut prefixes = Vec::new();
+        let mut keys = Vec::new();
+        self.inner.apply_batch(ops.inspect(|op| match op {
+            TrieOp::DeleteRecursive { prefix } => prefixes.push(prefix.clone()),
+            _ => keys.push(op.key().to_vec()),
+        }));
+        // Later puts may refill a deleted prefix, so keys sync last.
+        for prefix in &prefixes {
+            self.dirty_keys.mark_prefix(prefix);
+            self.nodes.remove_prefix(prefix);
+        }
+        for key in &keys {
+            self.dirty_keys.mark(key);
+            self.sync_node(key);
+        }
     }
 
     pub fn get_value_length(&self, key: &[u8]) -> Option<usize> {
@@ -81,23 +368,107 @@ impl NextUnitrie {
         collect_exact_size_keys(self.inner.keys(), byte_size)
     }
 
-    pub fn get_storage_keys(&mut self, account_address: &[u8]) -> Vec<Vec<u8>> {
+    pub fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        collect_exact_size_keys_page(self.inner.keys_after(start_after), byte_size, limit)
+    }
+
+    pub fn count_keys(&self, byte_size: usize) -> usize {
+        count_exact_size_keys(self.inner.keys(), byte_size)
+    }
+
+    pub fn iter_prefix(&self, prefix: &[u8]) -> EntryRange<'_> {
+        self.inner.iter_prefix(prefix)
+    }
+
+    pub fn iter_prefix_after(&self, prefix: &[u8], start_after: Option<&[u8]>) -> EntryRange<'_> {
+        self.inner.iter_prefix_after(prefix, start_after)
+    }
+
+    pub fn iter(&self) -> EntryRange<'_> {
+        self.inner.iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.inner.keys()
+    }
+
+    pub fn accounts_iter(&self) -> AccountIter<'_> {
+        self.inner.accounts_iter()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.inner.key_count()
+    }
+
+    pub fn total_value_bytes(&self) -> u64 {
+        self.inner.total_value_bytes()
+    }
+
+    pub fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.inner.collect_keys_with_prefix(prefix, limit)
+    }
+
+    pub fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
         self.storage_keys_bundle_for_account(account_address)
             .0
             .as_ref()
             .clone()
     }
 
-    pub fn get_storage_keys_packed(&mut self, account_address: &[u8]) -> Arc<Vec<u8>> {
+    pub fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
         self.storage_keys_bundle_for_account(account_address).1
     }
 
+    pub fn root_node(&mut self) -> Option<TrieNode> {
+        self.inner.root_node()
+    }
+
+    pub fn encode_root(&mut self) -> Vec<u8> {
+        self.inner.encode_root()
+    }
+
+    pub fn get_proof(&mut self, key: &[u8]) -> TrieProof {
+        self.inner.get_proof(key)
+    }
+
+    pub fn get_proofs<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Vec<TrieProof> {
+        self.inner.get_proofs(keys)
+    }
+
+    pub fn get_account_proof<K: AsRef<[u8]>>(
+        &mut self,
+        address: &[u8],
+        storage_keys: &[K],
+    ) -> AccountProof {
+        self.inner.get_account_proof(address, storage_keys)
+    }
+
+    pub fn subtree_digests(&mut self, depth: usize) -> Vec<SubtreeDigest> {
+        self.inner.subtree_digests(depth)
+    }
+
+    pub fn estimate_proof_size(&mut self, key: &[u8]) -> ProofSizeEstimate {
+        self.inner.estimate_proof_size(key)
+    }
+
+    pub fn estimate_multiproof_size<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> ProofSizeEstimate {
+        self.inner.estimate_multiproof_size(keys)
+    }
+
     pub fn root_hash(&mut self) -> [u8; HASH_SIZE] {
         if let Some(cached) = self.hash_state.root_hash() {
             return cached;
         }
 
-        let root = self.inner.root_hash();
+        let root = self
+            .nodes
+            .root_hash(self.inner.config())
+            .expect("tree nodes built from entries should be encodable");
         self.hash_state.update(root);
         root
     }
@@ -106,6 +477,10 @@ impl NextUnitrie {
         self.root_hash()
     }
 
+    pub fn same_state(&mut self, other: &mut Self) -> bool {
+        self.root_hash() == other.root_hash()
+    }
+
     pub fn snapshot(&mut self) -> TrieSnapshot {
         TrieSnapshot {
             root: self.current_root_hash(),
@@ -114,38 +489,158 @@ impl NextUnitrie {
     }
 
     pub fn save_to_store<T: RawStoreAdapter>(&mut self, store: &mut T) {
-        self.last_save_stats =
-            self.persistence
-                .save(&mut self.inner, store, self.node_arena.dirty_count());
-        self.node_arena.clear_dirty();
-        self.hash_state.update(self.inner.current_root_hash());
+        self.save_to_store_with_stats(store);
+    }
+
+    pub fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+        self.last_save_stats = self
+            .persistence
+            .save(&mut self.inner, store, self.dirty_keys.len());
+        self.dirty_keys.clear();
+        let root = self.inner.current_root_hash();
+        self.hash_state.update(root);
+        self.root_history.record(RootRecord {
+            root,
+            key_count: self.inner.key_count(),
+            save_stats: self.last_save_stats,
+        });
+        self.last_save_stats
+    }
+
+    // A cancelled save keeps the keys dirty and records no root.
+    pub fn save_to_store_with_progress<T, F>(
+        &mut self,
+        store: &mut T,
+        progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<SaveStats, String>
+    where
+        T: RawStoreAdapter,
+        F: FnMut(u64, u64),
+    {
+        self.last_save_stats = self.persistence.save_with_progress(
+            &mut self.inner,
+            store,
+            self.dirty_keys.len(),
+            progress,
+            cancel,
+        )?;
+        self.dirty_keys.clear();
+        let root = self.inner.current_root_hash();
+        self.hash_state.update(root);
+        self.root_history.record(RootRecord {
+            root,
+            key_count: self.inner.key_count(),
+            save_stats: self.last_save_stats,
+        });
+        Ok(self.last_save_stats)
+    }
+
+    pub fn revert_to_root<T: RawStoreAdapter>(
+        &mut self,
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Vec<EntryChange>, String> {
+        let changes = self.inner.revert_to_root(root_hash, store)?;
+        if !changes.is_empty() {
+            self.bump_mutation_generation();
+            for change in &changes {
+                self.dirty_keys.mark(change.key());
+                self.sync_node(change.key());
+            }
+            self.hash_state.update(self.inner.current_root_hash());
+        }
+        Ok(changes)
+    }
+
+    pub fn root_history(&self) -> &RootHistory {
+        &self.root_history
+    }
+
+    pub fn set_root_history_capacity(&mut self, capacity: usize) {
+        self.root_history.set_capacity(capacity);
+    }
+
+    // Accounts whose storage key listings are cached.
+    pub fn set_storage_cache_capacity(&mut self, capacity: usize) {
+        self.storage_iteration_cache
+            .get_mut()
+            .set_capacity(capacity);
+    }
+
+    pub fn estimate_save(&mut self) -> SaveEstimate {
+        self.persistence
+            .estimate(&mut self.inner, self.dirty_keys.len())
     }
 
     pub fn last_save_stats(&self) -> SaveStats {
         self.last_save_stats
     }
 
+    // Keys written or deleted since the last save, sorted. Touching a key and
+    // restoring its old value still leaves it dirty.
+    pub fn dirty_keys(&self) -> Vec<Vec<u8>> {
+        self.dirty_keys.sorted_keys()
+    }
+
+    // Prefixes deleted recursively since the last save, sorted; the empty
+    // prefix after `clear`.
+    pub fn dirty_prefixes(&self) -> Vec<Vec<u8>> {
+        self.dirty_keys.sorted_prefixes()
+    }
+
+    pub fn has_pending_changes(&self) -> bool {
+        !self.dirty_keys.is_empty()
+    }
+
+    pub fn persisted_node_count(&self) -> usize {
+        self.inner.persisted_node_count()
+    }
+
+    pub fn persisted_value_count(&self) -> usize {
+        self.inner.persisted_value_count()
+    }
+
+    // Also forgets the last saved root, which would otherwise skip an unchanged save.
+    pub fn forget_persistence_state(&mut self) {
+        self.inner.forget_persistence_state();
+        self.persistence = IncrementalPersistence::default();
+    }
+
     fn storage_keys_bundle_for_account(
-        &mut self,
+        &self,
         account_address: &[u8],
     ) -> (Arc<Vec<Vec<u8>>>, Arc<Vec<u8>>) {
+        let generation = self.mutation_generation.current();
+        let mut cache = self.storage_iteration_cache.lock();
         if let (Some(cached_keys), Some(cached_packed)) = (
-            self.storage_iteration_cache
-                .get_keys(account_address, self.mutation_generation.current()),
-            self.storage_iteration_cache
-                .get_packed(account_address, self.mutation_generation.current()),
+            cache.get_keys(account_address, generation),
+            cache.get_packed(account_address, generation),
         ) {
             return (cached_keys, cached_packed);
         }
 
         let keys = Arc::new(self.inner.get_storage_keys(account_address));
         let packed = Arc::new(storage_keys_packed::encode(keys.as_ref()));
-        self.storage_iteration_cache.insert(
-            account_address.to_vec(),
-            self.mutation_generation.current(),
-            keys,
-            packed,
-        )
+        cache.insert(account_address.to_vec(), generation, keys, packed)
+    }
+
+    // Copies the key's current value from the inner trie into the node tree.
+    fn sync_node(&mut self, key: &[u8]) {
+        self.nodes.set(key, node_value(&self.inner, key));
+    }
+
+    fn rebuild_nodes(&mut self) {
+        self.nodes = NodeTree::new();
+        for key in self.inner.keys() {
+            self.nodes.set(key, node_value(&self.inner, key));
+        }
+    }
+
+    fn record_mutation(&mut self, key: &[u8]) {
+        self.bump_mutation_generation();
+        self.dirty_keys.mark(key);
+        self.hash_state.invalidate();
     }
 
     fn bump_mutation_generation(&mut self) {
@@ -153,6 +648,28 @@ impl NextUnitrie {
     }
 }
 
+// Long values are held by hash, which the inner trie caches.
+fn node_value(inner: &Unitrie, key: &[u8]) -> NextValueRef {
+    match inner.get_ref(key) {
+        None => NextValueRef::Empty,
+        Some(value) if value.len() > inner.config().long_value_threshold => NextValueRef::Hashed {
+            hash: inner
+                .get_value_hash(key)
+                .expect("present key has a value hash"),
+            length: value.len(),
+        },
+        Some(value) => NextValueRef::Inline(value.to_vec()),
+    }
+}
+
+impl PartialEq for NextUnitrie {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Eq for NextUnitrie {}
+
 impl TrieEngine for NextUnitrie {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key)
@@ -162,12 +679,20 @@ impl TrieEngine for NextUnitrie {
         self.put(key, value);
     }
 
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.insert(key, value)
+    }
+
     fn delete(&mut self, key: &[u8]) {
         self.delete(key);
     }
 
-    fn delete_recursive(&mut self, prefix: &[u8]) {
-        self.delete_recursive(prefix);
+    fn delete_returning(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.delete_returning(key)
+    }
+
+    fn delete_recursive(&mut self, prefix: &[u8]) -> usize {
+        self.delete_recursive(prefix)
     }
 
     fn get_value_length(&self, key: &[u8]) -> Option<usize> {
@@ -182,10 +707,31 @@ impl TrieEngine for NextUnitrie {
         self.collect_keys(byte_size)
     }
 
-    fn get_storage_keys(&mut self, account_address: &[u8]) -> Vec<Vec<u8>> {
+    fn collect_keys_page(
+        &self,
+        byte_size: usize,
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Vec<u8>> {
+        self.collect_keys_page(byte_size, start_after, limit)
+    }
+
+    fn count_keys(&self, byte_size: usize) -> usize {
+        self.count_keys(byte_size)
+    }
+
+    fn collect_keys_with_prefix(&self, prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+        self.collect_keys_with_prefix(prefix, limit)
+    }
+
+    fn get_storage_keys(&self, account_address: &[u8]) -> Vec<Vec<u8>> {
         self.get_storage_keys(account_address)
     }
 
+    fn get_storage_keys_packed(&self, account_address: &[u8]) -> Arc<Vec<u8>> {
+        self.get_storage_keys_packed(account_address)
+    }
+
     fn current_root_hash(&mut self) -> [u8; HASH_SIZE] {
         self.current_root_hash()
     }
@@ -194,3 +740,16 @@ impl TrieEngine for NextUnitrie {
         self.snapshot()
     }
 }
+
+impl PersistentTrieEngine for NextUnitrie {
+    fn save_to_store_with_stats<T: RawStoreAdapter>(&mut self, store: &mut T) -> SaveStats {
+        self.save_to_store_with_stats(store)
+    }
+
+    fn from_persisted_root<T: RawStoreAdapter>(
+        root_hash: &[u8],
+        store: &mut T,
+    ) -> Result<Self, String> {
+        Self::from_persisted_root(root_hash, store)
+    }
+}
@@ -1,20 +1,201 @@
-use crate::node_ref::HASH_SIZE;
+use crate::codec_rskip107::ChildEncoding;
+use crate::core_trie::encode_node_metadata;
+use crate::next::node::{NextChildRef, NextNode, NextValueRef};
+use crate::next::node_arena::{NodeArena, NodeId};
+use crate::node_ref::{CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef, HASH_SIZE};
+use std::collections::HashMap;
 
+#[derive(Debug, Clone)]
+struct CachedNode {
+    hash: [u8; HASH_SIZE],
+    // How a parent references the node and the subtree size it contributes.
+    encoding: ChildEncoding,
+    reference_size: u64,
+}
+
+// Per-node hashes for arena nodes. A mutation only invalidates the nodes on the
+// path from the root to the changed node, so rehashing after a small change
+// re-encodes O(depth) nodes and reuses every other subtree.
 #[derive(Debug, Default, Clone)]
 pub struct HashCache {
-    cached_root: Option<[u8; HASH_SIZE]>,
+    nodes: HashMap<NodeId, CachedNode>,
+    encoded: u64,
 }
 
 impl HashCache {
-    pub fn invalidate(&mut self) {
-        self.cached_root = None;
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cached_hash(&self, id: NodeId) -> Option<[u8; HASH_SIZE]> {
+        self.nodes.get(&id).map(|cached| cached.hash)
+    }
+
+    // `path` lists the ids from the root down to the mutated node.
+    pub fn invalidate_path(&mut self, path: &[NodeId]) {
+        for id in path {
+            self.nodes.remove(id);
+        }
+    }
+
+    pub fn invalidate(&mut self, id: NodeId) {
+        self.nodes.remove(&id);
     }
 
-    pub fn update_root(&mut self, root_hash: [u8; HASH_SIZE]) {
-        self.cached_root = Some(root_hash);
+    pub fn clear(&mut self) {
+        self.nodes.clear();
     }
 
-    pub fn root_hash(&self) -> Option<[u8; HASH_SIZE]> {
-        self.cached_root
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Nodes encoded since the cache was created, for measuring reuse.
+    pub fn encoded_count(&self) -> u64 {
+        self.encoded
+    }
+
+    pub fn hash(
+        &mut self,
+        arena: &NodeArena,
+        id: NodeId,
+        config: &CodecConfig,
+    ) -> Result<[u8; HASH_SIZE], String> {
+        self.node(arena, id, config).map(|cached| cached.hash)
+    }
+
+    // Children first with an explicit stack, so deep tries cannot overflow the
+    // call stack.
+    fn node(
+        &mut self,
+        arena: &NodeArena,
+        id: NodeId,
+        config: &CodecConfig,
+    ) -> Result<CachedNode, String> {
+        let mut pending = vec![id];
+        while let Some(&next) = pending.last() {
+            if self.nodes.contains_key(&next) {
+                pending.pop();
+                continue;
+            }
+
+            let node = arena
+                .get(next)
+                .ok_or_else(|| "hashed node id is not live in the arena".to_string())?;
+            let unhashed = [&node.left, &node.right]
+                .into_iter()
+                .filter_map(|child| match child {
+                    NextChildRef::InMemory(child) if !self.nodes.contains_key(child) => {
+                        Some(*child)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if !unhashed.is_empty() {
+                pending.extend(unhashed);
+                continue;
+            }
+
+            pending.pop();
+            let left = self.child(&node.left)?;
+            let right = self.child(&node.right)?;
+            let metadata = encode_node_metadata(&as_trie_node(node), left, right, config)?;
+            self.encoded += 1;
+
+            let hash = metadata.hash;
+            let (encoding, reference_size) = metadata.into_child_encoding();
+            self.nodes.insert(
+                next,
+                CachedNode {
+                    hash,
+                    encoding,
+                    reference_size,
+                },
+            );
+        }
+        Ok(self.nodes[&id].clone())
+    }
+
+    // Children are hashed before their parent.
+    fn child(&self, child: &NextChildRef) -> Result<(ChildEncoding, u64), String> {
+        match child {
+            NextChildRef::Empty => Ok((ChildEncoding::Empty, 0)),
+            NextChildRef::InMemory(id) => {
+                let cached = &self.nodes[id];
+                Ok((cached.encoding.clone(), cached.reference_size))
+            }
+            NextChildRef::Hashed(_) => {
+                Err("cannot hash a node whose child is only known by hash".to_string())
+            }
+        }
+    }
+}
+
+// Path and value only; children are passed to the encoder separately.
+fn as_trie_node(node: &NextNode) -> TrieNode {
+    let value = match &node.value {
+        NextValueRef::Empty => ValueRef::empty(),
+        NextValueRef::Inline(value) => ValueRef::inline(value.clone()),
+        NextValueRef::Hashed { hash, length } => ValueRef::hashed(*hash, Some(*length)),
+    };
+    TrieNode::new(
+        SharedPath::from(&node.shared_path),
+        value,
+        NodeReference::Empty,
+        NodeReference::Empty,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashCache;
+    use crate::core_trie::Unitrie;
+    use crate::next::node::{NextChildRef, NextNode, NextValueRef};
+    use crate::next::node_arena::NodeArena;
+    use crate::next::path_bits::PathBits;
+    use crate::node_ref::CodecConfig;
+
+    #[test]
+    fn rehash_after_a_leaf_change_only_encodes_the_dirty_path() {
+        // Keys 0x00 and 0x80 split on the first bit; each leaf keeps 7 path bits.
+        let mut arena = NodeArena::new();
+        let leaf = |value: Vec<u8>| NextNode {
+            shared_path: PathBits::from_bits(&[0; 7]).unwrap(),
+            value: NextValueRef::Inline(value),
+            ..NextNode::empty()
+        };
+        let left = arena.alloc(leaf(vec![1; 40]));
+        let right = arena.alloc(leaf(vec![2; 8]));
+        let root = arena.alloc(NextNode {
+            left: NextChildRef::InMemory(left),
+            right: NextChildRef::InMemory(right),
+            ..NextNode::empty()
+        });
+
+        let config = CodecConfig::CONSENSUS;
+        let mut cache = HashCache::new();
+        let mut expected = Unitrie::new();
+        expected.put(vec![0x00], vec![1; 40]);
+        expected.put(vec![0x80], vec![2; 8]);
+        assert_eq!(
+            cache.hash(&arena, root, &config).unwrap(),
+            expected.root_hash()
+        );
+        assert_eq!(cache.encoded_count(), 3);
+        cache.hash(&arena, root, &config).unwrap();
+        assert_eq!(cache.encoded_count(), 3);
+
+        arena.get_mut(right).unwrap().value = NextValueRef::Inline(vec![3; 8]);
+        cache.invalidate_path(&[root, right]);
+        expected.put(vec![0x80], vec![3; 8]);
+        assert_eq!(
+            cache.hash(&arena, root, &config).unwrap(),
+            expected.root_hash()
+        );
+        assert_eq!(cache.encoded_count(), 5);
     }
 }
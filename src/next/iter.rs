@@ -8,13 +8,45 @@ pub fn collect_exact_size_keys<'a>(
         .collect()
 }
 
+pub fn collect_exact_size_keys_page<'a>(
+    keys: impl Iterator<Item = &'a Vec<u8>>,
+    byte_size: usize,
+    limit: usize,
+) -> Vec<Vec<u8>> {
+    let collect_all = byte_size == i32::MAX as usize;
+    keys.filter(|key| collect_all || key.len() == byte_size)
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+pub fn count_exact_size_keys<'a>(
+    keys: impl Iterator<Item = &'a Vec<u8>>,
+    byte_size: usize,
+) -> usize {
+    let collect_all = byte_size == i32::MAX as usize;
+    keys.filter(|key| collect_all || key.len() == byte_size)
+        .count()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::collect_exact_size_keys;
+    use super::{collect_exact_size_keys, collect_exact_size_keys_page, count_exact_size_keys};
 
     #[test]
     fn collect_exact_size_filters_keys() {
-        let keys = vec![vec![1], vec![2, 3], vec![4]];
+        let keys = [vec![1], vec![2, 3], vec![4]];
         assert_eq!(collect_exact_size_keys(keys.iter(), 1).len(), 2);
     }
+
+    #[test]
+    fn page_and_count_respect_byte_size() {
+        let keys = [vec![1], vec![2, 3], vec![4], vec![5]];
+        assert_eq!(
+            collect_exact_size_keys_page(keys.iter(), 1, 2),
+            vec![vec![1], vec![4]]
+        );
+        assert_eq!(count_exact_size_keys(keys.iter(), 1), 3);
+        assert_eq!(count_exact_size_keys(keys.iter(), i32::MAX as usize), 4);
+    }
 }
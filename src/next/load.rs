@@ -1,11 +1,13 @@
-use crate::codec_orchid::OrchidCodec;
-use crate::codec_rskip107::Rskip107Codec;
 use crate::node_ref::TrieNode;
+use crate::store_adapter::CodecHint;
 
 pub fn decode_persisted_node(payload: &[u8]) -> Result<TrieNode, String> {
-    if OrchidCodec::is_orchid_message(payload) {
-        return OrchidCodec::decode_node(payload);
-    }
+    decode_persisted_node_with_hint(payload, CodecHint::Auto)
+}
 
-    Rskip107Codec::decode_node(payload)
+pub fn decode_persisted_node_with_hint(
+    payload: &[u8],
+    hint: CodecHint,
+) -> Result<TrieNode, String> {
+    crate::core_trie::decode_persisted_node(payload, hint)
 }
@@ -8,6 +8,8 @@ pub mod load;
 pub mod mutation;
 pub mod node;
 pub mod node_arena;
+pub mod node_tree;
 pub mod path_bits;
 pub mod persistence;
+pub mod root_history;
 pub mod storage_iteration_cache;
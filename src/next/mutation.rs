@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
 pub struct MutationGeneration(u64);
 
@@ -6,12 +8,61 @@ impl MutationGeneration {
         self.0
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> u64 {
         self.0 = self.0.wrapping_add(1);
         self.0
     }
 }
 
+// Keys, and prefixes deleted recursively, touched since the last save.
+#[derive(Debug, Default, Clone)]
+pub struct DirtyKeys {
+    keys: HashSet<Vec<u8>>,
+    prefixes: HashSet<Vec<u8>>,
+}
+
+impl DirtyKeys {
+    pub fn mark(&mut self, key: &[u8]) {
+        if !self.keys.contains(key) {
+            self.keys.insert(key.to_vec());
+        }
+    }
+
+    pub fn mark_prefix(&mut self, prefix: &[u8]) {
+        if !self.prefixes.contains(prefix) {
+            self.prefixes.insert(prefix.to_vec());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len() + self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.prefixes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.prefixes.clear();
+    }
+
+    pub fn sorted_keys(&self) -> Vec<Vec<u8>> {
+        sorted(&self.keys)
+    }
+
+    pub fn sorted_prefixes(&self) -> Vec<Vec<u8>> {
+        sorted(&self.prefixes)
+    }
+}
+
+fn sorted(set: &HashSet<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut entries: Vec<Vec<u8>> = set.iter().cloned().collect();
+    entries.sort_unstable();
+    entries
+}
+
 #[cfg(test)]
 mod tests {
     use super::MutationGeneration;
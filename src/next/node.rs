@@ -1,10 +1,11 @@
+use crate::next::node_arena::NodeId;
 use crate::next::path_bits::PathBits;
-use crate::node_ref::{HASH_SIZE, LONG_VALUE_THRESHOLD};
+use crate::node_ref::{CodecConfig, HASH_SIZE};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum NextChildRef {
     Empty,
-    InMemory(u64),
+    InMemory(NodeId),
     Hashed([u8; HASH_SIZE]),
 }
 
@@ -27,8 +28,12 @@ impl NextValueRef {
         }
     }
 
-    pub fn has_long_value(&self) -> bool {
-        self.len() > LONG_VALUE_THRESHOLD
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn has_long_value_for(&self, config: &CodecConfig) -> bool {
+        self.len() > config.long_value_threshold
     }
 }
 
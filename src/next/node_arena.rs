@@ -1,23 +1,165 @@
-use std::collections::{BTreeSet, HashMap};
+use crate::next::node::{NextChildRef, NextNode};
+use std::collections::BTreeSet;
 
+// Slot index plus the generation the slot had when the node was allocated. A
+// freed slot bumps its generation, so stale ids stop resolving instead of
+// aliasing whatever node reuses the slot.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct NodeId(u64);
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+impl NodeId {
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u32,
+    node: Option<NextNode>,
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct NodeArena {
-    next_id: u64,
-    key_to_node: HashMap<Vec<u8>, NodeId>,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    len: usize,
     dirty_nodes: BTreeSet<NodeId>,
 }
 
 impl NodeArena {
-    pub fn mark_dirty_key(&mut self, key: &[u8]) {
-        let node_id = self.key_to_node.entry(key.to_vec()).or_insert_with(|| {
-            let current = self.next_id;
-            self.next_id = self.next_id.saturating_add(1);
-            NodeId(current)
-        });
-        self.dirty_nodes.insert(*node_id);
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // New nodes start dirty: they have never been hashed or persisted.
+    pub fn alloc(&mut self, node: NextNode) -> NodeId {
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                let index = u32::try_from(self.slots.len()).expect("node arena exceeds u32 slots");
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index,
+                    generation: 0,
+                }
+            }
+        };
+        self.len += 1;
+        self.dirty_nodes.insert(id);
+        id
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&NextNode> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    // Mutable access marks the node dirty.
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut NextNode> {
+        let node = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())?;
+        self.dirty_nodes.insert(id);
+        Some(node)
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn resolve(&self, child: &NextChildRef) -> Option<&NextNode> {
+        match child {
+            NextChildRef::InMemory(id) => self.get(*id),
+            NextChildRef::Empty | NextChildRef::Hashed(_) => None,
+        }
+    }
+
+    pub fn free(&mut self, id: NodeId) -> Option<NextNode> {
+        let slot = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?;
+        let node = slot.node.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+        self.dirty_nodes.remove(&id);
+        Some(node)
+    }
+
+    // Frees every node not reachable from `roots` through in-memory child
+    // references, returning how many were freed.
+    pub fn collect_garbage(&mut self, roots: &[NodeId]) -> usize {
+        let mut reachable = vec![false; self.slots.len()];
+        let mut pending: Vec<NodeId> = roots.to_vec();
+        while let Some(id) = pending.pop() {
+            let Some(node) = self.get(id) else {
+                continue;
+            };
+            if std::mem::replace(&mut reachable[id.index as usize], true) {
+                continue;
+            }
+            for child in [&node.left, &node.right] {
+                if let NextChildRef::InMemory(child) = child {
+                    pending.push(*child);
+                }
+            }
+        }
+
+        let garbage: Vec<NodeId> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(index, slot)| slot.node.is_some() && !reachable[*index])
+            .map(|(index, slot)| NodeId {
+                index: index as u32,
+                generation: slot.generation,
+            })
+            .collect();
+        for id in &garbage {
+            self.free(*id);
+        }
+        garbage.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn mark_dirty(&mut self, id: NodeId) {
+        if self.contains(id) {
+            self.dirty_nodes.insert(id);
+        }
+    }
+
+    pub fn is_dirty(&self, id: NodeId) -> bool {
+        self.dirty_nodes.contains(&id)
     }
 
     pub fn dirty_count(&self) -> usize {
@@ -27,4 +169,61 @@ impl NodeArena {
     pub fn clear_dirty(&mut self) {
         self.dirty_nodes.clear();
     }
+
+    // The nodes dirtied since the last call, leaving none dirty.
+    pub fn take_dirty(&mut self) -> BTreeSet<NodeId> {
+        std::mem::take(&mut self.dirty_nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeArena;
+    use crate::next::node::{NextChildRef, NextNode, NextValueRef};
+
+    fn leaf(value: u8) -> NextNode {
+        NextNode {
+            value: NextValueRef::Inline(vec![value]),
+            ..NextNode::empty()
+        }
+    }
+
+    #[test]
+    fn freed_slots_are_reused_under_a_new_generation() {
+        let mut arena = NodeArena::new();
+        let first = arena.alloc(leaf(1));
+        assert_eq!(arena.get(first), Some(&leaf(1)));
+
+        assert_eq!(arena.free(first), Some(leaf(1)));
+        assert!(arena.get(first).is_none());
+        assert!(arena.free(first).is_none());
+
+        let second = arena.alloc(leaf(2));
+        assert_eq!(second.index(), first.index());
+        assert_ne!(second.generation(), first.generation());
+        assert!(arena.get(first).is_none());
+        assert_eq!(
+            arena.resolve(&NextChildRef::InMemory(second)),
+            Some(&leaf(2))
+        );
+        assert_eq!(arena.len(), 1);
+    }
+
+    #[test]
+    fn garbage_collection_keeps_only_reachable_nodes() {
+        let mut arena = NodeArena::new();
+        let left = arena.alloc(leaf(1));
+        let right = arena.alloc(leaf(2));
+        let orphan = arena.alloc(leaf(3));
+        let root = arena.alloc(NextNode {
+            left: NextChildRef::InMemory(left),
+            right: NextChildRef::InMemory(right),
+            ..NextNode::empty()
+        });
+
+        assert_eq!(arena.collect_garbage(&[root]), 1);
+        assert!(!arena.contains(orphan));
+        assert!(arena.contains(left) && arena.contains(right) && arena.contains(root));
+        assert_eq!(arena.dirty_count(), 3);
+    }
 }
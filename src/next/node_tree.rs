@@ -0,0 +1,373 @@
+use crate::hash::empty_trie_hash;
+use crate::next::hash_cache::HashCache;
+use crate::next::node::{NextChildRef, NextNode, NextValueRef};
+use crate::next::node_arena::{NodeArena, NodeId};
+use crate::next::path_bits::PathBits;
+use crate::node_ref::{CodecConfig, HASH_SIZE};
+
+// The next engine's node tree, kept in a `NodeArena` in the shape the legacy
+// engine builds from its entries. A write only touches the nodes on its key's
+// path and leaves them dirty in the arena, so rehashing after it drops just
+// those nodes' cached hashes.
+#[derive(Debug, Default, Clone)]
+pub struct NodeTree {
+    arena: NodeArena,
+    root: Option<NodeId>,
+    hashes: HashCache,
+}
+
+impl NodeTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn arena(&self) -> &NodeArena {
+        &self.arena
+    }
+
+    pub fn root(&self) -> Option<NodeId> {
+        self.root
+    }
+
+    // Node count, not key count.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // Re-encodes the nodes dirtied since the last call, O(depth) per write, and
+    // reuses every other cached hash.
+    pub fn root_hash(&mut self, config: &CodecConfig) -> Result<[u8; HASH_SIZE], String> {
+        for id in self.arena.take_dirty() {
+            self.hashes.invalidate(id);
+        }
+        match self.root {
+            None => Ok(empty_trie_hash()),
+            Some(root) => self.hashes.hash(&self.arena, root, config),
+        }
+    }
+
+    // An empty value removes the key, as `put` does.
+    pub fn set(&mut self, key: &[u8], value: NextValueRef) {
+        if value.is_empty() {
+            self.remove(key);
+            return;
+        }
+
+        let key = PathBits::from_key_bytes(key);
+        let Some(mut id) = self.root else {
+            self.root = Some(self.arena.alloc(leaf(key, value)));
+            return;
+        };
+        let mut offset = 0;
+        loop {
+            self.arena.mark_dirty(id);
+            let path = &self.node(id).shared_path;
+            let compared = path.bit_len().min(key.bit_len() - offset);
+            let common = path.common_prefix_len(&key.slice(offset..offset + compared));
+            if common < path.bit_len() {
+                self.split(id, common, &key, offset + common, value);
+                return;
+            }
+
+            let end = offset + common;
+            let Some(bit) = key.get_bit(end) else {
+                self.node_mut(id).value = value;
+                return;
+            };
+            match child(self.node(id), bit) {
+                NextChildRef::InMemory(child) => {
+                    id = *child;
+                    offset = end + 1;
+                }
+                NextChildRef::Empty | NextChildRef::Hashed(_) => {
+                    let leaf = self
+                        .arena
+                        .alloc(leaf(key.slice(end + 1..key.bit_len()), value));
+                    *child_mut(self.node_mut(id), bit) = NextChildRef::InMemory(leaf);
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        let key = PathBits::from_key_bytes(key);
+        let Some(mut id) = self.root else {
+            return;
+        };
+        let mut ancestors = Vec::new();
+        let mut offset = 0;
+        loop {
+            let node = self.node(id);
+            if !key.matches_at(offset, &node.shared_path) {
+                return;
+            }
+            let end = offset + node.shared_path.bit_len();
+            let Some(bit) = key.get_bit(end) else {
+                break;
+            };
+            let NextChildRef::InMemory(child) = child(node, bit) else {
+                return;
+            };
+            ancestors.push((id, bit));
+            id = *child;
+            offset = end + 1;
+        }
+        if self.node(id).value.is_empty() {
+            return;
+        }
+
+        for (ancestor, _) in &ancestors {
+            self.arena.mark_dirty(*ancestor);
+        }
+        self.node_mut(id).value = NextValueRef::Empty;
+        self.prune(ancestors, id);
+    }
+
+    // Removes every key starting with `prefix` by dropping the subtree the
+    // prefix leads to.
+    pub fn remove_prefix(&mut self, prefix: &[u8]) {
+        let prefix = PathBits::from_key_bytes(prefix);
+        let Some(mut id) = self.root else {
+            return;
+        };
+        let mut ancestors = Vec::new();
+        let mut offset = 0;
+        loop {
+            let node = self.node(id);
+            let rest = prefix.bit_len() - offset;
+            if rest <= node.shared_path.bit_len() {
+                let rest = prefix.slice(offset..prefix.bit_len());
+                if !node.shared_path.matches_at(0, &rest) {
+                    return;
+                }
+                break;
+            }
+            if !prefix.matches_at(offset, &node.shared_path) {
+                return;
+            }
+            let end = offset + node.shared_path.bit_len();
+            let bit = prefix.get_bit(end).expect("prefix continues past the path");
+            let NextChildRef::InMemory(child) = child(node, bit) else {
+                return;
+            };
+            ancestors.push((id, bit));
+            id = *child;
+            offset = end + 1;
+        }
+
+        for (ancestor, _) in &ancestors {
+            self.arena.mark_dirty(*ancestor);
+        }
+        self.free_subtree(id);
+        match ancestors.pop() {
+            None => self.root = None,
+            Some((parent, bit)) => {
+                *child_mut(self.node_mut(parent), bit) = NextChildRef::Empty;
+                self.prune(ancestors, parent);
+            }
+        }
+    }
+
+    // Freed ids never resolve again, so their cached hashes go too.
+    fn free(&mut self, id: NodeId) -> Option<NextNode> {
+        self.hashes.invalidate(id);
+        self.arena.free(id)
+    }
+
+    fn node(&self, id: NodeId) -> &NextNode {
+        self.arena.get(id).expect("tree node is live")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut NextNode {
+        self.arena.get_mut(id).expect("tree node is live")
+    }
+
+    // Branches `id` after `common` bits of its path, where `key` leaves it or
+    // ends at bit `at`. `id` keeps its slot so the parent's reference stays
+    // valid; its old contents move to a new child.
+    fn split(&mut self, id: NodeId, common: usize, key: &PathBits, at: usize, value: NextValueRef) {
+        let node = self.node_mut(id);
+        let (prefix, suffix) = node.shared_path.split_at(common);
+        let mut moved = std::mem::replace(
+            node,
+            NextNode {
+                shared_path: prefix,
+                ..NextNode::empty()
+            },
+        );
+        let moved_bit = suffix.get_bit(0).expect("split inside the path");
+        moved.shared_path = suffix.slice(1..suffix.bit_len());
+        let moved = self.arena.alloc(moved);
+        *child_mut(self.node_mut(id), moved_bit) = NextChildRef::InMemory(moved);
+
+        match key.get_bit(at) {
+            None => self.node_mut(id).value = value,
+            Some(bit) => {
+                let leaf = self
+                    .arena
+                    .alloc(leaf(key.slice(at + 1..key.bit_len()), value));
+                *child_mut(self.node_mut(id), bit) = NextChildRef::InMemory(leaf);
+            }
+        }
+    }
+
+    // Restores the shape after `id` lost its value or a child: a node without
+    // a value and children is dropped, and one with a single child absorbs it.
+    // `ancestors` holds each node above `id` with the bit leading down.
+    fn prune(&mut self, mut ancestors: Vec<(NodeId, u8)>, mut id: NodeId) {
+        loop {
+            let node = self.node(id);
+            if !node.value.is_empty() {
+                return;
+            }
+            match (&node.left, &node.right) {
+                (NextChildRef::InMemory(child), NextChildRef::Empty) => {
+                    let child = *child;
+                    self.absorb_child(id, 0, child);
+                    return;
+                }
+                (NextChildRef::Empty, NextChildRef::InMemory(child)) => {
+                    let child = *child;
+                    self.absorb_child(id, 1, child);
+                    return;
+                }
+                (NextChildRef::Empty, NextChildRef::Empty) => {
+                    self.free(id);
+                    let Some((parent, bit)) = ancestors.pop() else {
+                        self.root = None;
+                        return;
+                    };
+                    *child_mut(self.node_mut(parent), bit) = NextChildRef::Empty;
+                    id = parent;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // Moves the only child of `id` into `id`'s slot, joining the paths.
+    fn absorb_child(&mut self, id: NodeId, bit: u8, child: NodeId) {
+        let mut absorbed = self.free(child).expect("tree node is live");
+        let node = self.node_mut(id);
+        let mut path = std::mem::take(&mut node.shared_path);
+        path.append(bit);
+        path.extend_from(&absorbed.shared_path);
+        absorbed.shared_path = path;
+        *node = absorbed;
+    }
+
+    fn free_subtree(&mut self, id: NodeId) {
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let Some(node) = self.free(id) else {
+                continue;
+            };
+            for child in [node.left, node.right] {
+                if let NextChildRef::InMemory(child) = child {
+                    pending.push(child);
+                }
+            }
+        }
+    }
+}
+
+fn leaf(shared_path: PathBits, value: NextValueRef) -> NextNode {
+    NextNode {
+        shared_path,
+        value,
+        ..NextNode::empty()
+    }
+}
+
+fn child(node: &NextNode, bit: u8) -> &NextChildRef {
+    if bit == 0 {
+        &node.left
+    } else {
+        &node.right
+    }
+}
+
+fn child_mut(node: &mut NextNode, bit: u8) -> &mut NextChildRef {
+    if bit == 0 {
+        &mut node.left
+    } else {
+        &mut node.right
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeTree;
+    use crate::core_trie::Unitrie;
+    use crate::hash::keccak256;
+    use crate::next::node::NextValueRef;
+    use crate::node_ref::CodecConfig;
+    use crate::rng::SeededRng;
+
+    #[test]
+    fn writes_keep_the_shape_the_legacy_engine_builds() {
+        let mut tree = NodeTree::new();
+        let mut expected = Unitrie::new();
+        let mut rng = SeededRng::new(7);
+        for step in 0..2_000u32 {
+            let state = rng.next();
+            // Short keys over a small alphabet collide, nest and split often.
+            let key: Vec<u8> = (0..state % 4)
+                .map(|shift| (state >> (8 * shift)) as u8 & 0x93)
+                .collect();
+            match state % 7 {
+                0 => {
+                    tree.remove(&key);
+                    expected.delete(&key);
+                }
+                1 => {
+                    let prefix = &key[..key.len().min(1)];
+                    tree.remove_prefix(prefix);
+                    expected.delete_recursive(prefix);
+                }
+                _ => {
+                    let value = vec![step as u8 | 1; 1 + (state % 5) as usize];
+                    tree.set(&key, NextValueRef::Inline(value.clone()));
+                    expected.put(key, value);
+                }
+            }
+            assert_eq!(
+                tree.root_hash(&CodecConfig::CONSENSUS).unwrap(),
+                expected.root_hash(),
+                "step {step}"
+            );
+        }
+
+        tree.remove_prefix(&[]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.len(), 0);
+        assert!(tree.hashes.is_empty());
+    }
+
+    #[test]
+    fn rehashing_after_one_write_encodes_only_its_path() {
+        let config = CodecConfig::CONSENSUS;
+        let mut tree = NodeTree::new();
+        let mut expected = Unitrie::new();
+        for index in 0u16..1_000 {
+            let key = keccak256(&index.to_be_bytes()).to_vec();
+            tree.set(&key, NextValueRef::Inline(vec![1; 8]));
+            expected.put(key, vec![1; 8]);
+        }
+        tree.root_hash(&config).unwrap();
+        let full = tree.hashes.encoded_count();
+        assert_eq!(full, tree.len() as u64);
+
+        let key = keccak256(&7u16.to_be_bytes()).to_vec();
+        tree.set(&key, NextValueRef::Inline(vec![2; 8]));
+        expected.put(key, vec![2; 8]);
+        assert_eq!(tree.root_hash(&config).unwrap(), expected.root_hash());
+        // A thousand hashed keys branch about ten levels deep.
+        assert!(tree.hashes.encoded_count() - full < 32);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::core_trie::{SaveStats, Unitrie};
+use crate::core_trie::{CancellationToken, SaveEstimate, SaveStats, Unitrie};
 use crate::node_ref::HASH_SIZE;
 use crate::store_adapter::RawStoreAdapter;
 
@@ -14,13 +14,46 @@ impl IncrementalPersistence {
         store: &mut T,
         dirty_nodes: usize,
     ) -> SaveStats {
-        let current_root = trie.current_root_hash();
-        if dirty_nodes == 0 && self.last_saved_root == Some(current_root) {
-            return SaveStats::default();
+        if self.is_unchanged(trie, dirty_nodes) {
+            return SaveStats::UNCHANGED;
         }
 
         let save_stats = trie.save_to_store_with_stats(store);
         self.last_saved_root = Some(trie.current_root_hash());
         save_stats
     }
+
+    // The last saved root only moves once the whole save went through.
+    pub fn save_with_progress<T, F>(
+        &mut self,
+        trie: &mut Unitrie,
+        store: &mut T,
+        dirty_nodes: usize,
+        progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<SaveStats, String>
+    where
+        T: RawStoreAdapter,
+        F: FnMut(u64, u64),
+    {
+        if self.is_unchanged(trie, dirty_nodes) {
+            return Ok(SaveStats::UNCHANGED);
+        }
+
+        let save_stats = trie.save_to_store_with_progress(store, progress, cancel)?;
+        self.last_saved_root = Some(trie.current_root_hash());
+        Ok(save_stats)
+    }
+
+    pub fn estimate(&self, trie: &mut Unitrie, dirty_nodes: usize) -> SaveEstimate {
+        if self.is_unchanged(trie, dirty_nodes) {
+            return SaveEstimate::default();
+        }
+
+        trie.estimate_save()
+    }
+
+    fn is_unchanged(&self, trie: &mut Unitrie, dirty_nodes: usize) -> bool {
+        dirty_nodes == 0 && self.last_saved_root == Some(trie.current_root_hash())
+    }
 }
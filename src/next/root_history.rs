@@ -0,0 +1,110 @@
+use crate::core_trie::SaveStats;
+use crate::node_ref::HASH_SIZE;
+use std::collections::VecDeque;
+
+pub const DEFAULT_ROOT_HISTORY_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RootRecord {
+    pub root: [u8; HASH_SIZE],
+    pub key_count: usize,
+    pub save_stats: SaveStats,
+}
+
+// The most recent saved roots, oldest first. Saves that leave the root
+// unchanged are not recorded again.
+#[derive(Debug, Clone)]
+pub struct RootHistory {
+    capacity: usize,
+    records: VecDeque<RootRecord>,
+}
+
+impl Default for RootHistory {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_ROOT_HISTORY_CAPACITY)
+    }
+}
+
+impl RootHistory {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, record: RootRecord) {
+        if self.capacity == 0 || self.latest().map(|latest| latest.root) == Some(record.root) {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn latest(&self) -> Option<&RootRecord> {
+        self.records.back()
+    }
+
+    pub fn find(&self, root: &[u8]) -> Option<&RootRecord> {
+        self.records.iter().rev().find(|record| record.root == root)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &RootRecord> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RootHistory, RootRecord};
+    use crate::core_trie::SaveStats;
+
+    fn record(byte: u8) -> RootRecord {
+        RootRecord {
+            root: [byte; 32],
+            key_count: byte as usize,
+            save_stats: SaveStats::default(),
+        }
+    }
+
+    #[test]
+    fn history_keeps_the_latest_distinct_roots() {
+        let mut history = RootHistory::with_capacity(3);
+        for byte in [1, 2, 2, 3, 4] {
+            history.record(record(byte));
+        }
+        let roots: Vec<u8> = history.iter().map(|record| record.root[0]).collect();
+        assert_eq!(roots, vec![2, 3, 4]);
+        assert_eq!(history.find(&[3; 32]).unwrap().key_count, 3);
+        assert!(history.find(&[1; 32]).is_none());
+
+        history.set_capacity(1);
+        assert_eq!(history.latest().unwrap().root, [4; 32]);
+        assert_eq!(history.len(), 1);
+    }
+}
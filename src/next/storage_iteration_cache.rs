@@ -1,7 +1,7 @@
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
-const DEFAULT_CAPACITY: usize = 256;
+pub const DEFAULT_STORAGE_CACHE_CAPACITY: usize = 256;
 
 #[derive(Debug, Clone)]
 struct CacheEntry {
@@ -19,7 +19,7 @@ pub struct StorageIterationCache {
 
 impl Default for StorageIterationCache {
     fn default() -> Self {
-        Self::new(DEFAULT_CAPACITY)
+        Self::new(DEFAULT_STORAGE_CACHE_CAPACITY)
     }
 }
 
@@ -81,6 +81,21 @@ impl StorageIterationCache {
         (Arc::clone(&entry.keys), Arc::clone(&entry.packed))
     }
 
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            let Some(evicted) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.entries.clear();
+    }
+
     fn touch(&mut self, account_address: &[u8]) {
         if let Some(position) = self
             .order
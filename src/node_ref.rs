@@ -1,9 +1,95 @@
 use crate::hash::keccak256;
-use crate::path::shared_path_serializer;
+use crate::path::path_bits::PathBits;
+use std::fmt;
 
 pub const HASH_SIZE: usize = 32;
 pub const LONG_VALUE_THRESHOLD: usize = 32;
 pub const MAX_EMBEDDED_NODE_SIZE_IN_BYTES: usize = 44;
+// Long value lengths are a uint24 in consensus encoding.
+pub const MAX_CONSENSUS_VALUE_LENGTH: usize = 0x00ff_ffff;
+
+// Encoding parameters. Only `CodecConfig::CONSENSUS` produces roots Rootstock
+// nodes agree on; other values are for experiments and other chains.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CodecConfig {
+    pub long_value_threshold: usize,
+    pub max_embedded_node_size: usize,
+    // Allows values longer than `MAX_CONSENSUS_VALUE_LENGTH`, written with the
+    // extended RSKIP107 version and stored in chunks. Rootstock nodes cannot
+    // read such nodes.
+    pub extended_values: bool,
+}
+
+impl CodecConfig {
+    pub const CONSENSUS: Self = Self {
+        long_value_threshold: LONG_VALUE_THRESHOLD,
+        max_embedded_node_size: MAX_EMBEDDED_NODE_SIZE_IN_BYTES,
+        extended_values: false,
+    };
+
+    pub fn new(long_value_threshold: usize, max_embedded_node_size: usize) -> Result<Self, String> {
+        // Embedded children are length-prefixed with a single byte.
+        if max_embedded_node_size > u8::MAX as usize {
+            return Err(format!(
+                "max embedded node size {max_embedded_node_size} does not fit in uint8"
+            ));
+        }
+
+        Ok(Self {
+            long_value_threshold,
+            max_embedded_node_size,
+            extended_values: false,
+        })
+    }
+
+    pub fn with_extended_values(mut self, extended_values: bool) -> Self {
+        self.extended_values = extended_values;
+        self
+    }
+
+    pub fn is_consensus(&self) -> bool {
+        *self == Self::CONSENSUS
+    }
+
+    pub fn max_value_length(&self) -> usize {
+        if self.extended_values {
+            usize::MAX
+        } else {
+            MAX_CONSENSUS_VALUE_LENGTH
+        }
+    }
+
+    pub fn check_value_length(&self, length: usize) -> Result<(), ValueTooLarge> {
+        let max_length = self.max_value_length();
+        if length > max_length {
+            return Err(ValueTooLarge { length, max_length });
+        }
+        Ok(())
+    }
+}
+
+// Returned by `try_put` for values the configured encoding cannot represent.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ValueTooLarge {
+    pub length: usize,
+    pub max_length: usize,
+}
+
+impl fmt::Display for ValueTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value of {} bytes exceeds the {}-byte limit",
+            self.length, self.max_length
+        )
+    }
+}
+
+impl Default for CodecConfig {
+    fn default() -> Self {
+        Self::CONSENSUS
+    }
+}
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CodecMode {
@@ -11,38 +97,46 @@ pub enum CodecMode {
     Orchid,
 }
 
+// Stored packed; the packed bytes are exactly the path's on-wire encoding.
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct SharedPath {
-    bits: Vec<u8>,
+    bits: PathBits,
 }
 
 impl SharedPath {
     pub fn empty() -> Self {
-        Self { bits: Vec::new() }
+        Self::default()
     }
 
+    // One byte per bit, each 0 or 1.
     pub fn from_bits(bits: Vec<u8>) -> Result<Self, String> {
-        if bits.iter().any(|bit| *bit > 1) {
-            return Err("shared path must contain only 0/1 bits".to_string());
-        }
+        PathBits::from_bits(&bits)
+            .map(Self::from_path_bits)
+            .map_err(|_| "shared path must contain only 0/1 bits".to_string())
+    }
 
-        Ok(Self { bits })
+    pub fn from_path_bits(bits: PathBits) -> Self {
+        Self { bits }
     }
 
     pub fn len(&self) -> usize {
-        self.bits.len()
+        self.bits.bit_len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.bits.is_empty()
     }
 
-    pub fn as_bits(&self) -> &[u8] {
+    pub fn bits(&self) -> &PathBits {
         &self.bits
     }
 
+    pub fn to_bits_vec(&self) -> Vec<u8> {
+        self.bits.to_bits_vec()
+    }
+
     pub fn encoded(&self) -> Vec<u8> {
-        shared_path_serializer::encode(&self.bits)
+        self.bits.to_packed_bytes().to_vec()
     }
 }
 
@@ -94,8 +188,12 @@ impl ValueRef {
     }
 
     pub fn has_long_value(&self) -> bool {
+        self.is_long_for(LONG_VALUE_THRESHOLD)
+    }
+
+    pub fn is_long_for(&self, threshold: usize) -> bool {
         match self.len() {
-            Some(length) => length > LONG_VALUE_THRESHOLD,
+            Some(length) => length > threshold,
             None => true,
         }
     }
@@ -151,12 +249,15 @@ impl NodeReference {
     }
 }
 
+// `children_size` is the RSKIP107 subtree size a decoded node was stored with;
+// nodes built in memory leave it `None` and have it computed when encoded.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct TrieNode {
     pub shared_path: SharedPath,
     pub value: ValueRef,
     pub left: NodeReference,
     pub right: NodeReference,
+    pub children_size: Option<u64>,
 }
 
 impl TrieNode {
@@ -171,9 +272,15 @@ impl TrieNode {
             value,
             left,
             right,
+            children_size: None,
         }
     }
 
+    pub fn with_children_size(mut self, children_size: Option<u64>) -> Self {
+        self.children_size = children_size;
+        self
+    }
+
     pub fn empty() -> Self {
         Self::new(
             SharedPath::empty(),
@@ -199,6 +306,10 @@ impl TrieNode {
         self.value.has_long_value()
     }
 
+    pub fn has_long_value_for(&self, config: &CodecConfig) -> bool {
+        self.value.is_long_for(config.long_value_threshold)
+    }
+
     pub fn is_empty_trie(&self) -> bool {
         !self.has_value() && self.left.is_empty() && self.right.is_empty()
     }
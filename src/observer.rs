@@ -0,0 +1,187 @@
+use crate::core_api::TrieOp;
+use crate::core_trie::SaveStats;
+use crate::TrieRoot;
+use std::fmt;
+use std::sync::Arc;
+
+// Callbacks run synchronously on the mutating thread, so implementations
+// should be cheap and use interior mutability for their own state. Putting an
+// empty value is reported as a delete, matching `put` semantics.
+pub trait TrieObserver: Send + Sync {
+    fn on_put(&self, _key: &[u8], _value: &[u8]) {}
+
+    fn on_delete(&self, _key: &[u8]) {}
+
+    fn on_delete_recursive(&self, _prefix: &[u8]) {}
+
+    fn on_save(&self, _root: &TrieRoot, _stats: &SaveStats) {}
+}
+
+// Observers of one trie. Observers such as the value index or the flat layer
+// mirror the trie they were attached to, so a cloned trie starts without any
+// and a clone's writes never reach them.
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Arc<dyn TrieObserver>>);
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Arc<dyn TrieObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn notify_put(&self, key: &[u8], value: &[u8]) {
+        if value.is_empty() {
+            self.notify_delete(key);
+            return;
+        }
+
+        for observer in &self.0 {
+            observer.on_put(key, value);
+        }
+    }
+
+    pub(crate) fn notify_delete(&self, key: &[u8]) {
+        for observer in &self.0 {
+            observer.on_delete(key);
+        }
+    }
+
+    pub(crate) fn notify_delete_recursive(&self, prefix: &[u8]) {
+        for observer in &self.0 {
+            observer.on_delete_recursive(prefix);
+        }
+    }
+
+    pub(crate) fn notify_op(&self, op: &TrieOp) {
+        match op {
+            TrieOp::Put { key, value } => self.notify_put(key, value),
+            TrieOp::Delete { key } => self.notify_delete(key),
+            TrieOp::DeleteRecursive { prefix } => self.notify_delete_recursive(prefix),
+        }
+    }
+
+    pub(crate) fn notify_save(&self, root: &TrieRoot, stats: &SaveStats) {
+        for observer in &self.0 {
+            observer.on_save(root, stats);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("count", &self.0.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrieObserver;
+    use crate::core_api::TrieOp;
+    use crate::core_trie::SaveStats;
+    use crate::store_adapter::MemoryStore;
+    use crate::{TrieRoot, UnitrieCore, UnitrieImplementation};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl RecordingObserver {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+    }
+
+    impl TrieObserver for RecordingObserver {
+        fn on_put(&self, key: &[u8], value: &[u8]) {
+            self.record(format!("put {key:?}={value:?}"));
+        }
+
+        fn on_delete(&self, key: &[u8]) {
+            self.record(format!("delete {key:?}"));
+        }
+
+        fn on_delete_recursive(&self, prefix: &[u8]) {
+            self.record(format!("delete_recursive {prefix:?}"));
+        }
+
+        fn on_save(&self, _root: &TrieRoot, stats: &SaveStats) {
+            self.record(format!("save nodes={}", stats.nodes_written));
+        }
+    }
+
+    #[test]
+    fn observers_see_every_mutation_and_save() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let observer = Arc::new(RecordingObserver::default());
+            let mut trie = UnitrieCore::new(implementation);
+            trie.add_observer(observer.clone());
+
+            trie.put(vec![1], vec![10]);
+            trie.put(vec![2], Vec::new());
+            trie.update(vec![1], |_| Some(vec![11]));
+            trie.delete(&[1]);
+            trie.apply_batch([
+                TrieOp::put(vec![3], vec![30]),
+                TrieOp::delete_recursive(vec![3]),
+            ]);
+            trie.save_to_store(&mut MemoryStore::new());
+
+            assert_eq!(
+                observer.take(),
+                vec![
+                    "put [1]=[10]",
+                    "delete [2]",
+                    "put [1]=[11]",
+                    "delete [1]",
+                    "put [3]=[30]",
+                    "delete_recursive [3]",
+                    "save nodes=1",
+                ]
+            );
+
+            trie.clear_observers();
+            trie.put(vec![4], vec![40]);
+            assert!(observer.take().is_empty());
+        }
+    }
+    #[test]
+    fn clones_start_without_the_original_observers() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let observer = Arc::new(RecordingObserver::default());
+            let mut trie = UnitrieCore::new(implementation);
+            trie.add_observer(observer.clone());
+
+            let mut clone = trie.clone();
+            clone.put(vec![1], vec![10]);
+            assert!(observer.take().is_empty());
+
+            trie.put(vec![2], vec![20]);
+            assert_eq!(observer.take(), vec!["put [2]=[20]"]);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::codec_rskip107::{ChildHeader, Rskip107Codec, ValueHeader};
+use crate::core_trie::decode_persisted_node;
+use crate::hash::is_empty_root;
+use crate::long_value::manifest_chunks;
+use crate::node_ref::{NodeReference, TrieNode, ValueRef, HASH_SIZE, MAX_CONSENSUS_VALUE_LENGTH};
+use crate::store_adapter::{CodecHint, IterableStore, RawStoreAdapter};
+use crate::TrieRoot;
+use std::collections::HashSet;
+
+// Stored entries that no given root reaches, i.e. what pruning down to those
+// roots would reclaim. Hashes are sorted.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OrphanReport {
+    pub reachable_nodes: usize,
+    pub reachable_values: usize,
+    // Reachable nodes the store does not hold; their subtrees go unmarked.
+    pub missing: usize,
+    pub orphan_nodes: Vec<Vec<u8>>,
+    pub orphan_values: Vec<Vec<u8>>,
+    pub orphan_node_bytes: u64,
+    pub orphan_value_bytes: u64,
+}
+
+impl OrphanReport {
+    pub fn orphan_count(&self) -> usize {
+        self.orphan_nodes.len() + self.orphan_values.len()
+    }
+
+    pub fn wasted_bytes(&self) -> u64 {
+        self.orphan_node_bytes + self.orphan_value_bytes
+    }
+}
+
+pub fn find_orphans<S: RawStoreAdapter + IterableStore>(
+    store: &mut S,
+    roots: &[TrieRoot],
+) -> Result<OrphanReport, String> {
+    let mut report = OrphanReport::default();
+    let mut reachable = Reachable::default();
+    for root in roots {
+        reachable.mark_root(root, store, &mut report)?;
+    }
+    reachable.mark_chunks(store);
+    report.reachable_nodes = reachable.nodes.len();
+    report.reachable_values = reachable.values.len();
+
+    report.orphan_nodes = store
+        .iter_node_hashes()
+        .filter(|hash| !reachable.nodes.contains(hash.as_slice()))
+        .collect();
+    report.orphan_values = store
+        .iter_value_hashes()
+        .filter(|hash| !reachable.values.contains(hash.as_slice()))
+        .collect();
+    for hash in &report.orphan_nodes {
+        report.orphan_node_bytes += payload_len(store.load_raw_node(hash));
+    }
+    for hash in &report.orphan_values {
+        report.orphan_value_bytes += payload_len(store.load_raw_value(hash));
+    }
+    report.orphan_nodes.sort_unstable();
+    report.orphan_values.sort_unstable();
+    Ok(report)
+}
+
+fn payload_len(payload: Option<Vec<u8>>) -> u64 {
+    payload.map_or(0, |payload| payload.len() as u64)
+}
+
+#[derive(Default)]
+struct Reachable {
+    nodes: HashSet<[u8; HASH_SIZE]>,
+    values: HashSet<[u8; HASH_SIZE]>,
+    // Reachable values that may be stored as a manifest of chunks.
+    manifests: Vec<[u8; HASH_SIZE]>,
+}
+
+impl Reachable {
+    fn mark_root<S: RawStoreAdapter>(
+        &mut self,
+        root: &TrieRoot,
+        store: &mut S,
+        report: &mut OrphanReport,
+    ) -> Result<(), String> {
+        // Saving an empty trie stores the empty node under the empty root.
+        if is_empty_root(root) {
+            self.nodes.insert(*root);
+            return Ok(());
+        }
+
+        let mut pending = vec![*root];
+        while let Some(hash) = pending.pop() {
+            if !self.nodes.insert(hash) {
+                continue;
+            }
+            let Some(payload) = store.load_raw_node(&hash) else {
+                report.missing += 1;
+                continue;
+            };
+            let hint = store.codec_hint();
+            if hint != CodecHint::Orchid && Rskip107Codec::is_rskip107_message(&payload) {
+                self.mark_header(&payload, &mut pending)?;
+            } else {
+                let node = decode_persisted_node(&payload, hint)?;
+                self.mark_node(&node, &mut pending);
+            }
+        }
+        Ok(())
+    }
+
+    // Same as `mark_node` without copying inline values.
+    fn mark_header(
+        &mut self,
+        payload: &[u8],
+        pending: &mut Vec<[u8; HASH_SIZE]>,
+    ) -> Result<(), String> {
+        let header = Rskip107Codec::decode_header(payload)?;
+        if let ValueHeader::Hashed { hash, length } = header.value {
+            self.mark_value(hash, Some(length));
+        }
+        for child in [header.left, header.right] {
+            match child {
+                ChildHeader::Empty => {}
+                ChildHeader::Embedded(child) => self.mark_header(child, pending)?,
+                ChildHeader::Hashed(hash) => pending.push(hash),
+            }
+        }
+        Ok(())
+    }
+
+    // Values within the consensus limit are always one payload; longer ones, or
+    // ones of unknown length, are checked for a manifest once all roots are
+    // marked.
+    fn mark_value(&mut self, hash: [u8; HASH_SIZE], length: Option<usize>) {
+        if self.values.insert(hash)
+            && length.is_none_or(|length| length > MAX_CONSENSUS_VALUE_LENGTH)
+        {
+            self.manifests.push(hash);
+        }
+    }
+
+    // The chunks an extended value's manifest lists are reachable with it.
+    fn mark_chunks<S: RawStoreAdapter>(&mut self, store: &mut S) {
+        for hash in std::mem::take(&mut self.manifests) {
+            let Some(payload) = store.load_raw_value(&hash) else {
+                continue;
+            };
+            if let Some(chunks) = manifest_chunks(&payload) {
+                for chunk in chunks.chunks_exact(HASH_SIZE) {
+                    self.values
+                        .insert(chunk.try_into().expect("chunk has hash length"));
+                }
+            }
+        }
+    }
+
+    // Marks the node's long value and queues its hashed children; embedded
+    // children are marked in place.
+    fn mark_node(&mut self, node: &TrieNode, pending: &mut Vec<[u8; HASH_SIZE]>) {
+        if let ValueRef::Hashed { hash, length } = &node.value {
+            self.mark_value(*hash, *length);
+        }
+        for child in [&node.left, &node.right] {
+            match child {
+                NodeReference::Empty => {}
+                NodeReference::Embedded(child) => self.mark_node(child, pending),
+                NodeReference::Hashed(hash) => pending.push(*hash),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_orphans;
+    use crate::long_value::VALUE_CHUNK_SIZE;
+    use crate::node_ref::{CodecConfig, MAX_CONSENSUS_VALUE_LENGTH};
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn finds_entries_no_root_reaches() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut store = MemoryStore::new();
+            let mut trie = UnitrieCore::new(implementation);
+            for index in 0u8..16 {
+                trie.put(vec![index; 3], vec![index; 64]);
+            }
+            trie.save_to_store(&mut store);
+            let old_root = trie.current_root_hash();
+            trie.put(vec![0x00; 3], vec![0xaa; 64]);
+            trie.save_to_store(&mut store);
+            let new_root = trie.current_root_hash();
+
+            let both = find_orphans(&mut store, &[old_root, new_root]).unwrap();
+            assert_eq!(both.orphan_count(), 0);
+            assert_eq!(both.reachable_nodes, store.node_count());
+            assert_eq!(both.reachable_values, store.value_count());
+
+            let latest = find_orphans(&mut store, &[new_root]).unwrap();
+            assert!(latest.orphan_nodes.contains(&old_root.to_vec()));
+            assert_eq!(latest.orphan_values.len(), 1);
+            assert_eq!(latest.orphan_value_bytes, 64);
+            assert_eq!(latest.missing, 0);
+            assert_eq!(
+                latest.orphan_nodes.len() + latest.reachable_nodes,
+                store.node_count()
+            );
+        }
+    }
+    #[test]
+    fn chunks_of_extended_values_stay_reachable() {
+        let extended: Vec<u8> = (0..MAX_CONSENSUS_VALUE_LENGTH + 2)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        let chunks = extended.len().div_ceil(VALUE_CHUNK_SIZE);
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut store = MemoryStore::new();
+            let mut trie = UnitrieCore::new(implementation)
+                .with_config(CodecConfig::CONSENSUS.with_extended_values(true));
+            trie.try_put(vec![0x01], extended.clone()).unwrap();
+            trie.put(vec![0x02], vec![0x22; 64]);
+            trie.save_to_store(&mut store);
+            trie.put(vec![0x02], vec![0x23; 64]);
+            trie.save_to_store(&mut store);
+
+            let report = find_orphans(&mut store, &[trie.current_root_hash()]).unwrap();
+            assert_eq!(report.orphan_values.len(), 1);
+            assert_eq!(report.orphan_value_bytes, 64);
+            assert_eq!(report.reachable_values, 1 + chunks + 1);
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod path_bits;
 pub mod shared_path_serializer;
@@ -0,0 +1,253 @@
+use crate::node_ref::SharedPath;
+use std::ops::Range;
+
+// Bits packed MSB-first. Bits past `bit_len` in the last byte are always zero,
+// so derived equality and hashing only see the path itself.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PathBits {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl PathBits {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    pub fn from_key_bytes(bytes: &[u8]) -> Self {
+        Self {
+            bytes: bytes.to_vec(),
+            bit_len: bytes.len().saturating_mul(8),
+        }
+    }
+
+    // `bit_len` bits of MSB-first packed `bytes`; unused trailing bits are cleared.
+    pub fn from_packed(mut bytes: Vec<u8>, bit_len: usize) -> Result<Self, String> {
+        if bytes.len() != bit_len.div_ceil(8) {
+            return Err(format!(
+                "{} packed bytes cannot hold exactly {bit_len} bits",
+                bytes.len()
+            ));
+        }
+        clear_trailing_bits(&mut bytes, bit_len);
+        Ok(Self { bytes, bit_len })
+    }
+
+    // Bits `range` of a key read MSB-first, without copying the whole key.
+    // Panics when `range` is out of bounds, like slice indexing.
+    pub fn from_key_range(key: &[u8], range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= key.len() * 8,
+            "bit range {range:?} out of bounds for key of {} bits",
+            key.len() * 8
+        );
+        let bit_len = range.len();
+        if range.start.is_multiple_of(8) {
+            let mut bytes = key[range.start / 8..range.end.div_ceil(8)].to_vec();
+            clear_trailing_bits(&mut bytes, bit_len);
+            return Self { bytes, bit_len };
+        }
+
+        let shift = range.start % 8;
+        let first = range.start / 8;
+        let mut bytes = Vec::with_capacity(bit_len.div_ceil(8));
+        for index in 0..bit_len.div_ceil(8) {
+            let high = key[first + index] << shift;
+            let low = key
+                .get(first + index + 1)
+                .map_or(0, |next| next >> (8 - shift));
+            bytes.push(high | low);
+        }
+        clear_trailing_bits(&mut bytes, bit_len);
+        Self { bytes, bit_len }
+    }
+
+    pub fn from_bits(bits: &[u8]) -> Result<Self, String> {
+        if bits.iter().any(|bit| *bit > 1) {
+            return Err("path bits must contain only 0 or 1".to_string());
+        }
+
+        if bits.is_empty() {
+            return Ok(Self::empty());
+        }
+
+        let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+        for (index, bit) in bits.iter().enumerate() {
+            if *bit == 1 {
+                let byte_index = index / 8;
+                let bit_index = 7 - (index % 8);
+                bytes[byte_index] |= 1u8 << bit_index;
+            }
+        }
+
+        Ok(Self {
+            bytes,
+            bit_len: bits.len(),
+        })
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bit_len == 0
+    }
+
+    pub fn get_bit(&self, bit_index: usize) -> Option<u8> {
+        if bit_index >= self.bit_len {
+            return None;
+        }
+
+        let byte_index = bit_index / 8;
+        let shift = 7 - (bit_index % 8);
+        Some((self.bytes[byte_index] >> shift) & 1)
+    }
+
+    pub fn to_bits_vec(&self) -> Vec<u8> {
+        (0..self.bit_len)
+            .map(|index| self.get_bit(index).unwrap_or(0))
+            .collect()
+    }
+
+    pub fn to_packed_bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    pub fn common_prefix_len(&self, other: &PathBits) -> usize {
+        let max = self.bit_len.min(other.bit_len);
+        let mut matched = 0usize;
+        for (left, right) in self.bytes.iter().zip(&other.bytes) {
+            let diff = left ^ right;
+            if diff != 0 {
+                matched += diff.leading_zeros() as usize;
+                break;
+            }
+            matched += 8;
+        }
+        matched.min(max)
+    }
+
+    // Panics when `bit` is not 0 or 1.
+    pub fn append(&mut self, bit: u8) {
+        assert!(bit <= 1, "path bit must be 0 or 1, got {bit}");
+        if self.bit_len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit == 1 {
+            let byte_index = self.bit_len / 8;
+            self.bytes[byte_index] |= 1u8 << (7 - self.bit_len % 8);
+        }
+        self.bit_len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        let bit = self.get_bit(self.bit_len.checked_sub(1)?)?;
+        self.bit_len -= 1;
+        self.bytes.truncate(self.bit_len.div_ceil(8));
+        clear_trailing_bits(&mut self.bytes, self.bit_len);
+        Some(bit)
+    }
+
+    pub fn extend_from(&mut self, other: &PathBits) {
+        if self.bit_len.is_multiple_of(8) {
+            self.bytes.extend_from_slice(&other.bytes);
+            self.bit_len += other.bit_len;
+            return;
+        }
+        for index in 0..other.bit_len {
+            self.append(other.get_bit(index).unwrap_or(0));
+        }
+    }
+
+    // True when the bits starting at `offset` begin with `prefix`.
+    pub fn matches_at(&self, offset: usize, prefix: &PathBits) -> bool {
+        offset + prefix.bit_len <= self.bit_len
+            && (0..prefix.bit_len)
+                .all(|index| self.get_bit(offset + index) == prefix.get_bit(index))
+    }
+
+    // Panics when `range` is out of bounds, like slice indexing.
+    pub fn slice(&self, range: Range<usize>) -> PathBits {
+        assert!(
+            range.end <= self.bit_len,
+            "bit range {range:?} out of bounds for path of {} bits",
+            self.bit_len
+        );
+        Self::from_key_range(&self.bytes, range)
+    }
+
+    // The first `bit_index` bits and the rest.
+    pub fn split_at(&self, bit_index: usize) -> (PathBits, PathBits) {
+        (
+            self.slice(0..bit_index),
+            self.slice(bit_index..self.bit_len),
+        )
+    }
+}
+
+fn clear_trailing_bits(bytes: &mut [u8], bit_len: usize) {
+    let used = bit_len % 8;
+    if used != 0 {
+        if let Some(last) = bytes.last_mut() {
+            *last &= 0xffu8 << (8 - used);
+        }
+    }
+}
+
+impl From<&SharedPath> for PathBits {
+    fn from(path: &SharedPath) -> Self {
+        path.bits().clone()
+    }
+}
+
+impl From<&PathBits> for SharedPath {
+    fn from(path: &PathBits) -> Self {
+        SharedPath::from_path_bits(path.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathBits;
+    use crate::node_ref::SharedPath;
+
+    #[test]
+    fn path_bits_round_trip_from_bits() {
+        let bits = vec![1, 0, 1, 1, 0, 1, 0, 0, 1];
+        let path = PathBits::from_bits(&bits).expect("valid bits");
+        assert_eq!(path.bit_len(), bits.len());
+        assert_eq!(path.to_bits_vec(), bits);
+    }
+
+    #[test]
+    fn path_bits_from_key_bytes_reads_msb_first() {
+        let path = PathBits::from_key_bytes(&[0b1010_0000]);
+        assert_eq!(path.bit_len(), 8);
+        assert_eq!(path.to_bits_vec(), vec![1, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn path_bits_structural_operations() {
+        let path = PathBits::from_key_bytes(&[0b1011_0110, 0b0100_0000]);
+        let other = PathBits::from_bits(&[1, 0, 1, 1, 0, 1, 1, 0, 0, 0]).unwrap();
+        assert_eq!(path.common_prefix_len(&other), 9);
+        assert_eq!(path.common_prefix_len(&path), 16);
+        assert_eq!(path.common_prefix_len(&PathBits::empty()), 0);
+
+        assert_eq!(path.slice(3..7).to_bits_vec(), vec![1, 0, 1, 1]);
+        assert_eq!(path.slice(8..10), PathBits::from_bits(&[0, 1]).unwrap());
+
+        let (head, tail) = path.split_at(5);
+        let mut joined = head.clone();
+        for bit in tail.to_bits_vec() {
+            joined.append(bit);
+        }
+        assert_eq!(joined, path);
+        assert_eq!(head.bit_len() + tail.bit_len(), 16);
+
+        let shared = SharedPath::from(&other);
+        assert_eq!(shared.to_bits_vec(), other.to_bits_vec());
+        assert_eq!(PathBits::from(&shared), other);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::path::path_bits::PathBits;
 use crate::varint;
 
 pub fn calculate_encoded_length(key_length: usize) -> usize {
@@ -50,17 +51,29 @@ pub fn serialize_into(shared_path_bits: &[u8], output: &mut Vec<u8>) {
         return;
     }
 
-    let bit_length = shared_path_bits.len();
+    write_path_bit_length(shared_path_bits.len(), output);
+    output.extend_from_slice(&encode(shared_path_bits));
+}
+
+// Same wire format as `serialize_into`, copying the packed bytes as they are.
+pub fn serialize_packed_into(path: &PathBits, output: &mut Vec<u8>) {
+    if path.is_empty() {
+        return;
+    }
+
+    write_path_bit_length(path.bit_len(), output);
+    output.extend_from_slice(path.to_packed_bytes());
+}
+
+fn write_path_bit_length(bit_length: usize, output: &mut Vec<u8>) {
     if (1..=32).contains(&bit_length) {
         output.push((bit_length - 1) as u8);
     } else if (160..=382).contains(&bit_length) {
         output.push((bit_length - 128) as u8);
     } else {
         output.push(0xff);
-        output.extend_from_slice(&varint::encode(bit_length as u64));
+        varint::encode_into(bit_length as u64, output);
     }
-
-    output.extend_from_slice(&encode(shared_path_bits));
 }
 
 pub fn deserialize_from_slice(
@@ -84,6 +97,27 @@ pub fn deserialize_from_slice(
     Ok(decode(encoded, bit_length))
 }
 
+// Padding bits after the last path bit are cleared, not validated.
+pub fn deserialize_to_packed(
+    input: &[u8],
+    offset: &mut usize,
+    shared_prefix_present: bool,
+) -> Result<PathBits, String> {
+    if !shared_prefix_present {
+        return Ok(PathBits::empty());
+    }
+
+    let bit_length = read_path_bit_length(input, offset)?;
+    let end = offset
+        .checked_add(calculate_encoded_length(bit_length))
+        .filter(|end| *end <= input.len())
+        .ok_or_else(|| "shared path encoded bytes are truncated".to_string())?;
+
+    let path = PathBits::from_packed(input[*offset..end].to_vec(), bit_length)?;
+    *offset = end;
+    Ok(path)
+}
+
 pub fn read_path_bit_length(input: &[u8], offset: &mut usize) -> Result<usize, String> {
     if *offset >= input.len() {
         return Err("shared path prefix is truncated".to_string());
@@ -106,9 +140,10 @@ pub fn read_path_bit_length(input: &[u8], offset: &mut usize) -> Result<usize, S
 #[cfg(test)]
 mod tests {
     use super::{
-        calculate_varint_size, decode, deserialize_from_slice, encode, read_path_bit_length,
-        serialize_into, serialized_length,
+        calculate_varint_size, decode, deserialize_from_slice, deserialize_to_packed, encode,
+        read_path_bit_length, serialize_into, serialize_packed_into, serialized_length,
     };
+    use crate::path::path_bits::PathBits;
 
     #[test]
     fn bit_pack_round_trip() {
@@ -143,6 +178,30 @@ mod tests {
         assert_eq!(serialized_length(&path), output.len());
     }
 
+    #[test]
+    fn packed_serialization_matches_bit_serialization() {
+        for bit_length in [1usize, 9, 32, 33, 120, 160, 256, 382, 400] {
+            let bits: Vec<u8> = (0..bit_length)
+                .map(|index| (index % 3 == 0) as u8)
+                .collect();
+            let path = PathBits::from_bits(&bits).unwrap();
+
+            let mut expected = Vec::new();
+            serialize_into(&bits, &mut expected);
+            let mut packed = Vec::new();
+            serialize_packed_into(&path, &mut packed);
+            assert_eq!(packed, expected);
+
+            let mut offset = 0usize;
+            assert_eq!(
+                deserialize_to_packed(&packed, &mut offset, true).unwrap(),
+                path
+            );
+            assert_eq!(offset, packed.len());
+        }
+        assert!(deserialize_to_packed(&[0x07], &mut 0usize, true).is_err());
+    }
+
     #[test]
     fn deserialize_returns_empty_when_not_present() {
         let mut offset = 0usize;
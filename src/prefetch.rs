@@ -0,0 +1,275 @@
+use crate::core_trie::decode_persisted_node;
+use crate::hash::is_empty_root;
+use crate::node_ref::{NodeReference, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PrefetchStats {
+    pub keys: usize,
+    pub nodes_loaded: usize,
+    pub values_loaded: usize,
+    pub missing_keys: usize,
+}
+
+// Raw payloads gathered by `prefetch`. It is plain data, so it can be filled on
+// a worker thread with its own store handle and handed over before execution.
+#[derive(Debug, Clone, Default)]
+pub struct PrefetchCache {
+    nodes: HashMap<[u8; HASH_SIZE], Vec<u8>>,
+    values: HashMap<[u8; HASH_SIZE], Vec<u8>>,
+}
+
+impl PrefetchCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn merge(&mut self, other: PrefetchCache) {
+        self.nodes.extend(other.nodes);
+        self.values.extend(other.values);
+    }
+
+    // Walks the path of every key from `root_hash`, caching each node and long
+    // value on the way. Keys absent from the trie still warm their path.
+    pub fn prefetch<T: RawStoreAdapter>(
+        &mut self,
+        root_hash: &[u8],
+        keys: &[Vec<u8>],
+        store: &mut T,
+    ) -> Result<PrefetchStats, String> {
+        let root: [u8; HASH_SIZE] = root_hash
+            .try_into()
+            .map_err(|_| format!("root hash must be {HASH_SIZE} bytes"))?;
+        let mut stats = PrefetchStats {
+            keys: keys.len(),
+            ..PrefetchStats::default()
+        };
+        if is_empty_root(&root) {
+            stats.missing_keys = keys.len();
+            return Ok(stats);
+        }
+
+        let mut decoded = HashMap::new();
+        for key in keys {
+            let bits = PathBits::from_key_bytes(key);
+            if !self.walk(root, &bits, store, &mut decoded, &mut stats)? {
+                stats.missing_keys += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    pub fn over<S: RawStoreAdapter>(self, inner: S) -> PrefetchedStore<S> {
+        PrefetchedStore {
+            cache: self,
+            inner,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn walk<T: RawStoreAdapter>(
+        &mut self,
+        root: [u8; HASH_SIZE],
+        key_bits: &PathBits,
+        store: &mut T,
+        decoded: &mut HashMap<[u8; HASH_SIZE], TrieNode>,
+        stats: &mut PrefetchStats,
+    ) -> Result<bool, String> {
+        let mut node = self.load_node(&root, store, decoded, stats)?;
+        let mut offset = 0usize;
+        loop {
+            let path = node.shared_path.bits();
+            if !key_bits.matches_at(offset, path) {
+                return Ok(false);
+            }
+            offset += path.bit_len();
+
+            if offset == key_bits.bit_len() {
+                if let ValueRef::Hashed { hash, .. } = &node.value {
+                    self.load_value(hash, store, stats)?;
+                }
+                return Ok(node.value.has_value());
+            }
+
+            let child = if key_bits.get_bit(offset) == Some(0) {
+                &node.left
+            } else {
+                &node.right
+            };
+            offset += 1;
+            node = match child {
+                NodeReference::Empty => return Ok(false),
+                NodeReference::Embedded(child) => child.as_ref().clone(),
+                NodeReference::Hashed(hash) => {
+                    let hash = *hash;
+                    self.load_node(&hash, store, decoded, stats)?
+                }
+            };
+        }
+    }
+
+    fn load_node<T: RawStoreAdapter>(
+        &mut self,
+        hash: &[u8; HASH_SIZE],
+        store: &mut T,
+        decoded: &mut HashMap<[u8; HASH_SIZE], TrieNode>,
+        stats: &mut PrefetchStats,
+    ) -> Result<TrieNode, String> {
+        if let Some(node) = decoded.get(hash) {
+            return Ok(node.clone());
+        }
+
+        let payload = match self.nodes.get(hash) {
+            Some(payload) => payload.clone(),
+            None => {
+                let payload = store
+                    .load_raw_node(hash)
+                    .ok_or_else(|| "prefetched node was not found in store".to_string())?;
+                self.nodes.insert(*hash, payload.clone());
+                stats.nodes_loaded += 1;
+                payload
+            }
+        };
+        let node = decode_persisted_node(&payload, store.codec_hint())?;
+        decoded.insert(*hash, node.clone());
+        Ok(node)
+    }
+
+    fn load_value<T: RawStoreAdapter>(
+        &mut self,
+        hash: &[u8; HASH_SIZE],
+        store: &mut T,
+        stats: &mut PrefetchStats,
+    ) -> Result<(), String> {
+        if self.values.contains_key(hash) {
+            return Ok(());
+        }
+
+        let value = store
+            .load_raw_value(hash)
+            .ok_or_else(|| "prefetched long value was not found in store".to_string())?;
+        self.values.insert(*hash, value);
+        stats.values_loaded += 1;
+        Ok(())
+    }
+}
+
+// Serves loads from a prefetch cache before falling back to the inner store.
+#[derive(Debug)]
+pub struct PrefetchedStore<S> {
+    cache: PrefetchCache,
+    inner: S,
+    hits: usize,
+    misses: usize,
+}
+
+impl<S: RawStoreAdapter> PrefetchedStore<S> {
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn into_parts(self) -> (PrefetchCache, S) {
+        (self.cache, self.inner)
+    }
+}
+
+impl<S: RawStoreAdapter> RawStoreAdapter for PrefetchedStore<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let cached = <[u8; HASH_SIZE]>::try_from(hash)
+            .ok()
+            .and_then(|hash| self.cache.nodes.get(&hash).cloned());
+        if cached.is_some() {
+            self.hits += 1;
+            return cached;
+        }
+        self.misses += 1;
+        self.inner.load_raw_node(hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let cached = <[u8; HASH_SIZE]>::try_from(hash)
+            .ok()
+            .and_then(|hash| self.cache.values.get(&hash).cloned());
+        if cached.is_some() {
+            self.hits += 1;
+            return cached;
+        }
+        self.misses += 1;
+        self.inner.load_raw_value(hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        self.inner.save_raw_node(hash, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        self.inner.save_raw_value(hash, value);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+// Convenience wrapper returning a fresh cache for one access set.
+pub fn prefetch<T: RawStoreAdapter>(
+    root_hash: &[u8],
+    keys: &[Vec<u8>],
+    store: &mut T,
+) -> Result<(PrefetchCache, PrefetchStats), String> {
+    let mut cache = PrefetchCache::new();
+    let stats = cache.prefetch(root_hash, keys, store)?;
+    Ok((cache, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefetch;
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+
+    #[test]
+    fn prefetch_warms_the_paths_of_requested_keys() {
+        let mut trie = Unitrie::new();
+        for index in 0u8..64 {
+            trie.put(vec![index, 0xaa, index], vec![index; 40]);
+        }
+        let root = trie.root_hash();
+        let mut store = MemoryStore::new();
+        trie.save_to_store(&mut store);
+
+        let keys = vec![vec![3, 0xaa, 3], vec![40, 0xaa, 40], vec![40, 0xbb]];
+        let (cache, stats) = prefetch(&root, &keys, &mut store).unwrap();
+        assert_eq!(stats.values_loaded, 2);
+        assert_eq!(stats.missing_keys, 1);
+        assert_eq!(stats.nodes_loaded, cache.node_count());
+        assert!(cache.node_count() < store.node_count());
+
+        // A cache over an empty store is enough to read the prefetched keys.
+        let mut warm = cache.over(MemoryStore::new());
+        for key in &keys[..2] {
+            let value = warm.load_raw_value(&crate::hash::keccak256(&[key[0]; 40]));
+            assert_eq!(value, Some(vec![key[0]; 40]));
+        }
+        assert_eq!(warm.misses(), 0);
+    }
+}
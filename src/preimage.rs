@@ -0,0 +1,199 @@
+use crate::hash::keccak256;
+use crate::key_scheme::KeyScheme;
+use crate::node_ref::HASH_SIZE;
+use crate::observer::TrieObserver;
+use crate::store_adapter::RawStoreAdapter;
+use crate::UnitrieCore;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+// Account keys carry 20-byte addresses, as in RSK.
+const ADDRESS_SIZE: usize = 20;
+
+#[derive(Debug, Default)]
+struct RegistryState {
+    preimages: HashMap<Vec<u8>, Vec<u8>>,
+    // Secure prefixes recorded since the last `persist`.
+    unpersisted: Vec<Vec<u8>>,
+}
+
+// Reverse lookups from secure key prefixes to the addresses and storage slots
+// they were derived from, for debugging and RPC. Registered as an observer it
+// records the pre-images of every account, code and storage key written.
+// Entries are not content-addressed, so persist them to a store (or a
+// `NamespacedStore`) of their own rather than next to trie nodes.
+#[derive(Debug)]
+pub struct PreimageRegistry {
+    key_scheme: KeyScheme,
+    state: Mutex<RegistryState>,
+}
+
+impl PreimageRegistry {
+    pub fn new(key_scheme: KeyScheme) -> Self {
+        Self {
+            key_scheme,
+            state: Mutex::new(RegistryState::default()),
+        }
+    }
+
+    pub fn from_trie(trie: &UnitrieCore) -> Self {
+        let registry = Self::new(*trie.key_scheme());
+        for (key, _) in trie.iter_prefix(&[]) {
+            registry.record_key(key);
+        }
+        registry
+    }
+
+    pub fn key_scheme(&self) -> &KeyScheme {
+        &self.key_scheme
+    }
+
+    // Records `preimage` and returns its secure prefix.
+    pub fn record(&self, preimage: &[u8]) -> Vec<u8> {
+        let secure_prefix = self.key_scheme.secure_key_prefix(preimage);
+        self.insert(secure_prefix.clone(), preimage.to_vec());
+        secure_prefix
+    }
+
+    // Records the address, and the slot for storage keys, behind a trie key.
+    // Keys outside the account layout are ignored.
+    pub fn record_key(&self, key: &[u8]) {
+        let scheme = &self.key_scheme;
+        let account_size = scheme.domain_prefix.len() + scheme.secure_key_size + ADDRESS_SIZE;
+        let Some(address) = key
+            .get(..account_size)
+            .and_then(|account_key| scheme.account_address(account_key))
+        else {
+            return;
+        };
+        self.record(address);
+
+        let slot = key[account_size..]
+            .strip_prefix(scheme.storage_prefix)
+            .filter(|rest| rest.len() > scheme.secure_key_size)
+            .map(|rest| rest.split_at(scheme.secure_key_size))
+            .filter(|(secure_prefix, slot)| scheme.secure_key_prefix(slot) == *secure_prefix);
+        if let Some((secure_prefix, slot)) = slot {
+            self.insert(secure_prefix.to_vec(), slot.to_vec());
+        }
+    }
+
+    pub fn lookup(&self, secure_prefix: &[u8]) -> Option<Vec<u8>> {
+        self.state().preimages.get(secure_prefix).cloned()
+    }
+
+    // `lookup`, falling back to entries persisted in `store`. Stored pre-images
+    // are checked against the prefix and cached.
+    pub fn lookup_in_store<S: RawStoreAdapter>(
+        &self,
+        secure_prefix: &[u8],
+        store: &mut S,
+    ) -> Option<Vec<u8>> {
+        if let Some(preimage) = self.lookup(secure_prefix) {
+            return Some(preimage);
+        }
+        let preimage = store
+            .load_raw_value(&preimage_store_key(secure_prefix))
+            .filter(|preimage| self.key_scheme.secure_key_prefix(preimage) == secure_prefix)?;
+        self.state()
+            .preimages
+            .insert(secure_prefix.to_vec(), preimage.clone());
+        Some(preimage)
+    }
+
+    // Writes the entries recorded since the last call; returns how many.
+    pub fn persist<S: RawStoreAdapter>(&self, store: &mut S) -> usize {
+        let mut state = self.state();
+        let unpersisted = std::mem::take(&mut state.unpersisted);
+        for secure_prefix in &unpersisted {
+            if let Some(preimage) = state.preimages.get(secure_prefix) {
+                store.save_raw_value(&preimage_store_key(secure_prefix), preimage);
+            }
+        }
+        unpersisted.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state().preimages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&self, secure_prefix: Vec<u8>, preimage: Vec<u8>) {
+        let mut state = self.state();
+        if state.preimages.contains_key(&secure_prefix) {
+            return;
+        }
+        state.unpersisted.push(secure_prefix.clone());
+        state.preimages.insert(secure_prefix, preimage);
+    }
+
+    fn state(&self) -> MutexGuard<'_, RegistryState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl TrieObserver for PreimageRegistry {
+    // Pre-images outlive their keys: deletes keep them for later lookups.
+    fn on_put(&self, key: &[u8], _value: &[u8]) {
+        self.record_key(key);
+    }
+}
+
+// Where the pre-image of `secure_prefix` is stored.
+pub fn preimage_store_key(secure_prefix: &[u8]) -> [u8; HASH_SIZE] {
+    let mut input = b"unitrie-preimage".to_vec();
+    input.extend_from_slice(secure_prefix);
+    keccak256(&input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{preimage_store_key, PreimageRegistry};
+    use crate::key_scheme::KeyScheme;
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn registry_records_written_keys_and_persists_them() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let scheme = KeyScheme::RSK;
+            let existing = [0x11u8; 20];
+            let account = [0x22u8; 20];
+            let slot = [0x33u8; 32];
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(scheme.account_key(&existing), vec![0x01]);
+
+            let registry = trie.enable_preimage_registry();
+            trie.put(scheme.account_key(&account), vec![0x02]);
+            trie.put_storage_value(&account, &slot, vec![0x03]);
+            trie.put(b"not-an-account".to_vec(), vec![0x04]);
+            assert_eq!(registry.len(), 3);
+            for preimage in [&existing[..], &account, &slot] {
+                let secure_prefix = scheme.secure_key_prefix(preimage);
+                assert_eq!(registry.lookup(&secure_prefix), Some(preimage.to_vec()));
+            }
+
+            let mut store = MemoryStore::new();
+            assert_eq!(registry.persist(&mut store), 3);
+            assert_eq!(registry.persist(&mut store), 0);
+
+            let reloaded = PreimageRegistry::new(scheme);
+            let slot_prefix = scheme.secure_key_prefix(&slot);
+            assert_eq!(reloaded.lookup(&slot_prefix), None);
+            assert_eq!(
+                reloaded.lookup_in_store(&slot_prefix, &mut store),
+                Some(slot.to_vec())
+            );
+            assert_eq!(reloaded.lookup(&slot_prefix), Some(slot.to_vec()));
+
+            let account_prefix = scheme.secure_key_prefix(&account);
+            store.save_raw_value(&preimage_store_key(&account_prefix), &[0x99; 20]);
+            assert_eq!(reloaded.lookup_in_store(&account_prefix, &mut store), None);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// The semver-stable surface: `use unitrie_rs::prelude::*;` covers what hosts
+// need to build, load, mutate and persist a trie. Modules marked
+// `#[doc(hidden)]` in the crate root are internals and may change at any time.
+pub use crate::config::UnitrieConfig;
+pub use crate::core_api::{
+    BlockStateResult, EmptyValuePolicy, PersistentTrieEngine, TrieEngine, TrieOp, TrieSnapshot,
+    WriteError,
+};
+pub use crate::core_trie::{CancellationToken, LoadError, LoadLimits, SaveStats, SubtreeDigest};
+pub use crate::generic_core::UnitrieCoreGeneric;
+pub use crate::index_trie::{ordered_list_root, IndexTrie};
+pub use crate::key_scheme::KeyScheme;
+pub use crate::node_ref::CodecConfig;
+pub use crate::proof::{
+    verified_get, verified_get_with_config, AccountProof, ProofError, ProofSizeEstimate,
+    StorageProof, TrieProof,
+};
+pub use crate::store_adapter::{MemoryStore, RawStoreAdapter, SharedStoreAdapter};
+pub use crate::value_handle::ValueHandle;
+#[cfg(feature = "next")]
+pub use crate::{RootHistory, RootRecord};
+pub use crate::{TrieRoot, UnitrieCore, UnitrieImplementation};
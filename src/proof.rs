@@ -0,0 +1,664 @@
+use crate::codec_rskip107::{DecodeOptions, Rskip107Codec};
+use crate::hash::{is_empty_root, keccak256};
+use crate::json::{from_hex, to_hex, JsonValue};
+use crate::node_ref::{CodecConfig, NodeReference, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
+use crate::varint;
+use std::fmt;
+
+// Leading byte of the wire format, bumped whenever the layout changes.
+const PROOF_FORMAT_VERSION: u8 = 0x02;
+
+// Inclusion or exclusion proof for one key: the RSKIP107 serializations of the
+// hashed nodes on the key's path, root first, as RSKj's `getNodes` returns
+// them. Embedded nodes are not listed; they travel inside their parent.
+// The proof of an empty trie has no nodes.
+//
+// Nodes only commit to the hash of long values, so proofs of keys holding one
+// carry the value itself in `value`; it is `None` otherwise.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TrieProof {
+    pub key: Vec<u8>,
+    pub nodes: Vec<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl TrieProof {
+    pub fn new(key: Vec<u8>, nodes: Vec<Vec<u8>>) -> Self {
+        Self {
+            key,
+            nodes,
+            value: None,
+        }
+    }
+
+    pub fn with_value(mut self, value: Option<Vec<u8>>) -> Self {
+        self.value = value;
+        self
+    }
+
+    // The root the proof commits to; `None` for the proof of an empty trie.
+    pub fn root_hash(&self) -> Option<[u8; HASH_SIZE]> {
+        self.nodes.first().map(|node| keccak256(node))
+    }
+
+    // Canonical wire format:
+    //   version || varint(key length) || key || varint(node count)
+    //   || (varint(node length) || node)* || 0x00
+    // or, with a long value, the trailing 0x00 replaced by
+    //   0x01 || varint(value length) || value
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(
+            1 + varint::size_of(self.key.len() as u64)
+                + self.key.len()
+                + varint::size_of(self.nodes.len() as u64)
+                + self
+                    .nodes
+                    .iter()
+                    .map(|node| varint::size_of(node.len() as u64) + node.len())
+                    .sum::<usize>()
+                + 1
+                + self
+                    .value
+                    .as_ref()
+                    .map_or(0, |value| varint::size_of(value.len() as u64) + value.len()),
+        );
+        encoded.push(PROOF_FORMAT_VERSION);
+        write_bytes(&self.key, &mut encoded);
+        varint::encode_into(self.nodes.len() as u64, &mut encoded);
+        for node in &self.nodes {
+            write_bytes(node, &mut encoded);
+        }
+        match &self.value {
+            None => encoded.push(0x00),
+            Some(value) => {
+                encoded.push(0x01);
+                write_bytes(value, &mut encoded);
+            }
+        }
+        encoded
+    }
+
+    pub fn decode(encoded: &[u8]) -> Result<Self, String> {
+        match encoded.first() {
+            Some(&PROOF_FORMAT_VERSION) => {}
+            Some(version) => return Err(format!("unsupported proof format 0x{version:02x}")),
+            None => return Err("proof is empty".to_string()),
+        }
+        let mut offset = 1usize;
+        let key = read_bytes(encoded, &mut offset)?;
+        let count = varint::decode_from_slice(encoded, &mut offset)?;
+        // Every node takes at least its length byte, which bounds the allocation.
+        if count > (encoded.len() - offset) as u64 {
+            return Err(format!("proof declares {count} nodes but is truncated"));
+        }
+        let mut nodes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            nodes.push(read_bytes(encoded, &mut offset)?);
+        }
+        let value = match encoded.get(offset) {
+            Some(0x00) => {
+                offset += 1;
+                None
+            }
+            Some(0x01) => {
+                offset += 1;
+                Some(read_bytes(encoded, &mut offset)?)
+            }
+            Some(marker) => return Err(format!("invalid long value marker 0x{marker:02x}")),
+            None => return Err("proof has no long value section".to_string()),
+        };
+        if offset != encoded.len() {
+            return Err(format!(
+                "proof has {} trailing bytes",
+                encoded.len() - offset
+            ));
+        }
+        Ok(Self { key, nodes, value })
+    }
+
+    // The storage proof entry shape of `eth_getProof` as served by RSKj:
+    //   {"key":"0x..","proof":["0x..",...]}
+    // plus `"longValue":"0x.."` when the proof carries one. RPC clients that
+    // do not know the field ignore it.
+    pub fn to_json(&self) -> String {
+        let long_value = self.value.as_ref().map_or(String::new(), |value| {
+            format!(",\"longValue\":\"{}\"", to_hex(value))
+        });
+        format!(
+            "{{\"key\":\"{}\",\"proof\":{}{long_value}}}",
+            to_hex(&self.key),
+            hex_list(&self.nodes)
+        )
+    }
+
+    // Accepts the output of `to_json` with any whitespace and field order;
+    // other fields, such as RPC `value`s, are ignored.
+    pub fn from_json(input: &str) -> Result<Self, String> {
+        let json = JsonValue::parse(input)?;
+        let nodes = json
+            .expect_field("proof")?
+            .as_array()?
+            .iter()
+            .map(|node| from_hex(node.as_str()?))
+            .collect::<Result<_, _>>()?;
+        let value = match json.field("longValue") {
+            None => None,
+            Some(value) => Some(from_hex(value.as_str()?)?),
+        };
+        Ok(Self {
+            key: from_hex(json.expect_field("key")?.as_str()?)?,
+            nodes,
+            value,
+        })
+    }
+}
+
+// Why `verified_get` rejected a proof.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ProofError {
+    // The proof was built for another key.
+    KeyMismatch,
+    // The path leads to a hashed node the proof does not include.
+    MissingNode {
+        hash: [u8; HASH_SIZE],
+    },
+    HashMismatch {
+        expected: [u8; HASH_SIZE],
+        actual: [u8; HASH_SIZE],
+    },
+    MalformedNode(String),
+    // Nodes left over once the lookup ended; canonical proofs have none.
+    UnusedNodes {
+        count: usize,
+    },
+    // The key holds a long value and the proof does not carry it.
+    MissingValue {
+        hash: [u8; HASH_SIZE],
+    },
+    ValueMismatch {
+        expected: [u8; HASH_SIZE],
+    },
+    // The proof carries a value for a key without a long value.
+    UnexpectedValue,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyMismatch => f.write_str("proof was built for another key"),
+            Self::MissingNode { hash } => write!(f, "proof is missing node {}", to_hex(hash)),
+            Self::HashMismatch { expected, actual } => write!(
+                f,
+                "proof node hashes to {}, expected {}",
+                to_hex(actual),
+                to_hex(expected)
+            ),
+            Self::MalformedNode(message) => write!(f, "malformed proof node: {message}"),
+            Self::UnusedNodes { count } => {
+                write!(f, "proof has {count} nodes off the key's path")
+            }
+            Self::MissingValue { hash } => {
+                write!(f, "proof is missing long value {}", to_hex(hash))
+            }
+            Self::ValueMismatch { expected } => write!(
+                f,
+                "proof value does not match long value {}",
+                to_hex(expected)
+            ),
+            Self::UnexpectedValue => f.write_str("proof carries a value the key does not hold"),
+        }
+    }
+}
+
+// Checks `proof` against a trusted `root` and returns the value it proves for
+// `key`: `Some` for an inclusion proof, `None` for an exclusion proof.
+pub fn verified_get(
+    root: &[u8; HASH_SIZE],
+    key: &[u8],
+    proof: &TrieProof,
+) -> Result<Option<Vec<u8>>, ProofError> {
+    verified_get_with_config(root, key, proof, &CodecConfig::CONSENSUS)
+}
+
+// `verified_get` for a trie encoded under `config`, whose nodes may inline
+// values up to its long value threshold.
+pub fn verified_get_with_config(
+    root: &[u8; HASH_SIZE],
+    key: &[u8],
+    proof: &TrieProof,
+    config: &CodecConfig,
+) -> Result<Option<Vec<u8>>, ProofError> {
+    if proof.key != key {
+        return Err(ProofError::KeyMismatch);
+    }
+
+    let mut nodes = proof.nodes.iter();
+    let value = if proof.nodes.is_empty() && is_empty_root(root) {
+        ValueRef::Empty
+    } else {
+        lookup_proven_value(root, key, &mut nodes, &DecodeOptions::untrusted_for(config))?
+    };
+    if nodes.len() > 0 {
+        return Err(ProofError::UnusedNodes { count: nodes.len() });
+    }
+
+    match value {
+        ValueRef::Hashed { hash, length } => {
+            let payload = proof
+                .value
+                .as_ref()
+                .ok_or(ProofError::MissingValue { hash })?;
+            if keccak256(payload) != hash || length.is_some_and(|length| length != payload.len()) {
+                return Err(ProofError::ValueMismatch { expected: hash });
+            }
+            Ok(Some(payload.clone()))
+        }
+        _ if proof.value.is_some() => Err(ProofError::UnexpectedValue),
+        ValueRef::Inline(value) => Ok(Some(value)),
+        ValueRef::Empty => Ok(None),
+    }
+}
+
+// Follows `key` from the root through the proof nodes, each of which must hash
+// to the reference its parent holds.
+fn lookup_proven_value<'a>(
+    root: &[u8; HASH_SIZE],
+    key: &[u8],
+    nodes: &mut impl Iterator<Item = &'a Vec<u8>>,
+    options: &DecodeOptions,
+) -> Result<ValueRef, ProofError> {
+    let key_bits = PathBits::from_key_bytes(key);
+    let mut node = next_proof_node(nodes, *root, options)?;
+    let mut offset = 0usize;
+    loop {
+        let TrieNode {
+            shared_path,
+            value,
+            left,
+            right,
+            ..
+        } = node;
+        if !key_bits.matches_at(offset, shared_path.bits()) {
+            return Ok(ValueRef::Empty);
+        }
+        offset += shared_path.len();
+        let child = match key_bits.get_bit(offset) {
+            None => return Ok(value),
+            Some(0) => left,
+            Some(_) => right,
+        };
+        offset += 1;
+        node = match child {
+            NodeReference::Empty => return Ok(ValueRef::Empty),
+            NodeReference::Embedded(child) => *child,
+            NodeReference::Hashed(hash) => next_proof_node(nodes, hash, options)?,
+        };
+    }
+}
+
+fn next_proof_node<'a>(
+    nodes: &mut impl Iterator<Item = &'a Vec<u8>>,
+    expected: [u8; HASH_SIZE],
+    options: &DecodeOptions,
+) -> Result<TrieNode, ProofError> {
+    let payload = nodes
+        .next()
+        .ok_or(ProofError::MissingNode { hash: expected })?;
+    let actual = keccak256(payload);
+    if actual != expected {
+        return Err(ProofError::HashMismatch { expected, actual });
+    }
+    Rskip107Codec::decode_node_with_options(payload, options).map_err(ProofError::MalformedNode)
+}
+
+// One requested storage slot of an `AccountProof`. `proof.key` is the full
+// trie key of the slot; `value` is `None` when the proof shows it is unset.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StorageProof {
+    pub storage_key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    pub proof: TrieProof,
+}
+
+// EIP-1186-style bundle: the proof of an account's node plus one proof per
+// requested storage slot, all against the same root. `account_state` is the
+// account node's value as stored, `None` for accounts that do not exist.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AccountProof {
+    pub address: Vec<u8>,
+    pub account_state: Option<Vec<u8>>,
+    pub account_proof: TrieProof,
+    pub storage_proofs: Vec<StorageProof>,
+}
+
+impl AccountProof {
+    pub fn root_hash(&self) -> Option<[u8; HASH_SIZE]> {
+        self.account_proof.root_hash()
+    }
+
+    // The `rsk_getProof` / `eth_getProof` result shape. Account fields the trie
+    // does not interpret (balance, nonce, ...) are left to the caller; unset
+    // slots read "0x0" as in `eth_getProof`.
+    pub fn to_json(&self) -> String {
+        let storage_proofs = self
+            .storage_proofs
+            .iter()
+            .map(|storage| {
+                format!(
+                    "{{\"key\":\"{}\",\"value\":\"{}\",\"proof\":{}}}",
+                    to_hex(&storage.storage_key),
+                    storage.value.as_deref().map_or("0x0".to_string(), to_hex),
+                    hex_list(&storage.proof.nodes)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"address\":\"{}\",\"accountProof\":{},\"storageProof\":[{storage_proofs}]}}",
+            to_hex(&self.address),
+            hex_list(&self.account_proof.nodes)
+        )
+    }
+}
+
+// EIP-2028 calldata prices.
+const CALLDATA_ZERO_BYTE_GAS: u64 = 4;
+const CALLDATA_NONZERO_BYTE_GAS: u64 = 16;
+
+// What a proof costs to ship, from `estimate_proof_size` and
+// `estimate_multiproof_size`. `encoded_bytes` and `calldata_gas` price the wire
+// format; a multiproof is priced as one encoding carrying every key and long
+// value followed by the distinct nodes of all their paths.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ProofSizeEstimate {
+    pub nodes: usize,
+    pub node_bytes: usize,
+    pub encoded_bytes: usize,
+    pub calldata_gas: u64,
+}
+
+impl Default for ProofSizeEstimate {
+    // An encoding with no keys and no nodes: the version and a zero node count.
+    fn default() -> Self {
+        let mut estimate = Self {
+            nodes: 0,
+            node_bytes: 0,
+            encoded_bytes: 0,
+            calldata_gas: 0,
+        };
+        estimate.add_bytes(&[PROOF_FORMAT_VERSION]);
+        estimate.add_bytes(&varint::encode(0));
+        estimate
+    }
+}
+
+impl ProofSizeEstimate {
+    pub(crate) fn add_key(&mut self, key: &[u8], long_value: Option<&[u8]>) {
+        self.add_bytes(&varint::encode(key.len() as u64));
+        self.add_bytes(key);
+        match long_value {
+            None => self.add_bytes(&[0x00]),
+            Some(value) => {
+                self.add_bytes(&[0x01]);
+                self.add_bytes(&varint::encode(value.len() as u64));
+                self.add_bytes(value);
+            }
+        }
+    }
+
+    // Prices a node from its length and how many of its bytes are zero, so the
+    // node itself need not be encoded.
+    pub(crate) fn add_node(&mut self, length: usize, zero_bytes: usize) {
+        self.remove_bytes(&varint::encode(self.nodes as u64));
+        self.nodes += 1;
+        self.add_bytes(&varint::encode(self.nodes as u64));
+        self.node_bytes += length;
+        self.add_bytes(&varint::encode(length as u64));
+        self.encoded_bytes += length;
+        self.calldata_gas += zero_bytes as u64 * CALLDATA_ZERO_BYTE_GAS
+            + (length - zero_bytes) as u64 * CALLDATA_NONZERO_BYTE_GAS;
+    }
+
+    fn add_bytes(&mut self, bytes: &[u8]) {
+        self.encoded_bytes += bytes.len();
+        self.calldata_gas += calldata_gas(bytes);
+    }
+
+    fn remove_bytes(&mut self, bytes: &[u8]) {
+        self.encoded_bytes -= bytes.len();
+        self.calldata_gas -= calldata_gas(bytes);
+    }
+}
+
+fn calldata_gas(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .map(|byte| {
+            if *byte == 0 {
+                CALLDATA_ZERO_BYTE_GAS
+            } else {
+                CALLDATA_NONZERO_BYTE_GAS
+            }
+        })
+        .sum()
+}
+
+fn write_bytes(bytes: &[u8], output: &mut Vec<u8>) {
+    varint::encode_into(bytes.len() as u64, output);
+    output.extend_from_slice(bytes);
+}
+
+fn read_bytes(input: &[u8], offset: &mut usize) -> Result<Vec<u8>, String> {
+    let length = varint::decode_from_slice(input, offset)?;
+    let end = usize::try_from(length)
+        .ok()
+        .and_then(|length| offset.checked_add(length))
+        .filter(|end| *end <= input.len())
+        .ok_or_else(|| format!("proof field of {length} bytes is truncated"))?;
+    let bytes = input[*offset..end].to_vec();
+    *offset = end;
+    Ok(bytes)
+}
+
+fn hex_list(items: &[Vec<u8>]) -> String {
+    let items = items
+        .iter()
+        .map(|item| format!("\"{}\"", to_hex(item)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("[{items}]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calldata_gas, verified_get, verified_get_with_config, ProofError, TrieProof};
+    use crate::core_trie::Unitrie;
+    use crate::hash::empty_trie_hash;
+    use crate::key_scheme::KeyScheme;
+    use crate::node_ref::CodecConfig;
+    use crate::UnitrieCore;
+    use crate::UnitrieImplementation;
+
+    fn sample_trie() -> Unitrie {
+        let mut trie = Unitrie::new();
+        for index in 0u8..40 {
+            trie.put(vec![0x01, index], vec![index; 1 + index as usize]);
+        }
+        trie
+    }
+
+    #[test]
+    fn proofs_list_hashed_path_nodes_from_the_root() {
+        let mut trie = sample_trie();
+        let root = trie.root_hash();
+
+        let proof = trie.get_proof(&[0x01, 39]);
+        assert_eq!(proof.root_hash(), Some(root));
+        assert_eq!(proof.nodes[0], trie.encode_root());
+        assert!(proof.nodes.len() > 1);
+
+        // A key diverging at the root stops after it.
+        assert_eq!(trie.get_proof(&[0xff]).nodes, vec![trie.encode_root()]);
+        assert!(Unitrie::new().get_proof(&[0x01]).nodes.is_empty());
+
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            for (key, value) in trie.iter() {
+                core.put(key.to_vec(), value.to_vec());
+            }
+            assert_eq!(core.get_proof(&[0x01, 39]), proof);
+        }
+    }
+
+    #[test]
+    fn proofs_round_trip_through_wire_format_and_json() {
+        let proof = sample_trie().get_proof(&[0x01, 7]);
+
+        let encoded = proof.encode();
+        assert_eq!(TrieProof::decode(&encoded).unwrap(), proof);
+        assert!(TrieProof::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(TrieProof::decode(&[0x02]).is_err());
+        let mut older = encoded.clone();
+        older[0] = 0x01;
+        assert!(TrieProof::decode(&older).is_err());
+
+        let json = proof.to_json();
+        assert!(json.starts_with("{\"key\":\"0x0107\",\"proof\":[\"0x"));
+        assert_eq!(TrieProof::from_json(&json).unwrap(), proof);
+
+        let rpc = "{ \"value\": \"0x07\", \"proof\": [ \"0x4f\" ], \"key\": \"0x0107\" }";
+        assert_eq!(
+            TrieProof::from_json(rpc).unwrap(),
+            TrieProof::new(vec![0x01, 0x07], vec![vec![0x4f]])
+        );
+        assert!(TrieProof::from_json("{\"key\":\"0x01\"}").is_err());
+        assert!(TrieProof::from_json("{\"key\":\"01\",\"proof\":[]}").is_err());
+    }
+
+    #[test]
+    fn account_proofs_bundle_account_and_storage_proofs() {
+        let scheme = KeyScheme::RSK;
+        let address = [0x5au8; 20];
+        let mut trie = sample_trie();
+        trie.put(scheme.account_key(&address), vec![0xac; 40]);
+        trie.put(scheme.storage_key(&address, &[0x01]), vec![0x11]);
+        trie.put(scheme.storage_key(&address, &[0x02]), vec![0x22]);
+
+        let bundle = trie.get_account_proof(&address, &[[0x02], [0x03]]);
+        assert_eq!(bundle.root_hash(), Some(trie.root_hash()));
+        assert_eq!(bundle.account_state, Some(vec![0xac; 40]));
+        assert_eq!(
+            bundle.account_proof,
+            trie.get_proof(&scheme.account_key(&address))
+        );
+        assert_eq!(bundle.storage_proofs.len(), 2);
+        assert_eq!(bundle.storage_proofs[0].value, Some(vec![0x22]));
+        assert_eq!(bundle.storage_proofs[1].value, None);
+        assert_eq!(
+            bundle.storage_proofs[1].proof,
+            trie.get_proof(&scheme.storage_key(&address, &[0x03]))
+        );
+
+        let json = bundle.to_json();
+        assert!(json.starts_with("{\"address\":\"0x5a5a"));
+        assert!(
+            json.contains("\"storageProof\":[{\"key\":\"0x02\",\"value\":\"0x22\",\"proof\":[\"0x")
+        );
+        assert!(json.contains("{\"key\":\"0x03\",\"value\":\"0x0\",\"proof\":[\"0x"));
+    }
+
+    #[test]
+    fn verified_get_checks_proofs_and_returns_values() {
+        let mut trie = sample_trie();
+        let root = trie.root_hash();
+
+        // Inclusion with inline and long values, and exclusion.
+        for (key, expected) in [
+            (vec![0x01, 3], Some(vec![3; 4])),
+            (vec![0x01, 39], Some(vec![39; 40])),
+            (vec![0x01, 0x80], None),
+            (vec![0xff], None),
+        ] {
+            let proof = trie.get_proof(&key);
+            assert_eq!(proof.value.is_some(), key == [0x01, 39]);
+            let decoded = TrieProof::decode(&proof.encode()).unwrap();
+            assert_eq!(verified_get(&root, &key, &decoded), Ok(expected));
+        }
+
+        let proof = trie.get_proof(&[0x01, 39]);
+        assert_eq!(
+            verified_get(&root, &[0x01, 38], &proof),
+            Err(ProofError::KeyMismatch)
+        );
+        assert!(matches!(
+            verified_get(&root, &proof.key, &proof.clone().with_value(None)),
+            Err(ProofError::MissingValue { .. })
+        ));
+        assert!(matches!(
+            verified_get(
+                &root,
+                &proof.key,
+                &proof.clone().with_value(Some(vec![0; 40]))
+            ),
+            Err(ProofError::ValueMismatch { .. })
+        ));
+        assert!(matches!(
+            verified_get(&[0x11; 32], &proof.key, &proof),
+            Err(ProofError::HashMismatch { .. })
+        ));
+        let mut truncated = proof.clone();
+        truncated.nodes.pop();
+        assert!(matches!(
+            verified_get(&root, &proof.key, &truncated),
+            Err(ProofError::MissingNode { .. })
+        ));
+
+        let empty = Unitrie::new().get_proof(&[0x01]);
+        assert_eq!(verified_get(&empty_trie_hash(), &[0x01], &empty), Ok(None));
+    }
+
+    #[test]
+    fn proofs_verify_under_the_config_of_their_trie() {
+        let config = CodecConfig::new(64, CodecConfig::CONSENSUS.max_embedded_node_size).unwrap();
+        let mut trie = Unitrie::new().with_config(config);
+        for index in 0u8..8 {
+            trie.put(vec![0x01, index], vec![index; 50]);
+        }
+        let root = trie.root_hash();
+        let proof = trie.get_proof(&[0x01, 5]);
+        assert_eq!(proof.value, None);
+
+        assert_eq!(
+            verified_get_with_config(&root, &proof.key, &proof, &config),
+            Ok(Some(vec![5; 50]))
+        );
+        assert!(matches!(
+            verified_get(&root, &proof.key, &proof),
+            Err(ProofError::MalformedNode(_))
+        ));
+    }
+
+    #[test]
+    fn size_estimates_match_encoded_proofs() {
+        let mut trie = sample_trie();
+        let mut keys: Vec<Vec<u8>> = trie.keys().cloned().collect();
+        keys.extend([vec![0x01, 0x80], vec![0xff], vec![]]);
+        for key in keys {
+            let encoded = trie.get_proof(&key).encode();
+            let estimate = trie.estimate_proof_size(&key);
+            assert_eq!(estimate.encoded_bytes, encoded.len());
+            assert_eq!(estimate.calldata_gas, calldata_gas(&encoded));
+        }
+
+        let first = trie.estimate_proof_size(&[0x01, 39]);
+        let second = trie.estimate_proof_size(&[0x01, 38]);
+        let both = trie.estimate_multiproof_size(&[[0x01, 39], [0x01, 38]]);
+        assert!(both.nodes < first.nodes + second.nodes);
+        assert!(both.node_bytes < first.node_bytes + second.node_bytes);
+
+        let empty = Unitrie::new().estimate_multiproof_size::<Vec<u8>>(&[]);
+        assert_eq!(empty.encoded_bytes, 2);
+        assert_eq!(empty.nodes, 0);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::json::{from_hex, to_hex};
+use std::fmt;
+
+// Bumped whenever a field is renamed, removed or changes meaning; consumers
+// should refuse reports with a version they do not know.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+// Summary of one bench run, the document CI comparison scripts and the RSKj
+// side tooling read. With the `serde` feature it (de)serializes with camelCase
+// field names, e.g.
+//
+//   {
+//     "schemaVersion": 1,
+//     "generatedAt": "1760572800s",
+//     "engine": "rust(next-core)",
+//     "workloads": [{
+//       "benchmark": "put-get-small",
+//       "engine": "rust(next-core)",
+//       "metrics": { "avgMicros": 12.5, "p95Micros": 14.0, "throughputOpsPerSec": 80000.0 },
+//       "sampleCount": 30,
+//       "checksum": "0x00000000000004d2"
+//     }]
+//   }
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct BenchReport {
+    pub schema_version: u32,
+    pub generated_at: String,
+    pub engine: String,
+    pub workloads: Vec<WorkloadReport>,
+}
+
+impl BenchReport {
+    pub fn new(generated_at: impl Into<String>, engine: impl Into<String>) -> Self {
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            generated_at: generated_at.into(),
+            engine: engine.into(),
+            workloads: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct WorkloadReport {
+    pub benchmark: String,
+    pub engine: String,
+    pub metrics: WorkloadMetrics,
+    pub sample_count: usize,
+    pub checksum: Checksum,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct WorkloadMetrics {
+    pub avg_micros: f64,
+    pub p95_micros: f64,
+    pub throughput_ops_per_sec: f64,
+}
+
+impl WorkloadMetrics {
+    // Metrics of per-run wall times in nanoseconds; all zero without samples.
+    pub fn from_samples_ns(samples_ns: &[f64]) -> Self {
+        if samples_ns.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples_ns.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let avg_ns = sorted.iter().sum::<f64>() / sorted.len() as f64;
+        let p95_index = ((sorted.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+        let p95_ns = sorted[p95_index.min(sorted.len() - 1)];
+        Self {
+            avg_micros: avg_ns / 1_000.0,
+            p95_micros: p95_ns / 1_000.0,
+            throughput_ops_per_sec: if avg_ns <= 0.0 {
+                0.0
+            } else {
+                1_000_000_000.0 / avg_ns
+            },
+        }
+    }
+}
+
+// Two engines run over the same workloads, e.g. `rust(legacy-v1)` as the
+// baseline and `rust(next-core)` as the candidate.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct ComparisonReport {
+    pub schema_version: u32,
+    pub generated_at: String,
+    pub baseline: String,
+    pub candidate: String,
+    pub workloads: Vec<WorkloadComparison>,
+}
+
+impl ComparisonReport {
+    // Pairs workloads by benchmark name; ones only one side ran are left out.
+    pub fn from_reports(baseline: &BenchReport, candidate: &BenchReport) -> Self {
+        let workloads = baseline
+            .workloads
+            .iter()
+            .filter_map(|baseline| {
+                candidate
+                    .workloads
+                    .iter()
+                    .find(|candidate| candidate.benchmark == baseline.benchmark)
+                    .map(|candidate| WorkloadComparison::new(baseline, candidate))
+            })
+            .collect();
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            generated_at: candidate.generated_at.clone(),
+            baseline: baseline.engine.clone(),
+            candidate: candidate.engine.clone(),
+            workloads,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct WorkloadComparison {
+    pub benchmark: String,
+    pub baseline: WorkloadMetrics,
+    pub candidate: WorkloadMetrics,
+    // Baseline over candidate average time: above 1 the candidate is faster.
+    pub speedup: f64,
+    // Engines disagreeing on a workload's result make its timings meaningless.
+    pub checksums_match: bool,
+}
+
+impl WorkloadComparison {
+    pub fn new(baseline: &WorkloadReport, candidate: &WorkloadReport) -> Self {
+        let speedup = if candidate.metrics.avg_micros <= 0.0 {
+            0.0
+        } else {
+            baseline.metrics.avg_micros / candidate.metrics.avg_micros
+        };
+        Self {
+            benchmark: baseline.benchmark.clone(),
+            baseline: baseline.metrics,
+            candidate: candidate.metrics,
+            speedup,
+            checksums_match: baseline.checksum == candidate.checksum,
+        }
+    }
+}
+
+// Opaque digest of what a workload computed, compared across engines and runs.
+// Serialized as 0x-prefixed hex so digests wider than a JSON number survive
+// tools that parse numbers as doubles.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+pub struct Checksum(pub Vec<u8>);
+
+impl Checksum {
+    pub fn from_u64(value: u64) -> Self {
+        Self(value.to_be_bytes().to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.0)
+    }
+
+    pub fn from_hex(text: &str) -> Result<Self, String> {
+        from_hex(text).map(Self)
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Checksum {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Checksum {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Self::from_hex(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BenchReport, Checksum, ComparisonReport, WorkloadMetrics, WorkloadReport,
+        REPORT_SCHEMA_VERSION,
+    };
+
+    #[test]
+    fn metrics_summarize_samples() {
+        let samples: Vec<f64> = (1..=20)
+            .rev()
+            .map(|micros| micros as f64 * 1_000.0)
+            .collect();
+        let metrics = WorkloadMetrics::from_samples_ns(&samples);
+        assert_eq!(metrics.avg_micros, 10.5);
+        assert_eq!(metrics.p95_micros, 19.0);
+        assert_eq!(metrics.throughput_ops_per_sec, 1_000_000_000.0 / 10_500.0);
+        assert_eq!(
+            WorkloadMetrics::from_samples_ns(&[]),
+            WorkloadMetrics::default()
+        );
+    }
+
+    #[test]
+    fn checksums_are_hex_encoded() {
+        let checksum = Checksum::from_u64(0x04d2);
+        assert_eq!(checksum.to_string(), "0x00000000000004d2");
+        assert_eq!(Checksum::from_hex(&checksum.to_hex()).unwrap(), checksum);
+        assert!(Checksum::from_hex("04d2").is_err());
+
+        let mut report = BenchReport::new("0s", "rust(next-core)");
+        report.workloads.push(WorkloadReport {
+            benchmark: "put".to_string(),
+            engine: report.engine.clone(),
+            metrics: WorkloadMetrics::default(),
+            sample_count: 0,
+            checksum,
+        });
+        assert_eq!(report.schema_version, REPORT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn comparisons_pair_workloads_by_name() {
+        let workload = |benchmark: &str, avg_micros: f64, checksum: u64| WorkloadReport {
+            benchmark: benchmark.to_string(),
+            engine: String::new(),
+            metrics: WorkloadMetrics {
+                avg_micros,
+                ..WorkloadMetrics::default()
+            },
+            sample_count: 1,
+            checksum: Checksum::from_u64(checksum),
+        };
+        let mut legacy = BenchReport::new("0s", "rust(legacy-v1)");
+        legacy.workloads = vec![workload("put", 30.0, 1), workload("get", 8.0, 2)];
+        let mut next = BenchReport::new("1s", "rust(next-core)");
+        next.workloads = vec![workload("get", 4.0, 3), workload("save", 1.0, 4)];
+
+        let comparison = ComparisonReport::from_reports(&legacy, &next);
+        assert_eq!(comparison.baseline, "rust(legacy-v1)");
+        assert_eq!(comparison.candidate, "rust(next-core)");
+        assert_eq!(comparison.workloads.len(), 1);
+        assert_eq!(comparison.workloads[0].benchmark, "get");
+        assert_eq!(comparison.workloads[0].speedup, 2.0);
+        assert!(!comparison.workloads[0].checksums_match);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reports_serialize_with_a_camel_case_schema() {
+        let report = BenchReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            generated_at: "0s".to_string(),
+            engine: "rust(next-core)".to_string(),
+            workloads: vec![WorkloadReport {
+                benchmark: "put".to_string(),
+                engine: "rust(next-core)".to_string(),
+                metrics: WorkloadMetrics::from_samples_ns(&[2_000.0]),
+                sample_count: 1,
+                checksum: Checksum::from_u64(7),
+            }],
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["schemaVersion"], 1);
+        assert_eq!(json["workloads"][0]["sampleCount"], 1);
+        assert_eq!(json["workloads"][0]["metrics"]["avgMicros"], 2.0);
+        assert_eq!(json["workloads"][0]["checksum"], "0x0000000000000007");
+        let parsed: BenchReport = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, report);
+    }
+}
@@ -0,0 +1,59 @@
+// Seeded xorshift64* shared by the workload generator and the fault-injecting
+// and latency stores: the same seed always yields the same stream. The
+// multiply scrambles the low bits, which lengths and picks rely on.
+#[derive(Debug, Clone)]
+pub(crate) struct SeededRng(u64);
+
+impl SeededRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift must not start from zero.
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        let mut state = self.0;
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        self.0 = state;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in 0..bound, or 0 for an empty range.
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next() % bound as u64) as usize
+    }
+
+    // Index drawn with probability proportional to its weight; `None` when
+    // every weight is zero.
+    pub(crate) fn weighted(&mut self, weights: &[u32]) -> Option<usize> {
+        let total: u64 = weights.iter().map(|weight| u64::from(*weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = self.next() % total;
+        weights.iter().position(|weight| {
+            let weight = u64::from(*weight);
+            if pick < weight {
+                return true;
+            }
+            pick -= weight;
+            false
+        })
+    }
+
+    pub(crate) fn fill(&mut self, output: &mut [u8]) {
+        for chunk in output.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, length: usize) -> Vec<u8> {
+        let mut output = vec![0u8; length];
+        self.fill(&mut output);
+        output
+    }
+}
@@ -0,0 +1,324 @@
+use crate::codec_rskip107::ChildEncoding;
+use crate::core_trie::{encode_node_metadata, NodeMetadata};
+use crate::hash::empty_trie_hash;
+use crate::json::to_hex;
+use crate::node_ref::{CodecConfig, NodeReference, SharedPath, TrieNode, ValueRef};
+use crate::path::path_bits::PathBits;
+use crate::TrieRoot;
+
+// Receives finished subtrees from `SortedTrieBuilder`, bottom-up. A subtree is
+// handed over exactly once, as soon as no later key can extend it.
+pub(crate) trait SubtreeSink {
+    type Subtree;
+
+    fn finish(
+        &mut self,
+        shared_path: SharedPath,
+        value: Vec<u8>,
+        left: Option<Self::Subtree>,
+        right: Option<Self::Subtree>,
+    ) -> Result<Self::Subtree, String>;
+}
+
+#[derive(Debug)]
+struct PendingNode<T> {
+    // Bit offset where this node's shared path starts (parent depth + 1).
+    start: usize,
+    // Bit offset of this node's branch point (end of its shared path).
+    depth: usize,
+    value: Vec<u8>,
+    left: Option<T>,
+    right: Option<T>,
+}
+
+impl<T> PendingNode<T> {
+    fn new(start: usize, depth: usize, value: Vec<u8>) -> Self {
+        Self {
+            start,
+            depth,
+            value,
+            left: None,
+            right: None,
+        }
+    }
+
+    fn attach(&mut self, bit: u8, child: T) {
+        if bit == 0 {
+            self.left = Some(child);
+        } else {
+            self.right = Some(child);
+        }
+    }
+}
+
+// Builds a trie from strictly ascending keys keeping only the right-most path
+// open: memory is bounded by trie depth, not by the number of entries.
+#[derive(Debug)]
+pub(crate) struct SortedTrieBuilder<S: SubtreeSink> {
+    sink: S,
+    stack: Vec<PendingNode<S::Subtree>>,
+    previous_key: Option<Vec<u8>>,
+}
+
+impl<S: SubtreeSink> SortedTrieBuilder<S> {
+    pub(crate) fn new(sink: S) -> Self {
+        Self {
+            sink,
+            stack: Vec::new(),
+            previous_key: None,
+        }
+    }
+
+    // Empty values are skipped, matching `put` semantics where they mean "absent".
+    pub(crate) fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), String> {
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        let key_bits = key.len() * 8;
+        match self.previous_key.take() {
+            None => self.stack.push(PendingNode::new(0, key_bits, value)),
+            Some(previous) => {
+                if key <= previous {
+                    return Err(format!(
+                        "sorted entries must be strictly ascending: {} follows {}",
+                        to_hex(&key),
+                        to_hex(&previous)
+                    ));
+                }
+
+                let common_bits = common_prefix_bits(&previous, &key);
+                self.unwind_to(&previous, common_bits)?;
+                self.stack
+                    .push(PendingNode::new(common_bits + 1, key_bits, value));
+            }
+        }
+
+        self.previous_key = Some(key);
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<Option<S::Subtree>, String> {
+        let Some(previous) = self.previous_key.take() else {
+            return Ok(None);
+        };
+
+        while let Some(node) = self.stack.pop() {
+            if self.stack.is_empty() {
+                return self.finish_node(&previous, node).map(Some);
+            }
+
+            let side = bit_at(&previous, node.start - 1);
+            let subtree = self.finish_node(&previous, node)?;
+            self.stack
+                .last_mut()
+                .expect("parent checked above")
+                .attach(side, subtree);
+        }
+
+        Ok(None)
+    }
+
+    // Closes every open node deeper than `common_bits`: keys arriving later branch
+    // off at or above that depth, so those subtrees are final.
+    fn unwind_to(&mut self, previous: &[u8], common_bits: usize) -> Result<(), String> {
+        while self.stack.last().is_some_and(|top| top.depth > common_bits) {
+            let mut node = self.stack.pop().expect("stack top checked above");
+            match self.stack.last().map(|parent| parent.depth) {
+                Some(parent_depth) if parent_depth >= common_bits => {
+                    let side = bit_at(previous, node.start - 1);
+                    let subtree = self.finish_node(previous, node)?;
+                    self.stack
+                        .last_mut()
+                        .expect("parent checked above")
+                        .attach(side, subtree);
+                }
+                _ => {
+                    // The new key diverges inside this node's shared path: split it
+                    // with a valueless branch node at the divergence point.
+                    let mut branch = PendingNode::new(node.start, common_bits, Vec::new());
+                    node.start = common_bits + 1;
+                    let side = bit_at(previous, common_bits);
+                    let subtree = self.finish_node(previous, node)?;
+                    branch.attach(side, subtree);
+                    self.stack.push(branch);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish_node(
+        &mut self,
+        previous: &[u8],
+        node: PendingNode<S::Subtree>,
+    ) -> Result<S::Subtree, String> {
+        let shared_path =
+            SharedPath::from_path_bits(PathBits::from_key_range(previous, node.start..node.depth));
+        self.sink
+            .finish(shared_path, node.value, node.left, node.right)
+    }
+}
+
+#[derive(Debug)]
+struct HashingSink {
+    config: CodecConfig,
+}
+
+impl SubtreeSink for HashingSink {
+    type Subtree = NodeMetadata;
+
+    fn finish(
+        &mut self,
+        shared_path: SharedPath,
+        value: Vec<u8>,
+        left: Option<NodeMetadata>,
+        right: Option<NodeMetadata>,
+    ) -> Result<NodeMetadata, String> {
+        let node = TrieNode::new(
+            shared_path,
+            ValueRef::inline(value),
+            NodeReference::empty(),
+            NodeReference::empty(),
+        );
+        let empty = || (ChildEncoding::Empty, 0);
+        encode_node_metadata(
+            &node,
+            left.map_or_else(empty, NodeMetadata::into_child_encoding),
+            right.map_or_else(empty, NodeMetadata::into_child_encoding),
+            &self.config,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct NodeSink;
+
+impl SubtreeSink for NodeSink {
+    type Subtree = TrieNode;
+
+    fn finish(
+        &mut self,
+        shared_path: SharedPath,
+        value: Vec<u8>,
+        left: Option<TrieNode>,
+        right: Option<TrieNode>,
+    ) -> Result<TrieNode, String> {
+        Ok(TrieNode::new(
+            shared_path,
+            ValueRef::inline(value),
+            left.map_or_else(NodeReference::empty, NodeReference::embedded),
+            right.map_or_else(NodeReference::empty, NodeReference::embedded),
+        ))
+    }
+}
+
+// Builds the in-memory node tree for strictly ascending entries in a single pass.
+pub(crate) fn build_trie_node<I>(entries: I) -> Result<Option<TrieNode>, String>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    let mut builder = SortedTrieBuilder::new(NodeSink);
+    for (key, value) in entries {
+        builder.push(key, value)?;
+    }
+    builder.finish()
+}
+
+// Computes the RSKIP107 root hash of the given strictly ascending entries without
+// materializing the trie: finished subtrees are hashed and dropped as the stream
+// advances.
+pub fn compute_root<I>(entries: I) -> Result<TrieRoot, String>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    compute_root_with_config(entries, CodecConfig::CONSENSUS)
+}
+
+pub fn compute_root_with_config<I>(entries: I, config: CodecConfig) -> Result<TrieRoot, String>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    let mut builder = SortedTrieBuilder::new(HashingSink { config });
+    for (key, value) in entries {
+        builder.push(key, value)?;
+    }
+
+    Ok(builder
+        .finish()?
+        .map_or_else(empty_trie_hash, |root| root.hash))
+}
+
+fn common_prefix_bits(left: &[u8], right: &[u8]) -> usize {
+    for (index, (left_byte, right_byte)) in left.iter().zip(right).enumerate() {
+        let diff = left_byte ^ right_byte;
+        if diff != 0 {
+            return index * 8 + diff.leading_zeros() as usize;
+        }
+    }
+
+    left.len().min(right.len()) * 8
+}
+
+fn bit_at(key: &[u8], bit_index: usize) -> u8 {
+    (key[bit_index / 8] >> (7 - (bit_index % 8))) & 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compute_root;
+    use crate::core_trie::Unitrie;
+    use crate::hash::empty_trie_hash;
+
+    #[test]
+    fn compute_root_matches_materialized_trie() {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (Vec::new(), b"root".to_vec()),
+            (vec![0x00], vec![0x01]),
+            (vec![0x00, 0x01], vec![0x42; 40]),
+            (vec![0x00, 0x01, 0x02], b"deep".to_vec()),
+            (vec![0x0f], vec![0x02]),
+            (vec![0x10, 0x20, 0x30], vec![0x77; 33]),
+            (vec![0x80], vec![0x03]),
+            (vec![0xff, 0xff], vec![0x04]),
+        ];
+        for seed in 0u8..40 {
+            entries.push((
+                vec![0x55, seed, seed.wrapping_mul(7)],
+                vec![seed; 1 + seed as usize],
+            ));
+        }
+        entries.sort();
+
+        let mut trie = Unitrie::new();
+        for (key, value) in &entries {
+            trie.put(key.clone(), value.clone());
+        }
+
+        assert_eq!(compute_root(entries).unwrap(), trie.root_hash());
+    }
+
+    #[test]
+    fn compute_root_handles_empty_and_single_entry_streams() {
+        assert_eq!(compute_root(Vec::new()).unwrap(), empty_trie_hash());
+
+        for key in [b"only".to_vec(), Vec::new()] {
+            let mut trie = Unitrie::new();
+            trie.put(key.clone(), b"value".to_vec());
+            assert_eq!(
+                compute_root(vec![(key, b"value".to_vec())]).unwrap(),
+                trie.root_hash()
+            );
+        }
+    }
+
+    #[test]
+    fn compute_root_rejects_unsorted_or_duplicate_keys() {
+        let unsorted = vec![(vec![0x02], vec![0x01]), (vec![0x01], vec![0x01])];
+        assert!(compute_root(unsorted).is_err());
+
+        let duplicated = vec![(vec![0x01], vec![0x01]), (vec![0x01], vec![0x02])];
+        assert!(compute_root(duplicated).is_err());
+    }
+}
@@ -1,4 +1,17 @@
 use crate::core_api::{TrieStoreReader, TrieStoreWriter};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+// Which node codec a store holds. `Auto` sniffs each payload; stores known to
+// hold a single format should say so, so a misclassified payload fails to
+// decode instead of decoding as garbage.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum CodecHint {
+    #[default]
+    Auto,
+    Orchid,
+    Rskip107,
+}
 
 pub trait RawStoreAdapter {
     fn load_raw_node(&mut self, _hash: &[u8]) -> Option<Vec<u8>> {
@@ -12,6 +25,308 @@ pub trait RawStoreAdapter {
     fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]);
 
     fn save_raw_value(&mut self, hash: &[u8], value: &[u8]);
+
+    // Whether a value payload is already stored, for stores that can answer
+    // without loading it. `None` means unknown, and the value is written again.
+    fn contains_raw_value(&mut self, _hash: &[u8]) -> Option<bool> {
+        None
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        CodecHint::Auto
+    }
+}
+
+// Stores that can enumerate what they hold, for features that must see every
+// stored key: orphan detection, compaction, full-store verification. Order is
+// unspecified.
+pub trait IterableStore {
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_>;
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_>;
+}
+
+// Lets wrappers borrow a store, e.g. several namespaces over one physical store.
+impl<T> RawStoreAdapter for &mut T
+where
+    T: RawStoreAdapter + ?Sized,
+{
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        T::load_raw_node(self, hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        T::load_raw_value(self, hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        T::save_raw_node(self, hash, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        T::save_raw_value(self, hash, value);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        T::contains_raw_value(self, hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        T::codec_hint(self)
+    }
+}
+
+impl<T> IterableStore for &mut T
+where
+    T: IterableStore + ?Sized,
+{
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        T::iter_node_hashes(self)
+    }
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        T::iter_value_hashes(self)
+    }
+}
+
+// Hash-keyed store held in memory, for tests, benches and tooling. Nodes and
+// values live in separate maps, and byte totals are kept as entries come and
+// go. Cloning takes a snapshot.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct MemoryStore {
+    nodes: HashMap<Vec<u8>, Vec<u8>>,
+    values: HashMap<Vec<u8>, Vec<u8>>,
+    node_bytes: usize,
+    value_bytes: usize,
+    codec_hint: CodecHint,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_codec_hint(mut self, codec_hint: CodecHint) -> Self {
+        self.codec_hint = codec_hint;
+        self
+    }
+
+    pub fn node(&self, hash: &[u8]) -> Option<&[u8]> {
+        self.nodes.get(hash).map(Vec::as_slice)
+    }
+
+    pub fn value(&self, hash: &[u8]) -> Option<&[u8]> {
+        self.values.get(hash).map(Vec::as_slice)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.nodes
+            .iter()
+            .map(|(hash, node)| (hash.as_slice(), node.as_slice()))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.values
+            .iter()
+            .map(|(hash, value)| (hash.as_slice(), value.as_slice()))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    // Payload bytes only; hashes and map overhead are not counted.
+    pub fn node_bytes(&self) -> usize {
+        self.node_bytes
+    }
+
+    pub fn value_bytes(&self) -> usize {
+        self.value_bytes
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.node_bytes + self.value_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.values.is_empty()
+    }
+
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    // For tests simulating missing or pruned entries.
+    pub fn remove_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let node = self.nodes.remove(hash)?;
+        self.node_bytes -= node.len();
+        Some(node)
+    }
+
+    pub fn remove_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        let value = self.values.remove(hash)?;
+        self.value_bytes -= value.len();
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.values.clear();
+        self.node_bytes = 0;
+        self.value_bytes = 0;
+    }
+}
+
+impl RawStoreAdapter for MemoryStore {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.nodes.get(hash).cloned()
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.values.get(hash).cloned()
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        self.node_bytes += serialized_node.len();
+        if let Some(previous) = self.nodes.insert(hash.to_vec(), serialized_node.to_vec()) {
+            self.node_bytes -= previous.len();
+        }
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        self.value_bytes += value.len();
+        if let Some(previous) = self.values.insert(hash.to_vec(), value.to_vec()) {
+            self.value_bytes -= previous.len();
+        }
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        Some(self.values.contains_key(hash))
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.codec_hint
+    }
+}
+
+impl IterableStore for MemoryStore {
+    fn iter_node_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        Box::new(self.nodes.keys().cloned())
+    }
+
+    fn iter_value_hashes(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        Box::new(self.values.keys().cloned())
+    }
+}
+
+// `&self` counterpart of `RawStoreAdapter`, for stores that synchronize
+// internally, so one handle can serve many threads. `&S` is a
+// `RawStoreAdapter` for any shared store, and `LockedStore` goes the other way.
+pub trait SharedStoreAdapter: Sync {
+    fn load_raw_node(&self, _hash: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_raw_value(&self, hash: &[u8]) -> Option<Vec<u8>> {
+        SharedStoreAdapter::load_raw_node(self, hash)
+    }
+
+    fn save_raw_node(&self, hash: &[u8], serialized_node: &[u8]);
+
+    fn save_raw_value(&self, hash: &[u8], value: &[u8]);
+
+    fn contains_raw_value(&self, _hash: &[u8]) -> Option<bool> {
+        None
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        CodecHint::Auto
+    }
+}
+
+impl<T> RawStoreAdapter for &T
+where
+    T: SharedStoreAdapter + ?Sized,
+{
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        SharedStoreAdapter::load_raw_node(*self, hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        SharedStoreAdapter::load_raw_value(*self, hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        SharedStoreAdapter::save_raw_node(*self, hash, serialized_node);
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        SharedStoreAdapter::save_raw_value(*self, hash, value);
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        SharedStoreAdapter::contains_raw_value(*self, hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        SharedStoreAdapter::codec_hint(*self)
+    }
+}
+
+// Shares an exclusive-access store by serializing calls through a mutex.
+#[derive(Debug, Default)]
+pub struct LockedStore<S> {
+    inner: Mutex<S>,
+}
+
+impl<S: RawStoreAdapter> LockedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, S> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S: RawStoreAdapter + Send> SharedStoreAdapter for LockedStore<S> {
+    fn load_raw_node(&self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.lock().load_raw_node(hash)
+    }
+
+    fn load_raw_value(&self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.lock().load_raw_value(hash)
+    }
+
+    fn save_raw_node(&self, hash: &[u8], serialized_node: &[u8]) {
+        self.lock().save_raw_node(hash, serialized_node);
+    }
+
+    fn save_raw_value(&self, hash: &[u8], value: &[u8]) {
+        self.lock().save_raw_value(hash, value);
+    }
+
+    fn contains_raw_value(&self, hash: &[u8]) -> Option<bool> {
+        self.lock().contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.lock().codec_hint()
+    }
 }
 
 impl<T> TrieStoreReader for T
@@ -42,36 +357,80 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::RawStoreAdapter;
-    use std::collections::HashMap;
+    use super::{LockedStore, MemoryStore, RawStoreAdapter, SharedStoreAdapter};
+    use crate::core_trie::LoadLimits;
+    use crate::{UnitrieCore, UnitrieImplementation};
 
-    #[derive(Default)]
-    struct InMemoryStore {
-        nodes: HashMap<Vec<u8>, Vec<u8>>,
-        values: HashMap<Vec<u8>, Vec<u8>>,
+    #[test]
+    fn memory_store_round_trip_node() {
+        let mut store = MemoryStore::new();
+        store.save_raw_node(&[1, 2, 3], &[9, 9, 9]);
+        assert_eq!(
+            store.load_raw_node(&[1, 2, 3]).as_deref(),
+            Some([9, 9, 9].as_ref())
+        );
     }
 
-    impl RawStoreAdapter for InMemoryStore {
-        fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
-            self.nodes.get(hash).cloned()
-        }
-
-        fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
-            self.nodes.insert(hash.to_vec(), serialized_node.to_vec());
-        }
+    #[test]
+    fn locked_store_shares_an_exclusive_store_across_threads() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let locked = LockedStore::new(MemoryStore::new());
+            let mut trie = UnitrieCore::new(implementation);
+            for index in 0u16..200 {
+                trie.put(index.to_be_bytes().to_vec(), vec![index as u8; 8]);
+            }
+            trie.save_to_store(&mut &locked);
+            let root = trie.current_root_hash();
 
-        fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
-            self.values.insert(hash.to_vec(), value.to_vec());
+            let loaded = UnitrieCore::from_persisted_root_parallel(
+                implementation,
+                &root,
+                &locked,
+                4,
+                LoadLimits::default(),
+            )
+            .unwrap();
+            assert!(loaded.iter_prefix(&[]).eq(trie.iter_prefix(&[])));
+            assert!(SharedStoreAdapter::load_raw_node(&locked, &root).is_some());
+            assert_eq!(
+                locked.into_inner().node_count(),
+                trie.persisted_node_count()
+            );
         }
     }
 
     #[test]
-    fn in_memory_store_round_trip_node() {
-        let mut store = InMemoryStore::default();
-        store.save_raw_node(&[1, 2, 3], &[9, 9, 9]);
-        assert_eq!(
-            store.load_raw_node(&[1, 2, 3]).as_deref(),
-            Some([9, 9, 9].as_ref())
-        );
+    fn memory_store_accounts_for_sizes_and_snapshots() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut store = MemoryStore::new();
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(vec![0x01], vec![0x11; 100]);
+            trie.put(vec![0x02], vec![0x22; 8]);
+            trie.save_to_store(&mut store);
+            assert_eq!(store.value_count(), 1);
+            assert_eq!(store.value_bytes(), 100);
+            assert_eq!(
+                store.node_bytes(),
+                store.nodes().map(|(_, node)| node.len()).sum::<usize>()
+            );
+
+            let snapshot = store.snapshot();
+            let root = trie.current_root_hash();
+            store.save_raw_node(&root, &[0x00]);
+            assert_eq!(store.node(&root), Some(&[0x00][..]));
+            assert_eq!(
+                store.total_bytes(),
+                snapshot.total_bytes() - snapshot.node(&root).unwrap().len() + 1
+            );
+            assert_ne!(store, snapshot);
+
+            let value_hash = snapshot.values().next().unwrap().0.to_vec();
+            assert_eq!(store.remove_value(&value_hash), Some(vec![0x11; 100]));
+            assert_eq!(store.value_bytes(), 0);
+            assert!(
+                UnitrieCore::from_persisted_root(implementation, &root, &mut snapshot.clone())
+                    .is_ok()
+            );
+        }
     }
 }
@@ -0,0 +1,246 @@
+use crate::core_trie::Unitrie;
+use crate::node_ref::{CodecConfig, ValueTooLarge};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// What to do when a key arrives again with a different value. Repeats of the
+// same value are always just counted.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum DuplicatePolicy {
+    // Keep the first value and report the later one as rejected.
+    #[default]
+    Reject,
+    KeepFirst,
+    KeepLast,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RejectReason {
+    EmptyKey,
+    // `put` would treat it as a delete; an import almost certainly did not
+    // mean one.
+    EmptyValue,
+    ValueTooLarge(ValueTooLarge),
+    ConflictingDuplicate,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyKey => f.write_str("empty key"),
+            Self::EmptyValue => f.write_str("empty value"),
+            Self::ValueTooLarge(error) => error.fmt(f),
+            Self::ConflictingDuplicate => f.write_str("key repeated with a different value"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RejectedEntry {
+    pub key: Vec<u8>,
+    pub reason: RejectReason,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BuildReport {
+    pub accepted: usize,
+    // Entries whose key was already present, rejected or not.
+    pub duplicates: usize,
+    pub rejected: Vec<RejectedEntry>,
+    pub value_bytes: u64,
+}
+
+impl BuildReport {
+    pub fn is_clean(&self) -> bool {
+        self.rejected.is_empty()
+    }
+}
+
+// Validated construction path for importers. Unlike `put`, entries that would
+// silently delete, collide or fail once the trie is hashed are set aside and
+// reported instead.
+#[derive(Debug, Clone, Default)]
+pub struct TrieBuilder {
+    config: CodecConfig,
+    allow_empty_keys: bool,
+    duplicates: DuplicatePolicy,
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    report: BuildReport,
+}
+
+impl TrieBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Values past uint24 are accepted with `CodecConfig::extended_values`,
+    // which stores them in chunks.
+    pub fn with_config(mut self, config: CodecConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // The empty key addresses the root's own value, which state tries never use.
+    pub fn allow_empty_keys(mut self, allow: bool) -> Self {
+        self.allow_empty_keys = allow;
+        self
+    }
+
+    pub fn on_duplicate(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    pub fn report(&self) -> &BuildReport {
+        &self.report
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Rejected entries are also recorded in the report.
+    pub fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<(), RejectReason> {
+        let result = self.accept(&key, value);
+        if let Err(reason) = &result {
+            self.report.rejected.push(RejectedEntry {
+                key,
+                reason: reason.clone(),
+            });
+        }
+        result
+    }
+
+    pub fn extend<I>(&mut self, entries: I)
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        for (key, value) in entries {
+            let _ = self.push(key, value);
+        }
+    }
+
+    pub fn build(self) -> (Unitrie, BuildReport) {
+        let trie = if self.config.is_consensus() {
+            Unitrie::from_sorted_entries(self.entries)
+        } else {
+            // Built lazily: eager hashing uses the consensus encoding.
+            let mut trie = Unitrie::new().with_config(self.config);
+            trie.extend_sorted(self.entries).map(|()| trie)
+        }
+        .expect("builder entries are sorted, unique and non-empty");
+        (trie, self.report)
+    }
+
+    fn accept(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), RejectReason> {
+        if key.is_empty() && !self.allow_empty_keys {
+            return Err(RejectReason::EmptyKey);
+        }
+        if value.is_empty() {
+            return Err(RejectReason::EmptyValue);
+        }
+        self.config
+            .check_value_length(value.len())
+            .map_err(RejectReason::ValueTooLarge)?;
+
+        match self.entries.entry(key.to_vec()) {
+            Entry::Vacant(entry) => {
+                self.report.accepted += 1;
+                self.report.value_bytes += value.len() as u64;
+                entry.insert(value);
+            }
+            Entry::Occupied(mut entry) => {
+                self.report.duplicates += 1;
+                if *entry.get() == value {
+                    return Ok(());
+                }
+                match self.duplicates {
+                    DuplicatePolicy::Reject => return Err(RejectReason::ConflictingDuplicate),
+                    DuplicatePolicy::KeepFirst => {}
+                    DuplicatePolicy::KeepLast => {
+                        self.report.value_bytes -= entry.get().len() as u64;
+                        self.report.value_bytes += value.len() as u64;
+                        entry.insert(value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DuplicatePolicy, RejectReason, TrieBuilder};
+    use crate::core_trie::Unitrie;
+    use crate::node_ref::{CodecConfig, ValueTooLarge, MAX_CONSENSUS_VALUE_LENGTH};
+
+    #[test]
+    fn builder_rejects_invalid_entries_and_reports_them() {
+        let mut builder = TrieBuilder::new();
+        builder.extend([
+            (vec![0x02], vec![0x22]),
+            (vec![0x01], vec![0x11; 40]),
+            (Vec::new(), vec![0x00]),
+            (vec![0x03], Vec::new()),
+            (vec![0x02], vec![0x22]),
+            (vec![0x02], vec![0x23]),
+        ]);
+        let too_large = vec![0x00; MAX_CONSENSUS_VALUE_LENGTH + 1];
+        assert!(matches!(
+            builder.push(vec![0x04], too_large),
+            Err(RejectReason::ValueTooLarge(_))
+        ));
+
+        let (mut trie, report) = builder.build();
+        assert_eq!(report.accepted, 2);
+        assert_eq!(report.duplicates, 2);
+        assert_eq!(report.value_bytes, 41);
+        let reasons: Vec<_> = report.rejected.iter().map(|entry| &entry.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                &RejectReason::EmptyKey,
+                &RejectReason::EmptyValue,
+                &RejectReason::ConflictingDuplicate,
+                &RejectReason::ValueTooLarge(ValueTooLarge {
+                    length: MAX_CONSENSUS_VALUE_LENGTH + 1,
+                    max_length: MAX_CONSENSUS_VALUE_LENGTH,
+                }),
+            ]
+        );
+
+        let mut expected = Unitrie::new();
+        expected.put(vec![0x01], vec![0x11; 40]);
+        expected.put(vec![0x02], vec![0x22]);
+        assert_eq!(trie.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn builder_policies_cover_duplicates_empty_keys_and_chunking() {
+        let mut builder = TrieBuilder::new()
+            .allow_empty_keys(true)
+            .on_duplicate(DuplicatePolicy::KeepLast)
+            .with_config(CodecConfig::CONSENSUS.with_extended_values(true));
+        builder.extend([
+            (Vec::new(), vec![0x00]),
+            (vec![0x01], vec![0x11]),
+            (vec![0x01], vec![0x12, 0x12]),
+            (vec![0x02], vec![0x22; MAX_CONSENSUS_VALUE_LENGTH + 1]),
+        ]);
+        let (trie, report) = builder.build();
+        assert!(report.is_clean());
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(trie.get(&[]), Some(vec![0x00]));
+        assert_eq!(trie.get(&[0x01]), Some(vec![0x12, 0x12]));
+        assert_eq!(
+            trie.get_value_length(&[0x02]),
+            Some(MAX_CONSENSUS_VALUE_LENGTH + 1)
+        );
+    }
+}
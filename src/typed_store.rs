@@ -0,0 +1,227 @@
+use crate::node_ref::HASH_SIZE;
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+
+// Store contract with separate node and value key domains. `RawStoreAdapter`
+// passes both kinds of hash as bare bytes and, by default, reads values from
+// the node space, which hides backends that forgot to route them; here each
+// space has its own trait and its own hash type, and nothing falls back.
+// Wrap an implementation in `TypedStoreAdapter` to hand it to a trie.
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct NodeHash(pub [u8; HASH_SIZE]);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ValueHash(pub [u8; HASH_SIZE]);
+
+impl NodeHash {
+    pub fn from_slice(hash: &[u8]) -> Option<Self> {
+        hash.try_into().ok().map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_SIZE] {
+        &self.0
+    }
+}
+
+impl ValueHash {
+    pub fn from_slice(hash: &[u8]) -> Option<Self> {
+        hash.try_into().ok().map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_SIZE] {
+        &self.0
+    }
+}
+
+pub trait NodeStore {
+    fn load_node(&mut self, hash: &NodeHash) -> Option<Vec<u8>>;
+
+    fn save_node(&mut self, hash: &NodeHash, serialized_node: &[u8]);
+
+    fn codec_hint(&self) -> CodecHint {
+        CodecHint::Auto
+    }
+}
+
+pub trait ValueStore {
+    fn load_value(&mut self, hash: &ValueHash) -> Option<Vec<u8>>;
+
+    fn save_value(&mut self, hash: &ValueHash, value: &[u8]);
+
+    // See `RawStoreAdapter::contains_raw_value`.
+    fn contains_value(&mut self, _hash: &ValueHash) -> Option<bool> {
+        None
+    }
+}
+
+// Backends serving both spaces, e.g. one database with two column families.
+pub trait NodeValueStore: NodeStore + ValueStore {}
+
+impl<T: NodeStore + ValueStore + ?Sized> NodeValueStore for T {}
+
+// Routes nodes and values to two independent backends.
+#[derive(Debug, Default)]
+pub struct SplitStore<N, V> {
+    nodes: N,
+    values: V,
+}
+
+impl<N: NodeStore, V: ValueStore> SplitStore<N, V> {
+    pub fn new(nodes: N, values: V) -> Self {
+        Self { nodes, values }
+    }
+
+    pub fn nodes(&self) -> &N {
+        &self.nodes
+    }
+
+    pub fn values(&self) -> &V {
+        &self.values
+    }
+
+    pub fn into_parts(self) -> (N, V) {
+        (self.nodes, self.values)
+    }
+}
+
+impl<N: NodeStore, V> NodeStore for SplitStore<N, V> {
+    fn load_node(&mut self, hash: &NodeHash) -> Option<Vec<u8>> {
+        self.nodes.load_node(hash)
+    }
+
+    fn save_node(&mut self, hash: &NodeHash, serialized_node: &[u8]) {
+        self.nodes.save_node(hash, serialized_node);
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.nodes.codec_hint()
+    }
+}
+
+impl<N, V: ValueStore> ValueStore for SplitStore<N, V> {
+    fn load_value(&mut self, hash: &ValueHash) -> Option<Vec<u8>> {
+        self.values.load_value(hash)
+    }
+
+    fn save_value(&mut self, hash: &ValueHash, value: &[u8]) {
+        self.values.save_value(hash, value);
+    }
+
+    fn contains_value(&mut self, hash: &ValueHash) -> Option<bool> {
+        self.values.contains_value(hash)
+    }
+}
+
+// Presents a typed store as a `RawStoreAdapter`. Hashes of the wrong length
+// never reach the backend: loads miss and saves are dropped.
+#[derive(Debug, Default)]
+pub struct TypedStoreAdapter<S> {
+    inner: S,
+}
+
+impl<S: NodeValueStore> TypedStoreAdapter<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: NodeValueStore> RawStoreAdapter for TypedStoreAdapter<S> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.inner.load_node(&NodeHash::from_slice(hash)?)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.inner.load_value(&ValueHash::from_slice(hash)?)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        if let Some(hash) = NodeHash::from_slice(hash) {
+            self.inner.save_node(&hash, serialized_node);
+        }
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        if let Some(hash) = ValueHash::from_slice(hash) {
+            self.inner.save_value(&hash, value);
+        }
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        self.inner.contains_value(&ValueHash::from_slice(hash)?)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        NodeStore::codec_hint(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeHash, NodeStore, SplitStore, TypedStoreAdapter, ValueHash, ValueStore};
+    use crate::{UnitrieCore, UnitrieImplementation};
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Nodes(HashMap<NodeHash, Vec<u8>>);
+
+    impl NodeStore for Nodes {
+        fn load_node(&mut self, hash: &NodeHash) -> Option<Vec<u8>> {
+            self.0.get(hash).cloned()
+        }
+
+        fn save_node(&mut self, hash: &NodeHash, serialized_node: &[u8]) {
+            self.0.insert(*hash, serialized_node.to_vec());
+        }
+    }
+
+    #[derive(Default)]
+    struct Values(HashMap<ValueHash, Vec<u8>>);
+
+    impl ValueStore for Values {
+        fn load_value(&mut self, hash: &ValueHash) -> Option<Vec<u8>> {
+            self.0.get(hash).cloned()
+        }
+
+        fn save_value(&mut self, hash: &ValueHash, value: &[u8]) {
+            self.0.insert(*hash, value.to_vec());
+        }
+    }
+
+    #[test]
+    fn split_stores_keep_nodes_and_values_apart() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut store =
+                TypedStoreAdapter::new(SplitStore::new(Nodes::default(), Values::default()));
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(vec![0x01], vec![0x11; 100]);
+            trie.put(vec![0x02], vec![0x22; 8]);
+            let stats = trie.save_to_store_with_stats(&mut store);
+            let root = trie.current_root_hash();
+
+            let split = store.inner();
+            assert_eq!(split.nodes().0.len() as u64, stats.nodes_written);
+            assert_eq!(split.values().0.len(), 1);
+            assert!(split
+                .nodes()
+                .0
+                .keys()
+                .all(|hash| !split.values().0.contains_key(&ValueHash(hash.0))));
+
+            let loaded =
+                UnitrieCore::from_persisted_root(implementation, &root, &mut store).unwrap();
+            assert_eq!(loaded.get(&[0x01]), Some(vec![0x11; 100]));
+        }
+    }
+}
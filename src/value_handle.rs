@@ -0,0 +1,213 @@
+use crate::core_trie::decode_persisted_node;
+use crate::hash::{is_empty_root, keccak256};
+use crate::json::to_hex;
+use crate::long_value::load_value;
+use crate::node_ref::{NodeReference, TrieNode, ValueRef, HASH_SIZE};
+use crate::path::path_bits::PathBits;
+use crate::store_adapter::RawStoreAdapter;
+use crate::TrieRoot;
+use std::borrow::Cow;
+
+// A value read from a trie without forcing a copy. Engines that keep values in
+// memory lend them out; ones that materialize values on the fly hand them
+// over, and lookups that read persisted nodes (`stored_value_ref`) leave long
+// values in the store, to be fetched with `load` when the caller actually
+// needs the bytes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ValueHandle<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Stored {
+        hash: [u8; HASH_SIZE],
+        length: usize,
+    },
+}
+
+impl<'a> ValueHandle<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Borrowed(value) => value.len(),
+            Self::Owned(value) => value.len(),
+            Self::Stored { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_stored(&self) -> bool {
+        matches!(self, Self::Stored { .. })
+    }
+
+    // The bytes when they are in memory; `None` for values left in the store.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Borrowed(value) => Some(value),
+            Self::Owned(value) => Some(value),
+            Self::Stored { .. } => None,
+        }
+    }
+
+    pub fn hash(&self) -> [u8; HASH_SIZE] {
+        match self {
+            Self::Borrowed(value) => keccak256(value),
+            Self::Owned(value) => keccak256(value),
+            Self::Stored { hash, .. } => *hash,
+        }
+    }
+
+    // The bytes, reading stored values from `store`, reassembling chunked ones,
+    // and checking them against their hash and length.
+    pub fn load<S: RawStoreAdapter>(self, store: &mut S) -> Result<Cow<'a, [u8]>, String> {
+        match self {
+            Self::Borrowed(value) => Ok(Cow::Borrowed(value)),
+            Self::Owned(value) => Ok(Cow::Owned(value)),
+            Self::Stored { hash, length } => {
+                let value = load_value(store, &hash, Some(length))?
+                    .ok_or_else(|| format!("value {} is missing from the store", to_hex(&hash)))?;
+                if value.len() != length || keccak256(&value) != hash {
+                    return Err(format!(
+                        "stored value {} does not match its hash or length {length}",
+                        to_hex(&hash)
+                    ));
+                }
+                Ok(Cow::Owned(value))
+            }
+        }
+    }
+
+    pub fn into_vec<S: RawStoreAdapter>(self, store: &mut S) -> Result<Vec<u8>, String> {
+        self.load(store).map(Cow::into_owned)
+    }
+}
+
+// Looks `key` up in the persisted trie under `root`, reading only the nodes on
+// its path. Inline values come back owned; values stored apart from their node
+// come back as `Stored` handles without being read.
+pub fn stored_value_ref<S: RawStoreAdapter>(
+    root: &TrieRoot,
+    key: &[u8],
+    store: &mut S,
+) -> Result<Option<ValueHandle<'static>>, String> {
+    if is_empty_root(root) {
+        return Ok(None);
+    }
+    let key = PathBits::from_key_bytes(key);
+    let mut node = load_node(root, store)?;
+    let mut offset = 0;
+    loop {
+        let path = node.shared_path.bits();
+        if !key.matches_at(offset, path) {
+            return Ok(None);
+        }
+        offset += path.bit_len();
+        let Some(bit) = key.get_bit(offset) else {
+            return value_handle(node.value, store);
+        };
+        offset += 1;
+        let child = if bit == 0 { node.left } else { node.right };
+        node = match child {
+            NodeReference::Empty => return Ok(None),
+            NodeReference::Embedded(child) => *child,
+            NodeReference::Hashed(hash) => load_node(&hash, store)?,
+        };
+    }
+}
+
+fn load_node<S: RawStoreAdapter>(
+    hash: &[u8; HASH_SIZE],
+    store: &mut S,
+) -> Result<TrieNode, String> {
+    let payload = store
+        .load_raw_node(hash)
+        .ok_or_else(|| format!("node {} is missing from the store", to_hex(hash)))?;
+    decode_persisted_node(&payload, store.codec_hint())
+}
+
+fn value_handle<S: RawStoreAdapter>(
+    value: ValueRef,
+    store: &mut S,
+) -> Result<Option<ValueHandle<'static>>, String> {
+    match value {
+        ValueRef::Empty => Ok(None),
+        ValueRef::Inline(value) if value.is_empty() => Ok(None),
+        ValueRef::Inline(value) => Ok(Some(ValueHandle::Owned(value))),
+        ValueRef::Hashed {
+            hash,
+            length: Some(length),
+        } => Ok(Some(ValueHandle::Stored { hash, length })),
+        // Codecs without a recorded length leave nothing to check a lazy load
+        // against, so the value is read now.
+        ValueRef::Hashed { hash, length: None } => load_value(store, &hash, None)?
+            .map(|value| Some(ValueHandle::Owned(value)))
+            .ok_or_else(|| format!("value {} is missing from the store", to_hex(&hash))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValueHandle;
+    use crate::hash::keccak256;
+    use crate::node_ref::{CodecConfig, MAX_CONSENSUS_VALUE_LENGTH};
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn stored_handles_load_and_verify_their_value() {
+        let value = vec![0x5a; 100];
+        let hash = keccak256(&value);
+        let mut store = MemoryStore::new();
+        let stored = ValueHandle::Stored { hash, length: 100 };
+        assert!(stored.as_bytes().is_none());
+        assert!(stored.clone().load(&mut store).is_err());
+
+        store.save_raw_value(&hash, &value);
+        assert_eq!(stored.hash(), ValueHandle::Borrowed(&value).hash());
+        assert_eq!(stored.into_vec(&mut store).unwrap(), value);
+
+        let truncated = ValueHandle::Stored { hash, length: 99 };
+        assert!(truncated.load(&mut store).is_err());
+        let borrowed = ValueHandle::Borrowed(&value);
+        assert_eq!(borrowed.len(), 100);
+        assert_eq!(borrowed.load(&mut store).unwrap().as_ref(), &value[..]);
+    }
+
+    #[test]
+    fn persisted_lookups_leave_long_values_in_the_store() {
+        let long: Vec<u8> = (0..MAX_CONSENSUS_VALUE_LENGTH + 2)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation)
+                .with_config(CodecConfig::CONSENSUS.with_extended_values(true));
+            core.try_put(vec![0x01], long.clone()).unwrap();
+            core.put(vec![0x01, 0x02], vec![0x12; 40]);
+            core.put(vec![0x03], vec![0x03]);
+            let mut store = MemoryStore::new();
+            core.save_to_store(&mut store);
+            let root = core.current_root_hash();
+
+            let stored = UnitrieCore::get_stored_ref(&root, &[0x01], &mut store)
+                .unwrap()
+                .unwrap();
+            assert!(stored.is_stored());
+            assert_eq!(stored.len(), long.len());
+            assert_eq!(stored.into_vec(&mut store).unwrap(), long);
+            assert_eq!(
+                UnitrieCore::get_stored_ref(&root, &[0x03], &mut store).unwrap(),
+                Some(ValueHandle::Owned(vec![0x03]))
+            );
+            let mid = UnitrieCore::get_stored_ref(&root, &[0x01, 0x02], &mut store).unwrap();
+            assert_eq!(mid.unwrap().into_vec(&mut store).unwrap(), vec![0x12; 40]);
+            assert_eq!(
+                UnitrieCore::get_stored_ref(&root, &[0x02], &mut store).unwrap(),
+                None
+            );
+            assert_eq!(
+                UnitrieCore::get_stored_ref(&root, &[0x01, 0x02, 0x03], &mut store).unwrap(),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::hash::keccak256;
+use crate::node_ref::CodecConfig;
+use crate::observer::TrieObserver;
+use crate::{TrieRoot, UnitrieCore};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Debug, Default)]
+struct IndexState {
+    keys_by_hash: HashMap<TrieRoot, BTreeSet<Vec<u8>>>,
+    hash_by_key: BTreeMap<Vec<u8>, TrieRoot>,
+}
+
+impl IndexState {
+    fn insert(&mut self, key: &[u8], value: &[u8], long_value_threshold: usize) {
+        self.remove(key);
+        if value.len() <= long_value_threshold {
+            return;
+        }
+
+        let hash = keccak256(value);
+        self.keys_by_hash
+            .entry(hash)
+            .or_default()
+            .insert(key.to_vec());
+        self.hash_by_key.insert(key.to_vec(), hash);
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(hash) = self.hash_by_key.remove(key) {
+            self.unlink(&hash, key);
+        }
+    }
+
+    fn remove_prefix(&mut self, prefix: &[u8]) {
+        let keys: Vec<Vec<u8>> = self
+            .hash_by_key
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn unlink(&mut self, hash: &TrieRoot, key: &[u8]) {
+        if let Some(keys) = self.keys_by_hash.get_mut(hash) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.keys_by_hash.remove(hash);
+            }
+        }
+    }
+}
+
+// Maps long-value hashes to the keys holding them. Registered as an observer it
+// stays in sync with every mutation of the trie it was built from.
+#[derive(Debug)]
+pub struct ValueHashIndex {
+    // Values longer than this are long under the indexed trie's config.
+    long_value_threshold: usize,
+    state: Mutex<IndexState>,
+}
+
+impl Default for ValueHashIndex {
+    fn default() -> Self {
+        Self::for_config(&CodecConfig::CONSENSUS)
+    }
+}
+
+impl ValueHashIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // An empty index for a trie encoded under `config`.
+    pub fn for_config(config: &CodecConfig) -> Self {
+        Self {
+            long_value_threshold: config.long_value_threshold,
+            state: Mutex::new(IndexState::default()),
+        }
+    }
+
+    pub fn from_trie(trie: &UnitrieCore) -> Self {
+        let long_value_threshold = trie.config().long_value_threshold;
+        let mut state = IndexState::default();
+        for (key, value) in trie.iter_prefix(&[]) {
+            state.insert(key, value, long_value_threshold);
+        }
+        Self {
+            long_value_threshold,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn keys_for(&self, value_hash: &TrieRoot) -> Vec<Vec<u8>> {
+        self.state()
+            .keys_by_hash
+            .get(value_hash)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn value_hash_of(&self, key: &[u8]) -> Option<TrieRoot> {
+        self.state().hash_by_key.get(key).copied()
+    }
+
+    // Long values referenced by more than one key, with their reference counts.
+    pub fn shared_values(&self) -> Vec<(TrieRoot, usize)> {
+        let mut shared: Vec<(TrieRoot, usize)> = self
+            .state()
+            .keys_by_hash
+            .iter()
+            .filter(|(_, keys)| keys.len() > 1)
+            .map(|(hash, keys)| (*hash, keys.len()))
+            .collect();
+        shared.sort_unstable();
+        shared
+    }
+
+    pub fn distinct_values(&self) -> usize {
+        self.state().keys_by_hash.len()
+    }
+
+    pub fn indexed_keys(&self) -> usize {
+        self.state().hash_by_key.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, IndexState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl TrieObserver for ValueHashIndex {
+    fn on_put(&self, key: &[u8], value: &[u8]) {
+        self.state().insert(key, value, self.long_value_threshold);
+    }
+
+    fn on_delete(&self, key: &[u8]) {
+        self.state().remove(key);
+    }
+
+    fn on_delete_recursive(&self, prefix: &[u8]) {
+        self.state().remove_prefix(prefix);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::keccak256;
+    use crate::node_ref::CodecConfig;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn index_tracks_keys_sharing_a_long_value() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let code = vec![0x60; 64];
+            let code_hash = keccak256(&code);
+            let mut trie = UnitrieCore::new(implementation);
+            trie.put(b"contract-a".to_vec(), code.clone());
+            trie.put(b"short".to_vec(), vec![0x01; 8]);
+
+            let index = trie.enable_value_index();
+            trie.put(b"contract-b".to_vec(), code.clone());
+            trie.put(b"contract-c".to_vec(), code);
+            assert_eq!(index.shared_values(), vec![(code_hash, 3)]);
+            assert_eq!(index.value_hash_of(b"short"), None);
+
+            trie.put(b"contract-a".to_vec(), vec![0x61; 64]);
+            trie.delete(b"contract-c");
+            assert_eq!(index.keys_for(&code_hash), vec![b"contract-b".to_vec()]);
+
+            trie.delete_recursive(b"contract");
+            assert_eq!(index.indexed_keys(), 0);
+            assert_eq!(index.distinct_values(), 0);
+        }
+    }
+
+    #[test]
+    fn index_uses_the_long_value_threshold_of_the_trie() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut trie =
+                UnitrieCore::new(implementation).with_config(CodecConfig::new(8, 0).unwrap());
+            trie.put(b"before".to_vec(), vec![0x01; 16]);
+            let index = trie.enable_value_index();
+            trie.put(b"after".to_vec(), vec![0x02; 16]);
+            trie.put(b"short".to_vec(), vec![0x03; 8]);
+
+            assert_eq!(index.value_hash_of(b"before"), Some(keccak256(&[0x01; 16])));
+            assert_eq!(index.value_hash_of(b"after"), Some(keccak256(&[0x02; 16])));
+            assert_eq!(index.value_hash_of(b"short"), None);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::json::to_hex;
+use crate::key_validation::{KeyKind, KeyValidator};
+use crate::TrieRoot;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+
+// Value lengths from `min_length` to `max_length` inclusive. Buckets double in
+// width: 1, 2-3, 4-7, 8-15 and so on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SizeBucket {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub count: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LargeValue {
+    pub key: Vec<u8>,
+    pub length: usize,
+    pub value_hash: TrieRoot,
+    // What the key addresses under the trie's key scheme, `None` for keys of
+    // no known shape.
+    pub kind: Option<KeyKind>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ValueSizeReport {
+    pub key_count: usize,
+    pub total_value_bytes: u64,
+    // Only buckets holding values, shortest lengths first.
+    pub buckets: Vec<SizeBucket>,
+    // Longest first; equal lengths in key order.
+    pub largest: Vec<LargeValue>,
+}
+
+impl fmt::Display for ValueSizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} values, {} bytes",
+            self.key_count, self.total_value_bytes
+        )?;
+        for bucket in &self.buckets {
+            writeln!(
+                f,
+                "{:>10} - {:<10} {:>10} values {:>14} bytes",
+                bucket.min_length, bucket.max_length, bucket.count, bucket.total_bytes
+            )?;
+        }
+        for value in &self.largest {
+            let kind = value
+                .kind
+                .map_or_else(|| "unknown".to_string(), |kind| format!("{kind:?}"));
+            writeln!(
+                f,
+                "{:>10} bytes {kind:<8} {}",
+                value.length,
+                to_hex(&value.key)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// One pass over `entries`, keeping only the `top_n` longest values' keys.
+pub(crate) fn value_size_report<'a, I, H>(
+    entries: I,
+    top_n: usize,
+    validator: &KeyValidator,
+    value_hash: H,
+) -> ValueSizeReport
+where
+    I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    H: Fn(&[u8], &[u8]) -> TrieRoot,
+{
+    let mut report = ValueSizeReport::default();
+    let mut buckets: Vec<SizeBucket> = Vec::new();
+    // Min-heap on (length, reversed key): the first evicted is the shortest
+    // value, and among equal lengths the greatest key.
+    let mut largest = BinaryHeap::with_capacity(top_n + 1);
+    for (key, value) in entries {
+        let length = value.len();
+        report.key_count += 1;
+        report.total_value_bytes += length as u64;
+
+        let index = bucket_index(length);
+        if buckets.len() <= index {
+            buckets.extend((buckets.len()..=index).map(empty_bucket));
+        }
+        buckets[index].count += 1;
+        buckets[index].total_bytes += length as u64;
+
+        if top_n > 0 {
+            largest.push(Reverse((length, Reverse(key), value)));
+            if largest.len() > top_n {
+                largest.pop();
+            }
+        }
+    }
+
+    report.buckets = buckets
+        .into_iter()
+        .filter(|bucket| bucket.count > 0)
+        .collect();
+    report.largest = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((length, Reverse(key), value))| LargeValue {
+            key: key.to_vec(),
+            length,
+            value_hash: value_hash(key, value),
+            kind: validator.validate(key).ok(),
+        })
+        .collect();
+    report
+}
+
+// Bucket 0 holds only the empty value, which a trie never stores.
+fn bucket_index(length: usize) -> usize {
+    (usize::BITS - length.leading_zeros()) as usize
+}
+
+fn empty_bucket(index: usize) -> SizeBucket {
+    let (min_length, max_length) = match index {
+        0 => (0, 0),
+        _ => (1 << (index - 1), (1 << (index - 1)) * 2 - 1),
+    };
+    SizeBucket {
+        min_length,
+        max_length,
+        count: 0,
+        total_bytes: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SizeBucket;
+    use crate::hash::keccak256;
+    use crate::key_scheme::KeyScheme;
+    use crate::key_validation::KeyKind;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn reports_bucket_value_lengths_and_rank_the_largest() {
+        for &implementation in UnitrieImplementation::enabled() {
+            let mut core = UnitrieCore::new(implementation);
+            let address = [0x12u8; 20];
+            core.put_code(&address, vec![0x60; 5000]);
+            core.put_storage_value(&address, &[0x01], vec![0x01; 32]);
+            core.put(vec![0x01], vec![0x01]);
+            core.put(vec![0x02], vec![0x02, 0x02]);
+            core.put(vec![0x03], vec![0x03; 3]);
+            core.put(vec![0x04], vec![0x04; 32]);
+
+            let report = core.value_size_report(3);
+            assert_eq!(report.key_count, 6);
+            assert_eq!(report.total_value_bytes, 5000 + 32 + 1 + 2 + 3 + 32);
+            let bucket = |min_length, max_length, count, total_bytes| SizeBucket {
+                min_length,
+                max_length,
+                count,
+                total_bytes,
+            };
+            assert_eq!(
+                report.buckets,
+                [
+                    bucket(1, 1, 1, 1),
+                    bucket(2, 3, 2, 5),
+                    bucket(32, 63, 2, 64),
+                    bucket(4096, 8191, 1, 5000),
+                ]
+            );
+
+            let code_key = KeyScheme::RSK.code_key(&address);
+            let largest: Vec<_> = report
+                .largest
+                .iter()
+                .map(|value| (value.key.clone(), value.length, value.kind))
+                .collect();
+            assert_eq!(
+                largest,
+                [
+                    (code_key, 5000, Some(KeyKind::Code)),
+                    (
+                        KeyScheme::RSK.storage_key(&address, &[0x01]),
+                        32,
+                        Some(KeyKind::Storage)
+                    ),
+                    (vec![0x04], 32, None),
+                ]
+            );
+            assert_eq!(report.largest[0].value_hash, keccak256(&[0x60; 5000]));
+            assert!(core.value_size_report(0).largest.is_empty());
+        }
+    }
+}
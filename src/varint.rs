@@ -47,6 +47,37 @@ pub fn decode_from_slice(input: &[u8], offset: &mut usize) -> Result<u64, String
     }
 }
 
+// Rejects values above `max_value` and encodings longer than `size_of` the value.
+// `offset` only advances when the varint is accepted.
+pub fn decode_from_slice_strict(
+    input: &[u8],
+    offset: &mut usize,
+    max_value: u64,
+) -> Result<u64, String> {
+    let start = *offset;
+    let mut end = start;
+    let value = decode_from_slice(input, &mut end)?;
+    if end - start != size_of(value) {
+        return Err(format!(
+            "varint {value} is not minimally encoded ({} bytes instead of {})",
+            end - start,
+            size_of(value)
+        ));
+    }
+    if value > max_value {
+        return Err(format!("varint {value} exceeds the maximum of {max_value}"));
+    }
+    *offset = end;
+    Ok(value)
+}
+
+// Decodes a varint at the start of `input` and returns it with the number of bytes consumed.
+pub fn decode_with_remaining(input: &[u8]) -> Result<(u64, usize), String> {
+    let mut offset = 0usize;
+    let value = decode_from_slice(input, &mut offset)?;
+    Ok((value, offset))
+}
+
 fn decode_u16(input: &[u8], offset: &mut usize) -> Result<u16, String> {
     let end = *offset + 2;
     if end > input.len() {
@@ -85,7 +116,10 @@ fn decode_u64(input: &[u8], offset: &mut usize) -> Result<u64, String> {
 
 #[cfg(test)]
 mod tests {
-    use super::{decode_from_slice, encode, encode_into, size_of};
+    use super::{
+        decode_from_slice, decode_from_slice_strict, decode_with_remaining, encode, encode_into,
+        size_of,
+    };
 
     #[test]
     fn size_matches_encoding_boundaries() {
@@ -117,4 +151,31 @@ mod tests {
             assert_eq!(direct, reused);
         }
     }
+
+    #[test]
+    fn strict_decoding_rejects_non_minimal_and_oversized_values() {
+        let mut offset = 0usize;
+        assert!(decode_from_slice_strict(&[0xfd, 0x10, 0x00], &mut offset, u64::MAX).is_err());
+        assert!(
+            decode_from_slice_strict(&[0xfe, 0xff, 0xff, 0x00, 0x00], &mut offset, u64::MAX)
+                .is_err()
+        );
+        assert!(decode_from_slice_strict(&[0x20], &mut offset, 0x1f).is_err());
+        assert_eq!(offset, 0);
+
+        let encoded = encode(65_536);
+        assert_eq!(
+            decode_from_slice_strict(&encoded, &mut offset, 65_536),
+            Ok(65_536)
+        );
+        assert_eq!(offset, encoded.len());
+    }
+
+    #[test]
+    fn decode_with_remaining_reports_consumed_bytes() {
+        let mut input = encode(300);
+        input.extend_from_slice(b"tail");
+        assert_eq!(decode_with_remaining(&input), Ok((300, 3)));
+        assert!(decode_with_remaining(&[0xfe, 0x01]).is_err());
+    }
 }
@@ -0,0 +1,437 @@
+use crate::store_adapter::{CodecHint, RawStoreAdapter};
+use crate::varint;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const RECORD_NODE: u8 = 0x01;
+const RECORD_VALUE: u8 = 0x02;
+const RECORD_COMMIT: u8 = 0x03;
+
+pub trait WalJournal {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), String>;
+
+    fn sync(&mut self) -> Result<(), String>;
+
+    fn read_all(&mut self) -> Result<Vec<u8>, String>;
+
+    fn truncate(&mut self) -> Result<(), String>;
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MemoryJournal {
+    bytes: Vec<u8>,
+}
+
+impl MemoryJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl WalJournal for MemoryJournal {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn read_all(&mut self) -> Result<Vec<u8>, String> {
+        Ok(self.bytes.clone())
+    }
+
+    fn truncate(&mut self) -> Result<(), String> {
+        self.bytes.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileJournal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|error| format!("could not open WAL {}: {error}", path.display()))?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl WalJournal for FileJournal {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(bytes)
+            .map_err(|error| format!("could not append to WAL {}: {error}", self.path.display()))
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        self.file
+            .sync_data()
+            .map_err(|error| format!("could not sync WAL {}: {error}", self.path.display()))
+    }
+
+    fn read_all(&mut self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.read_to_end(&mut bytes))
+            .map_err(|error| format!("could not read WAL {}: {error}", self.path.display()))?;
+        Ok(bytes)
+    }
+
+    fn truncate(&mut self) -> Result<(), String> {
+        self.file
+            .set_len(0)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|error| format!("could not truncate WAL {}: {error}", self.path.display()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WalRecovery {
+    Clean,
+    Replayed { nodes: usize, values: usize },
+    RolledBack { discarded: usize },
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct WalCommitStats {
+    pub nodes: usize,
+    pub values: usize,
+}
+
+// Buffers node/value writes in a journal and only applies them to the inner store
+// on `commit`. A crash before the commit marker is synced rolls the batch back on
+// `open`; a crash after it replays the batch, so the inner store never ends up
+// with a root whose descendants were not written.
+#[derive(Debug)]
+pub struct WalStore<S, J> {
+    inner: S,
+    journal: J,
+    pending_nodes: HashMap<Vec<u8>, Vec<u8>>,
+    pending_values: HashMap<Vec<u8>, Vec<u8>>,
+    pending_records: u64,
+    journal_error: Option<String>,
+}
+
+impl<S: RawStoreAdapter, J: WalJournal> WalStore<S, J> {
+    pub fn open(mut inner: S, mut journal: J) -> Result<(Self, WalRecovery), String> {
+        let log = journal.read_all()?;
+        let recovery = match parse_journal(&log)? {
+            ParsedJournal::Empty => WalRecovery::Clean,
+            ParsedJournal::Uncommitted(discarded) => {
+                journal.truncate()?;
+                WalRecovery::RolledBack { discarded }
+            }
+            ParsedJournal::Committed(records) => {
+                let (nodes, values) = apply_records(&mut inner, &records);
+                journal.truncate()?;
+                WalRecovery::Replayed { nodes, values }
+            }
+        };
+
+        Ok((
+            Self {
+                inner,
+                journal,
+                pending_nodes: HashMap::new(),
+                pending_values: HashMap::new(),
+                pending_records: 0,
+                journal_error: None,
+            },
+            recovery,
+        ))
+    }
+
+    pub fn commit(&mut self) -> Result<WalCommitStats, String> {
+        if let Some(error) = self.journal_error.take() {
+            self.rollback()?;
+            return Err(error);
+        }
+
+        let stats = WalCommitStats {
+            nodes: self.pending_nodes.len(),
+            values: self.pending_values.len(),
+        };
+        if self.pending_records == 0 {
+            return Ok(stats);
+        }
+
+        let mut marker = vec![RECORD_COMMIT];
+        varint::encode_into(self.pending_records, &mut marker);
+        self.journal.append(&marker)?;
+        self.journal.sync()?;
+
+        for (hash, payload) in self.pending_nodes.drain() {
+            self.inner.save_raw_node(&hash, &payload);
+        }
+        for (hash, value) in self.pending_values.drain() {
+            self.inner.save_raw_value(&hash, &value);
+        }
+        self.pending_records = 0;
+        self.journal.truncate()?;
+        Ok(stats)
+    }
+
+    pub fn rollback(&mut self) -> Result<(), String> {
+        self.pending_nodes.clear();
+        self.pending_values.clear();
+        self.pending_records = 0;
+        self.journal.truncate()
+    }
+
+    pub fn has_pending_writes(&self) -> bool {
+        self.pending_records > 0
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn journal(&self) -> &J {
+        &self.journal
+    }
+
+    pub fn into_parts(self) -> (S, J) {
+        (self.inner, self.journal)
+    }
+
+    fn journal_write(&mut self, kind: u8, hash: &[u8], payload: &[u8]) {
+        if self.journal_error.is_some() {
+            return;
+        }
+
+        if let Err(error) = self.journal.append(&encode_record(kind, hash, payload)) {
+            self.journal_error = Some(error);
+            return;
+        }
+        self.pending_records = self.pending_records.saturating_add(1);
+    }
+}
+
+impl<S: RawStoreAdapter, J: WalJournal> RawStoreAdapter for WalStore<S, J> {
+    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        if let Some(payload) = self.pending_nodes.get(hash) {
+            return Some(payload.clone());
+        }
+        self.inner.load_raw_node(hash)
+    }
+
+    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.pending_values.get(hash) {
+            return Some(value.clone());
+        }
+        self.inner.load_raw_value(hash)
+    }
+
+    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
+        self.journal_write(RECORD_NODE, hash, serialized_node);
+        self.pending_nodes
+            .insert(hash.to_vec(), serialized_node.to_vec());
+    }
+
+    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
+        self.journal_write(RECORD_VALUE, hash, value);
+        self.pending_values.insert(hash.to_vec(), value.to_vec());
+    }
+
+    fn contains_raw_value(&mut self, hash: &[u8]) -> Option<bool> {
+        if self.pending_values.contains_key(hash) {
+            return Some(true);
+        }
+        self.inner.contains_raw_value(hash)
+    }
+
+    fn codec_hint(&self) -> CodecHint {
+        self.inner.codec_hint()
+    }
+}
+
+struct WalRecord {
+    kind: u8,
+    hash: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+enum ParsedJournal {
+    Empty,
+    Uncommitted(usize),
+    Committed(Vec<WalRecord>),
+}
+
+fn encode_record(kind: u8, hash: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(
+        1 + varint::size_of(hash.len() as u64)
+            + hash.len()
+            + varint::size_of(payload.len() as u64)
+            + payload.len(),
+    );
+    record.push(kind);
+    varint::encode_into(hash.len() as u64, &mut record);
+    record.extend_from_slice(hash);
+    varint::encode_into(payload.len() as u64, &mut record);
+    record.extend_from_slice(payload);
+    record
+}
+
+fn parse_journal(log: &[u8]) -> Result<ParsedJournal, String> {
+    let mut records = Vec::new();
+    let mut offset = 0usize;
+    while offset < log.len() {
+        let kind = log[offset];
+        offset += 1;
+        match kind {
+            RECORD_NODE | RECORD_VALUE => {
+                // A torn tail record means the batch never reached its commit marker.
+                let Some((hash, payload)) = read_record_body(log, &mut offset) else {
+                    return Ok(ParsedJournal::Uncommitted(records.len()));
+                };
+                records.push(WalRecord {
+                    kind,
+                    hash,
+                    payload,
+                });
+            }
+            RECORD_COMMIT => {
+                let Ok(count) = varint::decode_from_slice(log, &mut offset) else {
+                    return Ok(ParsedJournal::Uncommitted(records.len()));
+                };
+                if count != records.len() as u64 {
+                    return Err(format!(
+                        "WAL commit marker covers {count} records but {} were journaled",
+                        records.len()
+                    ));
+                }
+                return Ok(ParsedJournal::Committed(records));
+            }
+            other => return Err(format!("unknown WAL record kind 0x{other:02x}")),
+        }
+    }
+
+    if records.is_empty() {
+        Ok(ParsedJournal::Empty)
+    } else {
+        Ok(ParsedJournal::Uncommitted(records.len()))
+    }
+}
+
+fn read_record_body(log: &[u8], offset: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    let hash = read_length_prefixed(log, offset)?;
+    let payload = read_length_prefixed(log, offset)?;
+    Some((hash, payload))
+}
+
+fn read_length_prefixed(log: &[u8], offset: &mut usize) -> Option<Vec<u8>> {
+    let length = varint::decode_from_slice(log, offset).ok()? as usize;
+    let end = offset.checked_add(length)?;
+    if end > log.len() {
+        return None;
+    }
+    let bytes = log[*offset..end].to_vec();
+    *offset = end;
+    Some(bytes)
+}
+
+fn apply_records<S: RawStoreAdapter>(inner: &mut S, records: &[WalRecord]) -> (usize, usize) {
+    let mut nodes = 0usize;
+    let mut values = 0usize;
+    for record in records {
+        if record.kind == RECORD_NODE {
+            inner.save_raw_node(&record.hash, &record.payload);
+            nodes += 1;
+        } else {
+            inner.save_raw_value(&record.hash, &record.payload);
+            values += 1;
+        }
+    }
+    (nodes, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_record, MemoryJournal, WalRecovery, WalStore, RECORD_COMMIT, RECORD_NODE,
+        RECORD_VALUE,
+    };
+    use crate::store_adapter::{MemoryStore, RawStoreAdapter};
+
+    #[test]
+    fn writes_reach_inner_store_only_on_commit() {
+        let (mut store, recovery) =
+            WalStore::open(MemoryStore::new(), MemoryJournal::new()).unwrap();
+        assert_eq!(recovery, WalRecovery::Clean);
+
+        store.save_raw_node(&[1], &[0x40]);
+        store.save_raw_value(&[2], &[9; 40]);
+        assert_eq!(store.load_raw_node(&[1]), Some(vec![0x40]));
+        assert_eq!(store.inner().node_count(), 0);
+
+        let stats = store.commit().unwrap();
+        assert_eq!((stats.nodes, stats.values), (1, 1));
+        assert_eq!(store.inner().node(&[1]), Some(&[0x40][..]));
+        assert!(store.journal().bytes().is_empty());
+    }
+
+    #[test]
+    fn uncommitted_journal_is_rolled_back_on_open() {
+        let (mut store, _) = WalStore::open(MemoryStore::new(), MemoryJournal::new()).unwrap();
+        store.save_raw_node(&[1], &[0x40]);
+        store.save_raw_node(&[2], &[0x41]);
+        let (_, journal) = store.into_parts();
+
+        // Simulate a crash that tore the last record in half.
+        let mut torn = journal.bytes().to_vec();
+        torn.truncate(torn.len() - 1);
+        let (reopened, recovery) =
+            WalStore::open(MemoryStore::new(), MemoryJournal::from_bytes(torn)).unwrap();
+        assert_eq!(recovery, WalRecovery::RolledBack { discarded: 1 });
+        assert_eq!(reopened.inner().node_count(), 0);
+        assert!(reopened.journal().bytes().is_empty());
+    }
+
+    #[test]
+    fn committed_journal_is_replayed_on_open() {
+        let mut log = encode_record(RECORD_NODE, &[1], &[0x40]);
+        log.extend_from_slice(&encode_record(RECORD_VALUE, &[2], &[7; 33]));
+        log.extend_from_slice(&[RECORD_COMMIT, 2]);
+
+        let (mut store, recovery) =
+            WalStore::open(MemoryStore::new(), MemoryJournal::from_bytes(log)).unwrap();
+        assert_eq!(
+            recovery,
+            WalRecovery::Replayed {
+                nodes: 1,
+                values: 1
+            }
+        );
+        assert_eq!(store.load_raw_value(&[2]), Some(vec![7; 33]));
+    }
+}
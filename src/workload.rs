@@ -0,0 +1,627 @@
+use crate::hash::keccak256;
+use crate::key_scheme::KeyScheme;
+use crate::node_ref::HASH_SIZE;
+use crate::rng::SeededRng;
+use crate::store_adapter::RawStoreAdapter;
+use crate::UnitrieCore;
+use std::str::FromStr;
+
+// One step of a replayable workload, as listed in bench corpora.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WorkloadOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Get { key: Vec<u8> },
+    Delete { key: Vec<u8> },
+    DeleteRecursive { prefix: Vec<u8> },
+    GetValueLength { key: Vec<u8> },
+    GetValueHash { key: Vec<u8> },
+    CollectKeys { byte_size: usize },
+    Save,
+    // Saves, then reloads the trie from the saved root.
+    SaveReload,
+    RootHash,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Workload {
+    pub name: String,
+    pub operations: Vec<WorkloadOp>,
+}
+
+// How many storage slots each account starts with.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StorageDistribution {
+    Uniform { min: usize, max: usize },
+    // `max / k` slots for a uniformly drawn k in 1..=max: most accounts hold a
+    // few slots and a handful hold many, like contracts on mainnet.
+    Harmonic { max: usize },
+}
+
+// Values are drawn by first picking a bucket by weight, then a length
+// uniformly within it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ValueSizeBucket {
+    pub min_len: usize,
+    pub max_len: usize,
+    pub weight: u32,
+}
+
+// Relative weights of the operations following the initial population.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct OpMix {
+    pub put: u32,
+    pub get: u32,
+    pub delete: u32,
+    pub delete_account: u32,
+    pub get_value_length: u32,
+    pub get_value_hash: u32,
+    pub root_hash: u32,
+    pub save: u32,
+}
+
+// Shape of a generated workload: `accounts` accounts are created with their
+// storage first, then `operations` operations are drawn from `op_mix`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WorkloadProfile {
+    pub accounts: usize,
+    pub storage: StorageDistribution,
+    pub value_sizes: Vec<ValueSizeBucket>,
+    pub op_mix: OpMix,
+    pub operations: usize,
+}
+
+impl WorkloadProfile {
+    // Quick enough for tests and CI smoke benches.
+    pub fn small() -> Self {
+        Self {
+            accounts: 64,
+            storage: StorageDistribution::Uniform { min: 0, max: 8 },
+            value_sizes: default_value_sizes(),
+            op_mix: OpMix {
+                put: 40,
+                get: 40,
+                delete: 5,
+                delete_account: 1,
+                get_value_length: 5,
+                get_value_hash: 5,
+                root_hash: 3,
+                save: 1,
+            },
+            operations: 1_000,
+        }
+    }
+
+    // Contract-heavy state with skewed storage and read-mostly traffic.
+    pub fn storage_heavy() -> Self {
+        Self {
+            accounts: 256,
+            storage: StorageDistribution::Harmonic { max: 2_048 },
+            value_sizes: default_value_sizes(),
+            op_mix: OpMix {
+                put: 25,
+                get: 60,
+                delete: 5,
+                delete_account: 1,
+                get_value_length: 4,
+                get_value_hash: 4,
+                root_hash: 1,
+                save: 1,
+            },
+            operations: 20_000,
+        }
+    }
+}
+
+impl FromStr for WorkloadProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "small" => Ok(Self::small()),
+            "storage-heavy" => Ok(Self::storage_heavy()),
+            other => Err(format!(
+                "unknown workload profile '{other}', expected one of: small, storage-heavy"
+            )),
+        }
+    }
+}
+
+// Mostly slot-sized values, some just past the long value threshold and a few
+// code-sized ones.
+fn default_value_sizes() -> Vec<ValueSizeBucket> {
+    vec![
+        ValueSizeBucket {
+            min_len: 1,
+            max_len: 32,
+            weight: 80,
+        },
+        ValueSizeBucket {
+            min_len: 33,
+            max_len: 128,
+            weight: 15,
+        },
+        ValueSizeBucket {
+            min_len: 129,
+            max_len: 4_096,
+            weight: 5,
+        },
+    ]
+}
+
+// A workload that only depends on `seed` and `profile`, with RSK account and
+// storage keys. Deleted keys are never reused, so every get, delete and value
+// query targets a key the trie holds unless stated otherwise: one get in ten
+// looks up a key that was never written.
+pub fn generate(seed: u64, profile: &WorkloadProfile) -> Workload {
+    let mut generator = Generator {
+        rng: SeededRng::new(seed),
+        profile,
+        accounts: Vec::new(),
+        storage: Vec::new(),
+        operations: Vec::new(),
+    };
+    for _ in 0..profile.accounts {
+        let account = generator.new_account();
+        let slots = generator.initial_slot_count();
+        for _ in 0..slots {
+            generator.new_storage_slot(account);
+        }
+    }
+    for _ in 0..profile.operations {
+        generator.random_operation();
+    }
+    Workload {
+        name: format!("generated-{seed}"),
+        operations: generator.operations,
+    }
+}
+
+struct Generator<'a> {
+    rng: SeededRng,
+    profile: &'a WorkloadProfile,
+    accounts: Vec<[u8; 20]>,
+    // Live storage keys with the index of their account in `accounts`.
+    storage: Vec<(usize, Vec<u8>)>,
+    operations: Vec<WorkloadOp>,
+}
+
+impl Generator<'_> {
+    fn new_account(&mut self) -> usize {
+        let mut address = [0u8; 20];
+        self.rng.fill(&mut address);
+        // Account states are short RLP lists.
+        let length = 8 + self.rng.below(24);
+        let value = self.rng.bytes(length);
+        self.operations.push(WorkloadOp::Put {
+            key: KeyScheme::RSK.account_key(&address),
+            value,
+        });
+        self.accounts.push(address);
+        self.accounts.len() - 1
+    }
+
+    fn new_storage_slot(&mut self, account: usize) {
+        let mut slot = [0u8; 32];
+        self.rng.fill(&mut slot);
+        let key = KeyScheme::RSK.storage_key(&self.accounts[account], &slot);
+        let value = self.value();
+        self.operations.push(WorkloadOp::Put {
+            key: key.clone(),
+            value,
+        });
+        self.storage.push((account, key));
+    }
+
+    fn initial_slot_count(&mut self) -> usize {
+        match self.profile.storage {
+            StorageDistribution::Uniform { min, max } => {
+                min + self.rng.below(max.saturating_sub(min) + 1)
+            }
+            StorageDistribution::Harmonic { max } if max > 0 => max / (1 + self.rng.below(max)),
+            StorageDistribution::Harmonic { .. } => 0,
+        }
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        let buckets = &self.profile.value_sizes;
+        let weights: Vec<u32> = buckets.iter().map(|bucket| bucket.weight).collect();
+        let bucket = self
+            .rng
+            .weighted(&weights)
+            .map(|index| buckets[index])
+            .unwrap_or(ValueSizeBucket {
+                min_len: 1,
+                max_len: 32,
+                weight: 1,
+            });
+        // Empty values would delete the key instead.
+        let min_len = bucket.min_len.max(1);
+        let length = min_len + self.rng.below(bucket.max_len.saturating_sub(min_len) + 1);
+        self.rng.bytes(length)
+    }
+
+    fn existing_key(&mut self) -> Option<Vec<u8>> {
+        let total = self.accounts.len() + self.storage.len();
+        if total == 0 {
+            return None;
+        }
+        let index = self.rng.below(total);
+        Some(match self.accounts.get(index) {
+            Some(address) => KeyScheme::RSK.account_key(address),
+            None => self.storage[index - self.accounts.len()].1.clone(),
+        })
+    }
+
+    fn random_operation(&mut self) {
+        let mix = self.profile.op_mix;
+        let weights = [
+            mix.put,
+            mix.get,
+            mix.delete,
+            mix.delete_account,
+            mix.get_value_length,
+            mix.get_value_hash,
+            mix.root_hash,
+            mix.save,
+        ];
+        let choice = self.rng.weighted(&weights).unwrap_or(0);
+
+        match choice {
+            1 => self.get(),
+            2 if !self.storage.is_empty() => {
+                let (_, key) = self.storage.swap_remove(self.rng.below(self.storage.len()));
+                self.operations.push(WorkloadOp::Delete { key });
+            }
+            3 if !self.accounts.is_empty() => self.delete_account(),
+            4 | 5 => match self.existing_key() {
+                Some(key) if choice == 4 => {
+                    self.operations.push(WorkloadOp::GetValueLength { key })
+                }
+                Some(key) => self.operations.push(WorkloadOp::GetValueHash { key }),
+                None => self.put(),
+            },
+            6 => self.operations.push(WorkloadOp::RootHash),
+            7 => self.operations.push(WorkloadOp::Save),
+            // Puts, and deletes with nothing left to delete.
+            _ => self.put(),
+        }
+    }
+
+    // Updates half of the time, otherwise adds a slot or, rarely, an account.
+    fn put(&mut self) {
+        let roll = self.rng.below(10);
+        if roll < 5 && !self.storage.is_empty() {
+            let key = self.storage[self.rng.below(self.storage.len())].1.clone();
+            let value = self.value();
+            self.operations.push(WorkloadOp::Put { key, value });
+        } else if roll < 9 && !self.accounts.is_empty() {
+            let account = self.rng.below(self.accounts.len());
+            self.new_storage_slot(account);
+        } else {
+            self.new_account();
+        }
+    }
+
+    fn get(&mut self) {
+        let key = match self.existing_key() {
+            Some(key) if self.rng.below(10) > 0 => key,
+            _ => {
+                let mut address = [0u8; 20];
+                self.rng.fill(&mut address);
+                KeyScheme::RSK.account_key(&address)
+            }
+        };
+        self.operations.push(WorkloadOp::Get { key });
+    }
+
+    // The account key prefixes its code and storage keys.
+    fn delete_account(&mut self) {
+        let account = self.rng.below(self.accounts.len());
+        let last = self.accounts.len() - 1;
+        let address = self.accounts.swap_remove(account);
+        self.storage.retain(|(owner, _)| *owner != account);
+        for (owner, _) in &mut self.storage {
+            if *owner == last {
+                *owner = account;
+            }
+        }
+        self.operations.push(WorkloadOp::DeleteRecursive {
+            prefix: KeyScheme::RSK.account_key(&address),
+        });
+    }
+}
+
+// Canonical digest of a replay, identical for any engine (and for RSKj) that
+// behaves the same on the same operations. Starting from
+// `keccak256("unitrie-replay-v1")`, every operation folds in as
+//
+//   state = keccak256(state || tag || operation || outcome)
+//
+// with byte strings encoded as a 4-byte big-endian length and the bytes,
+// integers as 8-byte big-endian, absent results as 0x00 and present ones as
+// 0x01 followed by the result. Per operation, in tag order:
+//
+//   0x01 put             key, value                    -> nothing
+//   0x02 get             key                           -> optional value
+//   0x03 delete          key                           -> nothing
+//   0x04 deleteRecursive prefix                        -> deleted key count
+//   0x05 getValueLength  key                           -> optional length
+//   0x06 getValueHash    key                           -> optional 32-byte hash
+//   0x07 collectKeys     byte size                     -> count, then keys in
+//                                                         ascending order
+//   0x08 save, 0x09 saveReload, 0x0a rootHash          -> 32-byte root after it
+#[derive(Debug, Clone)]
+pub struct ReplayChecksum {
+    state: [u8; HASH_SIZE],
+    operations: u64,
+    buffer: Vec<u8>,
+}
+
+// What an operation returned; see `ReplayChecksum` for which goes with which.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OpOutcome<'a> {
+    Done,
+    Value(Option<&'a [u8]>),
+    Count(usize),
+    Length(Option<usize>),
+    Hash(Option<[u8; HASH_SIZE]>),
+    Keys(&'a [Vec<u8>]),
+    Root([u8; HASH_SIZE]),
+}
+
+impl Default for ReplayChecksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayChecksum {
+    pub fn new() -> Self {
+        Self {
+            state: keccak256(b"unitrie-replay-v1"),
+            operations: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, operation: &WorkloadOp, outcome: OpOutcome<'_>) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        buffer.extend_from_slice(&self.state);
+        match operation {
+            WorkloadOp::Put { key, value } => {
+                buffer.push(0x01);
+                write_bytes(&mut buffer, key);
+                write_bytes(&mut buffer, value);
+            }
+            WorkloadOp::Get { key } => {
+                buffer.push(0x02);
+                write_bytes(&mut buffer, key);
+            }
+            WorkloadOp::Delete { key } => {
+                buffer.push(0x03);
+                write_bytes(&mut buffer, key);
+            }
+            WorkloadOp::DeleteRecursive { prefix } => {
+                buffer.push(0x04);
+                write_bytes(&mut buffer, prefix);
+            }
+            WorkloadOp::GetValueLength { key } => {
+                buffer.push(0x05);
+                write_bytes(&mut buffer, key);
+            }
+            WorkloadOp::GetValueHash { key } => {
+                buffer.push(0x06);
+                write_bytes(&mut buffer, key);
+            }
+            WorkloadOp::CollectKeys { byte_size } => {
+                buffer.push(0x07);
+                write_integer(&mut buffer, *byte_size);
+            }
+            WorkloadOp::Save => buffer.push(0x08),
+            WorkloadOp::SaveReload => buffer.push(0x09),
+            WorkloadOp::RootHash => buffer.push(0x0a),
+        }
+        match outcome {
+            OpOutcome::Done => {}
+            OpOutcome::Value(value) => {
+                write_option(&mut buffer, value, |buffer, value| {
+                    write_bytes(buffer, value)
+                });
+            }
+            OpOutcome::Count(count) => write_integer(&mut buffer, count),
+            OpOutcome::Length(length) => write_option(&mut buffer, length, write_integer),
+            OpOutcome::Hash(hash) => {
+                write_option(&mut buffer, hash, |buffer, hash| {
+                    buffer.extend_from_slice(&hash)
+                });
+            }
+            OpOutcome::Keys(keys) => {
+                let mut sorted: Vec<&Vec<u8>> = keys.iter().collect();
+                sorted.sort();
+                write_integer(&mut buffer, sorted.len());
+                for key in sorted {
+                    write_bytes(&mut buffer, key);
+                }
+            }
+            OpOutcome::Root(root) => buffer.extend_from_slice(&root),
+        }
+        self.state = keccak256(&buffer);
+        self.operations += 1;
+        self.buffer = buffer;
+    }
+
+    pub fn operations(&self) -> u64 {
+        self.operations
+    }
+
+    pub fn finish(&self) -> [u8; HASH_SIZE] {
+        self.state
+    }
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_integer(buffer: &mut Vec<u8>, value: usize) {
+    buffer.extend_from_slice(&(value as u64).to_be_bytes());
+}
+
+fn write_option<T>(buffer: &mut Vec<u8>, value: Option<T>, write: impl FnOnce(&mut Vec<u8>, T)) {
+    match value {
+        None => buffer.push(0x00),
+        Some(value) => {
+            buffer.push(0x01);
+            write(buffer, value);
+        }
+    }
+}
+
+// Replays `operations` on `trie`, persisting to `store` for saves, and returns
+// the replay checksum. A reload keeps the trie's engine and codec config.
+pub fn replay<S: RawStoreAdapter>(
+    trie: &mut UnitrieCore,
+    store: &mut S,
+    operations: &[WorkloadOp],
+) -> Result<[u8; HASH_SIZE], String> {
+    let mut checksum = ReplayChecksum::new();
+    for operation in operations {
+        match operation {
+            WorkloadOp::Put { key, value } => {
+                trie.put(key.clone(), value.clone());
+                checksum.record(operation, OpOutcome::Done);
+            }
+            WorkloadOp::Get { key } => {
+                let value = trie
+                    .get_ref(key)
+                    .map(|value| value.load(store))
+                    .transpose()?;
+                checksum.record(operation, OpOutcome::Value(value.as_deref()));
+            }
+            WorkloadOp::Delete { key } => {
+                trie.delete(key);
+                checksum.record(operation, OpOutcome::Done);
+            }
+            WorkloadOp::DeleteRecursive { prefix } => {
+                let deleted = trie.delete_recursive(prefix);
+                checksum.record(operation, OpOutcome::Count(deleted));
+            }
+            WorkloadOp::GetValueLength { key } => {
+                let length = trie.get_value_length(key);
+                checksum.record(operation, OpOutcome::Length(length));
+            }
+            WorkloadOp::GetValueHash { key } => {
+                let hash = trie.get_value_hash(key);
+                checksum.record(operation, OpOutcome::Hash(hash));
+            }
+            WorkloadOp::CollectKeys { byte_size } => {
+                let keys = trie.collect_keys(*byte_size);
+                checksum.record(operation, OpOutcome::Keys(&keys));
+            }
+            WorkloadOp::Save => {
+                trie.save_to_store(store);
+                checksum.record(operation, OpOutcome::Root(trie.current_root_hash()));
+            }
+            WorkloadOp::SaveReload => {
+                trie.save_to_store(store);
+                let root = trie.current_root_hash();
+                let config = *trie.config();
+                *trie = UnitrieCore::from_persisted_root(trie.implementation(), &root, store)?
+                    .with_config(config);
+                checksum.record(operation, OpOutcome::Root(root));
+            }
+            WorkloadOp::RootHash => {
+                checksum.record(operation, OpOutcome::Root(trie.current_root_hash()));
+            }
+        }
+    }
+    Ok(checksum.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, replay, OpOutcome, ReplayChecksum, WorkloadOp, WorkloadProfile};
+    use crate::core_trie::Unitrie;
+    use crate::store_adapter::MemoryStore;
+    use crate::{UnitrieCore, UnitrieImplementation};
+
+    #[test]
+    fn generation_is_deterministic_per_seed() {
+        let profile = WorkloadProfile::small();
+        let workload = generate(7, &profile);
+        assert_eq!(generate(7, &profile), workload);
+        assert_ne!(generate(8, &profile).operations, workload.operations);
+        assert_eq!(workload.name, "generated-7");
+        assert!(workload.operations.len() >= profile.accounts + profile.operations);
+        assert!("storage-heavy".parse::<WorkloadProfile>().is_ok());
+        assert!("huge".parse::<WorkloadProfile>().is_err());
+    }
+
+    #[test]
+    fn generated_operations_target_live_keys() {
+        let workload = generate(11, &WorkloadProfile::small());
+        let mut trie = Unitrie::new();
+        let (mut hits, mut misses) = (0, 0);
+        for operation in workload.operations {
+            match operation {
+                WorkloadOp::Put { key, value } => {
+                    assert!(!value.is_empty());
+                    trie.put(key, value);
+                }
+                WorkloadOp::Get { key } => match trie.get(&key) {
+                    Some(_) => hits += 1,
+                    None => misses += 1,
+                },
+                WorkloadOp::Delete { key } => {
+                    assert!(trie.get(&key).is_some());
+                    trie.delete(&key);
+                }
+                WorkloadOp::DeleteRecursive { prefix } => {
+                    assert!(trie.get(&prefix).is_some());
+                    trie.delete_recursive(&prefix);
+                }
+                WorkloadOp::GetValueLength { key } | WorkloadOp::GetValueHash { key } => {
+                    assert!(trie.get(&key).is_some());
+                }
+                _ => {}
+            }
+        }
+        assert!(hits > misses && misses > 0);
+    }
+
+    #[test]
+    fn replay_checksums_agree_across_engines_and_track_results() {
+        let mut operations = generate(5, &WorkloadProfile::small()).operations;
+        operations.push(WorkloadOp::SaveReload);
+        operations.push(WorkloadOp::CollectKeys { byte_size: 31 });
+        let checksum = |implementation| {
+            let mut trie = UnitrieCore::new(implementation);
+            let mut store = MemoryStore::new();
+            replay(&mut trie, &mut store, &operations).unwrap()
+        };
+        let checksums: Vec<_> = UnitrieImplementation::enabled()
+            .iter()
+            .map(|&implementation| checksum(implementation))
+            .collect();
+        assert!(checksums.windows(2).all(|pair| pair[0] == pair[1]));
+
+        let get = WorkloadOp::Get { key: vec![1] };
+        let digest = |outcome| {
+            let mut checksum = ReplayChecksum::new();
+            checksum.record(&get, outcome);
+            assert_eq!(checksum.operations(), 1);
+            checksum.finish()
+        };
+        assert_ne!(
+            digest(OpOutcome::Value(None)),
+            digest(OpOutcome::Value(Some(&[])))
+        );
+        assert_ne!(
+            digest(OpOutcome::Value(Some(&[1]))),
+            digest(OpOutcome::Value(Some(&[2])))
+        );
+        assert_ne!(digest(OpOutcome::Done), ReplayChecksum::new().finish());
+    }
+}
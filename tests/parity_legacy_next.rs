@@ -1,30 +1,6 @@
-use std::collections::HashMap;
-use unitrie_rs::hash::keccak256;
-use unitrie_rs::{RawStoreAdapter, UnitrieCore, UnitrieImplementation};
-
-#[derive(Default)]
-struct InMemoryStore {
-    nodes: HashMap<Vec<u8>, Vec<u8>>,
-    values: HashMap<Vec<u8>, Vec<u8>>,
-}
-
-impl RawStoreAdapter for InMemoryStore {
-    fn load_raw_node(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
-        self.nodes.get(hash).cloned()
-    }
+#![cfg(all(feature = "legacy", feature = "next"))]
 
-    fn load_raw_value(&mut self, hash: &[u8]) -> Option<Vec<u8>> {
-        self.values.get(hash).cloned()
-    }
-
-    fn save_raw_node(&mut self, hash: &[u8], serialized_node: &[u8]) {
-        self.nodes.insert(hash.to_vec(), serialized_node.to_vec());
-    }
-
-    fn save_raw_value(&mut self, hash: &[u8], value: &[u8]) {
-        self.values.insert(hash.to_vec(), value.to_vec());
-    }
-}
+use unitrie_rs::prelude::*;
 
 #[test]
 fn legacy_and_next_match_on_deterministic_operations() {
@@ -35,16 +11,11 @@ fn legacy_and_next_match_on_deterministic_operations() {
     let storage_key_a = vec![0x01, 0x02, 0x03];
     let storage_key_b = vec![0xaau8; 32];
 
-    let storage_prefixed_a = storage_full_key(&account, &storage_key_a);
-    let storage_prefixed_b = storage_full_key(&account, &storage_key_b);
-
     let operations: Vec<(Vec<u8>, Vec<u8>)> = vec![
         (b"aa".to_vec(), b"v1".to_vec()),
         (b"ab".to_vec(), vec![0x99; 32]),
         (b"ab".to_vec(), vec![0x98; 33]),
         (b"abc".to_vec(), b"v3".to_vec()),
-        (storage_prefixed_a.clone(), b"sv-a".to_vec()),
-        (storage_prefixed_b.clone(), b"sv-b".to_vec()),
     ];
 
     for (key, value) in operations {
@@ -53,6 +24,17 @@ fn legacy_and_next_match_on_deterministic_operations() {
         assert_step_parity(&mut legacy, &mut next, &key);
     }
 
+    for (slot, value) in [(&storage_key_a, b"sv-a"), (&storage_key_b, b"sv-b")] {
+        legacy.put_storage_value(&account, slot, value.to_vec());
+        next.put_storage_value(&account, slot, value.to_vec());
+        assert_eq!(
+            legacy.get_storage_value(&account, slot),
+            Some(value.to_vec())
+        );
+        assert_eq!(next.get_storage_value(&account, slot), Some(value.to_vec()));
+        assert_eq!(legacy.current_root_hash(), next.current_root_hash());
+    }
+
     legacy.delete(b"aa");
     next.delete(b"aa");
     assert_step_parity(&mut legacy, &mut next, b"aa");
@@ -81,8 +63,8 @@ fn next_and_legacy_are_cross_read_write_compatible() {
     next.put(b"k1".to_vec(), b"legacy".to_vec());
     next.put(b"k2".to_vec(), vec![0x42; 33]);
 
-    let mut legacy_store = InMemoryStore::default();
-    let mut next_store = InMemoryStore::default();
+    let mut legacy_store = MemoryStore::new();
+    let mut next_store = MemoryStore::new();
 
     let legacy_root = legacy.current_root_hash();
     let next_root = next.current_root_hash();
@@ -113,27 +95,62 @@ fn next_and_legacy_are_cross_read_write_compatible() {
     assert_eq!(legacy_from_next.current_root_hash(), next_root);
 }
 
+#[test]
+fn legacy_and_next_match_on_mutation_results() {
+    for &implementation in UnitrieImplementation::enabled() {
+        let mut trie = UnitrieCore::new(implementation);
+        assert_eq!(trie.insert(b"k1".to_vec(), b"v1".to_vec()), None);
+        assert_eq!(
+            trie.insert(b"k1".to_vec(), b"v2".to_vec()),
+            Some(b"v1".to_vec())
+        );
+        trie.put(b"k2".to_vec(), b"v3".to_vec());
+        trie.put(b"x".to_vec(), b"v4".to_vec());
+
+        assert_eq!(trie.delete_returning(b"x"), Some(b"v4".to_vec()));
+        assert_eq!(trie.delete_recursive(b"k"), 2, "{implementation}");
+        assert_eq!(trie.collect_keys(i32::MAX as usize).len(), 0);
+    }
+}
+
+#[test]
+fn batch_application_matches_individual_operations() {
+    let ops = vec![
+        TrieOp::put(b"aa".to_vec(), b"v1".to_vec()),
+        TrieOp::put(b"ab".to_vec(), vec![0x42; 40]),
+        TrieOp::put(b"ac".to_vec(), b"v3".to_vec()),
+        TrieOp::delete(b"aa".to_vec()),
+        TrieOp::put(b"b".to_vec(), b"v4".to_vec()),
+        TrieOp::delete_recursive(b"ac".to_vec()),
+    ];
+
+    let mut sequential = UnitrieCore::new(UnitrieImplementation::LegacyV1);
+    sequential.put(b"aa".to_vec(), b"v1".to_vec());
+    sequential.put(b"ab".to_vec(), vec![0x42; 40]);
+    sequential.put(b"ac".to_vec(), b"v3".to_vec());
+    sequential.delete(b"aa");
+    sequential.put(b"b".to_vec(), b"v4".to_vec());
+    sequential.delete_recursive(b"ac");
+    let expected_root = sequential.current_root_hash();
+
+    for &implementation in UnitrieImplementation::enabled() {
+        let mut batched = UnitrieCore::new(implementation);
+        batched.apply_batch(ops.clone());
+        assert_eq!(
+            batched.current_root_hash(),
+            expected_root,
+            "{implementation}"
+        );
+        assert_eq!(
+            batched.collect_keys(i32::MAX as usize),
+            sequential.collect_keys(i32::MAX as usize)
+        );
+    }
+}
+
 fn assert_step_parity(legacy: &mut UnitrieCore, next: &mut UnitrieCore, key: &[u8]) {
     assert_eq!(legacy.get(key), next.get(key));
     assert_eq!(legacy.get_value_length(key), next.get_value_length(key));
     assert_eq!(legacy.get_value_hash(key), next.get_value_hash(key));
     assert_eq!(legacy.current_root_hash(), next.current_root_hash());
 }
-
-fn storage_full_key(account_address: &[u8], storage_key: &[u8]) -> Vec<u8> {
-    let mut output = Vec::new();
-    output.push(0x00);
-    output.extend_from_slice(&secure_prefix(account_address));
-    output.extend_from_slice(account_address);
-    output.push(0x00);
-    output.extend_from_slice(&secure_prefix(storage_key));
-    output.extend_from_slice(storage_key);
-    output
-}
-
-fn secure_prefix(input: &[u8]) -> [u8; 10] {
-    let hash = keccak256(input);
-    let mut prefix = [0u8; 10];
-    prefix.copy_from_slice(&hash[..10]);
-    prefix
-}